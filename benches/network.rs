@@ -5,12 +5,13 @@ use alpenglow::consensus::{Cert, ConsensusMessage, NotarCert, Vote};
 use alpenglow::crypto::aggsig::SecretKey;
 use alpenglow::crypto::merkle::GENESIS_BLOCK_HASH;
 use alpenglow::crypto::{aggsig, signature};
-use alpenglow::network::localhost_ip_sockaddr;
+use alpenglow::network::{Network, UdpNetwork, localhost_ip_sockaddr};
 use alpenglow::shredder::{MAX_DATA_PER_SLICE, RegularShredder, Shred, Shredder};
 use alpenglow::types::Slot;
 use alpenglow::types::slice::create_slice_with_invalid_txs;
 use alpenglow::{ValidatorId, ValidatorInfo};
 use divan::counter::{BytesCount, ItemsCount};
+use tokio::runtime::Runtime;
 
 fn main() {
     // run registered benchmarks.
@@ -151,6 +152,82 @@ fn deserialize_slice(bencher: divan::Bencher) {
         });
 }
 
+/// Number of packets handled per iteration of the UDP receive benchmarks.
+///
+/// Small enough for all packets to fit into the default socket receive buffer.
+const UDP_PACKETS: usize = 32;
+
+fn generate_shred() -> Shred {
+    let mut rng = rand::rng();
+    let slice = create_slice_with_invalid_txs(MAX_DATA_PER_SLICE);
+    let sk = signature::SecretKey::new(&mut rng);
+    let shreds = RegularShredder::default().shred(slice, &sk).unwrap();
+    shreds.into_iter().next().unwrap().into_shred()
+}
+
+fn udp_receiver_with_packets() -> (Runtime, UdpNetwork<Shred, Shred>) {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let sender: UdpNetwork<Shred, Shred> = UdpNetwork::new_with_any_port();
+    let receiver = UdpNetwork::new_with_any_port();
+    let addr = localhost_ip_sockaddr(receiver.port());
+    let shred = generate_shred();
+    runtime.block_on(async {
+        for _ in 0..UDP_PACKETS {
+            sender.send(&shred, addr).await.unwrap();
+        }
+    });
+    (runtime, receiver)
+}
+
+#[divan::bench(sample_size = 1)]
+fn udp_receive(bencher: divan::Bencher) {
+    bencher
+        .counter(ItemsCount::new(UDP_PACKETS))
+        .with_inputs(udp_receiver_with_packets)
+        .bench_local_refs(|(runtime, receiver)| {
+            runtime.block_on(async {
+                for _ in 0..UDP_PACKETS {
+                    receiver.receive().await.unwrap();
+                }
+            });
+        });
+}
+
+#[divan::bench(sample_size = 1)]
+fn udp_receive_batch(bencher: divan::Bencher) {
+    bencher
+        .counter(ItemsCount::new(UDP_PACKETS))
+        .with_inputs(udp_receiver_with_packets)
+        .bench_local_refs(|(runtime, receiver)| {
+            runtime.block_on(async {
+                let mut received = 0;
+                while received < UDP_PACKETS {
+                    received += receiver.receive_batch(UDP_PACKETS).await.unwrap().len();
+                }
+            });
+        });
+}
+
+#[divan::bench(args = [1, 10, 100, 1000])]
+fn udp_send_to_many(bencher: divan::Bencher, num_destinations: usize) {
+    let runtime = Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let sender: UdpNetwork<Shred, Shred> = UdpNetwork::new_with_any_port();
+    // packets are never received, only the sending side is measured
+    let receiver: UdpNetwork<Shred, Shred> = UdpNetwork::new_with_any_port();
+    let addr = localhost_ip_sockaddr(receiver.port());
+    let shred = generate_shred();
+    bencher
+        .counter(ItemsCount::new(num_destinations))
+        .bench_local(|| {
+            runtime.block_on(async {
+                let addrs = std::iter::repeat_n(addr, num_destinations);
+                sender.send_to_many(&shred, addrs).await.unwrap();
+            });
+        });
+}
+
 pub fn generate_validators(num_validators: u64) -> (Vec<SecretKey>, Vec<ValidatorInfo>) {
     let mut rng = rand::rng();
     let mut sks = Vec::new();
@@ -0,0 +1,79 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::LazyLock;
+
+use alpenglow::consensus::BlockMetadata;
+use alpenglow::crypto::Hash;
+use alpenglow::crypto::merkle::BlockHash;
+use alpenglow::storage::{BlockHashes, Metadata, RocksDbStorage, Storage};
+use alpenglow::types::Slot;
+use divan::counter::ItemsCount;
+use rand::prelude::*;
+
+/// Number of blocks stored before running the lookup benchmarks.
+const NUM_BLOCKS: u64 = 1_000_000;
+
+/// Storage filled with [`NUM_BLOCKS`] blocks, plus the hashes of all of them.
+static STORAGE: LazyLock<(Box<dyn Storage>, Vec<BlockHash>)> = LazyLock::new(|| {
+    let path = std::env::temp_dir().join(format!("alpenglow-bench-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let storage: Box<dyn Storage> = Box::new(RocksDbStorage::open(&path).unwrap());
+    let mut rng = rand::rng();
+    let mut hashes = Vec::with_capacity(NUM_BLOCKS as usize);
+    for chunk in (0..NUM_BLOCKS).collect::<Vec<_>>().chunks(10_000) {
+        let mut batch = storage.batch();
+        for &slot in chunk {
+            let hash = Hash::from(rng.random::<[u8; 32]>());
+            let metadata = BlockMetadata {
+                slot: Slot::new(slot),
+                hash: hash.clone(),
+                producer: slot % 100,
+                proposed_timestamp: slot,
+                finalized_timestamp: None,
+            };
+            let hash = BlockHash::from(hash);
+            batch
+                .put::<Metadata>(&(Slot::new(slot), hash.clone()), &metadata)
+                .unwrap();
+            batch.put::<BlockHashes>(&hash, &Slot::new(slot)).unwrap();
+            hashes.push(hash);
+        }
+        batch.commit().unwrap();
+    }
+    (storage, hashes)
+});
+
+fn main() {
+    divan::main();
+}
+
+#[divan::bench]
+fn lookup_by_hash_indexed(bencher: divan::Bencher) {
+    let (storage, hashes) = &*STORAGE;
+    bencher
+        .counter(ItemsCount::new(1_usize))
+        .with_inputs(|| hashes.choose(&mut rand::rng()).unwrap().clone())
+        .bench_refs(|hash| {
+            let slot = storage.get::<BlockHashes>(hash).unwrap().unwrap();
+            storage
+                .get::<Metadata>(&(slot, hash.clone()))
+                .unwrap()
+                .unwrap()
+        });
+}
+
+#[divan::bench(sample_count = 10, sample_size = 1)]
+fn lookup_by_hash_scan(bencher: divan::Bencher) {
+    let (storage, hashes) = &*STORAGE;
+    bencher
+        .counter(ItemsCount::new(1_usize))
+        .with_inputs(|| hashes.choose(&mut rand::rng()).unwrap().clone())
+        .bench_refs(|hash| {
+            storage
+                .iter::<Metadata>()
+                .map(Result::unwrap)
+                .find(|((_, block_hash), _)| block_hash == hash)
+                .unwrap()
+        });
+}
@@ -11,6 +11,9 @@
 //! - [`TrivialAll2All`] implements a simple best-effort all-to-all broadcast protocol.
 //! - [`RobustAll2All`] is a more robust implementation, handling retransmits.
 //!
+//! Over an [`AuthenticatedNetwork`], both also implement [`AuthenticatedAll2All`],
+//! which additionally reports the sender of each received message.
+//!
 //! The exact guarantees, however, also depend on the underlying [`Network`],
 //! since both implementations are generic over the [`Network`] trait.
 //! For example, [`TrivialAll2All`] over a TCP-based network might still give
@@ -31,6 +34,7 @@
 //! ```
 //!
 //! [`Network`]: crate::network::Network
+//! [`AuthenticatedNetwork`]: crate::network::AuthenticatedNetwork
 
 mod robust;
 mod trivial;
@@ -39,6 +43,7 @@ use async_trait::async_trait;
 
 pub use self::robust::RobustAll2All;
 pub use self::trivial::TrivialAll2All;
+use crate::ValidatorId;
 use crate::consensus::ConsensusMessage;
 
 /// Abstraction for a direct all-to-all network communication protocol.
@@ -59,9 +64,35 @@ pub trait All2All {
     ///
     /// Resolves to the next successfully deserialized [`ConsensusMessage`].
     /// Does not provide information on which node sent the message.
+    /// For that, see [`AuthenticatedAll2All::receive_from`].
     ///
     /// # Errors
     ///
     /// Implementors should return an [`std::io::Error`] iff the underlying network fails.
     async fn receive(&self) -> std::io::Result<ConsensusMessage>;
 }
+
+// allows mixing different implementations, e.g. for adversarial nodes in tests
+#[async_trait]
+impl<A: All2All + Send + Sync + ?Sized> All2All for Box<A> {
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        (**self).broadcast(msg).await
+    }
+
+    async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+        (**self).receive().await
+    }
+}
+
+/// An [`All2All`] protocol that can attribute received messages to their sender.
+#[async_trait]
+pub trait AuthenticatedAll2All: All2All {
+    /// Receives a message from any of the other nodes, together with the sender's ID.
+    ///
+    /// The sender is authenticated by the underlying network.
+    ///
+    /// # Errors
+    ///
+    /// Implementors should return an [`std::io::Error`] iff the underlying network fails.
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, ConsensusMessage)>;
+}
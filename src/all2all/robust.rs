@@ -10,10 +10,10 @@ use std::iter::repeat_n;
 
 use async_trait::async_trait;
 
-use super::All2All;
-use crate::ValidatorInfo;
+use super::{All2All, AuthenticatedAll2All};
 use crate::consensus::ConsensusMessage;
-use crate::network::{ConsensusNetwork, Network};
+use crate::network::{AuthenticatedNetwork, ConsensusNetwork, Network};
+use crate::{ValidatorId, ValidatorInfo};
 
 /// Instance of the robust all-to-all broadcast protocol.
 // TODO: acutally make more robust (retransmits, ...)
@@ -65,6 +65,16 @@ where
     }
 }
 
+#[async_trait]
+impl<N> AuthenticatedAll2All for RobustAll2All<N>
+where
+    N: ConsensusNetwork + AuthenticatedNetwork,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, ConsensusMessage)> {
+        self.network.receive_from().await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
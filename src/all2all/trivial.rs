@@ -9,10 +9,10 @@
 
 use async_trait::async_trait;
 
-use super::All2All;
-use crate::ValidatorInfo;
+use super::{All2All, AuthenticatedAll2All};
 use crate::consensus::ConsensusMessage;
-use crate::network::{ConsensusNetwork, Network};
+use crate::network::{AuthenticatedNetwork, ConsensusNetwork, Network};
+use crate::{ValidatorId, ValidatorInfo};
 
 /// Instance of the trivial all-to-all broadcast protocol.
 pub struct TrivialAll2All<N: Network> {
@@ -33,11 +33,14 @@ impl<N: Network> TrivialAll2All<N> {
     }
 }
 
-impl<N: Network> All2All for TrivialAll2All<N> {
-    async fn broadcast(&self, msg: &NetworkMessage) -> Result<(), NetworkError> {
-        log::info!("trivialAll2All broadcasting message type {:?} via BROADCAST", 
-                   std::mem::discriminant(msg));
-        self.network.send(msg, "BROADCAST").await
+#[async_trait]
+impl<N> All2All for TrivialAll2All<N>
+where
+    N: ConsensusNetwork,
+{
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        let addrs = self.validators.iter().map(|v| v.all2all_address);
+        self.network.send_to_many(msg, addrs).await
     }
 
     async fn receive(&self) -> std::io::Result<ConsensusMessage> {
@@ -45,6 +48,16 @@ impl<N: Network> All2All for TrivialAll2All<N> {
     }
 }
 
+#[async_trait]
+impl<N> AuthenticatedAll2All for TrivialAll2All<N>
+where
+    N: ConsensusNetwork + AuthenticatedNetwork,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, ConsensusMessage)> {
+        self.network.receive_from().await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
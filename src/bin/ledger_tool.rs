@@ -0,0 +1,451 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Inspects and maintains the database of a node.
+//!
+//! The database is opened read-only, so it can be inspected while the node is running.
+//! Only `truncate` opens it for writing, which requires the node to be stopped.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use alpenglow::ValidatorInfo;
+use alpenglow::consensus::{BlockMetadata, Cert, EpochInfo};
+use alpenglow::crypto::merkle::{BlockHash, MerkleRoot};
+use alpenglow::storage::inspect::{find_gaps, walk_finalized_chain};
+use alpenglow::storage::{Blocks, Certs, Metadata, RocksDbStorage, Storage};
+use alpenglow::types::Slot;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+/// Inspects and maintains the database of a node.
+#[derive(Clone, Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Database directory of the node, e.g. `ledger/0`.
+    #[arg(long)]
+    ledger: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Command {
+    /// Lists slots with their blocks and certificates.
+    Slots {
+        #[command(flatten)]
+        range: SlotRange,
+    },
+    /// Prints a block with its metadata and transactions.
+    Block {
+        slot: u64,
+        /// Prefix of the block hash in hex, needed if the slot holds several blocks.
+        #[arg(long)]
+        hash: Option<String>,
+    },
+    /// Prints certificates, optionally verifying them.
+    Certs {
+        #[command(flatten)]
+        range: SlotRange,
+        /// Verifies against the validator set in this node config file.
+        #[arg(long)]
+        verify: Option<PathBuf>,
+    },
+    /// Shows the finalized chain, newest block first.
+    Chain {
+        /// Maximum number of blocks to show.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Detects slots with neither a block nor a skip certificate, and blocks without parent.
+    Gaps,
+    /// Exports blocks with their metadata.
+    Export {
+        #[command(flatten)]
+        range: SlotRange,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Output file, standard output if not given.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Deletes all data after the given slot, to recover from a corrupted tail.
+    ///
+    /// The node must be stopped.
+    Truncate { slot: u64 },
+}
+
+/// Range of slots to operate on.
+#[derive(Clone, Debug, clap::Args)]
+struct SlotRange {
+    /// First slot, inclusive.
+    #[arg(long, default_value_t = 0)]
+    from: u64,
+    /// Last slot, inclusive.
+    #[arg(long)]
+    to: Option<u64>,
+}
+
+impl SlotRange {
+    fn start(&self) -> Slot {
+        Slot::new(self.from)
+    }
+
+    fn includes(&self, slot: Slot) -> bool {
+        self.to.is_none_or(|to| slot.inner() <= to)
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// The only part of a node config file needed here.
+#[derive(Deserialize)]
+struct ValidatorSet {
+    gossip: Vec<ValidatorInfo>,
+}
+
+/// A block as written by `export`.
+#[derive(Serialize)]
+struct BlockRow {
+    slot: u64,
+    hash: String,
+    parent_slot: Option<u64>,
+    parent_hash: Option<String>,
+    producer: u64,
+    proposed_timestamp: u64,
+    finalized_timestamp: Option<u64>,
+    /// `None` if the full data of the block was pruned.
+    num_transactions: Option<usize>,
+    /// Kinds of all certificates in the slot, separated by `|`.
+    certs: String,
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+    if !args.ledger.is_dir() {
+        bail!("no database found at {}", args.ledger.display());
+    }
+
+    let storage = match args.command {
+        Command::Truncate { .. } => RocksDbStorage::open(&args.ledger),
+        _ => RocksDbStorage::open_read_only(&args.ledger),
+    };
+    let storage = storage.context("Can not open database")?;
+    let storage: &dyn Storage = &storage;
+
+    match args.command {
+        Command::Slots { range } => list_slots(storage, &range),
+        Command::Block { slot, hash } => print_block(storage, Slot::new(slot), hash.as_deref()),
+        Command::Certs { range, verify } => print_certs(storage, &range, verify),
+        Command::Chain { limit } => print_chain(storage, limit),
+        Command::Gaps => print_gaps(storage),
+        Command::Export {
+            range,
+            format,
+            output,
+        } => export(storage, &range, format, output),
+        Command::Truncate { slot } => {
+            let deleted = storage.truncate_after(Slot::new(slot))?;
+            let finalized = storage.finalized_slot()?.unwrap_or(Slot::genesis());
+            println!("deleted {deleted} blocks after slot {slot}, finalized slot is {finalized}");
+            Ok(())
+        }
+    }
+}
+
+fn list_slots(storage: &dyn Storage, range: &SlotRange) -> Result<()> {
+    let blocks = load_metadata(storage, range)?;
+    let certs = load_certs(storage, range)?;
+    let slots: BTreeSet<_> = blocks.keys().chain(certs.keys()).copied().collect();
+
+    println!(
+        "{:>10}  {:<16}  {:>8}  {:>5}  certs",
+        "slot", "hash", "producer", "txs"
+    );
+    for slot in slots {
+        let kinds = certs
+            .get(&slot)
+            .map(|certs| cert_kinds(certs))
+            .unwrap_or_default();
+        let Some(slot_blocks) = blocks.get(&slot) else {
+            println!("{slot:>10}  {:<16}  {:>8}  {:>5}  {kinds}", "-", "-", "-");
+            continue;
+        };
+        for metadata in slot_blocks {
+            let hash = BlockHash::from(metadata.hash.clone());
+            let block = storage.get::<Blocks>(&(slot, hash.clone()))?;
+            let txs = block.map_or("-".to_owned(), |block| {
+                block.transactions().len().to_string()
+            });
+            println!(
+                "{slot:>10}  {:<16}  {:>8}  {txs:>5}  {kinds}",
+                short_hex(&hash),
+                metadata.producer
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_block(storage: &dyn Storage, slot: Slot, hash_prefix: Option<&str>) -> Result<()> {
+    let mut candidates = Vec::new();
+    for item in storage.iter_slot::<Metadata>(slot) {
+        let ((_, hash), metadata) = item?;
+        if hash_prefix.is_none_or(|prefix| hex::encode(hash.as_hash()).starts_with(prefix)) {
+            candidates.push((hash, metadata));
+        }
+    }
+    let (hash, metadata) = match candidates.len() {
+        0 => bail!("no block found in slot {slot}"),
+        1 => candidates.pop().unwrap(),
+        _ => {
+            for (hash, _) in &candidates {
+                println!("{}", hex::encode(hash.as_hash()));
+            }
+            bail!("slot {slot} holds several blocks, choose one with --hash");
+        }
+    };
+
+    println!("slot:           {slot}");
+    println!("hash:           {}", hex::encode(hash.as_hash()));
+    println!("producer:       {}", metadata.producer);
+    println!("proposed at:    {} ms", metadata.proposed_timestamp);
+    match metadata.finalized_timestamp {
+        Some(timestamp) => println!("finalized at:   {timestamp} ms"),
+        None => println!("finalized at:   -"),
+    }
+    let certs = storage
+        .iter_slot::<Certs>(slot)
+        .map(|item| item.map(|(_, cert)| cert))
+        .collect::<Result<Vec<_>, _>>()?;
+    println!("certificates:   {}", cert_kinds(&certs));
+
+    let Some(block) = storage.get::<Blocks>(&(slot, hash))? else {
+        let retained = storage.retained_slot()?;
+        println!("full data pruned, retained from slot {retained}");
+        return Ok(());
+    };
+    println!(
+        "parent:         {} in slot {}",
+        hex::encode(block.parent_hash()),
+        block.parent()
+    );
+    println!("transactions:   {}", block.transactions().len());
+    for (i, tx) in block.transactions().iter().enumerate() {
+        let preview = hex::encode(&tx.0[..tx.0.len().min(16)]);
+        println!("  {i:>5}  {:>4} bytes  {preview}", tx.0.len());
+    }
+    Ok(())
+}
+
+fn print_certs(storage: &dyn Storage, range: &SlotRange, verify: Option<PathBuf>) -> Result<()> {
+    let validator_set = match verify {
+        Some(path) => {
+            let config = std::fs::read_to_string(&path)
+                .with_context(|| format!("Can not read {}", path.display()))?;
+            let config: ValidatorSet = toml::from_str(&config).context("Can not parse config")?;
+            let validators = config.gossip;
+            Some((EpochInfo::new(0, validators.clone()), validators))
+        }
+        None => None,
+    };
+
+    let mut invalid = 0;
+    for certs in load_certs(storage, range)?.values() {
+        for cert in certs {
+            let hash = cert.block_hash().map_or("-".to_owned(), short_hex);
+            let signers = cert.signers().count();
+            let mut line = format!(
+                "{:>10}  {:<14}  {hash:<16}  {signers:>4} signers",
+                cert.slot(),
+                cert_kind(cert)
+            );
+            if let Some((epoch_info, validators)) = &validator_set {
+                let status = if !cert.check_sig(validators) {
+                    "INVALID signature"
+                } else if !cert.check_threshold(epoch_info) {
+                    "INVALID stake"
+                } else {
+                    "ok"
+                };
+                if status != "ok" {
+                    invalid += 1;
+                }
+                line.push_str("  ");
+                line.push_str(status);
+            }
+            println!("{line}");
+        }
+    }
+    if invalid > 0 {
+        bail!("{invalid} invalid certificates");
+    }
+    Ok(())
+}
+
+fn print_chain(storage: &dyn Storage, limit: usize) -> Result<()> {
+    let Some(finalized) = storage.finalized_slot()? else {
+        println!("nothing finalized yet");
+        return Ok(());
+    };
+    let chain = walk_finalized_chain(storage, finalized, limit)?;
+
+    println!("{:>10}  {:<16}  {:<8}  finalized at", "slot", "hash", "via");
+    for link in &chain {
+        let timestamp = link
+            .finalized_timestamp
+            .map_or("-".to_owned(), |timestamp| format!("{timestamp} ms"));
+        let via = if link.explicit { "cert" } else { "child" };
+        println!(
+            "{:>10}  {:<16}  {via:<8}  {timestamp}",
+            link.slot,
+            short_hex(&link.hash)
+        );
+    }
+    // the walk only stops early at genesis or where full data is missing
+    if chain.len() < limit
+        && chain
+            .last()
+            .is_some_and(|link| link.slot != Slot::genesis())
+    {
+        let retained = storage.retained_slot()?;
+        println!("full data before slot {retained} is pruned, chain ends here");
+    }
+    Ok(())
+}
+
+fn print_gaps(storage: &dyn Storage) -> Result<()> {
+    let Some(finalized) = storage.finalized_slot()? else {
+        println!("nothing finalized yet");
+        return Ok(());
+    };
+    let first = storage.retained_slot()?.max(Slot::genesis().next());
+    let gaps = find_gaps(storage, first, finalized)?;
+
+    for (start, end) in &gaps.missing {
+        println!("gap: slots {start} to {end} have neither a block nor a skip certificate");
+    }
+    for (slot, hash, parent) in &gaps.orphans {
+        println!(
+            "orphan: block {} in slot {slot} has no parent in slot {parent}",
+            short_hex(hash)
+        );
+    }
+    if gaps.is_empty() {
+        println!("no gaps in slots {first} to {finalized}");
+    }
+    Ok(())
+}
+
+fn export(
+    storage: &dyn Storage,
+    range: &SlotRange,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let certs = load_certs(storage, range)?;
+    let mut rows = Vec::new();
+    for (slot, blocks) in load_metadata(storage, range)? {
+        for metadata in blocks {
+            let hash = BlockHash::from(metadata.hash.clone());
+            let block = storage.get::<Blocks>(&(slot, hash.clone()))?;
+            rows.push(BlockRow {
+                slot: slot.inner(),
+                hash: hex::encode(hash.as_hash()),
+                parent_slot: block.as_ref().map(|block| block.parent().inner()),
+                parent_hash: block.as_ref().map(|block| hex::encode(block.parent_hash())),
+                producer: metadata.producer,
+                proposed_timestamp: metadata.proposed_timestamp,
+                finalized_timestamp: metadata.finalized_timestamp,
+                num_transactions: block.as_ref().map(|block| block.transactions().len()),
+                certs: certs
+                    .get(&slot)
+                    .map(|certs| cert_kinds(certs))
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    let writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Can not create {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        ExportFormat::Json => {
+            let mut writer = writer;
+            serde_json::to_writer_pretty(&mut writer, &rows)?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in &rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
+    if let Some(path) = output {
+        eprintln!("exported {} blocks to {}", rows.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Loads the metadata of all blocks in `range`, grouped by slot.
+fn load_metadata(
+    storage: &dyn Storage,
+    range: &SlotRange,
+) -> Result<BTreeMap<Slot, Vec<BlockMetadata>>> {
+    let mut blocks = BTreeMap::<Slot, Vec<_>>::new();
+    for item in storage.iter_from::<Metadata>(range.start()) {
+        let ((slot, _), metadata) = item?;
+        if !range.includes(slot) {
+            break;
+        }
+        blocks.entry(slot).or_default().push(metadata);
+    }
+    Ok(blocks)
+}
+
+/// Loads all certificates in `range`, grouped by slot.
+fn load_certs(storage: &dyn Storage, range: &SlotRange) -> Result<BTreeMap<Slot, Vec<Cert>>> {
+    let mut certs = BTreeMap::<Slot, Vec<_>>::new();
+    for item in storage.iter_from::<Certs>(range.start()) {
+        let (key, cert) = item?;
+        if !range.includes(key.slot) {
+            break;
+        }
+        certs.entry(key.slot).or_default().push(cert);
+    }
+    Ok(certs)
+}
+
+fn cert_kind(cert: &Cert) -> &'static str {
+    match cert {
+        Cert::Notar(_) => "notar",
+        Cert::NotarFallback(_) => "notar-fallback",
+        Cert::Skip(_) => "skip",
+        Cert::FastFinal(_) => "fast-final",
+        Cert::Final(_) => "final",
+    }
+}
+
+fn cert_kinds(certs: &[Cert]) -> String {
+    let kinds: Vec<_> = certs.iter().map(cert_kind).collect();
+    kinds.join("|")
+}
+
+fn short_hex(hash: &BlockHash) -> String {
+    hex::encode(&hash.as_hash().as_ref()[..8])
+}
@@ -1,10 +1,10 @@
 // Copyright (c) Anza Technology, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::borrow::Cow;
 use std::fs::File;
-use std::io::Read;
+use std::io::{LineWriter, Read};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use alpenglow::all2all::TrivialAll2All;
@@ -13,22 +13,31 @@ use alpenglow::crypto::aggsig;
 use alpenglow::crypto::signature::SecretKey;
 use alpenglow::disseminator::Rotor;
 use alpenglow::disseminator::rotor::StakeWeightedSampler;
-use alpenglow::network::UdpNetwork;
+use alpenglow::metrics::MetricsServer;
+use alpenglow::network::{
+    Channel, EgressConfig, EgressScheduler, MessageClass, MultiplexedFrame, MultiplexedNetwork,
+    Multiplexer, RecordingNetwork, ReplayNetwork, ScheduledNetwork, SignedNetwork, SignedPacket,
+    TraceRecorder, TraceReplayer, TransactionNetwork, UdpNetwork,
+};
+use alpenglow::repair::{RepairRequest, RepairResponse};
+use alpenglow::rpc::RpcServer;
 use alpenglow::shredder::Shred;
-use alpenglow::{Transaction, ValidatorInfo, logging};
+use alpenglow::storage::{
+    DEFAULT_DATA_DIR, RetentionPolicy, StorageBackend, StorageConfig, StorageError,
+};
+use alpenglow::tracing::{self, TracingBackend, TracingConfig};
+use alpenglow::{All2All, Disseminator, Transaction, ValidatorInfo, logging};
 use clap::Parser;
 use color_eyre::Result;
 use color_eyre::eyre::Context;
-use fastrace::collector::Config;
 use fastrace::prelude::*;
-use fastrace_opentelemetry::OpenTelemetryReporter;
 use log::warn;
-use opentelemetry::{InstrumentationScope, KeyValue};
-use opentelemetry_otlp::{SpanExporter, WithExportConfig};
-use opentelemetry_sdk::Resource;
 use rand::rng;
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use wincode::{SchemaRead, SchemaWrite};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct ConfigFile {
@@ -50,6 +59,45 @@ struct Args {
     /// Config file name to use.
     #[arg(long)]
     config_name: String,
+    /// Records all network traffic of the node to this trace file.
+    #[arg(long)]
+    record_trace: Option<String>,
+    /// Replays the incoming traffic from this trace file, instead of using the network.
+    #[arg(long, conflicts_with = "record_trace")]
+    replay_trace: Option<String>,
+    /// Serves the JSON-RPC API on this address, e.g. `127.0.0.1:8899`.
+    #[arg(long)]
+    rpc_addr: Option<SocketAddr>,
+    /// Serves Prometheus metrics on this address, under `/metrics`.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Writes a JSON line per slot with the time each consensus stage was reached to this file.
+    #[arg(long)]
+    timeline_log: Option<String>,
+    /// Root directory for the database, which is kept in a subdirectory named by the node's ID.
+    #[arg(long, default_value = DEFAULT_DATA_DIR)]
+    data_dir: PathBuf,
+    /// Syncs each database write to disk before it is acknowledged.
+    #[arg(long)]
+    sync_writes: bool,
+    /// Keeps full block data of only this many recent finalized slots.
+    #[arg(long)]
+    retain_slots: Option<u64>,
+    /// Keeps full block data of only as many recent finalized slots as fit into this many GB.
+    #[arg(long)]
+    retain_gb: Option<u64>,
+    /// Exports finalized blocks to segment files in this directory before deleting them.
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+    /// Limits outgoing traffic to this many Mbit/s, sending consensus messages first.
+    #[arg(long)]
+    upload_mbps: Option<usize>,
+    /// Where to export spans: `none`, `stdout`, `otlp[=<endpoint>]` or `file=<path>`.
+    #[arg(long, env = "ALPENGLOW_TRACING", default_value = "none")]
+    tracing: TracingBackend,
+    /// Fraction of slots for which spans are recorded, within [0, 1].
+    #[arg(long, env = "ALPENGLOW_TRACE_SAMPLING_RATE", default_value_t = 1.0)]
+    trace_sampling_rate: f64,
 }
 
 #[tokio::main]
@@ -69,24 +117,10 @@ async fn main() -> Result<()> {
     let config: ConfigFile = toml::from_str(&config_string).context("Can not parse config")?;
 
     // enable `fastrace` tracing
-    let reporter = OpenTelemetryReporter::new(
-        SpanExporter::builder()
-            .with_tonic()
-            .with_endpoint("http://127.0.0.1:4317".to_string())
-            .with_protocol(opentelemetry_otlp::Protocol::Grpc)
-            .with_timeout(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT)
-            .build()
-            .expect("initialize oltp exporter"),
-        Cow::Owned(
-            Resource::builder()
-                .with_attributes([KeyValue::new("service.name", "alpenglow-main")])
-                .build(),
-        ),
-        InstrumentationScope::builder("alpenglow")
-            .with_version(env!("CARGO_PKG_VERSION"))
-            .build(),
-    );
-    fastrace::set_reporter(reporter, Config::default());
+    let tracing_config = TracingConfig::new(format!("alpenglow-node-{}", config.id))
+        .with_backend(args.tracing)
+        .with_sampling_rate(args.trace_sampling_rate);
+    tracing::enable_tracing(&tracing_config).context("Can not enable tracing")?;
 
     logging::enable_logforth();
 
@@ -94,9 +128,39 @@ async fn main() -> Result<()> {
     let root_span = Span::root(format!("Alpenglow node {}", config.id), span_context);
 
     // start the node with the provided config
-    let node = create_node(config);
-    let cancel_token = node.get_cancel_token();
-    let node_task = tokio::spawn(node.run().in_span(root_span));
+    let mut retention = RetentionPolicy::default();
+    if let Some(slots) = args.retain_slots {
+        retention = retention.with_max_slots(slots);
+    }
+    if let Some(gb) = args.retain_gb {
+        retention = retention.with_max_bytes(gb * 1_000_000_000);
+    }
+    if let Some(archive_dir) = args.archive_dir {
+        retention = retention.with_archive_dir(archive_dir);
+    }
+    let storage_config = StorageConfig::new(StorageBackend::RocksDb(args.data_dir))
+        .with_sync_writes(args.sync_writes)
+        .with_retention(retention);
+    let (cancel_token, node_task) = if let Some(trace) = args.replay_trace {
+        let replayer = TraceReplayer::open(trace).context("Can not read trace file")?;
+        let node = create_replay_node(config, &replayer, &storage_config)
+            .context("Can not open storage")?;
+        let node = enable_timeline(node, args.timeline_log)?;
+        spawn_node(node, args.rpc_addr, args.metrics_addr, root_span)
+    } else {
+        let recorder = match args.record_trace {
+            Some(trace) => TraceRecorder::create(trace).context("Can not create trace file")?,
+            None => TraceRecorder::disabled(),
+        };
+        let mut egress_config = EgressConfig::default();
+        if let Some(mbps) = args.upload_mbps {
+            egress_config = egress_config.with_link_rate(mbps * 1_000_000 / 8);
+        }
+        let node = create_node(config, Arc::new(recorder), &storage_config, egress_config)
+            .context("Can not open storage")?;
+        let node = enable_timeline(node, args.timeline_log)?;
+        spawn_node(node, args.rpc_addr, args.metrics_addr, root_span)
+    };
 
     // wait for shutdown signal (Ctrl + C)
     tokio::signal::ctrl_c().await?;
@@ -109,23 +173,144 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Enables the per-slot timeline of `node`, if a `timeline_log` file is given.
+fn enable_timeline<A, D, T>(
+    node: Alpenglow<A, D, T>,
+    timeline_log: Option<String>,
+) -> Result<Alpenglow<A, D, T>>
+where
+    A: All2All + Send + Sync + 'static,
+    D: Disseminator + Send + Sync + 'static,
+    T: TransactionNetwork + 'static,
+{
+    let Some(path) = timeline_log else {
+        return Ok(node);
+    };
+    let file = File::create(path).context("Can not create timeline log")?;
+    Ok(node.with_timeline(LineWriter::new(file)))
+}
+
+fn spawn_node<A, D, T>(
+    node: Alpenglow<A, D, T>,
+    rpc_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    root_span: Span,
+) -> (CancellationToken, JoinHandle<Result<()>>)
+where
+    A: All2All + Send + Sync + 'static,
+    D: Disseminator + Send + Sync + 'static,
+    T: TransactionNetwork + 'static,
+{
+    let cancel_token = node.get_cancel_token();
+    if let Some(addr) = rpc_addr {
+        // submitted transactions are forwarded from a separate, arbitrary port
+        let txs_network = Multiplexer::new(UdpNetwork::new_with_any_port())
+            .channel::<Transaction, Transaction>(Channel::Transactions, Channel::Transactions);
+        let rpc = RpcServer::new(&node, txs_network);
+        let cancel_token = cancel_token.clone();
+        tokio::spawn(async move {
+            if let Err(err) = rpc.serve(addr, cancel_token).await {
+                warn!("JSON-RPC server failed: {err}");
+            }
+        });
+    }
+    if let Some(addr) = metrics_addr {
+        let metrics = MetricsServer::new(&node);
+        let cancel_token = cancel_token.clone();
+        tokio::spawn(async move {
+            if let Err(err) = metrics.serve(addr, cancel_token).await {
+                warn!("metrics server failed: {err}");
+            }
+        });
+    }
+    let node_task = tokio::spawn(node.run().in_span(root_span));
+    (cancel_token, node_task)
+}
+
+/// Sub-network multiplexed over the node's single UDP port, with optional recording.
+///
+/// Outgoing messages of all sub-networks share the node's [`EgressScheduler`].
+type NodeNetwork<S, R> = RecordingNetwork<ScheduledMuxNetwork<S, R>>;
+
+type MuxNetwork<S, R> = MultiplexedNetwork<UdpNetwork<MultiplexedFrame, MultiplexedFrame>, S, R>;
+
+type ScheduledMuxNetwork<S, R> = ScheduledNetwork<MuxNetwork<S, R>, Classifier<S>>;
+
+/// Assigns each outgoing message its [`MessageClass`].
+type Classifier<S> = Box<dyn Fn(&S) -> MessageClass + Send + Sync>;
+
+/// Same as [`NodeNetwork`], but signs every packet to authenticate its sender.
+type SignedNodeNetwork<S, R> =
+    RecordingNetwork<SignedNetwork<ScheduledMuxNetwork<SignedPacket, SignedPacket>, S, R>>;
+
 type Node = Alpenglow<
-    TrivialAll2All<UdpNetwork<ConsensusMessage, ConsensusMessage>>,
-    Rotor<UdpNetwork<Shred, Shred>, StakeWeightedSampler>,
-    UdpNetwork<Transaction, Transaction>,
+    TrivialAll2All<NodeNetwork<ConsensusMessage, ConsensusMessage>>,
+    Rotor<NodeNetwork<Shred, Shred>, StakeWeightedSampler>,
+    NodeNetwork<Transaction, Transaction>,
 >;
 
-fn create_node(config: ConfigFile) -> Node {
+fn create_node(
+    config: ConfigFile,
+    recorder: Arc<TraceRecorder>,
+    storage_config: &StorageConfig,
+    egress_config: EgressConfig,
+) -> Result<Node, StorageError> {
     // turn ConfigFile into an actual node
     let epoch_info = Arc::new(EpochInfo::new(config.id, config.gossip.clone()));
-    let start_port = config.port;
-    let network = UdpNetwork::new(start_port);
-    let all2all = TrivialAll2All::new(config.gossip, network);
-    let network = UdpNetwork::new(start_port + 1);
+    // all protocols share a single port, and its upload bandwidth
+    let mux = Multiplexer::new(UdpNetwork::new(config.port));
+    let scheduler = Arc::new(EgressScheduler::new(egress_config));
+    let network = node_network(
+        &mux,
+        &recorder,
+        &scheduler,
+        Channel::Consensus,
+        Channel::Consensus,
+        Box::new(MessageClass::of_consensus_message),
+    );
+    let all2all = TrivialAll2All::new(config.gossip.clone(), network);
+    let (own_id, leaders) = (config.id, Arc::clone(&epoch_info));
+    let network = node_network(
+        &mux,
+        &recorder,
+        &scheduler,
+        Channel::Shreds,
+        Channel::Shreds,
+        Box::new(move |shred: &Shred| {
+            if leaders.leader(shred.slot()).id == own_id {
+                MessageClass::LeaderShred
+            } else {
+                MessageClass::RelayedShred
+            }
+        }),
+    );
     let disseminator = Rotor::new(network, epoch_info.clone());
-    let repair_network = UdpNetwork::new(start_port + 2);
-    let repair_request_network = UdpNetwork::new(start_port + 3);
-    let txs_receiver = UdpNetwork::new(start_port + 4);
+    // repair requests name whom to respond to, so the sender has to be authenticated
+    let repair_network: SignedNodeNetwork<RepairRequest, RepairResponse> = signed_node_network(
+        &mux,
+        &recorder,
+        &scheduler,
+        &config,
+        Channel::RepairRequests,
+        Channel::RepairResponses,
+    );
+    let repair_request_network: SignedNodeNetwork<RepairResponse, RepairRequest> =
+        signed_node_network(
+            &mux,
+            &recorder,
+            &scheduler,
+            &config,
+            Channel::RepairResponses,
+            Channel::RepairRequests,
+        );
+    let txs_receiver = node_network(
+        &mux,
+        &recorder,
+        &scheduler,
+        Channel::Transactions,
+        Channel::Transactions,
+        Box::new(|_: &Transaction| MessageClass::Transaction),
+    );
     Alpenglow::new(
         config.identity_key,
         config.voting_key,
@@ -135,6 +320,79 @@ fn create_node(config: ConfigFile) -> Node {
         repair_request_network,
         epoch_info,
         txs_receiver,
+        storage_config,
+    )
+}
+
+/// Creates a [`NodeNetwork`], recording its traffic labeled with its receive channel.
+///
+/// Outgoing messages are scheduled with `scheduler`, as classified by `classify`.
+fn node_network<S, R>(
+    mux: &Multiplexer<UdpNetwork<MultiplexedFrame, MultiplexedFrame>>,
+    recorder: &Arc<TraceRecorder>,
+    scheduler: &Arc<EgressScheduler>,
+    send: Channel,
+    recv: Channel,
+    classify: Classifier<S>,
+) -> NodeNetwork<S, R>
+where
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    let network = ScheduledNetwork::new(mux.channel(send, recv), Arc::clone(scheduler), classify);
+    RecordingNetwork::new(network, Arc::clone(recorder), recv)
+}
+
+/// Creates a [`SignedNodeNetwork`], signing with the identity key from `config`.
+///
+/// All outgoing messages are scheduled with `scheduler` as [`MessageClass::Repair`].
+fn signed_node_network<S, R>(
+    mux: &Multiplexer<UdpNetwork<MultiplexedFrame, MultiplexedFrame>>,
+    recorder: &Arc<TraceRecorder>,
+    scheduler: &Arc<EgressScheduler>,
+    config: &ConfigFile,
+    send: Channel,
+    recv: Channel,
+) -> SignedNodeNetwork<S, R> {
+    let network = ScheduledNetwork::new(
+        mux.channel(send, recv),
+        Arc::clone(scheduler),
+        Box::new(|_: &SignedPacket| MessageClass::Repair) as Classifier<SignedPacket>,
+    );
+    let network = SignedNetwork::new(
+        network,
+        config.id,
+        config.identity_key.clone(),
+        &config.gossip,
+    );
+    RecordingNetwork::new(network, Arc::clone(recorder), recv)
+}
+
+type ReplayNode = Alpenglow<
+    TrivialAll2All<ReplayNetwork<ConsensusMessage, ConsensusMessage>>,
+    Rotor<ReplayNetwork<Shred, Shred>, StakeWeightedSampler>,
+    ReplayNetwork<Transaction, Transaction>,
+>;
+
+/// Creates a node that receives the traffic recorded in `replayer`, and sends nothing.
+fn create_replay_node(
+    config: ConfigFile,
+    replayer: &TraceReplayer,
+    storage_config: &StorageConfig,
+) -> Result<ReplayNode, StorageError> {
+    let epoch_info = Arc::new(EpochInfo::new(config.id, config.gossip.clone()));
+    let all2all = TrivialAll2All::new(config.gossip, replayer.channel(Channel::Consensus));
+    let disseminator = Rotor::new(replayer.channel(Channel::Shreds), epoch_info.clone());
+    Alpenglow::new(
+        config.identity_key,
+        config.voting_key,
+        all2all,
+        disseminator,
+        replayer.channel(Channel::RepairResponses),
+        replayer.channel(Channel::RepairRequests),
+        epoch_info,
+        replayer.channel(Channel::Transactions),
+        storage_config,
     )
 }
 
@@ -166,9 +424,9 @@ async fn create_node_configs(
             pubkey: sks[id as usize].to_pk(),
             voting_pubkey: voting_sks[id as usize].to_pk(),
             all2all_address: sockaddr,
-            disseminator_address: SocketAddr::new(sockaddr.ip(), sockaddr.port() + 1),
-            repair_request_address: SocketAddr::new(sockaddr.ip(), sockaddr.port() + 2),
-            repair_response_address: SocketAddr::new(sockaddr.ip(), sockaddr.port() + 3),
+            disseminator_address: sockaddr,
+            repair_request_address: sockaddr,
+            repair_response_address: sockaddr,
         });
     }
 
@@ -12,8 +12,12 @@ use alpenglow::crypto::signature::SecretKey;
 use alpenglow::disseminator::Rotor;
 use alpenglow::disseminator::rotor::StakeWeightedSampler;
 use alpenglow::network::simulated::SimulatedNetworkCore;
-use alpenglow::network::{SimulatedNetwork, UdpNetwork, localhost_ip_sockaddr};
+use alpenglow::network::{
+    SignedNetwork, SignedPacket, SimulatedNetwork, UdpNetwork, localhost_ip_sockaddr,
+};
+use alpenglow::repair::{RepairRequest, RepairResponse};
 use alpenglow::shredder::Shred;
+use alpenglow::storage::StorageConfig;
 use alpenglow::types::Slot;
 use alpenglow::{Alpenglow, Transaction, ValidatorInfo, logging};
 use color_eyre::Result;
@@ -43,10 +47,10 @@ async fn create_test_nodes(count: u64) -> Vec<TestNode> {
         .map(|_| UdpNetwork::new_with_any_port())
         .collect::<VecDeque<_>>();
     let mut repair_networks = (0..count)
-        .map(|_| UdpNetwork::new_with_any_port())
+        .map(|_| UdpNetwork::<SignedPacket, SignedPacket>::new_with_any_port())
         .collect::<VecDeque<_>>();
     let mut repair_request_networks = (0..count)
-        .map(|_| UdpNetwork::new_with_any_port())
+        .map(|_| UdpNetwork::<SignedPacket, SignedPacket>::new_with_any_port())
         .collect::<VecDeque<_>>();
 
     // first `count` networks are for all2all and the next `count` networks are for disseminator
@@ -111,8 +115,20 @@ async fn create_test_nodes(count: u64) -> Vec<TestNode> {
                 disseminator_networks.pop_front().unwrap(),
                 epoch_info.clone(),
             );
-            let repair_network = repair_networks.pop_front().unwrap();
-            let repair_request_network = repair_request_networks.pop_front().unwrap();
+            let repair_network: SignedNetwork<_, RepairRequest, RepairResponse> =
+                SignedNetwork::new(
+                    repair_networks.pop_front().unwrap(),
+                    v.id,
+                    sks[v.id as usize].clone(),
+                    &validators,
+                );
+            let repair_request_network: SignedNetwork<_, RepairResponse, RepairRequest> =
+                SignedNetwork::new(
+                    repair_request_networks.pop_front().unwrap(),
+                    v.id,
+                    sks[v.id as usize].clone(),
+                    &validators,
+                );
             let txs_receiver = tx_receivers.pop_front().unwrap();
             Alpenglow::new(
                 sks[v.id as usize].clone(),
@@ -123,7 +139,9 @@ async fn create_test_nodes(count: u64) -> Vec<TestNode> {
                 repair_request_network,
                 epoch_info,
                 txs_receiver,
+                &StorageConfig::in_memory(),
             )
+            .expect("in-memory storage can always be opened")
         })
         .collect()
 }
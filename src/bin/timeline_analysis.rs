@@ -0,0 +1,117 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Merges per-slot timeline logs of several nodes into latency percentiles per stage.
+//!
+//! Latencies are measured from the start of each slot, which is the earliest time
+//! any node received its first shred (or reached any other stage, if none did).
+//! Node clocks are assumed to be roughly synchronized.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use alpenglow::consensus::{SlotOutcome, SlotTimeline, TimelineStage};
+use alpenglow::types::Slot;
+use clap::Parser;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+
+/// Analyzes timeline logs written with `node --timeline-log`.
+#[derive(Clone, Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Timeline log files, usually one per node.
+    #[arg(required = true)]
+    files: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+
+    // group records of all nodes by slot
+    let mut slots = BTreeMap::<Slot, Vec<SlotTimeline>>::new();
+    for path in &args.files {
+        let file = File::open(path).with_context(|| format!("Can not open {path}"))?;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let timeline: SlotTimeline = serde_json::from_str(&line)
+                .with_context(|| format!("Can not parse line {} of {path}", i + 1))?;
+            slots.entry(timeline.slot).or_default().push(timeline);
+        }
+    }
+
+    // collect latencies relative to slot start
+    let mut latencies = BTreeMap::<TimelineStage, Vec<u64>>::new();
+    let (mut records, mut finalized, mut skipped, mut repaired) = (0, 0, 0, 0);
+    for timelines in slots.values() {
+        let Some(start) = slot_start(timelines) else {
+            continue;
+        };
+        for timeline in timelines {
+            records += 1;
+            match timeline.outcome {
+                Some(SlotOutcome::Finalized) => finalized += 1,
+                Some(SlotOutcome::Skipped) => skipped += 1,
+                None => {}
+            }
+            if timeline.repaired {
+                repaired += 1;
+            }
+            for (stage, time) in &timeline.stages {
+                latencies
+                    .entry(*stage)
+                    .or_default()
+                    .push(time.saturating_sub(start));
+            }
+        }
+    }
+
+    println!(
+        "{} slots, {records} records ({finalized} finalized, {skipped} skipped, {repaired} repaired)",
+        slots.len()
+    );
+    println!();
+    println!(
+        "{:<22} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "count", "p50 [ms]", "p90 [ms]", "p99 [ms]", "max [ms]"
+    );
+    for (stage, mut values) in latencies {
+        values.sort_unstable();
+        let stage = serde_json::to_value(stage)?;
+        println!(
+            "{:<22} {:>8} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+            stage.as_str().unwrap_or_default(),
+            values.len(),
+            millis(percentile(&values, 0.5)),
+            millis(percentile(&values, 0.9)),
+            millis(percentile(&values, 0.99)),
+            millis(*values.last().unwrap()),
+        );
+    }
+    Ok(())
+}
+
+/// Returns the start time of a slot, given the records of all nodes for it.
+fn slot_start(timelines: &[SlotTimeline]) -> Option<u64> {
+    let first_shred = timelines
+        .iter()
+        .filter_map(|timeline| timeline.stages.get(&TimelineStage::FirstShred))
+        .min();
+    let any_stage = timelines
+        .iter()
+        .flat_map(|timeline| timeline.stages.values())
+        .min();
+    first_shred.or(any_stage).copied()
+}
+
+/// Returns the `p`-th percentile of the non-empty, sorted `values`.
+fn percentile(values: &[u64], p: f64) -> u64 {
+    let rank = (p * values.len() as f64).ceil() as usize;
+    values[rank.saturating_sub(1).min(values.len() - 1)]
+}
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
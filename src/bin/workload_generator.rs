@@ -5,6 +5,7 @@ use std::net::{SocketAddr, UdpSocket};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
+use alpenglow::network::{Channel, MultiplexedFrame};
 use alpenglow::{Transaction, logging};
 use clap::Parser;
 use color_eyre::Result;
@@ -15,7 +16,7 @@ use rand::RngCore;
 #[derive(Debug, Clone, Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Validator address or receiving transactions.
+    /// Address of the validator receiving transactions.
     #[arg(long)]
     validator: SocketAddr,
     /// Initial wait time, before sending any transactions.
@@ -59,7 +60,8 @@ fn main() -> Result<()> {
     loop {
         rng.fill_bytes(&mut buf);
         let tx = Transaction(buf.clone());
-        let msg_bytes = wincode::serialize(&tx)?;
+        let frame = MultiplexedFrame::new(Channel::Transactions, wincode::serialize(&tx)?);
+        let msg_bytes = wincode::serialize(&frame)?;
         socket.send_to(&msg_bytes, validator_addr).unwrap();
         txs_sent += 1;
 
@@ -0,0 +1,497 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Byzantine node behaviors for testing.
+//!
+//! Each behavior is implemented as a wrapper around an honest [`All2All`] or
+//! [`Disseminator`] implementation, which deviates from the protocol:
+//! - [`EquivocatingDisseminator`] sends conflicting versions of each slice as leader.
+//! - [`WithholdingDisseminator`] never forwards shreds as relay.
+//! - [`DoubleVotingAll2All`] casts conflicting votes for each slot.
+//! - [`WithholdingAll2All`] never sends any votes.
+//! - [`CertSpammingAll2All`] floods other nodes with invalid certificates.
+//!
+//! [`Behavior`] selects one of these for a node, see also [`crate::cluster`].
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::sync::Mutex;
+
+use crate::consensus::{Cert, ConsensusMessage, EpochInfo, FinalCert, NotarCert, SkipCert, Vote};
+use crate::crypto::merkle::{BlockHash, MerkleRoot};
+use crate::crypto::{aggsig, hash, signature};
+use crate::shredder::{RegularShredder, Shred, Shredder, TOTAL_SHREDS, ValidatedShred};
+use crate::types::Slot;
+use crate::{All2All, Disseminator, ValidatorId, ValidatorInfo};
+
+/// All-to-all protocol of a node with any [`Behavior`].
+pub type DynAll2All = Box<dyn All2All + Send + Sync>;
+/// Block dissemination protocol of a node with any [`Behavior`].
+pub type DynDisseminator = Box<dyn Disseminator + Send + Sync>;
+
+/// Behavior of a single node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Behavior {
+    /// Follows the protocol.
+    #[default]
+    Honest,
+    /// Equivocates when leader, see [`EquivocatingDisseminator`].
+    EquivocatingLeader,
+    /// Does not forward shreds when relay, see [`WithholdingDisseminator`].
+    WithholdingRelay,
+    /// Votes for conflicting blocks, see [`DoubleVotingAll2All`].
+    DoubleVoting,
+    /// Does not vote at all, see [`WithholdingAll2All`].
+    WithholdingVotes,
+    /// Sends invalid certificates, see [`CertSpammingAll2All`].
+    CertSpamming,
+}
+
+impl Behavior {
+    /// Returns `true` iff this is [`Behavior::Honest`].
+    #[must_use]
+    pub const fn is_honest(self) -> bool {
+        matches!(self, Self::Honest)
+    }
+
+    /// Wraps the honest protocol instances of a node to make it exhibit this behavior.
+    ///
+    /// `secret_key` and `voting_secret_key` are the node's keys, needed to sign conflicting messages.
+    pub fn apply<A, D>(
+        self,
+        all2all: A,
+        disseminator: D,
+        secret_key: &signature::SecretKey,
+        voting_secret_key: &aggsig::SecretKey,
+        epoch_info: &EpochInfo,
+    ) -> (DynAll2All, DynDisseminator)
+    where
+        A: All2All + Send + Sync + 'static,
+        D: Disseminator + Send + Sync + 'static,
+    {
+        let own_id = epoch_info.own_id;
+        let voting_secret_key = voting_secret_key.clone();
+        match self {
+            Self::Honest => (Box::new(all2all), Box::new(disseminator)),
+            Self::EquivocatingLeader => {
+                let disseminator = EquivocatingDisseminator::new(disseminator, secret_key.clone());
+                (Box::new(all2all), Box::new(disseminator))
+            }
+            Self::WithholdingRelay => (
+                Box::new(all2all),
+                Box::new(WithholdingDisseminator::new(disseminator)),
+            ),
+            Self::DoubleVoting => {
+                let all2all = DoubleVotingAll2All::new(all2all, voting_secret_key, own_id);
+                (Box::new(all2all), Box::new(disseminator))
+            }
+            Self::WithholdingVotes => (
+                Box::new(WithholdingAll2All::new(all2all)),
+                Box::new(disseminator),
+            ),
+            Self::CertSpamming => {
+                let validators = epoch_info.validators.clone();
+                let all2all =
+                    CertSpammingAll2All::new(all2all, voting_secret_key, own_id, validators);
+                (Box::new(all2all), Box::new(disseminator))
+            }
+        }
+    }
+}
+
+/// Leader that sends conflicting versions of each slice to different relays.
+///
+/// Shreds are buffered until a whole slice was sent.
+/// Then, a conflicting slice is created by changing the payload.
+/// Even shred indices are sent from the original slice, odd ones from the conflicting one.
+/// Both versions are properly signed, so honest nodes see equivocation.
+pub struct EquivocatingDisseminator<D: Disseminator> {
+    disseminator: D,
+    secret_key: signature::SecretKey,
+    /// Shreds of the slice currently being sent.
+    pending: Mutex<Vec<Shred>>,
+}
+
+impl<D: Disseminator> EquivocatingDisseminator<D> {
+    /// Creates a new equivocating leader, signing with the leader's `secret_key`.
+    pub fn new(disseminator: D, secret_key: signature::SecretKey) -> Self {
+        Self {
+            disseminator,
+            secret_key,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the shreds of a slice conflicting with the given `shreds`, ordered by index.
+    ///
+    /// Returns `None` if the given shreds do not form a valid slice.
+    fn conflicting_shreds(&self, shreds: &[Shred]) -> Option<Vec<Shred>> {
+        let pk = self.secret_key.to_pk();
+        let mut roots = BTreeMap::new();
+        let mut validated = [const { None }; TOTAL_SHREDS];
+        for shred in shreds {
+            let slice_index = shred.payload().header.slice_index;
+            let index = *shred.payload().shred_index;
+            let shred = ValidatedShred::try_new(shred.clone(), roots.entry(slice_index), &pk);
+            validated[index] = Some(shred.ok()?);
+        }
+        let (mut slice, _) = RegularShredder::default().deshred(&validated).ok()?;
+        match slice.data.last_mut() {
+            Some(byte) => *byte ^= 0xff,
+            None => slice.data.push(0),
+        }
+        slice.merkle_root = None;
+        let shreds = RegularShredder::default()
+            .shred(slice, &self.secret_key)
+            .ok()?;
+        Some(shreds.into_iter().map(ValidatedShred::into_shred).collect())
+    }
+}
+
+#[async_trait]
+impl<D: Disseminator + Send + Sync> Disseminator for EquivocatingDisseminator<D> {
+    async fn send(&self, shred: &Shred) -> std::io::Result<()> {
+        let shreds = {
+            let mut pending = self.pending.lock().await;
+            pending.push(shred.clone());
+            if pending.len() < TOTAL_SHREDS {
+                return Ok(());
+            }
+            std::mem::take(&mut *pending)
+        };
+        let Some(conflicting) = self.conflicting_shreds(&shreds) else {
+            warn!("failed to create conflicting slice, sending original");
+            for shred in &shreds {
+                self.disseminator.send(shred).await?;
+            }
+            return Ok(());
+        };
+        for shred in &shreds {
+            let index = *shred.payload().shred_index;
+            let shred = if index % 2 == 0 {
+                shred
+            } else {
+                &conflicting[index]
+            };
+            self.disseminator.send(shred).await?;
+        }
+        Ok(())
+    }
+
+    async fn forward(&self, shred: &Shred) -> std::io::Result<()> {
+        self.disseminator.forward(shred).await
+    }
+
+    async fn receive(&self) -> std::io::Result<Shred> {
+        self.disseminator.receive().await
+    }
+}
+
+/// Relay that never forwards any shreds.
+///
+/// Acts honestly as leader.
+pub struct WithholdingDisseminator<D: Disseminator> {
+    disseminator: D,
+}
+
+impl<D: Disseminator> WithholdingDisseminator<D> {
+    /// Creates a new withholding relay.
+    pub const fn new(disseminator: D) -> Self {
+        Self { disseminator }
+    }
+}
+
+#[async_trait]
+impl<D: Disseminator + Send + Sync> Disseminator for WithholdingDisseminator<D> {
+    async fn send(&self, shred: &Shred) -> std::io::Result<()> {
+        self.disseminator.send(shred).await
+    }
+
+    async fn forward(&self, _shred: &Shred) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn receive(&self) -> std::io::Result<Shred> {
+        self.disseminator.receive().await
+    }
+}
+
+/// Validator that votes for conflicting outcomes of each slot.
+///
+/// For each of its own notarization votes, it additionally votes to notarize
+/// a different block in the same slot, and to skip the slot.
+pub struct DoubleVotingAll2All<A: All2All> {
+    all2all: A,
+    voting_secret_key: aggsig::SecretKey,
+    own_id: ValidatorId,
+}
+
+impl<A: All2All> DoubleVotingAll2All<A> {
+    /// Creates a new double-voting validator, signing with its `voting_secret_key`.
+    pub const fn new(
+        all2all: A,
+        voting_secret_key: aggsig::SecretKey,
+        own_id: ValidatorId,
+    ) -> Self {
+        Self {
+            all2all,
+            voting_secret_key,
+            own_id,
+        }
+    }
+}
+
+#[async_trait]
+impl<A: All2All + Send + Sync> All2All for DoubleVotingAll2All<A> {
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        self.all2all.broadcast(msg).await?;
+        let ConsensusMessage::Vote(vote) = msg else {
+            return Ok(());
+        };
+        let (Some(block_hash), true) = (vote.block_hash(), vote.is_notar()) else {
+            return Ok(());
+        };
+        if vote.signer() != self.own_id {
+            return Ok(());
+        }
+        let slot = vote.slot();
+        let sk = &self.voting_secret_key;
+        let other_hash = conflicting_block_hash(block_hash);
+        let notar = Vote::new_notar(slot, other_hash, sk, self.own_id);
+        self.all2all.broadcast(&notar.into()).await?;
+        let skip = Vote::new_skip(slot, sk, self.own_id);
+        self.all2all.broadcast(&skip.into()).await
+    }
+
+    async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+        self.all2all.receive().await
+    }
+}
+
+/// Validator that never sends any votes.
+///
+/// It still broadcasts certificates.
+pub struct WithholdingAll2All<A: All2All> {
+    all2all: A,
+}
+
+impl<A: All2All> WithholdingAll2All<A> {
+    /// Creates a new vote-withholding validator.
+    pub const fn new(all2all: A) -> Self {
+        Self { all2all }
+    }
+}
+
+#[async_trait]
+impl<A: All2All + Send + Sync> All2All for WithholdingAll2All<A> {
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        match msg {
+            ConsensusMessage::Vote(_) => Ok(()),
+            ConsensusMessage::Cert(_) => self.all2all.broadcast(msg).await,
+        }
+    }
+
+    async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+        self.all2all.receive().await
+    }
+}
+
+/// Validator that floods other nodes with invalid certificates.
+///
+/// For each of its own votes, it additionally broadcasts notarization (for a bogus block),
+/// skip and finalization certificates for the same slot, signed only by itself.
+/// These do not meet the stake threshold and should thus be rejected.
+pub struct CertSpammingAll2All<A: All2All> {
+    all2all: A,
+    voting_secret_key: aggsig::SecretKey,
+    own_id: ValidatorId,
+    validators: Vec<ValidatorInfo>,
+}
+
+impl<A: All2All> CertSpammingAll2All<A> {
+    /// Creates a new certificate-spamming validator, signing with its `voting_secret_key`.
+    pub const fn new(
+        all2all: A,
+        voting_secret_key: aggsig::SecretKey,
+        own_id: ValidatorId,
+        validators: Vec<ValidatorInfo>,
+    ) -> Self {
+        Self {
+            all2all,
+            voting_secret_key,
+            own_id,
+            validators,
+        }
+    }
+
+    fn invalid_certs(&self, slot: Slot) -> Vec<Cert> {
+        let sk = &self.voting_secret_key;
+        let bogus_hash = hash(&slot.inner().to_le_bytes()).into();
+        let notar = Vote::new_notar(slot, bogus_hash, sk, self.own_id);
+        let skip = Vote::new_skip(slot, sk, self.own_id);
+        let fin = Vote::new_final(slot, sk, self.own_id);
+        vec![
+            Cert::Notar(NotarCert::new_unchecked(&[notar], &self.validators)),
+            Cert::Skip(SkipCert::new_unchecked(&[skip], &self.validators)),
+            Cert::Final(FinalCert::new_unchecked(&[fin], &self.validators)),
+        ]
+    }
+}
+
+#[async_trait]
+impl<A: All2All + Send + Sync> All2All for CertSpammingAll2All<A> {
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        self.all2all.broadcast(msg).await?;
+        let ConsensusMessage::Vote(vote) = msg else {
+            return Ok(());
+        };
+        if vote.signer() != self.own_id {
+            return Ok(());
+        }
+        for cert in self.invalid_certs(vote.slot()) {
+            self.all2all.broadcast(&cert.into()).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+        self.all2all.receive().await
+    }
+}
+
+/// Derives a block hash different from the given `block_hash`.
+fn conflicting_block_hash(block_hash: &BlockHash) -> BlockHash {
+    hash(block_hash.as_hash().as_ref()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::disseminator::MockDisseminator;
+    use crate::shredder::MAX_DATA_PER_SLICE;
+    use crate::test_utils::generate_validators;
+    use crate::types::slice::create_slice_with_invalid_txs;
+
+    /// All-to-all instance that only records broadcast messages.
+    #[derive(Default)]
+    struct RecordingAll2All(std::sync::Mutex<Vec<ConsensusMessage>>);
+
+    #[async_trait]
+    impl All2All for Arc<RecordingAll2All> {
+        async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(msg.clone());
+            Ok(())
+        }
+
+        async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn equivocation() {
+        let sk = signature::SecretKey::new(&mut rand::rng());
+        let slice = create_slice_with_invalid_txs(MAX_DATA_PER_SLICE);
+        let shreds = RegularShredder::default().shred(slice, &sk).unwrap();
+
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut inner = MockDisseminator::new();
+        let s = sent.clone();
+        inner.expect_send().returning(move |shred| {
+            s.lock().unwrap().push(shred.clone());
+            Box::pin(async { Ok(()) })
+        });
+        let disseminator = EquivocatingDisseminator::new(inner, sk);
+        for shred in shreds {
+            disseminator.send(&shred).await.unwrap();
+        }
+
+        // half of the shreds are from each version of the slice
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), TOTAL_SHREDS);
+        let roots = sent.iter().map(|s| s.merkle_root.clone());
+        assert_eq!(roots.collect::<HashSet<_>>().len(), 2);
+        let original = &sent[0].merkle_root;
+        let from_original = sent.iter().filter(|s| &s.merkle_root == original);
+        assert_eq!(from_original.count(), TOTAL_SHREDS / 2);
+    }
+
+    #[tokio::test]
+    async fn withholding_relay() {
+        let sk = signature::SecretKey::new(&mut rand::rng());
+        let slice = create_slice_with_invalid_txs(MAX_DATA_PER_SLICE);
+        let shreds = RegularShredder::default().shred(slice, &sk).unwrap();
+
+        let mut inner = MockDisseminator::new();
+        inner
+            .expect_send()
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(()) }));
+        inner.expect_forward().never();
+        let disseminator = WithholdingDisseminator::new(inner);
+        disseminator.send(&shreds[0]).await.unwrap();
+        disseminator.forward(&shreds[1]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn double_voting() {
+        let (sks, _) = generate_validators(4);
+        let recorder = Arc::new(RecordingAll2All::default());
+        let all2all = DoubleVotingAll2All::new(recorder.clone(), sks[0].clone(), 0);
+
+        let slot = Slot::new(1);
+        let vote = Vote::new_notar(slot, hash(b"block").into(), &sks[0], 0);
+        all2all.broadcast(&vote.clone().into()).await.unwrap();
+
+        // original vote, conflicting notarization and skip
+        let sent = recorder.0.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        let votes = sent.iter().map(|msg| match msg {
+            ConsensusMessage::Vote(v) => v.clone(),
+            ConsensusMessage::Cert(_) => panic!("unexpected certificate"),
+        });
+        let votes = votes.collect::<Vec<_>>();
+        assert_eq!(votes[0], vote);
+        assert!(votes[1].is_notar());
+        assert_ne!(votes[1].block_hash(), vote.block_hash());
+        assert!(votes[2].is_skip());
+        assert!(votes.iter().all(|v| v.slot() == slot && v.signer() == 0));
+    }
+
+    #[tokio::test]
+    async fn withholding_votes() {
+        let (sks, _) = generate_validators(4);
+        let recorder = Arc::new(RecordingAll2All::default());
+        let all2all = WithholdingAll2All::new(recorder.clone());
+
+        let vote = Vote::new_skip(Slot::new(1), &sks[0], 0);
+        all2all.broadcast(&vote.into()).await.unwrap();
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cert_spamming() {
+        let (sks, epoch_info) = generate_validators(4);
+        let recorder = Arc::new(RecordingAll2All::default());
+        let validators = epoch_info.validators.clone();
+        let all2all = CertSpammingAll2All::new(recorder.clone(), sks[0].clone(), 0, validators);
+
+        let vote = Vote::new_skip(Slot::new(1), &sks[0], 0);
+        all2all.broadcast(&vote.into()).await.unwrap();
+
+        // original vote and invalid certificates
+        let sent = recorder.0.lock().unwrap();
+        assert_eq!(sent.len(), 4);
+        for msg in &sent[1..] {
+            let ConsensusMessage::Cert(cert) = msg else {
+                panic!("expected certificate");
+            };
+            assert!(!cert.check_threshold(&epoch_info));
+        }
+    }
+}
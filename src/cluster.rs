@@ -0,0 +1,317 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deterministic simulation of a whole cluster in virtual time.
+//!
+//! A [`SimulatedCluster`] consists of [`Alpenglow`](crate::Alpenglow) nodes
+//! connected via a [`SimulatedNetworkCore`].
+//! Individual nodes can be made Byzantine, see [`ClusterConfig::with_behavior`].
+//! [`ClusterConfig::run`] runs it on a single-threaded tokio runtime with a paused clock.
+//! Time then only advances while all nodes are idle, so hours of consensus take seconds.
+//!
+//! All randomness of the simulation (keys, repair peers, jitter, packet loss)
+//! is derived from a single seed.
+//! Thus, any failing run can be reproduced by running it again with the same seed.
+//! Note that tokio's `select!` macro also picks branches at random, so the order in which
+//! tasks observe events is only reproducible if that is seeded as well.
+//! This requires `--cfg tokio_unstable`, which `.cargo/config.toml` sets for this repository.
+//!
+//! This module is only available with the `simulation` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use alpenglow::cluster::ClusterConfig;
+//!
+//! ClusterConfig::new(6).with_seed(42).run(|cluster| async move {
+//!     cluster.run_for(Duration::from_secs(3600)).await;
+//!     assert!(cluster.finalized_slots().await.iter().all(|s| s.inner() > 0));
+//! });
+//! ```
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::info;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::byzantine::Behavior;
+use crate::consensus::Pool;
+use crate::crypto::merkle::BlockHash;
+use crate::network::simulated::{FaultSchedule, SimulatedNetworkCore};
+use crate::safety::{SafetyChecker, SafetyViolation};
+use crate::types::Slot;
+use crate::{ValidatorId, create_simulated_byzantine_test_nodes};
+
+/// Configuration of a [`SimulatedCluster`].
+#[derive(Clone, Debug)]
+pub struct ClusterConfig {
+    num_nodes: u64,
+    seed: u64,
+    latency: Duration,
+    jitter_ms: f64,
+    packet_loss: f64,
+    faults: FaultSchedule,
+    behaviors: BTreeMap<ValidatorId, Behavior>,
+}
+
+impl ClusterConfig {
+    /// Creates a new configuration for a cluster of `num_nodes` nodes with equal stake.
+    ///
+    /// By default, links have 100 ms latency with up to 5 ms jitter and no packet loss.
+    #[must_use]
+    pub fn new(num_nodes: u64) -> Self {
+        Self {
+            num_nodes,
+            seed: 0,
+            latency: Duration::from_millis(100),
+            jitter_ms: 5.0,
+            packet_loss: 0.0,
+            faults: FaultSchedule::default(),
+            behaviors: BTreeMap::new(),
+        }
+    }
+
+    /// Turns this config into a new config with a different seed.
+    #[must_use]
+    pub const fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Turns this config into a new config with a different latency for all links.
+    #[must_use]
+    pub const fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Turns this config into a new config with a different latency jitter.
+    #[must_use]
+    pub const fn with_jitter(mut self, jitter_ms: f64) -> Self {
+        self.jitter_ms = jitter_ms;
+        self
+    }
+
+    /// Turns this config into a new config with a different packet loss rate.
+    #[must_use]
+    pub const fn with_packet_loss(mut self, probability: f64) -> Self {
+        self.packet_loss = probability;
+        self
+    }
+
+    /// Turns this config into a new config with the given faults.
+    ///
+    /// Times in `faults` are relative to the start of the simulation.
+    #[must_use]
+    pub fn with_faults(mut self, faults: FaultSchedule) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Turns this config into a new config where `node` exhibits the given `behavior`.
+    ///
+    /// By default, all nodes are honest.
+    #[must_use]
+    pub fn with_behavior(mut self, node: ValidatorId, behavior: Behavior) -> Self {
+        self.behaviors.insert(node, behavior);
+        self
+    }
+
+    /// Returns the seed all randomness of the simulation is derived from.
+    #[must_use]
+    pub const fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Starts the cluster and runs `test` against it, all in virtual time.
+    ///
+    /// This creates a new single-threaded runtime with a paused clock.
+    /// It must therefore not be called from within another runtime.
+    /// All nodes are stopped once `test` completes.
+    pub fn run<F, Fut, T>(self, test: F) -> T
+    where
+        F: FnOnce(SimulatedCluster) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut builder = tokio::runtime::Builder::new_current_thread();
+        builder.enable_all().start_paused(true);
+        #[cfg(tokio_unstable)]
+        builder.rng_seed(tokio::runtime::RngSeed::from_bytes(
+            &self.seed.to_le_bytes(),
+        ));
+        let runtime = builder.build().expect("build simulation runtime");
+        info!("running simulated cluster with seed {}", self.seed);
+        runtime.block_on(async move {
+            let cluster = SimulatedCluster::start(self).await;
+            test(cluster).await
+        })
+    }
+}
+
+/// Cluster of nodes running on a simulated network, see [`ClusterConfig::run`].
+pub struct SimulatedCluster {
+    core: Arc<SimulatedNetworkCore>,
+    pools: Vec<Arc<RwLock<Box<dyn Pool + Send + Sync>>>>,
+    cancel_tokens: Vec<CancellationToken>,
+    behaviors: Vec<Behavior>,
+    safety: Arc<Mutex<SafetyChecker>>,
+    started_at: Instant,
+}
+
+impl SimulatedCluster {
+    async fn start(config: ClusterConfig) -> Self {
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_default_latency(config.latency)
+                .with_jitter(config.jitter_ms)
+                .with_packet_loss(config.packet_loss)
+                .with_seed(config.seed),
+        );
+
+        let behaviors = (0..config.num_nodes)
+            .map(|id| config.behaviors.get(&id).copied().unwrap_or_default())
+            .collect::<Vec<_>>();
+        let safety = Arc::new(Mutex::new(SafetyChecker::new()));
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let nodes =
+            create_simulated_byzantine_test_nodes(&core, &behaviors, &safety, &mut rng).await;
+        let mut pools = Vec::new();
+        let mut cancel_tokens = Vec::new();
+        for node in nodes {
+            pools.push(node.get_pool());
+            cancel_tokens.push(node.get_cancel_token());
+            tokio::spawn(node.run());
+        }
+        core.schedule_faults(config.faults);
+
+        Self {
+            core,
+            pools,
+            cancel_tokens,
+            behaviors,
+            safety,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Returns the network core all nodes are connected to.
+    ///
+    /// This can be used to change latencies or schedule more faults during the simulation.
+    #[must_use]
+    pub const fn core(&self) -> &Arc<SimulatedNetworkCore> {
+        &self.core
+    }
+
+    /// Returns the number of nodes in the cluster, including crashed ones.
+    #[must_use]
+    pub fn num_nodes(&self) -> usize {
+        self.pools.len()
+    }
+
+    /// Returns the virtual time elapsed since the cluster was started.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Lets the cluster run for the given amount of virtual time.
+    pub async fn run_for(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Returns the highest finalized slot of the given `node`.
+    pub async fn finalized_slot(&self, node: ValidatorId) -> Slot {
+        self.pools[node as usize].read().await.finalized_slot()
+    }
+
+    /// Returns the highest finalized slot of each node.
+    pub async fn finalized_slots(&self) -> Vec<Slot> {
+        let mut slots = Vec::with_capacity(self.pools.len());
+        for pool in &self.pools {
+            slots.push(pool.read().await.finalized_slot());
+        }
+        slots
+    }
+
+    /// Returns the hash of the block the given `node` finalized in `slot`, if any.
+    pub async fn finalized_block(&self, node: ValidatorId, slot: Slot) -> Option<BlockHash> {
+        self.pools[node as usize].read().await.finalized_block(slot)
+    }
+
+    /// Returns the behavior of the given `node`.
+    #[must_use]
+    pub fn behavior(&self, node: ValidatorId) -> Behavior {
+        self.behaviors[node as usize]
+    }
+
+    /// Returns `true` iff the given `node` is honest, i.e., follows the protocol.
+    ///
+    /// Crashed nodes are still considered honest.
+    #[must_use]
+    pub fn is_honest(&self, node: ValidatorId) -> bool {
+        self.behavior(node).is_honest()
+    }
+
+    /// Checks safety invariants across all honest nodes, see [`SafetyChecker`].
+    ///
+    /// Covers all certificates honest nodes created or received so far.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`SafetyViolation`] found, with the certificates proving it.
+    pub async fn check_safety(&self) -> Result<(), SafetyViolation> {
+        let finalized = {
+            let checker = self.safety.lock().unwrap();
+            let blocks = checker.finalized().iter();
+            blocks
+                .map(|(slot, f)| (*slot, f.block_hash.clone()))
+                .collect::<Vec<_>>()
+        };
+        // parent links are only known to the pools
+        let mut links = Vec::new();
+        for block in finalized {
+            for (node, pool) in self.pools.iter().enumerate() {
+                let node = node as ValidatorId;
+                if !self.is_honest(node) {
+                    continue;
+                }
+                if let Some(parent) = pool.read().await.parent(&block) {
+                    links.push((node, block.clone(), parent));
+                }
+            }
+        }
+        let mut checker = self.safety.lock().unwrap();
+        for (node, block, parent) in links {
+            checker.observe_parent(node, block, parent);
+        }
+        checker.check()
+    }
+
+    /// Crashes the given `node`, it stops participating in consensus.
+    pub fn crash(&self, node: ValidatorId) {
+        self.cancel_tokens[node as usize].cancel();
+    }
+
+    /// Returns `true` iff the given `node` has been crashed.
+    #[must_use]
+    pub fn is_crashed(&self, node: ValidatorId) -> bool {
+        self.cancel_tokens[node as usize].is_cancelled()
+    }
+}
+
+impl Drop for SimulatedCluster {
+    fn drop(&mut self) {
+        for token in &self.cancel_tokens {
+            token.cancel();
+        }
+    }
+}
@@ -16,41 +16,54 @@
 //! - [`Cert`] represents a certificate of votes of a specific type.
 //! - [`Vote`] represents a vote of a specific type.
 //! - [`EpochInfo`] holds information about the epoch and all validators.
+//! - [`ConsensusEvent`] is emitted to subscribers, see [`Alpenglow::subscribe`].
+//! - [`SlotTimeline`] records when a slot reached each stage, see [`Alpenglow::with_timeline`].
 
 mod block_producer;
 mod blockstore;
 mod cert;
 mod epoch_info;
+mod events;
 mod pool;
+mod timeline;
 mod vote;
 pub(crate) mod votor;
 
+use std::io::Write;
 use std::marker::{Send, Sync};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use color_eyre::Result;
 use fastrace::Span;
 use fastrace::future::FutureExt;
 use log::{trace, warn};
 use static_assertions::const_assert;
-use tokio::sync::{RwLock, mpsc};
+use tokio::sync::{RwLock, broadcast, mpsc};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use wincode::{SchemaRead, SchemaWrite};
 
 use crate::crypto::{Hash, aggsig, signature};
-use crate::network::{Network, NetworkError, NetworkMessage};
+use crate::metrics::METRICS;
+use crate::network::AuthenticatedNetwork;
 use crate::repair::{Repair, RepairMessage};
-use crate::shredder::{MAX_DATA_PER_SLICE, RegularShredder, Shred, Shredder, Slice};
 use crate::shredder;
+use crate::shredder::{MAX_DATA_PER_SLICE, RegularShredder, Shred, Shredder, Slice};
+use crate::storage::{StorageConfig, StorageError, run_retention};
+use crate::tracing::slot_span;
 use crate::{All2All, Disseminator, Slot, ValidatorInfo};
 
-pub use blockstore::{BlockInfo, Blockstore};
 pub use blockstore::BlockMetadata;
+pub use blockstore::{BlockInfo, Blockstore};
 pub use cert::Cert;
+pub(crate) use cert::{FastFinalCert, FinalCert, NotarCert, SkipCert};
 pub use epoch_info::EpochInfo;
-pub use pool::{Pool, PoolError};
+pub use events::{ConsensusEvent, EVENT_CHANNEL_CAPACITY};
+pub use pool::{AddCertError, Pool, PoolError, SlashableOffence};
+pub use timeline::{SlotOutcome, SlotTimeline, Timeline, TimelineParent, TimelineStage};
 pub use vote::Vote;
+pub(crate) use vote::VoteKind;
 use votor::{Votor, VotorEvent};
 
 /// Number of slots in each leader window.
@@ -113,6 +126,10 @@ where
     /// Block dissemination network protocol for shreds.
     disseminator: Arc<D>,
 
+    /// Channel for publishing [`ConsensusEvent`]s, see [`Self::subscribe`].
+    events: broadcast::Sender<ConsensusEvent>,
+    /// Per-slot timeline, disabled unless enabled with [`Self::with_timeline`].
+    timeline: Timeline,
     /// Indicates whether the node is shutting down.
     cancel_token: CancellationToken,
     /// Votor task handle.
@@ -129,7 +146,13 @@ where
     ///
     /// `repair_network` - [`RepairNetwork`] for sending requests and receiving responses.
     /// `repair_request_network` - [`RepairRequestNetwork`] for answering incoming requests.
-    #[must_use]
+    /// Both need to authenticate senders, so that requests can only direct responses at their sender,
+    /// and invalid responses are only ever blamed on their actual sender.
+    /// `storage_config` - [`StorageConfig`] choosing where the node persists its data.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`StorageError`] if the storage can not be opened.
     #[allow(clippy::too_many_arguments)]
     pub fn new<RN, RR>(
         secret_key: signature::SecretKey,
@@ -140,37 +163,129 @@ where
         repair_request_network: RR,
         epoch_info: Arc<EpochInfo>,
         txs_receiver: T,
-    ) -> Self
+        storage_config: &StorageConfig,
+    ) -> Result<Self, StorageError>
     where
-        RR: RepairRequestNetwork + 'static,
-        RN: RepairNetwork + 'static,
+        RR: RepairRequestNetwork + AuthenticatedNetwork + 'static,
+        RN: RepairNetwork + AuthenticatedNetwork + 'static,
+    {
+        Self::create(
+            secret_key,
+            voting_secret_key,
+            all2all,
+            disseminator,
+            repair_network,
+            repair_request_network,
+            epoch_info,
+            txs_receiver,
+            storage_config,
+            None,
+        )
+    }
+
+    /// Same as [`Self::new`], but all randomness of the node is derived from `seed`.
+    ///
+    /// This should only be used for reproducible tests and simulations,
+    /// as other nodes could predict the node's choices, e.g. of repair peers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`StorageError`] if the storage can not be opened.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_seeded<RN, RR>(
+        secret_key: signature::SecretKey,
+        voting_secret_key: aggsig::SecretKey,
+        all2all: A,
+        disseminator: D,
+        repair_network: RN,
+        repair_request_network: RR,
+        epoch_info: Arc<EpochInfo>,
+        txs_receiver: T,
+        storage_config: &StorageConfig,
+        seed: u64,
+    ) -> Result<Self, StorageError>
+    where
+        RR: RepairRequestNetwork + AuthenticatedNetwork + 'static,
+        RN: RepairNetwork + AuthenticatedNetwork + 'static,
+    {
+        Self::create(
+            secret_key,
+            voting_secret_key,
+            all2all,
+            disseminator,
+            repair_network,
+            repair_request_network,
+            epoch_info,
+            txs_receiver,
+            storage_config,
+            Some(seed),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create<RN, RR>(
+        secret_key: signature::SecretKey,
+        voting_secret_key: aggsig::SecretKey,
+        all2all: A,
+        disseminator: D,
+        repair_network: RN,
+        repair_request_network: RR,
+        epoch_info: Arc<EpochInfo>,
+        txs_receiver: T,
+        storage_config: &StorageConfig,
+        seed: Option<u64>,
+    ) -> Result<Self, StorageError>
+    where
+        RR: RepairRequestNetwork + AuthenticatedNetwork + 'static,
+        RN: RepairNetwork + AuthenticatedNetwork + 'static,
     {
         let cancel_token = CancellationToken::new();
         let (votor_tx, votor_rx) = mpsc::channel(1024);
         let (repair_tx, repair_rx) = mpsc::channel(1024);
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
         let all2all = Arc::new(all2all);
 
-        let blockstore: Box<dyn Blockstore + Send + Sync> =
-            Box::new(BlockstoreImpl::new(epoch_info.clone(), votor_tx.clone()));
+        let storage = storage_config.open(epoch_info.own_id)?;
+        let blockstore: Box<dyn Blockstore + Send + Sync> = Box::new(BlockstoreImpl::new(
+            epoch_info.clone(),
+            Arc::clone(&storage),
+            votor_tx.clone(),
+        ));
         let blockstore = Arc::new(RwLock::new(blockstore));
-        let mut pool = Pool::new(epoch_info.clone(), votor_tx.clone(), repair_tx.clone());
-        pool.set_blockstore(Arc::clone(&blockstore));
+        let mut pool = Pool::new(
+            epoch_info.clone(),
+            Arc::clone(&storage),
+            votor_tx.clone(),
+            repair_tx.clone(),
+        );
+        pool.set_event_sender(events.clone());
         let pool = Arc::new(RwLock::new(pool));
 
+        let _retention_handle = tokio::spawn(run_retention(
+            Arc::clone(&storage),
+            storage_config.retention().clone(),
+            cancel_token.clone(),
+        ));
+
         let repair_request_handler = RepairRequestHandler::new(
             epoch_info.clone(),
             blockstore.clone(),
             repair_request_network,
         );
         let _repair_request_handler =
-            tokio::spawn(async move { repair_request_handler.run().await });
+            tokio::spawn(async move { repair_request_handler.run_authenticated().await });
 
+        let timeline = Timeline::new(epoch_info.own_id);
         let mut repair = Repair::new(
             Arc::clone(&blockstore),
             Arc::clone(&pool),
             repair_network,
             epoch_info.clone(),
         );
+        if let Some(seed) = seed {
+            repair = repair.with_seed(seed);
+        }
+        repair.set_timeline(timeline.clone());
 
         let _repair_handle = tokio::spawn(
             async move { repair.repair_loop(repair_rx).await }
@@ -184,6 +299,7 @@ where
             votor_rx,
             all2all.clone(),
         );
+        votor.set_timeline(timeline.clone());
         let votor_handle = tokio::spawn(
             async move { votor.voting_loop().await.unwrap() }
                 .in_span(Span::enter_with_local_parent("voting loop")),
@@ -203,16 +319,28 @@ where
             DELTA_FIRST_SLICE,
         ));
 
-        Self {
+        Ok(Self {
             epoch_info,
             blockstore,
             pool,
             block_producer,
             all2all,
             disseminator,
+            events,
+            timeline,
             cancel_token,
             votor_handle,
-        }
+        })
+    }
+
+    /// Enables the per-slot timeline of this node, see [`Timeline`].
+    ///
+    /// Writes one line of JSON per concluded slot to `writer`.
+    #[must_use]
+    pub fn with_timeline(self, writer: impl Write + Send + 'static) -> Self {
+        self.timeline.set_writer(Box::new(writer));
+        tokio::spawn(self.timeline.clone().track(self.subscribe()));
+        self
     }
 
     /// Starts the different tasks of the Alpenglow node.
@@ -266,6 +394,10 @@ where
         self.epoch_info.validator(self.epoch_info.own_id)
     }
 
+    pub fn get_epoch_info(&self) -> Arc<EpochInfo> {
+        Arc::clone(&self.epoch_info)
+    }
+
     pub fn get_pool(&self) -> Arc<RwLock<Box<dyn Pool + Send + Sync>>> {
         Arc::clone(&self.pool)
     }
@@ -274,6 +406,17 @@ where
         self.cancel_token.clone()
     }
 
+    /// Subscribes to [`ConsensusEvent`]s of this node.
+    ///
+    /// The returned receiver gets all events emitted after this call.
+    /// Subscribe before calling [`Self::run`] to not miss any events.
+    /// A receiver that lags more than [`EVENT_CHANNEL_CAPACITY`] events behind
+    /// misses the oldest ones, see [`broadcast::Receiver::recv`].
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<ConsensusEvent> {
+        self.events.subscribe()
+    }
+
     /// Handles incoming messages on all the different network interfaces.
     ///
     /// [`All2All`]: Handles incoming votes and certificates. Adds them to the [`Pool`].
@@ -402,7 +545,7 @@ where
         parent_ready: bool,
     ) -> Result<()> {
         let (parent_slot, parent_hash) = parent;
-        let _slot_span = Span::enter_with_local_parent(format!("slot {slot}"));
+        let _slot_span = slot_span(slot);
         let mut rng = SmallRng::seed_from_u64(slot);
         let ph = &hex::encode(parent_hash)[..8];
         info!("producing block in slot {slot} with parent {ph} in slot {parent_slot}",);
@@ -460,22 +603,34 @@ where
     async fn handle_all2all_message(&self, msg: ConsensusMessage) {
         trace!("received all2all msg: {msg:?}");
         match msg {
-            ConsensusMessage::Vote(v) => match self.pool.write().await.add_vote(v).await {
-                Ok(()) => {}
-                Err(AddVoteError::Slashable(offence)) => {
-                    warn!("slashable offence detected: {offence}");
+            ConsensusMessage::Vote(v) => {
+                METRICS.votes_received.inc();
+                let res = self.pool.write().await.add_vote(v).await;
+                if let Err(err) = &res {
+                    METRICS.vote_rejected(err);
+                }
+                match res {
+                    Ok(()) => {}
+                    Err(AddVoteError::Slashable(offence)) => {
+                        warn!("slashable offence detected: {offence}");
+                    }
+                    Err(err) => trace!("ignoring invalid vote: {err}"),
+                }
+            }
+            ConsensusMessage::Cert(c) => {
+                METRICS.certs_received.inc();
+                if let Err(err) = self.pool.write().await.add_cert(c).await {
+                    METRICS.cert_rejected(&err);
+                    trace!("ignoring invalid cert: {err}");
                 }
-                Err(err) => trace!("ignoring invalid vote: {err}"),
-            },
-            ConsensusMessage::Cert(c) => match self.pool.write().await.add_cert(c).await {
-                Ok(()) => {}
-                Err(err) => trace!("ignoring invalid cert: {err}"),
-            },
+            }
         }
     }
 
     #[fastrace::trace(short_name = true)]
     async fn handle_disseminator_shred(&self, shred: Shred) -> std::io::Result<()> {
+        METRICS.shreds_received.inc();
+
         // potentially forward shred
         self.disseminator.forward(&shred).await?;
 
@@ -4,16 +4,15 @@
 //! Block production, leader-side of the consensus protocol.
 
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use color_eyre::Result;
 use either::Either;
-use fastrace::Span;
 use log::{debug, info, warn};
 use static_assertions::const_assert;
 use tokio::pin;
 use tokio::sync::{RwLock, oneshot};
-use tokio::time::sleep;
+use tokio::time::{Instant, sleep};
 use tokio_util::sync::CancellationToken;
 
 use crate::consensus::{Blockstore, EpochInfo, Pool};
@@ -21,6 +20,7 @@ use crate::crypto::merkle::{BlockHash, GENESIS_BLOCK_HASH, MerkleRoot};
 use crate::crypto::signature;
 use crate::network::{Network, TransactionNetwork};
 use crate::shredder::{MAX_DATA_PER_SLICE, RegularShredder, Shredder};
+use crate::tracing::slot_span;
 use crate::types::{Slice, SliceHeader, SliceIndex, SlicePayload, Slot};
 use crate::{BlockId, Disseminator, MAX_TRANSACTION_SIZE};
 
@@ -172,7 +172,7 @@ where
         parent_block_id: BlockId,
         mut parent_ready_receiver: oneshot::Receiver<BlockId>,
     ) -> Result<BlockId> {
-        let _slot_span = Span::enter_with_local_parent(format!("slot {slot}"));
+        let _slot_span = slot_span(slot);
         let (parent_slot, parent_hash) = &parent_block_id;
         assert_eq!(*parent_slot, slot.prev());
         assert!(slot.is_start_of_window());
@@ -289,7 +289,7 @@ where
         slot: Slot,
         parent_block_id: BlockId,
     ) -> Result<BlockId> {
-        let _slot_span = Span::enter_with_local_parent(format!("slot {slot}"));
+        let _slot_span = slot_span(slot);
         let (parent_slot, parent_hash) = &parent_block_id;
         info!(
             "producing block in slot {} with ready parent {} in slot {}",
@@ -6,10 +6,11 @@
 mod slot_block_data;
 
 use std::collections::BTreeMap;
+use std::ops::Bound;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use log::debug;
+use log::{debug, warn};
 use mockall::automock;
 use tokio::sync::mpsc::Sender;
 
@@ -17,16 +18,19 @@ use self::slot_block_data::{AddShredError, SlotBlockData};
 use super::epoch_info::EpochInfo;
 use super::votor::VotorEvent;
 use crate::consensus::blockstore::slot_block_data::BlockData;
-use crate::crypto::merkle::{BlockHash, DoubleMerkleProof, MerkleRoot, SliceRoot};
+use crate::crypto::merkle::{
+    BlockHash, DoubleMerkleProof, DoubleMerkleTree, MerkleRoot, SliceRoot,
+};
 use crate::shredder::{RegularShredder, Shred, ShredIndex, ShredderPool, ValidatedShred};
+use crate::storage::{BlockHashes, Blocks, Metadata, ShredKey, Shreds, Storage, StorageError};
 use crate::types::SliceIndex;
 use crate::{Block, BlockId, Slot};
 
-use rocksdb::{DB, Options, IteratorMode, WriteBatch};
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
+use wincode::{SchemaRead, SchemaWrite};
 
 /// additional metadata (might need refactor @e)
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, SchemaRead, SchemaWrite)]
 pub struct BlockMetadata {
     pub slot: Slot,
     pub hash: Hash,
@@ -68,8 +72,8 @@ pub struct BlockstoreImpl {
     /// Cache of previously verified Merkle roots.
     merkle_root_cache: HashMap<(Slot, usize), Hash>,
 
-    /// Persistent RocksDB handle for durable block storage.
-    db: DB,
+    /// Persistent storage of blocks and their metadata, shared with the pool.
+    storage: Arc<dyn Storage>,
     /// Set of slots for which conflicting shreds have been seen (leader equivocated).
     equivocated_slots: BTreeSet<Slot>,
 }
@@ -81,16 +85,13 @@ impl BlockstoreImpl {
     /// - [`VotorEvent::FirstShred`] when receiving the first shred for a slot
     ///   from the block dissemination protocol
     /// - [`VotorEvent::Block`] for any reconstructed block
-    pub fn new(epoch_info: Arc<EpochInfo>, votor_channel: Sender<VotorEvent>) -> Self {
-        // ensure the data directory exists and open/create RocksDB database
-        std::fs::create_dir_all("data").ok();
-        let db_path = format!("data/blockstore/{}", epoch_info.own_id);
-        std::fs::create_dir_all(&db_path).ok();
-
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        let db = DB::open(&opts, db_path).expect("open RocksDB");
-
+    ///
+    /// Blocks are persisted to and reloaded from the given `storage`.
+    pub fn new(
+        epoch_info: Arc<EpochInfo>,
+        storage: Arc<dyn Storage>,
+        votor_channel: Sender<VotorEvent>,
+    ) -> Self {
         // initialise in-memory structures
         let mut s = Self {
             shreds: BTreeMap::new(),
@@ -104,21 +105,15 @@ impl BlockstoreImpl {
             votor_channel,
             epoch_info,
             merkle_root_cache: HashMap::new(),
-            db,
+            storage,
             equivocated_slots: BTreeSet::new(),
         };
 
         // warm cache with at most HOT_BLOCK_LIMIT most recent blocks so the node can resume quickly without having to load all into ram
-        let mut loaded = 0usize;
-        for item in s.db.iterator(IteratorMode::End) {
-            if loaded == HOT_BLOCK_LIMIT { break; }
-            if let Ok((_k, raw_val)) = item {
-                if let Ok((block, _)) = bincode::serde::decode_from_slice::<Block, _>(&raw_val, bincode::config::standard()) {
-                    s.canonical.insert(block.slot(), block.block_hash());
-                    s.blocks.insert((block.slot(), block.block_hash()), block);
-                    loaded += 1;
-                }
-            }
+        let recent = s.storage.iter_rev::<Blocks>().filter_map(Result::ok);
+        for (block_id, block) in recent.take(HOT_BLOCK_LIMIT).collect::<Vec<_>>() {
+            s.canonical.insert(block_id.0, block_id.1.clone());
+            s.blocks.insert(block_id, block);
         }
 
         s
@@ -129,14 +124,26 @@ impl BlockstoreImpl {
         self.block_data = self.block_data.split_off(&slot);
     }
 
-    async fn send_votor_event(&self, event: VotorEvent) -> Option<BlockInfo> {
+    /// Sends the given `event` to Votor.
+    ///
+    /// For [`VotorEvent::Block`], first persists the block.
+    /// If that fails, the event is not sent and [`AddShredError::Storage`] is returned.
+    async fn send_votor_event(
+        &self,
+        event: VotorEvent,
+    ) -> Result<Option<BlockInfo>, AddShredError> {
         match &event {
             VotorEvent::FirstShred(_) => {
                 self.votor_channel.send(event).await.unwrap();
-                None
+                Ok(None)
             }
             VotorEvent::Block { slot, block_info } => {
                 let block_info = block_info.clone();
+                // persist before anyone can vote for the block, so it survives a crash
+                if let Err(err) = self.persist_block(&(*slot, block_info.hash.clone())) {
+                    warn!("failed to persist block in slot {slot}: {err}");
+                    return Err(AddShredError::Storage);
+                }
                 debug!(
                     "reconstructed block {} in slot {} with parent {} in slot {}",
                     &hex::encode(block_info.hash.as_hash())[..8],
@@ -146,7 +153,7 @@ impl BlockstoreImpl {
                 );
                 self.votor_channel.send(event).await.unwrap();
 
-                Some(block_info)
+                Ok(Some(block_info))
             }
             ev => panic!("unexpected event {ev:?}"),
         }
@@ -253,7 +260,7 @@ impl Blockstore for BlockstoreImpl {
             leader_pk,
             &mut shredder,
         )? {
-            Some(event) => Ok(self.send_votor_event(event).await),
+            Some(event) => self.send_votor_event(event).await,
             None => Ok(None),
         }
     }
@@ -288,7 +295,7 @@ impl Blockstore for BlockstoreImpl {
             leader_pk,
             &mut shredder,
         )? {
-            Some(event) => Ok(self.send_votor_event(event).await),
+            Some(event) => self.send_votor_event(event).await,
             None => Ok(None),
         }
     }
@@ -324,91 +331,20 @@ impl Blockstore for BlockstoreImpl {
 
     /// Gives the last slice index for the given `block_id`.
     ///
+    /// Falls back to persisted shreds if the block is not held in memory.
+    ///
     /// Returns `None` if blockstore does not know the last slice yet.
     fn get_last_slice_index(&self, block_id: &BlockId) -> Option<SliceIndex> {
-        let block_data = self.get_block_data(block_id)?;
-        block_data.last_slice
-    }
-
-    /// Gives the Merkle root for the given `slice_index` of the given `block_id`.
-    ///
-    /// Returns `Some(slot, block_info)` if a block was reconstructed, `None` otherwise.
-    /// In the `Some`-case, `block_info` is the [`BlockInfo`] of the reconstructed block.
-    async fn try_reconstruct_block(&mut self, slot: Slot) -> Option<(Slot, BlockInfo)> {
-        if self.canonical_block_hash(slot).is_some() {
-            trace!("already have block for slot {slot}");
-            return None;
-        }
-        let last_slice = self.last_slices.get(&slot)?;
-        if self.stored_slices_for_slot(slot) != last_slice + 1 {
-            trace!("don't have all slices for slot {slot} yet");
-            return None;
-        }
-
-        // calculate double-Merkle tree & block hash
-        let merkle_roots: Vec<_> = self
-            .slices
-            .range(&(slot, 0)..)
-            .take_while(|(key, _)| key.0 == slot)
-            .map(|(_, s)| s.merkle_root.as_ref().unwrap())
-            .collect();
-        let tree = MerkleTree::new(&merkle_roots);
-        let block_hash = tree.get_root();
-        self.double_merkle_trees.insert(slot, tree);
-
-        // reconstruct block header
-        let first_slice = self.slices.get(&(slot, 0)).unwrap();
-        let parent_slot = u64::from_be_bytes(first_slice.data[0..8].try_into().unwrap());
-        let parent_hash = first_slice.data[8..40].try_into().unwrap();
-        self.canonical.insert(slot, block_hash);
-        // TODO: reconstruct actual block content
-        let block = Block {
-            slot,
-            block_hash,
-            parent: parent_slot,
-            parent_hash,
-            transactions: vec![],
-        };
-        let block_info = BlockInfo::from(&block);
-        self.blocks.insert((slot, block_hash), block.clone());
-
-        // persist canonical block to RocksDB for durability
-        let key = format!("{:016X}{}", slot, hex::encode(block_hash));
-        if let Ok(value) = bincode::serde::encode_to_vec(&block, bincode::config::standard()) {
-            let _ = self.db.put(key.as_bytes(), value);
-        }
-
-        // block metadata with current timestamp
-        let metadata = BlockMetadata {
-            slot,
-            hash: block_hash,
-            producer: self.epoch_info.leader(slot).id,
-            proposed_timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-            finalized_timestamp: None,
-        };
-        let meta_key = format!("meta|{:016X}{}", slot, hex::encode(block_hash));
-        if let Ok(value) = bincode::serde::encode_to_vec(&metadata, bincode::config::standard()) {
-            let _ = self.db.put(meta_key.as_bytes(), value);
+        if let Some(block_data) = self.get_block_data(block_id) {
+            return block_data.last_slice;
         }
-
-        // clean up raw slices
-        for slice_index in 0..=*last_slice {
-            self.slices.remove(&(slot, slice_index));
-        }
-
-        // notify Votor of block and print block info
-        let event = VotorEvent::Block { slot, block_info };
-        self.votor_channel.send(event).await.unwrap();
-        let h = &hex::encode(block_hash)[..8];
-        let ph = &hex::encode(parent_hash)[..8];
-        debug!("reconstructed block {h} in slot {slot} with parent {ph} in slot {parent_slot}");
-        Some((slot, block_info))
+        let num_slices = self.load_slice_roots(block_id).len();
+        SliceIndex::new(num_slices.checked_sub(1)?)
     }
 
-    /// Gives reference to stored shred for given `block_id`, `slice_index` and `shred_index`.
+    /// Gives the stored shred for given `block_id`, `slice_index` and `shred_index`.
+    ///
+    /// Falls back to persisted shreds if the shred is not held in memory.
     ///
     /// Returns `None` if blockstore does not hold that shred.
     fn get_shred(
@@ -416,22 +352,36 @@ impl Blockstore for BlockstoreImpl {
         block_id: &BlockId,
         slice_index: SliceIndex,
         shred_index: ShredIndex,
-    ) -> Option<&ValidatedShred> {
-        let block_data = self.get_block_data(block_id)?;
-        let slice_shreds = block_data.shreds.get(&slice_index)?;
-        slice_shreds[*shred_index].as_ref()
+    ) -> Option<ValidatedShred> {
+        let in_memory = self
+            .get_block_data(block_id)
+            .and_then(|block_data| block_data.shreds.get(&slice_index))
+            .and_then(|slice_shreds| slice_shreds[*shred_index].as_ref());
+        match in_memory {
+            Some(shred) => Some(shred.clone()),
+            None => self.load_shred(block_id, slice_index, shred_index),
+        }
     }
 
     /// Generates a Merkle proof for the given `slice_index` of the given `block_id`.
     ///
+    /// Falls back to persisted shreds if the block is not held in memory.
+    ///
     /// Returns `None` if blockstore does not hold that block yet.
     fn create_double_merkle_proof(
         &self,
         block_id: &BlockId,
         slice_index: SliceIndex,
     ) -> Option<DoubleMerkleProof> {
-        let block_data = self.get_block_data(block_id)?;
-        let tree = block_data.double_merkle_tree.as_ref()?;
+        if let Some(block_data) = self.get_block_data(block_id) {
+            let tree = block_data.double_merkle_tree.as_ref()?;
+            return Some(tree.create_proof(slice_index.inner()));
+        }
+        let slice_roots = self.load_slice_roots(block_id);
+        if slice_index.inner() >= slice_roots.len() {
+            return None;
+        }
+        let tree = DoubleMerkleTree::new(&slice_roots);
         Some(tree.create_proof(slice_index.inner()))
     }
 
@@ -442,56 +392,102 @@ impl Blockstore for BlockstoreImpl {
         self.shreds.len()
     }
 
-    /// Fetches a block directly from RocksDB without caching it in RAM.
-    pub fn load_block_from_db(&self, slot: Slot, hash: Hash) -> Option<Block> {
-        let key = format!("{:016X}{}", slot, hex::encode(hash));
-        if let Ok(Some(val)) = self.db.get(key.as_bytes()) {
-            if let Ok((block, _)) = bincode::serde::decode_from_slice::<Block, _>(&val, bincode::config::standard()) {
-                return Some(block);
-            }
-        }
-        None
+    /// Returns approximate RocksDB sizes in bytes, labeled by what they measure.
+    pub fn db_sizes(&self) -> [(&'static str, u64); 3] {
+        self.storage.sizes()
     }
 
-    /// Searches RocksDB for a block with the given hash (slow path, should be o(n) tbd @e).
-    /// Returns slot and block if found.
-    pub fn load_block_by_hash(&self, hash: Hash) -> Option<(Slot, Block)> {
-        let suffix = hex::encode(hash);
-        let suffix_bytes = suffix.as_bytes();
-        for item in self.db.iterator(IteratorMode::Start) {
-            if let Ok((k, v)) = item {
-                if k.len() >= 16 + suffix_bytes.len() && &k[k.len()-suffix_bytes.len()..] == suffix_bytes {
-                    let slot_str = std::str::from_utf8(&k[0..16]).ok()?;
-                    let slot = u64::from_str_radix(slot_str, 16).ok()?;
-                    if let Ok((block, _)) = bincode::serde::decode_from_slice::<Block, _>(&v, bincode::config::standard()) {
-                        return Some((slot, block));
-                    }
-                }
-            }
-        }
-        None
+    /// Fetches a block directly from storage without caching it in RAM.
+    pub fn load_block_from_db(&self, slot: Slot, hash: BlockHash) -> Option<Block> {
+        self.storage.get::<Blocks>(&(slot, hash)).ok().flatten()
     }
 
-    /// Loads block metadata from RocksDB.
-    pub fn load_block_metadata(&self, slot: Slot, hash: Hash) -> Option<BlockMetadata> {
-        let key = format!("meta|{:016X}{}", slot, hex::encode(hash));
-        if let Ok(Some(val)) = self.db.get(key.as_bytes()) {
-            if let Ok((metadata, _)) = bincode::serde::decode_from_slice::<BlockMetadata, _>(&val, bincode::config::standard()) {
-                return Some(metadata);
+    /// Atomically stores the reconstructed block with the given `block_id`,
+    /// its metadata, its shreds and its entry in the hash index.
+    ///
+    /// Shreds are persisted to serve repair after a restart.
+    /// Old ones are deleted by the retention pass, see [`RetentionPolicy`].
+    ///
+    /// [`RetentionPolicy`]: crate::storage::RetentionPolicy
+    fn persist_block(&self, block_id: &BlockId) -> Result<(), StorageError> {
+        let (slot, hash) = block_id;
+        let (Some(block), Some(block_data)) =
+            (self.get_block(block_id), self.get_block_data(block_id))
+        else {
+            return Ok(());
+        };
+        let metadata = BlockMetadata {
+            slot: *slot,
+            hash: hash.as_hash().clone(),
+            producer: self.epoch_info.leader(*slot).id,
+            proposed_timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            finalized_timestamp: None,
+        };
+        let mut batch = self.storage.batch();
+        batch.put::<Blocks>(block_id, block)?;
+        batch.put::<Metadata>(block_id, &metadata)?;
+        batch.put::<BlockHashes>(hash, slot)?;
+        for (slice, slice_shreds) in &block_data.shreds {
+            for shred in slice_shreds.iter().flatten() {
+                let key = ShredKey {
+                    block_id: block_id.clone(),
+                    slice: *slice,
+                    shred: shred.payload().shred_index,
+                };
+                batch.put::<Shreds>(&key, shred)?;
             }
         }
-        None
+        batch.commit()
     }
 
-    /// Updates the finalized timestamp for a block.
-    pub fn update_finalized_timestamp(&self, slot: Slot, hash: Hash, timestamp: u64) {
-        if let Some(mut metadata) = self.load_block_metadata(slot, hash) {
-            metadata.finalized_timestamp = Some(timestamp);
-            let key = format!("meta|{:016X}{}", slot, hex::encode(hash));
-            if let Ok(value) = bincode::serde::encode_to_vec(&metadata, bincode::config::standard()) {
-                let _ = self.db.put(key.as_bytes(), value);
-            }
-        }
+    /// Loads the persisted shred for the given `block_id`, `slice_index` and `shred_index`.
+    fn load_shred(
+        &self,
+        block_id: &BlockId,
+        slice_index: SliceIndex,
+        shred_index: ShredIndex,
+    ) -> Option<ValidatedShred> {
+        let key = ShredKey {
+            block_id: block_id.clone(),
+            slice: slice_index,
+            shred: shred_index,
+        };
+        let shred = self.storage.get::<Shreds>(&key).ok().flatten()?;
+        // shreds are only persisted after they were validated
+        Some(ValidatedShred::new_validated(shred))
+    }
+
+    /// Loads the Merkle roots of all slices of the given `block_id` from persisted shreds.
+    ///
+    /// Returns an empty list if the shreds of this block are not persisted.
+    fn load_slice_roots(&self, block_id: &BlockId) -> Vec<SliceRoot> {
+        // shreds are only persisted for complete blocks, so each slice has a first shred
+        let first_shred = ShredIndex::new(0).unwrap();
+        SliceIndex::all()
+            .map_while(|slice| self.load_shred(block_id, slice, first_shred))
+            .map(|shred| shred.merkle_root.clone())
+            .collect()
+    }
+
+    /// Looks up the slot of the stored block with the given hash via the hash index.
+    pub fn block_slot(&self, hash: &BlockHash) -> Option<Slot> {
+        self.storage.get::<BlockHashes>(hash).ok().flatten()
+    }
+
+    /// Fetches the block with the given hash from storage.
+    /// Returns slot and block if found.
+    pub fn load_block_by_hash(&self, hash: BlockHash) -> Option<(Slot, Block)> {
+        let slot = self.block_slot(&hash)?;
+        let block = self.load_block_from_db(slot, hash)?;
+        Some((slot, block))
+    }
+
+    /// Loads block metadata from storage.
+    pub fn load_block_metadata(&self, slot: Slot, hash: BlockHash) -> Option<BlockMetadata> {
+        self.storage.get::<Metadata>(&(slot, hash)).ok().flatten()
     }
 
     /// Loads highest finalized slot from Pool DB and prunes all blocks beyond it.
@@ -499,49 +495,55 @@ impl Blockstore for BlockstoreImpl {
     pub fn clean_beyond_finalized(&mut self, highest_finalized_slot: Slot) {
         println!("[Blockstore::clean_beyond_finalized] pruning blocks beyond slot {}", highest_finalized_slot);
 
-        let mut batch = WriteBatch::default();
+        let beyond = (Bound::Excluded(highest_finalized_slot), Bound::Unbounded);
+        let deleted_meta_count = self
+            .storage
+            .iter_from::<Metadata>(highest_finalized_slot.next())
+            .count();
+        let mut batch = self.storage.batch();
         let mut deleted_count = 0;
-        let mut deleted_meta_count = 0;
-        for item in self.db.iterator(IteratorMode::Start) {
-            if let Ok((k, _v)) = item {
-                if k.starts_with(b"meta|") {
-                    if k.len() >= 21 {
-                        if let Ok(slot_hex) = std::str::from_utf8(&k[5..21]) {
-                            if let Ok(slot_val) = u64::from_str_radix(slot_hex, 16) {
-                                if slot_val > highest_finalized_slot {
-                                    batch.delete(&k);
-                                    deleted_meta_count += 1;
-                                }
-                            }
-                        }
-                    }
-                } else if k.len() >= 16 {
-                    if let Ok(slot_hex) = std::str::from_utf8(&k[0..16]) {
-                        if let Ok(slot_val) = u64::from_str_radix(slot_hex, 16) {
-                            if slot_val > highest_finalized_slot {
-                                batch.delete(&k);
-                                deleted_count += 1;
-                            }
-                        }
-                    }
-                }
+        for item in self
+            .storage
+            .iter_from::<Blocks>(highest_finalized_slot.next())
+        {
+            if let Ok(((_, hash), _)) = item {
+                batch.delete::<BlockHashes>(&hash);
+                deleted_count += 1;
             }
         }
-        let _ = self.db.write(batch);
-        
+        batch.delete_slots::<Blocks>(beyond);
+        batch.delete_slots::<Metadata>(beyond);
+        batch.delete_slots::<Shreds>(beyond);
+        if let Err(err) = batch.commit() {
+            warn!("failed to delete blocks beyond finalized slot: {err}");
+        }
+
         // (redundantly) clean up in-memory structures
-        self.shreds.retain(|(slot, _), _| *slot <= highest_finalized_slot);
-        self.slices.retain(|(slot, _), _| *slot <= highest_finalized_slot);
-        self.blocks.retain(|(slot, _), _| *slot <= highest_finalized_slot);
-        self.canonical.retain(|slot, _| *slot <= highest_finalized_slot);
-        self.alternatives.retain(|slot, _| *slot <= highest_finalized_slot);
-        self.first_shred_seen.retain(|slot| *slot <= highest_finalized_slot);
-        self.double_merkle_trees.retain(|slot, _| *slot <= highest_finalized_slot);
-        self.last_slices.retain(|slot, _| *slot <= highest_finalized_slot);
-        self.merkle_root_cache.retain(|(slot, _), _| *slot <= highest_finalized_slot);
-        
-        println!("[Blockstore::clean_beyond_finalized] deleted {} blocks and {} metadata entries from DB, retained {} blocks in memory", 
-                 deleted_count, deleted_meta_count, self.blocks.len());
+        self.shreds
+            .retain(|(slot, _), _| *slot <= highest_finalized_slot);
+        self.slices
+            .retain(|(slot, _), _| *slot <= highest_finalized_slot);
+        self.blocks
+            .retain(|(slot, _), _| *slot <= highest_finalized_slot);
+        self.canonical
+            .retain(|slot, _| *slot <= highest_finalized_slot);
+        self.alternatives
+            .retain(|slot, _| *slot <= highest_finalized_slot);
+        self.first_shred_seen
+            .retain(|slot| *slot <= highest_finalized_slot);
+        self.double_merkle_trees
+            .retain(|slot, _| *slot <= highest_finalized_slot);
+        self.last_slices
+            .retain(|slot, _| *slot <= highest_finalized_slot);
+        self.merkle_root_cache
+            .retain(|(slot, _), _| *slot <= highest_finalized_slot);
+
+        println!(
+            "[Blockstore::clean_beyond_finalized] deleted {} blocks and {} metadata entries from DB, retained {} blocks in memory",
+            deleted_count,
+            deleted_meta_count,
+            self.blocks.len()
+        );
     }
 }
 
@@ -552,15 +554,22 @@ mod tests {
 
     use super::*;
     use crate::ValidatorInfo;
-    use crate::crypto::merkle::DoubleMerkleTree;
     use crate::crypto::signature::SecretKey;
     use crate::crypto::{Hash, aggsig};
     use crate::network::dontcare_sockaddr;
     use crate::shredder::{DATA_SHREDS, TOTAL_SHREDS};
+    use crate::storage::{MemoryStorage, RocksDbStorage};
     use crate::test_utils::create_random_shredded_block;
     use crate::types::SliceIndex;
 
     fn test_setup(tx: Sender<VotorEvent>) -> (SecretKey, BlockstoreImpl) {
+        test_setup_with_storage(tx, Arc::new(MemoryStorage::default()))
+    }
+
+    fn test_setup_with_storage(
+        tx: Sender<VotorEvent>,
+        storage: Arc<dyn Storage>,
+    ) -> (SecretKey, BlockstoreImpl) {
         let sk = SecretKey::new(&mut rand::rng());
         let voting_sk = aggsig::SecretKey::new(&mut rand::rng());
         let info = ValidatorInfo {
@@ -575,7 +584,7 @@ mod tests {
         };
         let validators = vec![info];
         let epoch_info = EpochInfo::new(0, validators);
-        (sk, BlockstoreImpl::new(Arc::new(epoch_info), tx))
+        (sk, BlockstoreImpl::new(Arc::new(epoch_info), storage, tx))
     }
 
     async fn add_shred_ignore_duplicate(
@@ -628,6 +637,115 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn lookup_by_hash() -> Result<()> {
+        let slot = Slot::genesis().next();
+        let (tx, _rx) = mpsc::channel(100);
+        let (sk, mut blockstore) = test_setup(tx);
+
+        let (block_hash, _, shreds) = create_random_shredded_block(slot, 1, &sk);
+        assert!(blockstore.load_block_by_hash(block_hash.clone()).is_none());
+        for shred in &shreds[0] {
+            add_shred_ignore_duplicate(&mut blockstore, shred.clone().into_shred()).await?;
+        }
+        assert_eq!(blockstore.block_slot(&block_hash), Some(slot));
+        let (found_slot, _) = blockstore.load_block_by_hash(block_hash.clone()).unwrap();
+        assert_eq!(found_slot, slot);
+
+        // index entries are removed together with the blocks
+        blockstore.clean_beyond_finalized(Slot::genesis());
+        assert!(blockstore.block_slot(&block_hash).is_none());
+        assert!(blockstore.load_block_by_hash(block_hash).is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn crash_consistency() -> Result<()> {
+        let slot = Slot::genesis().next();
+        let (tx, _rx) = mpsc::channel(100);
+        let rocksdb = Arc::new(RocksDbStorage::open_temporary());
+        let (sk, mut blockstore) = test_setup_with_storage(tx, rocksdb.clone());
+
+        // crash after every single shred
+        let (block_hash, _, shreds) = create_random_shredded_block(slot, 1, &sk);
+        let block_id = (slot, block_hash.clone());
+        for shred in &shreds[0] {
+            add_shred_ignore_duplicate(&mut blockstore, shred.clone().into_shred()).await?;
+            let crashed: Arc<dyn Storage> = Arc::new(rocksdb.crash_snapshot());
+            crashed.assert_consistent();
+            let complete = blockstore.get_block(&block_id).is_some();
+            assert_eq!(
+                crashed.get::<Blocks>(&block_id).unwrap().is_some(),
+                complete
+            );
+        }
+
+        // block can be served after restarting
+        let crashed = Arc::new(rocksdb.crash_snapshot());
+        let (tx, _rx) = mpsc::channel(100);
+        let restarted = BlockstoreImpl::new(blockstore.epoch_info.clone(), crashed, tx);
+        assert!(
+            restarted
+                .load_block_metadata(slot, block_hash.clone())
+                .is_some()
+        );
+        let (found_slot, _) = restarted.load_block_by_hash(block_hash).unwrap();
+        assert_eq!(found_slot, slot);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serve_repair_after_restart() -> Result<()> {
+        let slot = Slot::genesis().next();
+        let (tx, _rx) = mpsc::channel(100);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let (sk, mut blockstore) = test_setup_with_storage(tx, storage.clone());
+
+        let (block_hash, _, slices) = create_random_shredded_block(slot, 2, &sk);
+        let block_id = (slot, block_hash.clone());
+        for shred in slices.iter().flatten() {
+            add_shred_ignore_duplicate(&mut blockstore, shred.clone().into_shred()).await?;
+        }
+        assert!(blockstore.get_block(&block_id).is_some());
+
+        // restart on the same storage, with nothing in memory
+        let (tx, _rx) = mpsc::channel(100);
+        let restarted = BlockstoreImpl::new(blockstore.epoch_info.clone(), storage, tx);
+        let last_slice = SliceIndex::new_unchecked(1);
+        assert_eq!(restarted.get_last_slice_index(&block_id), Some(last_slice));
+        for (slice, shreds) in slices.iter().enumerate() {
+            let slice = SliceIndex::new_unchecked(slice);
+            for shred in shreds {
+                let shred_index = shred.payload().shred_index;
+                let stored = restarted.get_shred(&block_id, slice, shred_index).unwrap();
+                assert_eq!(stored.payload().data, shred.payload().data);
+            }
+            let proof = restarted
+                .create_double_merkle_proof(&block_id, slice)
+                .unwrap();
+            let slice_root = &shreds[0].merkle_root;
+            assert!(DoubleMerkleTree::check_proof(
+                slice_root,
+                slice.inner(),
+                &block_hash,
+                &proof
+            ));
+        }
+
+        // unknown blocks are still not served
+        let unknown = (slot.next(), block_hash);
+        assert!(restarted.get_last_slice_index(&unknown).is_none());
+        assert!(
+            restarted
+                .create_double_merkle_proof(&unknown, SliceIndex::first())
+                .is_none()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn store_two_slice_block() -> Result<()> {
         let slot = Slot::genesis().next();
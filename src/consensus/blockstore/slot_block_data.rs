@@ -32,6 +32,8 @@ pub enum AddShredError {
     Equivocation,
     #[error("shred was invalid and leader did not equivocate")]
     InvalidShred,
+    #[error("reconstructed block could not be persisted")]
+    Storage,
 }
 
 impl From<ShredVerifyError> for AddShredError {
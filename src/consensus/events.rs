@@ -0,0 +1,75 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consensus events that applications can subscribe to.
+//!
+//! These are emitted by [`super::Pool`] as it makes progress.
+//! Use [`super::Alpenglow::subscribe`] to receive them, instead of polling the pool.
+
+use crate::BlockId;
+use crate::consensus::pool::SlashableOffence;
+use crate::crypto::merkle::BlockHash;
+use crate::types::Slot;
+
+/// Number of events buffered per subscriber.
+///
+/// Subscribers that fall further behind miss the oldest events, see [`tokio::sync::broadcast`].
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Observable progress of the consensus protocol on a single node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConsensusEvent {
+    /// The given block, with the given parent, was received and reconstructed.
+    BlockReceived {
+        slot: Slot,
+        block_hash: BlockHash,
+        parent: BlockId,
+    },
+    /// The given block was notarized.
+    Notarized { slot: Slot, block_hash: BlockHash },
+    /// The given block was finalized by a fast-finalization certificate.
+    FastFinalized { slot: Slot, block_hash: BlockHash },
+    /// The given block was finalized by finalization and notarization certificates.
+    SlowFinalized { slot: Slot, block_hash: BlockHash },
+    /// The given block was finalized as an ancestor of a finalized block.
+    ImplicitlyFinalized { slot: Slot, block_hash: BlockHash },
+    /// The given slot was skipped by a skip certificate.
+    Skipped(Slot),
+    /// The given slot was skipped as it lies between a finalized block and its parent.
+    ImplicitlySkipped(Slot),
+    /// No progress was made for a long time, recovery was triggered.
+    ///
+    /// The provided slot is the highest finalized slot.
+    Standstill(Slot),
+    /// A validator committed a slashable offence.
+    SlashableOffence(SlashableOffence),
+}
+
+impl ConsensusEvent {
+    /// Returns the slot this event is about, if any.
+    #[must_use]
+    pub const fn slot(&self) -> Option<Slot> {
+        match self {
+            Self::BlockReceived { slot, .. }
+            | Self::Notarized { slot, .. }
+            | Self::FastFinalized { slot, .. }
+            | Self::SlowFinalized { slot, .. }
+            | Self::ImplicitlyFinalized { slot, .. }
+            | Self::Skipped(slot)
+            | Self::ImplicitlySkipped(slot)
+            | Self::Standstill(slot) => Some(*slot),
+            Self::SlashableOffence(_) => None,
+        }
+    }
+
+    /// Returns `true` iff this event finalizes a block (directly or implicitly).
+    #[must_use]
+    pub const fn is_finalization(&self) -> bool {
+        matches!(
+            self,
+            Self::FastFinalized { .. }
+                | Self::SlowFinalized { .. }
+                | Self::ImplicitlyFinalized { .. }
+        )
+    }
+}
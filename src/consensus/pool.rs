@@ -11,7 +11,7 @@ mod parent_ready_tracker;
 mod slot_state;
 
 use std::collections::BTreeMap;
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -19,13 +19,12 @@ use either::Either;
 use log::{debug, info, trace, warn};
 use mockall::automock;
 use thiserror::Error;
-use tokio::sync::{mpsc::Sender, RwLock};
+use tokio::sync::{broadcast, mpsc::Sender};
 
 use crate::crypto::Hash;
-use crate::{Slot, ValidatorId};
 
 use super::blockstore::BlockInfo;
-use super::blockstore::Blockstore;
+use super::events::ConsensusEvent;
 use super::votor::VotorEvent;
 use super::{Cert, EpochInfo, Vote};
 use crate::consensus::cert::NotarCert;
@@ -37,8 +36,7 @@ use crate::{BlockId, Slot, ValidatorId};
 use parent_ready_tracker::ParentReadyTracker;
 use slot_state::SlotState;
 
-use rocksdb::{DB, Options, IteratorMode};
-use bincode;
+use crate::storage::{CertKey, Certs, Metadata, Storage, StorageError};
 
 /// Errors the Pool may throw when adding a vote or certificate.
 #[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
@@ -51,6 +49,8 @@ pub enum PoolError {
     Duplicate,
     #[error("vote constitutes a slashable offence")]
     Slashable(SlashableOffence),
+    #[error("resulting certificate could not be persisted")]
+    Storage,
 }
 
 /// Errors the Pool may return when adding a certificate.
@@ -64,6 +64,8 @@ pub enum AddCertError {
     InvalidSignature,
     #[error("duplicate cert")]
     Duplicate,
+    #[error("cert could not be persisted")]
+    Storage,
 }
 
 /// Slashable offences that may be detected by the Pool.
@@ -90,6 +92,9 @@ pub trait Pool {
     async fn add_block(&mut self, block_id: BlockId, parent_id: BlockId);
     async fn recover_from_standstill(&self);
     fn finalized_slot(&self) -> Slot;
+    fn finalized_block(&self, slot: Slot) -> Option<BlockHash>;
+    fn parent(&self, block: &BlockId) -> Option<BlockId>;
+    fn certs(&self, slot: Slot) -> Vec<Cert>;
     fn parents_ready(&self, slot: Slot) -> &[BlockId];
     fn wait_for_parent_ready(&mut self, slot: Slot) -> Either<BlockId, oneshot::Receiver<BlockId>>;
 }
@@ -116,28 +121,23 @@ pub struct PoolImpl {
     ///
     repair_channel: Sender<(Slot, Hash)>,
 
-    /// RocksDB handle for persisting certificates & metadata.
-    db: DB,
-    /// Reference to blockstore for updating finalized timestamps.
-    blockstore: Option<Arc<RwLock<Blockstore>>>,
+    /// Persistent storage of certificates, shared with the blockstore.
+    storage: Arc<dyn Storage>,
+    /// Channel for publishing [`ConsensusEvent`]s to subscribers, if any.
+    event_sender: Option<broadcast::Sender<ConsensusEvent>>,
 }
 
 impl PoolImpl {
     /// Creates a new empty pool containing no votes or certificates.
     ///
     /// Any later emitted events will be sent on provided `votor_event_channel`.
+    /// Certificates are persisted to and reloaded from the given `storage`.
     pub fn new(
         epoch_info: Arc<EpochInfo>,
+        storage: Arc<dyn Storage>,
         votor_event_channel: Sender<VotorEvent>,
         repair_channel: Sender<BlockId>,
     ) -> Self {
-        std::fs::create_dir_all("data").ok();
-        let db_path = format!("data/pool/{}", epoch_info.own_id);
-        std::fs::create_dir_all(&db_path).ok();
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        let db = DB::open(&opts, db_path).expect("open RocksDB pool db");
-
         let mut s = Self {
             slot_states: BTreeMap::new(),
             parent_ready_tracker: ParentReadyTracker::default(),
@@ -146,17 +146,25 @@ impl PoolImpl {
             epoch_info,
             votor_event_channel,
             repair_channel,
-            db,
-            blockstore: None,
+            storage,
+            event_sender: None,
         };
 
         s.load_from_db();
         s
     }
 
-    /// Sets the blockstore reference for updating finalized timestamps.
-    pub fn set_blockstore(&mut self, blockstore: Arc<RwLock<Blockstore>>) {
-        self.blockstore = Some(blockstore);
+    /// Sets the channel for publishing [`ConsensusEvent`]s.
+    pub fn set_event_sender(&mut self, event_sender: broadcast::Sender<ConsensusEvent>) {
+        self.event_sender = Some(event_sender);
+    }
+
+    /// Publishes the given `event` to all subscribers, if any.
+    fn emit(&self, event: ConsensusEvent) {
+        if let Some(sender) = &self.event_sender {
+            // fails only if there are no subscribers, which is fine
+            let _ = sender.send(event);
+        }
     }
 
     /// Adds a new certificate to the pool. Certificate is assumed to be valid.
@@ -165,19 +173,16 @@ impl PoolImpl {
     /// - slot is not too old or too far in the future
     /// - signature is valid
     /// - certificate is not a duplicate
-    async fn add_valid_cert(&mut self, cert: Cert) {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate could not be persisted.
+    /// In that case, the pool state is left unchanged.
+    async fn add_valid_cert(&mut self, cert: Cert) -> Result<(), StorageError> {
         let slot = cert.slot();
-
-        let kind_byte: u8 = match &cert {
-            Cert::Notar(_) => 0,
-            Cert::NotarFallback(_) => 1,
-            Cert::Skip(_) => 2,
-            Cert::FastFinal(_) => 3,
-            Cert::Final(_) => 4,
-        };
-        let key = format!("cert|{:016X}|{}", cert.slot(), kind_byte);
-        if let Ok(val) = bincode::serde::encode_to_vec(&cert, bincode::config::standard()) {
-            let _ = self.db.put(key.as_bytes(), val);
+        if let Err(err) = self.persist_cert(&cert) {
+            warn!("failed to persist cert in slot {slot}: {err}");
+            return Err(err);
         }
 
         // actually add certificate
@@ -195,10 +200,14 @@ impl PoolImpl {
                     slot
                 );
                 if matches!(cert, Cert::Notar(_)) {
+                    self.emit(ConsensusEvent::Notarized {
+                        slot,
+                        block_hash: block_hash.clone(),
+                    });
                     let finalization_event = self
                         .finality_tracker
                         .mark_notarized(slot, block_hash.clone());
-                    self.handle_finalization(finalization_event).await;
+                    self.handle_finalization(finalization_event, false).await;
                 }
 
                 // potentially notify child waiting for safe-to-notar
@@ -227,47 +236,22 @@ impl PoolImpl {
             }
             Cert::Skip(_) => {
                 warn!("skipped slot {slot}");
+                self.emit(ConsensusEvent::Skipped(slot));
                 let new_parents_ready = self.parent_ready_tracker.mark_skipped(slot);
                 self.send_parent_ready_events(new_parents_ready).await;
             }
             Cert::FastFinal(ff_cert) => {
                 info!("fast finalized slot {slot}");
-                self.highest_finalized_slot = slot.max(self.highest_finalized_slot);
-                
-                if let Some(ref blockstore) = self.blockstore {
-                    if let Some(hash) = cert.block_hash() {
-                        let timestamp = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64;
-                        if let Ok(bs) = blockstore.try_read() {
-                            bs.update_finalized_timestamp(slot, hash, timestamp);
-                        }
-                    }
-                }
-                
+                let block_hash = ff_cert.block_hash().clone();
+                let finalization_event =
+                    self.finality_tracker.mark_fast_finalized(slot, block_hash);
+                self.handle_finalization(finalization_event, true).await;
                 self.prune();
             }
             Cert::Final(_) => {
                 info!("slow finalized slot {slot}");
-                self.highest_finalized_slot = slot.max(self.highest_finalized_slot);
-                
-                if let Some(ref blockstore) = self.blockstore {
-                    if let Some(state) = self.slot_states.get(&slot) {
-                        if let Some(ref notar_cert) = state.certificates.notar {
-                            if let Some(hash) = Cert::Notar(notar_cert.clone()).block_hash() {
-                                let timestamp = std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis() as u64;
-                                if let Ok(bs) = blockstore.try_read() {
-                                    bs.update_finalized_timestamp(slot, hash, timestamp);
-                                }
-                            }
-                        }
-                    }
-                }
-                
+                let finalization_event = self.finality_tracker.mark_finalized(slot);
+                self.handle_finalization(finalization_event, false).await;
                 self.prune();
             }
         }
@@ -275,6 +259,43 @@ impl PoolImpl {
         // send to votor for broadcasting
         let event = VotorEvent::CertCreated(Box::new(cert));
         self.votor_event_channel.send(event).await.unwrap();
+        Ok(())
+    }
+
+    /// Atomically persists `cert` together with any finality markers it implies.
+    ///
+    /// For finalization certificates, this also advances the persisted finalized slot
+    /// and records the finalization time in the metadata of the finalized block.
+    fn persist_cert(&self, cert: &Cert) -> Result<(), StorageError> {
+        let slot = cert.slot();
+        let mut batch = self.storage.batch();
+        batch.put::<Certs>(&CertKey::new(cert), cert)?;
+        let finalized_hash = match cert {
+            Cert::FastFinal(ff_cert) => Some(ff_cert.block_hash().clone()),
+            Cert::Final(_) => self
+                .slot_states
+                .get(&slot)
+                .and_then(|state| state.certificates.notar.as_ref())
+                .map(|notar_cert| notar_cert.block_hash().clone()),
+            _ => None,
+        };
+        if matches!(cert, Cert::FastFinal(_) | Cert::Final(_)) {
+            if slot > self.finalized_slot() {
+                batch.set_finalized_slot(slot);
+            }
+            // metadata only exists if this node reconstructed the block
+            if let Some(hash) = finalized_hash
+                && let Some(mut metadata) = self.storage.get::<Metadata>(&(slot, hash.clone()))?
+            {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                metadata.finalized_timestamp = Some(timestamp);
+                batch.put::<Metadata>(&(slot, hash), &metadata)?;
+            }
+        }
+        batch.commit()
     }
 
     /// Mutably accesses the [`SlotState`] for the given `slot`.
@@ -411,7 +432,23 @@ impl PoolImpl {
             .is_some_and(|state| state.certificates.skip.is_some())
     }
 
-    async fn handle_finalization(&mut self, event: FinalizationEvent) {
+    /// Handles newly finalized slots, as reported by the finality tracker.
+    ///
+    /// `fast` indicates whether the directly finalized block, if any, was fast finalized.
+    async fn handle_finalization(&mut self, event: FinalizationEvent, fast: bool) {
+        if let Some((slot, block_hash)) = event.finalized.clone() {
+            self.emit(if fast {
+                ConsensusEvent::FastFinalized { slot, block_hash }
+            } else {
+                ConsensusEvent::SlowFinalized { slot, block_hash }
+            });
+        }
+        for (slot, block_hash) in event.implicitly_finalized.iter().cloned() {
+            self.emit(ConsensusEvent::ImplicitlyFinalized { slot, block_hash });
+        }
+        for slot in event.implicitly_skipped.iter().copied() {
+            self.emit(ConsensusEvent::ImplicitlySkipped(slot));
+        }
         let new_parents_ready = self.parent_ready_tracker.handle_finalization(event);
         self.send_parent_ready_events(new_parents_ready).await;
     }
@@ -468,8 +505,9 @@ impl Pool for PoolImpl {
             return Err(AddCertError::Duplicate);
         }
 
-        self.add_valid_cert(cert).await;
-        Ok(())
+        self.add_valid_cert(cert)
+            .await
+            .map_err(|_| AddCertError::Storage)
     }
 
     /// Adds a new vote to the pool. Checks validity of the vote.
@@ -493,6 +531,7 @@ impl Pool for PoolImpl {
         let voter = vote.signer();
         let voter_stake = self.epoch_info.validator(voter).stake;
         if let Some(offence) = self.slot_state(slot).check_slashable_offence(&vote) {
+            self.emit(ConsensusEvent::SlashableOffence(offence));
             return Err(AddVoteError::Slashable(offence));
         } else if self.slot_state(slot).should_ignore_vote(&vote) {
             return Err(AddVoteError::Duplicate);
@@ -504,8 +543,13 @@ impl Pool for PoolImpl {
             self.slot_state(slot).add_vote(vote, voter_stake);
 
         // handle any resulting events
+        // the vote is already counted, if persisting fails the certificate will
+        // still be received from other nodes, which are broadcasting it as well
+        let mut result = Ok(());
         for cert in new_certs {
-            self.add_valid_cert(cert).await;
+            if self.add_valid_cert(cert).await.is_err() {
+                result = Err(AddVoteError::Storage);
+            }
         }
         for event in votor_events {
             self.votor_event_channel.send(event).await.unwrap();
@@ -513,7 +557,7 @@ impl Pool for PoolImpl {
         for (slot, block_hash) in blocks_to_repair {
             self.repair_channel.send((slot, block_hash)).await.unwrap();
         }
-        Ok(())
+        result
     }
 
     /// Registers a new block with its respective parent in the pool.
@@ -525,13 +569,15 @@ impl Pool for PoolImpl {
         let (slot, block_hash) = &block_id;
         let (parent_slot, parent_hash) = &parent_id;
 
+        self.emit(ConsensusEvent::BlockReceived {
+            slot: *slot,
+            block_hash: block_hash.clone(),
+            parent: parent_id.clone(),
+        });
         let finalization_event = self
             .finality_tracker
             .add_parent(block_id.clone(), parent_id.clone());
-        let new_parents_ready = self
-            .parent_ready_tracker
-            .handle_finalization(finalization_event);
-        self.send_parent_ready_events(new_parents_ready).await;
+        self.handle_finalization(finalization_event, false).await;
 
         self.slot_state(*slot)
             .notify_parent_known(block_hash.clone());
@@ -567,6 +613,7 @@ impl Pool for PoolImpl {
         let votes = self.get_own_votes(slot.next()..);
 
         warn!("recovering from standstill at slot {slot}");
+        self.emit(ConsensusEvent::Standstill(slot));
         debug!(
             "re-broadcasting {} certificates and {} votes",
             certs.len(),
@@ -586,6 +633,23 @@ impl Pool for PoolImpl {
         self.finality_tracker.highest_finalized_slot()
     }
 
+    /// Gives the hash of the block finalized in the given slot, if any.
+    fn finalized_block(&self, slot: Slot) -> Option<BlockHash> {
+        self.finality_tracker.finalized_block(slot).cloned()
+    }
+
+    /// Gives the parent of the given block, if it is known.
+    fn parent(&self, block: &BlockId) -> Option<BlockId> {
+        self.finality_tracker.parent(block).cloned()
+    }
+
+    /// Gives all certificates for the given slot.
+    ///
+    /// Slots before the highest finalized slot may already have been pruned.
+    fn certs(&self, slot: Slot) -> Vec<Cert> {
+        self.get_certs(slot..=slot)
+    }
+
     /// Returns all possible parents for the given slot that are ready.
     fn parents_ready(&self, slot: Slot) -> &[BlockId] {
         self.parent_ready_tracker.parents_ready(slot)
@@ -601,124 +665,123 @@ impl Pool for PoolImpl {
 
     fn load_from_db(&mut self) {
         //println!("[Pool::load_from_db] starting reload for validator {}", self.epoch_info.own_id);
-        if let Ok(Some(val)) = self.db.get(b"meta|final_slot") {
-            if val.len() == 8 {
-                let arr: [u8;8] = val[..8].try_into().unwrap();
-                self.highest_finalized_slot = u64::from_be_bytes(arr);
-            }
+        let mut highest_finalized = Slot::genesis();
+        if let Ok(Some(slot)) = self.storage.finalized_slot() {
+            highest_finalized = slot;
         }
-        //println!("[Pool::load_from_db] meta highest_finalized_slot = {}", self.highest_finalized_slot);
+        //println!("[Pool::load_from_db] meta highest_finalized_slot = {}", highest_finalized);
         let mut raw_certs: Vec<Cert> = Vec::new();
-        let mut highest_nf_slot: Slot = 0;
-        let mut num_keys = 0;
-        for item in self.db.iterator(IteratorMode::Start) {
-            if let Ok((k, v)) = item {
-                num_keys += 1;
-                if k.starts_with(b"cert|") {
-                    if let Ok((cert, _)) = bincode::serde::decode_from_slice::<Cert, _>(&v, bincode::config::standard()) {
-                        match cert {
-                            Cert::FastFinal(_) | Cert::Final(_) => {
-                                self.highest_finalized_slot = self.highest_finalized_slot.max(cert.slot());
-                            }
-                            Cert::Notar(_) | Cert::NotarFallback(_) => {
-                                highest_nf_slot = highest_nf_slot.max(cert.slot());
-                            }
-                            _ => {}
-                        }
-                        raw_certs.push(cert);
-                    }
+        let mut highest_nf_slot = Slot::genesis();
+        for (_, cert) in self.storage.iter::<Certs>().filter_map(Result::ok) {
+            match cert {
+                Cert::FastFinal(_) | Cert::Final(_) => {
+                    highest_finalized = highest_finalized.max(cert.slot());
                 }
+                Cert::Notar(_) | Cert::NotarFallback(_) => {
+                    highest_nf_slot = highest_nf_slot.max(cert.slot());
+                }
+                _ => {}
             }
+            raw_certs.push(cert);
         }
 
-        //println!("[Pool::load_from_db] found {num_keys} keys, {} certs, highest_finalized_slot = {}, highest_notar_fallback_slot = {}", raw_certs.len(), self.highest_finalized_slot, highest_nf_slot);
+        //println!("[Pool::load_from_db] found {} certs, highest_finalized_slot = {}, highest_notar_fallback_slot = {}", raw_certs.len(), highest_finalized, highest_nf_slot);
 
-        let retain_up_to = highest_nf_slot.max(self.highest_finalized_slot);
+        let retain_up_to = highest_nf_slot.max(highest_finalized);
 
-        let certs: Vec<Cert> = raw_certs.into_iter().filter(|c| c.slot() <= retain_up_to).collect();
-        println!("[Pool::load_from_db] retaining {} certs after filter (<= slot {})", certs.len(), retain_up_to);
+        let certs: Vec<Cert> = raw_certs
+            .into_iter()
+            .filter(|c| c.slot() <= retain_up_to)
+            .collect();
+        println!(
+            "[Pool::load_from_db] retaining {} certs after filter (<= slot {})",
+            certs.len(),
+            retain_up_to
+        );
 
-        // remove older cert keys > highest_finalized_slot
-        for item in self.db.iterator(IteratorMode::Start) {
-            if let Ok((k, _v)) = item {
-                if k.starts_with(b"cert|") {
-                    if k.len() >= 21 { 
-                        if let Ok(slot_hex) = std::str::from_utf8(&k[5..21]) {
-                            if let Ok(slot_val) = u64::from_str_radix(slot_hex, 16) {
-                                if slot_val > retain_up_to {
-                                    let _ = self.db.delete(k);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        // remove cert keys > retain_up_to
+        let beyond = (Bound::Excluded(retain_up_to), Bound::Unbounded);
+        if let Err(err) = self.storage.delete_slots::<Certs>(beyond) {
+            warn!("failed to delete certs beyond slot {retain_up_to}: {err}");
         }
 
-        self.parent_ready_tracker = ParentReadyTracker::new();
+        self.parent_ready_tracker = ParentReadyTracker::default();
         self.slot_states.clear();
 
         for cert in certs {
             let slot = cert.slot();
             self.slot_state(slot).add_cert(cert.clone());
 
-            match &cert {
+            let newly = match &cert {
                 Cert::Notar(_) | Cert::NotarFallback(_) => {
-                    if let Some(hash) = cert.block_hash() {
-                        let newly = self.parent_ready_tracker.mark_notar_fallback((slot, hash));
-                        for (s, (p_slot, p_hash)) in newly {
-                            if s > self.highest_finalized_slot {
-                                let _ = self
-                                    .votor_event_channel
-                                    .try_send(VotorEvent::ParentReady { slot: s, parent_slot: p_slot, parent_hash: p_hash });
-                            }
-                        }
-                    }
-                    self.highest_notarized_fallback_slot = self.highest_notarized_fallback_slot.max(slot);
+                    let block_id = (slot, cert.block_hash().cloned().unwrap());
+                    self.parent_ready_tracker.mark_notar_fallback(&block_id)
                 }
-                Cert::Skip(_) => {
-                    let newly = self.parent_ready_tracker.mark_skipped(slot);
-                    for (s, (p_slot, p_hash)) in newly {
-                        if s > self.highest_finalized_slot {
-                            let _ = self
-                                .votor_event_channel
-                                .try_send(VotorEvent::ParentReady { slot: s, parent_slot: p_slot, parent_hash: p_hash });
-                        }
-                    }
+                Cert::Skip(_) => self.parent_ready_tracker.mark_skipped(slot),
+                Cert::FastFinal(_) | Cert::Final(_) => continue,
+            };
+            for (s, (parent_slot, parent_hash)) in newly {
+                if s > highest_finalized {
+                    let _ = self.votor_event_channel.try_send(VotorEvent::ParentReady {
+                        slot: s,
+                        parent_slot,
+                        parent_hash,
+                    });
                 }
-                _ => {}
             }
         }
 
-        // persist meta|final_slot
-        let _ = self.db.put(b"meta|final_slot", self.highest_finalized_slot.to_be_bytes());
+        // persist highest finalized slot
+        if let Err(err) = self.storage.set_finalized_slot(highest_finalized) {
+            warn!("failed to persist finalized slot: {err}");
+        }
 
         // mid window check
-        let next_slot = self.highest_finalized_slot + 1;
-        let current_window_start = (self.highest_finalized_slot / SLOTS_PER_WINDOW) * SLOTS_PER_WINDOW;
-        let current_window_end = current_window_start + SLOTS_PER_WINDOW - 1;
-        
+        let next_slot = highest_finalized.next();
+        let current_window_end = highest_finalized.last_slot_in_window();
+
         // timeout if mid window
-        if self.highest_finalized_slot < current_window_end {
-            println!("[Pool::load_from_db] Mid-window restart detected, emitting timeouts for slots {}..{}", 
-                     next_slot, current_window_end);
-            for slot in next_slot..=current_window_end {
-                println!("[Pool::load_from_db] emitting Timeout for mid-window slot {}", slot);
+        if highest_finalized < current_window_end {
+            println!(
+                "[Pool::load_from_db] Mid-window restart detected, emitting timeouts for slots {}..{}",
+                next_slot, current_window_end
+            );
+            for slot in highest_finalized
+                .future_slots()
+                .take_while(|slot| *slot <= current_window_end)
+            {
+                println!(
+                    "[Pool::load_from_db] emitting Timeout for mid-window slot {}",
+                    slot
+                );
                 let _ = self.votor_event_channel.try_send(VotorEvent::Timeout(slot));
             }
         } else {
             // emit parent ready if clean window boundary cutoff
-            let next_window_start = self.highest_finalized_slot + 1;
-            if let Some((parent_slot, parent_hash)) = self.parent_ready_tracker.parents_ready(next_window_start).first() {
-                println!("[Pool::load_from_db] Clean window boundary, ParentReady already exists for slot {} (parent {}@{})", 
-                         next_window_start, &hex::encode(parent_hash)[..8], parent_slot);
+            let next_window_start = next_slot;
+            if let Some((parent_slot, parent_hash)) = self
+                .parent_ready_tracker
+                .parents_ready(next_window_start)
+                .first()
+            {
+                println!(
+                    "[Pool::load_from_db] Clean window boundary, ParentReady already exists for slot {} (parent {}@{})",
+                    next_window_start,
+                    &hex::encode(parent_hash.as_hash())[..8],
+                    parent_slot
+                );
             } else {
-                println!("[Pool::load_from_db] Clean window boundary, but no ParentReady for slot {} yet", 
-                         next_window_start);
+                println!(
+                    "[Pool::load_from_db] Clean window boundary, but no ParentReady for slot {} yet",
+                    next_window_start
+                );
             }
         }
 
-        println!("[Pool::load_from_db] finished reload; highest_finalized_slot = {}, highest_notarized_fallback_slot = {}", self.highest_finalized_slot, self.highest_notarized_fallback_slot);
+        println!(
+            "[Pool::load_from_db] finished reload; highest_finalized_slot = {}, highest_notarized_fallback_slot = {}",
+            highest_finalized, highest_nf_slot
+        );
     }
 }
 
@@ -727,12 +790,16 @@ mod tests {
     use tokio::sync::mpsc;
 
     use super::*;
-    use crate::consensus::cert::{FastFinalCert, NotarCert, SkipCert};
+    use crate::consensus::blockstore::{Blockstore, BlockstoreImpl};
+    use crate::consensus::cert::{FastFinalCert, FinalCert, NotarCert, SkipCert};
     use crate::consensus::vote::VoteKind;
     use crate::crypto::Hash;
     use crate::crypto::aggsig::SecretKey;
     use crate::crypto::merkle::GENESIS_BLOCK_HASH;
-    use crate::test_utils::generate_validators;
+    use crate::storage::{MemoryStorage, RocksDbStorage};
+    use crate::test_utils::{
+        create_random_shredded_block, generate_validators, generate_validators_with_keys,
+    };
     use crate::types::SLOTS_PER_WINDOW;
 
     #[tokio::test]
@@ -740,7 +807,12 @@ mod tests {
         let (_, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let wrong_sk = SecretKey::new(&mut rand::rng());
         let vote = Vote::new_notar(Slot::new(0), GENESIS_BLOCK_HASH, &wrong_sk, 0);
@@ -755,7 +827,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // all nodes notarize block in slot 0
         assert!(!pool.has_notar_cert(Slot::new(0)));
@@ -787,7 +864,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // all nodes vote skip on slot 0
         assert!(!pool.has_skip_cert(Slot::new(0)));
@@ -819,7 +901,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // just enough nodes vote notar, this is NOT enough on its own to finalize
         let slot1 = Slot::genesis().next();
@@ -870,12 +957,58 @@ mod tests {
         assert_eq!(pool.finalized_slot(), slot2);
     }
 
+    #[tokio::test]
+    async fn finalize_from_certs() {
+        let (sks, epoch_info) = generate_validators(11);
+        let (votor_tx, _votor_rx) = mpsc::channel(1024);
+        let (repair_tx, _repair_rx) = mpsc::channel(1024);
+        let mut pool = PoolImpl::new(
+            epoch_info.clone(),
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
+
+        // notar cert alone does NOT finalize slot 1
+        let slot1 = Slot::genesis().next();
+        let hash1: BlockHash = Hash::random_for_test().into();
+        let votes: Vec<_> = (0..7)
+            .map(|v| Vote::new_notar(slot1, hash1.clone(), &sks[v as usize], v))
+            .collect();
+        let notar_cert = NotarCert::try_new(&votes, &epoch_info.validators).unwrap();
+        assert_eq!(pool.add_cert(Cert::Notar(notar_cert)).await, Ok(()));
+        assert_eq!(pool.finalized_slot(), Slot::genesis());
+
+        // final cert on top of it does
+        let votes: Vec<_> = (0..7)
+            .map(|v| Vote::new_final(slot1, &sks[v as usize], v))
+            .collect();
+        let final_cert = FinalCert::try_new(&votes, &epoch_info.validators).unwrap();
+        assert_eq!(pool.add_cert(Cert::Final(final_cert)).await, Ok(()));
+        assert_eq!(pool.finalized_slot(), slot1);
+
+        // fast finalization cert finalizes slot 2 on its own
+        let slot2 = slot1.next();
+        let hash2: BlockHash = Hash::random_for_test().into();
+        let votes: Vec<_> = (0..9)
+            .map(|v| Vote::new_notar(slot2, hash2.clone(), &sks[v as usize], v))
+            .collect();
+        let ff_cert = FastFinalCert::try_new(&votes, &epoch_info.validators).unwrap();
+        assert_eq!(pool.add_cert(Cert::FastFinal(ff_cert)).await, Ok(()));
+        assert_eq!(pool.finalized_slot(), slot2);
+    }
+
     #[tokio::test]
     async fn fast_finalize_block() {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // all nodes vote notarize on slot 0
         assert!(!pool.has_final_cert(Slot::new(0)));
@@ -905,12 +1038,89 @@ mod tests {
         assert_eq!(pool.finalized_slot(), Slot::new(1));
     }
 
+    #[tokio::test]
+    async fn consensus_events() {
+        let (sks, epoch_info) = generate_validators(11);
+        let (votor_tx, _votor_rx) = mpsc::channel(1024);
+        let (repair_tx, _repair_rx) = mpsc::channel(1024);
+        let (events_tx, mut events_rx) = broadcast::channel(1024);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
+        pool.set_event_sender(events_tx);
+
+        // slow finalize slot 1
+        let slot1 = Slot::genesis().next();
+        let hash1: BlockHash = Hash::random_for_test().into();
+        for v in 0..7 {
+            let vote = Vote::new_notar(slot1, hash1.clone(), &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+        }
+        for v in 0..7 {
+            let vote = Vote::new_final(slot1, &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+        }
+
+        // fast finalize slot 2
+        let slot2 = slot1.next();
+        let hash2: BlockHash = Hash::random_for_test().into();
+        for v in 0..9 {
+            let vote = Vote::new_notar(slot2, hash2.clone(), &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+        }
+
+        // skip slot 3, then node 0 also tries to notarize
+        let slot3 = slot2.next();
+        for v in 0..7 {
+            let vote = Vote::new_skip(slot3, &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+        }
+        let vote = Vote::new_notar(slot3, Hash::random_for_test().into(), &sks[0], 0);
+        assert!(pool.add_vote(vote).await.is_err());
+
+        let mut events = Vec::new();
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            vec![
+                ConsensusEvent::Notarized {
+                    slot: slot1,
+                    block_hash: hash1.clone()
+                },
+                ConsensusEvent::SlowFinalized {
+                    slot: slot1,
+                    block_hash: hash1
+                },
+                ConsensusEvent::Notarized {
+                    slot: slot2,
+                    block_hash: hash2.clone()
+                },
+                ConsensusEvent::FastFinalized {
+                    slot: slot2,
+                    block_hash: hash2
+                },
+                ConsensusEvent::Skipped(slot3),
+                ConsensusEvent::SlashableOffence(SlashableOffence::SkipAndNotarize(0, slot3)),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn simple_branch_certified() {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let window = Slot::genesis().slots_in_window().collect::<Vec<_>>();
         let hashes: Vec<BlockHash> = window
@@ -938,7 +1148,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // receive mixed notar & notar-fallback votes
         let window = Slot::genesis().slots_in_window().collect::<Vec<_>>();
@@ -969,7 +1184,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // first see skip votes for later slots
         let mut window = Slot::new(0).slots_in_window().collect::<Vec<_>>();
@@ -1006,7 +1226,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info.clone(), votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info.clone(),
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // first see skip votes for later slots
         let window = Slot::genesis().slots_in_window().collect::<Vec<_>>();
@@ -1041,7 +1266,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let hashes: Vec<BlockHash> = (0..SLOTS_PER_WINDOW)
             .map(|_| Hash::random_for_test().into())
@@ -1070,7 +1300,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let hashes: Vec<BlockHash> = (0..SLOTS_PER_WINDOW)
             .map(|_| Hash::random_for_test().into())
@@ -1109,7 +1344,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let hashes: Vec<BlockHash> = (0..SLOTS_PER_WINDOW)
             .map(|_| Hash::random_for_test().into())
@@ -1152,7 +1392,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let hashes: Vec<BlockHash> = (0..SLOTS_PER_WINDOW)
             .map(|_| Hash::random_for_test().into())
@@ -1193,7 +1438,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         let hashes: Vec<BlockHash> = (0..3 * SLOTS_PER_WINDOW + 10)
             .map(|_| Hash::random_for_test().into())
@@ -1262,7 +1512,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // insert a notar vote from validator 0
         let vote = Vote::new_notar(Slot::new(0), GENESIS_BLOCK_HASH, &sks[0], 0);
@@ -1284,7 +1539,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info.clone(), votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info.clone(),
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // insert a notar cert for first slot
         let mut votes = Vec::new();
@@ -1326,7 +1586,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // all nodes vote finalize last slot of 3rd leader windows
         let slot = Slot::new(3 * SLOTS_PER_WINDOW - 1);
@@ -1363,7 +1628,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, _votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info.clone(), votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info.clone(),
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // insert a notar cert for last slot of 3rd leader window
         let slot = Slot::new(3 * SLOTS_PER_WINDOW - 1);
@@ -1413,7 +1683,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, mut votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // all nodes vote for first slot (it's fast finalized)
         let slot1 = Slot::genesis().next();
@@ -1482,7 +1757,12 @@ mod tests {
         let (sks, epoch_info) = generate_validators(11);
         let (votor_tx, mut votor_rx) = mpsc::channel(1024);
         let (repair_tx, _repair_rx) = mpsc::channel(1024);
-        let mut pool = PoolImpl::new(epoch_info, votor_tx, repair_tx);
+        let mut pool = PoolImpl::new(
+            epoch_info,
+            Arc::new(MemoryStorage::default()),
+            votor_tx,
+            repair_tx,
+        );
 
         // fast finalize block in 2nd slot of 2nd window
         let slot1 = Slot::windows().nth(1).unwrap();
@@ -1533,4 +1813,64 @@ mod tests {
             _ => unreachable!("unexpected event {event:?}"),
         }
     }
+
+    #[tokio::test]
+    async fn crash_consistency() {
+        let (signing_sks, sks, validators) = generate_validators_with_keys(11);
+        let epoch_info = Arc::new(EpochInfo::new(0, validators));
+        let rocksdb = Arc::new(RocksDbStorage::open_temporary());
+        let storage: Arc<dyn Storage> = rocksdb.clone();
+        let (votor_tx, _votor_rx) = mpsc::channel(1024);
+        let (repair_tx, _repair_rx) = mpsc::channel(1024);
+        let mut blockstore =
+            BlockstoreImpl::new(epoch_info.clone(), storage.clone(), votor_tx.clone());
+        let mut pool = PoolImpl::new(
+            epoch_info.clone(),
+            storage.clone(),
+            votor_tx.clone(),
+            repair_tx.clone(),
+        );
+
+        // blockstore and pool write to the same storage, crash after every vote
+        let mut slot = Slot::genesis();
+        for _ in 0..3 {
+            slot = slot.next();
+            let leader = epoch_info.leader(slot).id as usize;
+            let (hash, _, shreds) = create_random_shredded_block(slot, 1, &signing_sks[leader]);
+            for shred in shreds.into_iter().flatten() {
+                let _ = blockstore
+                    .add_shred_from_disseminator(shred.into_shred())
+                    .await;
+            }
+            for v in 0..7 {
+                let vote = Vote::new_notar(slot, hash.clone(), &sks[v as usize], v);
+                assert_eq!(pool.add_vote(vote).await, Ok(()));
+                let vote = Vote::new_final(slot, &sks[v as usize], v);
+                assert_eq!(pool.add_vote(vote).await, Ok(()));
+
+                let crashed: Arc<dyn Storage> = Arc::new(rocksdb.crash_snapshot());
+                crashed.assert_consistent();
+                let finalized = crashed.finalized_slot().unwrap().unwrap_or(Slot::genesis());
+                assert!(finalized <= pool.finalized_slot());
+
+                // restarting from the crashed state reloads all persisted certs
+                let num_certs = crashed.iter::<Certs>().count();
+                let restarted = PoolImpl::new(
+                    epoch_info.clone(),
+                    crashed,
+                    votor_tx.clone(),
+                    repair_tx.clone(),
+                );
+                let reloaded: usize = (1..=slot.inner())
+                    .map(|s| restarted.certs(Slot::new(s)).len())
+                    .sum();
+                assert_eq!(reloaded, num_certs);
+            }
+            assert_eq!(pool.finalized_slot(), slot);
+
+            // finalization is recorded in the block metadata
+            let metadata = storage.get::<Metadata>(&(slot, hash)).unwrap().unwrap();
+            assert!(metadata.finalized_timestamp.is_some());
+        }
+    }
 }
@@ -198,6 +198,23 @@ impl FinalityTracker {
         self.highest_finalized_slot
     }
 
+    /// Returns the hash of the block finalized (directly or implicitly) in `slot`.
+    ///
+    /// Returns `None` if the slot is not known to be finalized, or was skipped.
+    pub fn finalized_block(&self, slot: Slot) -> Option<&BlockHash> {
+        match self.status.get(&slot)? {
+            FinalizationStatus::Finalized(hash) | FinalizationStatus::ImplicitlyFinalized(hash) => {
+                Some(hash)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the parent of the given `block`, if known.
+    pub fn parent(&self, block: &BlockId) -> Option<&BlockId> {
+        self.parents.get(block)
+    }
+
     /// Handles the direct finalization of the given block.
     ///
     /// Recurses through ancestors, potentially implicitly finalizing them.
@@ -356,6 +373,11 @@ mod tests {
             vec![(slot7.prev().prev(), hash5)]
         );
         assert_eq!(event.implicitly_skipped, vec![slot7.prev()]);
+        assert_eq!(tracker.finalized_block(slot7), Some(&hash7));
+        assert!(tracker.finalized_block(slot7.prev().prev()).is_some());
+        assert_eq!(tracker.finalized_block(slot7.prev()), None);
+        let parent = tracker.parent(&(slot7, hash7));
+        assert_eq!(parent.map(|(slot, _)| *slot), Some(slot7.prev().prev()));
     }
 
     #[test]
@@ -0,0 +1,348 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structured per-slot timeline of consensus progress on a single node.
+//!
+//! [`Timeline`] collects when each [`TimelineStage`] was first reached for a slot.
+//! Local stages (shreds, blocks, own votes) are recorded by Votor, repair by [`crate::repair`].
+//! Certificates and outcomes are derived from the [`ConsensusEvent`]s emitted by the Pool.
+//! Once a slot is finalized or skipped, its [`SlotTimeline`] is written as a single line of JSON.
+//!
+//! Timelines of several nodes can be merged and analyzed with the `timeline_analysis` binary.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::ConsensusEvent;
+use crate::crypto::merkle::MerkleRoot;
+use crate::types::Slot;
+use crate::{BlockId, ValidatorId};
+
+/// Stage of the consensus protocol a slot can reach on a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineStage {
+    /// First shred of the slot was received via block dissemination.
+    FirstShred,
+    /// The block was fully received and reconstructed.
+    BlockReconstructed,
+    /// Own notarization vote was sent.
+    NotarVote,
+    /// Own notar-fallback vote was sent.
+    NotarFallbackVote,
+    /// Own skip vote was sent.
+    SkipVote,
+    /// Own skip-fallback vote was sent.
+    SkipFallbackVote,
+    /// Own finalization vote was sent.
+    FinalVote,
+    /// A notarization certificate was observed.
+    NotarCert,
+    /// A fast-finalization certificate was observed.
+    FastFinalCert,
+    /// A finalization certificate (and notarization) was observed.
+    FinalCert,
+    /// A skip certificate was observed.
+    SkipCert,
+}
+
+/// How consensus concluded for a slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotOutcome {
+    /// A block in this slot was finalized, directly or implicitly.
+    Finalized,
+    /// The slot was skipped, directly or implicitly.
+    Skipped,
+}
+
+/// Parent a node voted to build on, as hex-encoded hash.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineParent {
+    pub slot: Slot,
+    pub hash: String,
+}
+
+/// Record of a single slot on a single node, written as one line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotTimeline {
+    pub node: ValidatorId,
+    pub slot: Slot,
+    /// `None` if the slot was passed by finalization without a conclusive event.
+    pub outcome: Option<SlotOutcome>,
+    /// Parent of the block this node voted to notarize, if any.
+    pub parent: Option<TimelineParent>,
+    /// Whether the block had to be repaired.
+    pub repaired: bool,
+    /// First time each stage was reached, in microseconds since the Unix epoch.
+    pub stages: BTreeMap<TimelineStage, u64>,
+}
+
+impl SlotTimeline {
+    fn new(node: ValidatorId, slot: Slot) -> Self {
+        Self {
+            node,
+            slot,
+            outcome: None,
+            parent: None,
+            repaired: false,
+            stages: BTreeMap::new(),
+        }
+    }
+}
+
+/// Handle for recording the timeline of a node.
+///
+/// Cheap to clone, all clones write to the same log.
+/// Does nothing until a writer is set with [`Timeline::set_writer`].
+#[derive(Clone)]
+pub struct Timeline {
+    node: ValidatorId,
+    state: Arc<Mutex<TimelineState>>,
+}
+
+#[derive(Default)]
+struct TimelineState {
+    writer: Option<Box<dyn Write + Send>>,
+    /// Slots that did not conclude yet.
+    pending: BTreeMap<Slot, SlotTimeline>,
+    /// Highest finalized slot, older slots are not tracked anymore.
+    finalized: Slot,
+    /// Skipped slots above [`Self::finalized`], which are not tracked anymore.
+    skipped: BTreeSet<Slot>,
+}
+
+impl Timeline {
+    /// Creates a new timeline for the given `node`, which is disabled until a writer is set.
+    #[must_use]
+    pub fn new(node: ValidatorId) -> Self {
+        Self {
+            node,
+            state: Arc::default(),
+        }
+    }
+
+    /// Enables the timeline, writing one line of JSON per concluded slot to `writer`.
+    pub fn set_writer(&self, writer: Box<dyn Write + Send>) {
+        self.state.lock().unwrap().writer = Some(writer);
+    }
+
+    /// Records that `slot` reached `stage` just now, unless it did so before.
+    pub fn record(&self, slot: Slot, stage: TimelineStage) {
+        let now = unix_micros();
+        self.update(slot, |timeline| {
+            timeline.stages.entry(stage).or_insert(now);
+        });
+    }
+
+    /// Records that this node voted for a block in `slot` building on `parent`.
+    pub fn record_parent(&self, slot: Slot, parent: &BlockId) {
+        let (parent_slot, parent_hash) = parent;
+        let parent = TimelineParent {
+            slot: *parent_slot,
+            hash: hex::encode(parent_hash.as_hash()),
+        };
+        self.update(slot, |timeline| timeline.parent = Some(parent));
+    }
+
+    /// Records that a block in `slot` is being repaired.
+    pub fn record_repair(&self, slot: Slot) {
+        self.update(slot, |timeline| timeline.repaired = true);
+    }
+
+    /// Records certificates and outcomes from `events`, until the node shuts down.
+    pub async fn track(self, mut events: broadcast::Receiver<ConsensusEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.observe(&event),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("timeline missed {n} consensus events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
+    fn observe(&self, event: &ConsensusEvent) {
+        match event {
+            ConsensusEvent::Notarized { slot, .. } => self.record(*slot, TimelineStage::NotarCert),
+            ConsensusEvent::FastFinalized { slot, .. } => {
+                self.record(*slot, TimelineStage::FastFinalCert);
+                self.conclude(*slot, SlotOutcome::Finalized);
+            }
+            ConsensusEvent::SlowFinalized { slot, .. } => {
+                self.record(*slot, TimelineStage::FinalCert);
+                self.conclude(*slot, SlotOutcome::Finalized);
+            }
+            ConsensusEvent::ImplicitlyFinalized { slot, .. } => {
+                self.conclude(*slot, SlotOutcome::Finalized);
+            }
+            ConsensusEvent::Skipped(slot) => {
+                self.record(*slot, TimelineStage::SkipCert);
+                self.conclude(*slot, SlotOutcome::Skipped);
+            }
+            ConsensusEvent::ImplicitlySkipped(slot) => self.conclude(*slot, SlotOutcome::Skipped),
+            ConsensusEvent::BlockReceived { .. }
+            | ConsensusEvent::Standstill(_)
+            | ConsensusEvent::SlashableOffence(_) => {}
+        }
+    }
+
+    /// Applies `f` to the timeline of `slot`, if it is still tracked.
+    fn update(&self, slot: Slot, f: impl FnOnce(&mut SlotTimeline)) {
+        let mut state = self.state.lock().unwrap();
+        if state.writer.is_none() || slot <= state.finalized || state.skipped.contains(&slot) {
+            return;
+        }
+        let timeline = state
+            .pending
+            .entry(slot)
+            .or_insert_with(|| SlotTimeline::new(self.node, slot));
+        f(timeline);
+    }
+
+    /// Writes out the timeline of `slot`, and stops tracking it.
+    ///
+    /// If the slot was finalized, also writes out any older slots that did not conclude.
+    fn conclude(&self, slot: Slot, outcome: SlotOutcome) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        if state.writer.is_none() || slot <= state.finalized || state.skipped.contains(&slot) {
+            return;
+        }
+        let mut concluded = Vec::new();
+        match outcome {
+            SlotOutcome::Finalized => {
+                let newer = state.pending.split_off(&slot.next());
+                let older = std::mem::replace(&mut state.pending, newer);
+                concluded.extend(older.into_values());
+                state.finalized = slot;
+                state.skipped = state.skipped.split_off(&slot.next());
+            }
+            SlotOutcome::Skipped => {
+                concluded.extend(state.pending.remove(&slot));
+                state.skipped.insert(slot);
+            }
+        }
+        if concluded
+            .last()
+            .is_none_or(|timeline| timeline.slot != slot)
+        {
+            concluded.push(SlotTimeline::new(self.node, slot));
+        }
+        concluded.last_mut().unwrap().outcome = Some(outcome);
+
+        let writer = state.writer.as_mut().unwrap();
+        for timeline in concluded {
+            let line = serde_json::to_string(&timeline).unwrap();
+            if let Err(err) = writeln!(writer, "{line}") {
+                warn!("failed to write timeline of slot {}: {err}", timeline.slot);
+            }
+        }
+    }
+}
+
+fn unix_micros() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    since_epoch.as_micros() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Hash;
+
+    /// Writer that can be inspected after handing it to the timeline.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn lines(&self) -> Vec<SlotTimeline> {
+            let buf = self.0.lock().unwrap();
+            let text = std::str::from_utf8(&buf).unwrap();
+            text.lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let timeline = Timeline::new(0);
+        timeline.record(Slot::new(1), TimelineStage::FirstShred);
+        assert!(timeline.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn slot_lifecycle() {
+        let buf = SharedBuf::default();
+        let timeline = Timeline::new(3);
+        timeline.set_writer(Box::new(buf.clone()));
+
+        let slot = Slot::new(4);
+        let block_hash = Hash::random_for_test().into();
+        let parent = (slot.prev(), Hash::random_for_test().into());
+        timeline.record(slot, TimelineStage::FirstShred);
+        timeline.record(slot, TimelineStage::BlockReconstructed);
+        timeline.record(slot, TimelineStage::NotarVote);
+        timeline.record_parent(slot, &parent);
+        timeline.record(slot.next(), TimelineStage::FirstShred);
+        timeline.record_repair(slot.next());
+        timeline.observe(&ConsensusEvent::Notarized {
+            slot,
+            block_hash: Hash::random_for_test().into(),
+        });
+        timeline.observe(&ConsensusEvent::Skipped(slot.prev()));
+        timeline.observe(&ConsensusEvent::FastFinalized { slot, block_hash });
+        assert_eq!(buf.lines().len(), 2);
+
+        // late events for concluded slots are ignored
+        timeline.record(slot, TimelineStage::FinalVote);
+        timeline.record(slot.prev(), TimelineStage::SkipVote);
+        timeline.observe(&ConsensusEvent::Skipped(slot.next()));
+
+        let lines = buf.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].slot, slot.prev());
+        assert_eq!(lines[0].outcome, Some(SlotOutcome::Skipped));
+        assert!(lines[0].stages.contains_key(&TimelineStage::SkipCert));
+
+        let finalized = &lines[1];
+        assert_eq!(finalized.node, 3);
+        assert_eq!(finalized.outcome, Some(SlotOutcome::Finalized));
+        assert_eq!(finalized.parent.as_ref().unwrap().slot, slot.prev());
+        assert!(!finalized.repaired);
+        let stages = finalized.stages.keys().copied().collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            [
+                TimelineStage::FirstShred,
+                TimelineStage::BlockReconstructed,
+                TimelineStage::NotarVote,
+                TimelineStage::NotarCert,
+                TimelineStage::FastFinalCert,
+            ]
+        );
+        assert!(finalized.stages.values().is_sorted());
+
+        assert_eq!(lines[2].slot, slot.next());
+        assert!(lines[2].repaired);
+        assert_eq!(lines[2].outcome, Some(SlotOutcome::Skipped));
+    }
+}
@@ -19,10 +19,11 @@ use log::{debug, trace, warn};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use super::blockstore::BlockInfo;
-use super::{Cert, DELTA_BLOCK, DELTA_TIMEOUT, Vote};
+use super::{Cert, DELTA_BLOCK, DELTA_TIMEOUT, Timeline, TimelineStage, Vote};
 use crate::consensus::DELTA_FIRST_SLICE;
 use crate::crypto::aggsig::SecretKey;
 use crate::crypto::merkle::{BlockHash, GENESIS_BLOCK_HASH, MerkleRoot};
+use crate::tracing::slot_span;
 use crate::{All2All, Slot, ValidatorId};
 
 /// Events that Votor is interested in.
@@ -99,6 +100,8 @@ pub struct Votor<A: All2All> {
     event_sender: Sender<VotorEvent>,
     /// [`All2All`] instance used to broadcast votes.
     all2all: Arc<A>,
+    /// Records when slots reach local stages, see [`Timeline`].
+    timeline: Timeline,
 }
 
 impl<A: All2All> Votor<A> {
@@ -137,11 +140,17 @@ impl<A: All2All> Votor<A> {
             event_receiver,
             event_sender,
             all2all,
+            timeline: Timeline::new(validator_id),
         };
         votor.set_timeouts(Slot::new(0));
         votor
     }
 
+    /// Sets the timeline to record local stages in.
+    pub fn set_timeline(&mut self, timeline: Timeline) {
+        self.timeline = timeline;
+    }
+
     /// Handles the voting (leader and non-leader) side of consensus protocol.
     ///
     /// Checks consensus conditions and broadcasts new votes.
@@ -154,6 +163,7 @@ impl<A: All2All> Votor<A> {
                 continue;
             }
             trace!("votor event: {event:?}");
+            let _slot_span = slot_span(event.slot());
             match event {
                 // events from Pool
                 VotorEvent::ParentReady {
@@ -173,6 +183,7 @@ impl<A: All2All> Votor<A> {
                     let vote =
                         Vote::new_notar_fallback(slot, hash, &self.voting_key, self.validator_id);
                     self.all2all.broadcast(&vote.into()).await.unwrap();
+                    self.timeline.record(slot, TimelineStage::NotarFallbackVote);
                     self.try_skip_window(slot).await;
                     self.bad_window.insert(slot);
                 }
@@ -181,6 +192,7 @@ impl<A: All2All> Votor<A> {
                     debug!("voted skip-fallback in slot {slot}");
                     let vote = Vote::new_skip_fallback(slot, &self.voting_key, self.validator_id);
                     self.all2all.broadcast(&vote.into()).await.unwrap();
+                    self.timeline.record(slot, TimelineStage::SkipFallbackVote);
                     self.try_skip_window(slot).await;
                     self.bad_window.insert(slot);
                 }
@@ -215,9 +227,15 @@ impl<A: All2All> Votor<A> {
                 VotorEvent::FirstShred(slot) => {
                     //println!("[Votor {}] FIRST_SHRED slot {}", self.validator_id, slot);
                     self.received_shred.insert(slot);
+                    self.timeline.record(slot, TimelineStage::FirstShred);
                 }
                 VotorEvent::Block { slot, block_info } => {
-                    println!("[Votor {}] BLOCK slot {} info {:?}", self.validator_id, slot, block_info);
+                    println!(
+                        "[Votor {}] BLOCK slot {} info {:?}",
+                        self.validator_id, slot, block_info
+                    );
+                    self.timeline
+                        .record(slot, TimelineStage::BlockReconstructed);
                     if self.voted.contains(&slot) {
                         let h = &hex::encode(block_info.hash.as_hash())[..8];
                         warn!("not voting for block {h} in slot {slot}, already voted");
@@ -310,6 +328,9 @@ impl<A: All2All> Votor<A> {
         //println!("[Votor {}] VOTE_NOTAR slot {} hash {}", self.validator_id, slot, &hex::encode(hash)[..8]);
         let vote = Vote::new_notar(slot, hash, &self.voting_key, self.validator_id);
         self.all2all.broadcast(&vote.into()).await.unwrap();
+        self.timeline.record(slot, TimelineStage::NotarVote);
+        self.timeline
+            .record_parent(slot, &(parent_slot, parent_hash));
         self.voted.insert(slot);
         self.voted_notar.insert(slot, hash.clone());
         self.pending_blocks.remove(&slot);
@@ -325,6 +346,7 @@ impl<A: All2All> Votor<A> {
         if notarized && voted_notar && not_bad {
             let vote = Vote::new_final(slot, &self.voting_key, self.validator_id);
             self.all2all.broadcast(&vote.into()).await.unwrap();
+            self.timeline.record(slot, TimelineStage::FinalVote);
             self.retired_slots.insert(slot);
         }
     }
@@ -336,6 +358,7 @@ impl<A: All2All> Votor<A> {
             if self.voted.insert(s) {
                 let vote = Vote::new_skip(s, &self.voting_key, self.validator_id);
                 self.all2all.broadcast(&vote.into()).await.unwrap();
+                self.timeline.record(s, TimelineStage::SkipVote);
                 self.bad_window.insert(s);
                 debug!("voted skip for slot {s}");
             }
@@ -35,3 +35,19 @@ pub trait Disseminator {
     /// Receives the next shred from the network.
     async fn receive(&self) -> std::io::Result<Shred>;
 }
+
+// allows mixing different implementations, e.g. for adversarial nodes in tests
+#[async_trait]
+impl<D: Disseminator + Send + Sync + ?Sized> Disseminator for Box<D> {
+    async fn send(&self, shred: &Shred) -> std::io::Result<()> {
+        (**self).send(shred).await
+    }
+
+    async fn forward(&self, shred: &Shred) -> std::io::Result<()> {
+        (**self).forward(shred).await
+    }
+
+    async fn receive(&self) -> std::io::Result<Shred> {
+        (**self).receive().await
+    }
+}
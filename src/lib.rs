@@ -8,21 +8,30 @@
 #![deny(rustdoc::broken_intra_doc_links)]
 
 pub mod all2all;
+pub mod byzantine;
+#[cfg(feature = "simulation")]
+pub mod cluster;
 pub mod consensus;
 pub mod crypto;
 pub mod disseminator;
 pub mod logging;
+pub mod metrics;
 pub mod network;
 pub mod repair;
+pub mod rpc;
+pub mod safety;
 pub mod shredder;
+pub mod storage;
 #[cfg(test)]
 pub mod test_utils;
+pub mod tracing;
 pub mod types;
 pub mod validator;
 
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use static_assertions::const_assert_eq;
 use wincode::{SchemaRead, SchemaWrite};
@@ -35,14 +44,21 @@ pub use self::disseminator::Disseminator;
 use self::types::Slot;
 pub use self::validator::Validator;
 use crate::all2all::TrivialAll2All;
+use crate::byzantine::{Behavior, DynAll2All, DynDisseminator};
 use crate::consensus::{ConsensusMessage, EpochInfo};
 use crate::crypto::merkle::BlockHash;
 use crate::crypto::signature::SecretKey;
 use crate::disseminator::Rotor;
 use crate::disseminator::rotor::StakeWeightedSampler;
-use crate::network::{UdpNetwork, localhost_ip_sockaddr};
+use crate::network::simulated::SimulatedNetworkCore;
+use crate::network::{
+    Channel, MultiplexedFrame, MultiplexedNetwork, Multiplexer, SignedNetwork, SignedPacket,
+    SimulatedNetwork, UdpNetwork, localhost_ip_sockaddr,
+};
 use crate::repair::{RepairRequest, RepairResponse};
+use crate::safety::{ObservingAll2All, SafetyChecker};
 use crate::shredder::Shred;
+use crate::storage::StorageConfig;
 
 // NOTE: In many places we assume that `usize` is 64 bits wide.
 // So, for now, we only support 64-bit architectures.
@@ -59,7 +75,7 @@ pub type BlockId = (Slot, BlockHash);
 const MAX_TRANSACTION_SIZE: usize = 512;
 
 /// Parsed block with information about parent and transactions as payload.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
 pub struct Block {
     // TODO: unused
     _slot: Slot,
@@ -86,6 +102,10 @@ impl Block {
     pub fn block_hash(&self) -> Hash {
         self.block_hash
     }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self._transactions
+    }
 }
 
 /// Dummy transaction containing payload bytes.
@@ -116,11 +136,14 @@ type TestNode = Alpenglow<
     UdpNetwork<Transaction, Transaction>,
 >;
 
+/// Repair sub-network of a [`TestNode`], authenticating senders by signing every packet.
+type SignedTestNetwork<S, R> = SignedNetwork<UdpNetwork<SignedPacket, SignedPacket>, S, R>;
+
 struct Networks {
     all2all: UdpNetwork<ConsensusMessage, ConsensusMessage>,
     disseminator: UdpNetwork<Shred, Shred>,
-    repair: UdpNetwork<RepairRequest, RepairResponse>,
-    repair_request: UdpNetwork<RepairResponse, RepairRequest>,
+    repair: UdpNetwork<SignedPacket, SignedPacket>,
+    repair_request: UdpNetwork<SignedPacket, SignedPacket>,
     txs: UdpNetwork<Transaction, Transaction>,
 }
 
@@ -177,8 +200,15 @@ pub fn create_test_nodes(count: u64) -> Vec<TestNode> {
             let epoch_info = Arc::new(EpochInfo::new(id as u64, validators.clone()));
             let all2all = TrivialAll2All::new(validators.clone(), network.all2all);
             let disseminator = Rotor::new(network.disseminator, epoch_info.clone());
-            let repair_network = network.repair;
-            let repair_request_network = network.repair_request;
+            let repair_network: SignedTestNetwork<RepairRequest, RepairResponse> =
+                SignedNetwork::new(network.repair, id as u64, sks[id].clone(), &validators);
+            let repair_request_network: SignedTestNetwork<RepairResponse, RepairRequest> =
+                SignedNetwork::new(
+                    network.repair_request,
+                    id as u64,
+                    sks[id].clone(),
+                    &validators,
+                );
             let txs_receiver = network.txs;
             Alpenglow::new(
                 sks[id].clone(),
@@ -189,7 +219,141 @@ pub fn create_test_nodes(count: u64) -> Vec<TestNode> {
                 repair_request_network,
                 epoch_info,
                 txs_receiver,
+                &StorageConfig::in_memory(),
             )
+            .expect("in-memory storage can always be opened")
         })
         .collect()
 }
+
+/// Sub-network of a simulated test node, multiplexed over a single [`SimulatedNetwork`].
+type SimulatedTestNetwork<S, R> =
+    MultiplexedNetwork<SimulatedNetwork<MultiplexedFrame, MultiplexedFrame>, S, R>;
+
+type SimulatedTestNode = Alpenglow<
+    TrivialAll2All<SimulatedTestNetwork<ConsensusMessage, ConsensusMessage>>,
+    Rotor<SimulatedTestNetwork<Shred, Shred>, StakeWeightedSampler>,
+    SimulatedTestNetwork<Transaction, Transaction>,
+>;
+
+type ByzantineTestNode =
+    Alpenglow<DynAll2All, DynDisseminator, SimulatedTestNetwork<Transaction, Transaction>>;
+
+/// Creates nodes connected via the given simulated network `core`, for testing purposes.
+///
+/// Each node joins `core` once, with its validator ID, and multiplexes all protocols over that.
+/// This allows injecting faults for specific validators, see [`SimulatedNetworkCore::schedule_faults`].
+/// All keys and seeds are derived from `rng`, so a seeded `rng` yields the same nodes every time.
+/// It should not be used in production code.
+pub async fn create_simulated_test_nodes(
+    core: &Arc<SimulatedNetworkCore>,
+    count: u64,
+    rng: &mut impl CryptoRng,
+) -> Vec<SimulatedTestNode> {
+    let (sks, voting_sks, validators) = generate_simulated_validators(count, rng);
+
+    // turn validator info into actual nodes
+    let mut nodes = Vec::new();
+    for id in 0..count {
+        let mux = Multiplexer::new_authenticated(core.join_unlimited(id).await);
+        let epoch_info = Arc::new(EpochInfo::new(id, validators.clone()));
+        let network = mux.channel(Channel::Consensus, Channel::Consensus);
+        let all2all = TrivialAll2All::new(validators.clone(), network);
+        let network = mux.channel(Channel::Shreds, Channel::Shreds);
+        let disseminator = Rotor::new(network, epoch_info.clone());
+        nodes.push(
+            Alpenglow::new_seeded(
+                sks[id as usize].clone(),
+                voting_sks[id as usize].clone(),
+                all2all,
+                disseminator,
+                mux.channel(Channel::RepairRequests, Channel::RepairResponses),
+                mux.channel(Channel::RepairResponses, Channel::RepairRequests),
+                epoch_info,
+                mux.channel(Channel::Transactions, Channel::Transactions),
+                &StorageConfig::in_memory(),
+                rng.next_u64(),
+            )
+            .expect("in-memory storage can always be opened"),
+        );
+    }
+    nodes
+}
+
+/// Same as [`create_simulated_test_nodes`], but node `i` exhibits `behaviors[i]`.
+///
+/// See [`Behavior`] for the supported kinds of misbehavior.
+/// All certificates of honest nodes are reported to the safety `checker`.
+/// It should not be used in production code.
+pub async fn create_simulated_byzantine_test_nodes(
+    core: &Arc<SimulatedNetworkCore>,
+    behaviors: &[Behavior],
+    checker: &Arc<Mutex<SafetyChecker>>,
+    rng: &mut impl CryptoRng,
+) -> Vec<ByzantineTestNode> {
+    let count = behaviors.len() as u64;
+    let (sks, voting_sks, validators) = generate_simulated_validators(count, rng);
+
+    // turn validator info into actual nodes
+    let mut nodes = Vec::new();
+    for (id, behavior) in behaviors.iter().enumerate() {
+        let mux = Multiplexer::new_authenticated(core.join_unlimited(id as u64).await);
+        let epoch_info = Arc::new(EpochInfo::new(id as u64, validators.clone()));
+        let network = mux.channel(Channel::Consensus, Channel::Consensus);
+        let all2all = TrivialAll2All::new(validators.clone(), network);
+        let network = mux.channel(Channel::Shreds, Channel::Shreds);
+        let disseminator = Rotor::new(network, epoch_info.clone());
+        let (sk, voting_sk) = (&sks[id], &voting_sks[id]);
+        let (all2all, disseminator) = if behavior.is_honest() {
+            let all2all = ObservingAll2All::new(all2all, id as u64, Arc::clone(checker));
+            behavior.apply(all2all, disseminator, sk, voting_sk, &epoch_info)
+        } else {
+            behavior.apply(all2all, disseminator, sk, voting_sk, &epoch_info)
+        };
+        nodes.push(
+            Alpenglow::new_seeded(
+                sks[id].clone(),
+                voting_sks[id].clone(),
+                all2all,
+                disseminator,
+                mux.channel(Channel::RepairRequests, Channel::RepairResponses),
+                mux.channel(Channel::RepairResponses, Channel::RepairRequests),
+                epoch_info,
+                mux.channel(Channel::Transactions, Channel::Transactions),
+                &StorageConfig::in_memory(),
+                rng.next_u64(),
+            )
+            .expect("in-memory storage can always be opened"),
+        );
+    }
+    nodes
+}
+
+/// Generates keys and [`ValidatorInfo`] with equal stake for `count` simulated nodes.
+///
+/// Returns the identity secret keys, the voting secret keys and the info of all validators.
+fn generate_simulated_validators(
+    count: u64,
+    rng: &mut impl CryptoRng,
+) -> (Vec<SecretKey>, Vec<aggsig::SecretKey>, Vec<ValidatorInfo>) {
+    let mut sks = Vec::new();
+    let mut voting_sks = Vec::new();
+    let mut validators = Vec::new();
+    for id in 0..count {
+        sks.push(SecretKey::new(rng));
+        voting_sks.push(aggsig::SecretKey::new(rng));
+        // the simulated network uses the port as the node ID
+        let address = localhost_ip_sockaddr(id as u16);
+        validators.push(ValidatorInfo {
+            id,
+            stake: 1,
+            pubkey: sks[id as usize].to_pk(),
+            voting_pubkey: voting_sks[id as usize].to_pk(),
+            all2all_address: address,
+            disseminator_address: address,
+            repair_request_address: address,
+            repair_response_address: address,
+        });
+    }
+    (sks, voting_sks, validators)
+}
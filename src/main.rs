@@ -1,17 +1,24 @@
 // Copyright (c) Anza Technology, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::borrow::Cow;
-
+use alpenglow::tracing::{self, TracingBackend, TracingConfig};
 use alpenglow::{create_test_nodes, logging};
+use clap::Parser;
 use color_eyre::Result;
-use fastrace::collector::Config;
 use fastrace::prelude::*;
-use fastrace_opentelemetry::OpenTelemetryReporter;
 use log::warn;
-use opentelemetry::{InstrumentationScope, KeyValue};
-use opentelemetry_otlp::{SpanExporter, WithExportConfig};
-use opentelemetry_sdk::Resource;
+
+/// Runs a local cluster of two nodes until Ctrl + C.
+#[derive(Clone, Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Where to export spans: `none`, `stdout`, `otlp[=<endpoint>]` or `file=<path>`.
+    #[arg(long, env = "ALPENGLOW_TRACING", default_value = "none")]
+    tracing: TracingBackend,
+    /// Fraction of slots for which spans are recorded, within [0, 1].
+    #[arg(long, env = "ALPENGLOW_TRACE_SAMPLING_RATE", default_value_t = 1.0)]
+    trace_sampling_rate: f64,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,24 +26,11 @@ async fn main() -> Result<()> {
     color_eyre::install()?;
 
     // enable `fastrace` tracing
-    let reporter = OpenTelemetryReporter::new(
-        SpanExporter::builder()
-            .with_tonic()
-            .with_endpoint("http://127.0.0.1:4317".to_string())
-            .with_protocol(opentelemetry_otlp::Protocol::Grpc)
-            .with_timeout(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT)
-            .build()
-            .expect("initialize oltp exporter"),
-        Cow::Owned(
-            Resource::builder()
-                .with_attributes([KeyValue::new("service.name", "alpenglow-main")])
-                .build(),
-        ),
-        InstrumentationScope::builder("alpenglow")
-            .with_version(env!("CARGO_PKG_VERSION"))
-            .build(),
-    );
-    fastrace::set_reporter(reporter, Config::default());
+    let args = Args::parse();
+    let tracing_config = TracingConfig::new("alpenglow-main")
+        .with_backend(args.tracing)
+        .with_sampling_rate(args.trace_sampling_rate);
+    tracing::enable_tracing(&tracing_config)?;
 
     logging::enable_logforth();
 
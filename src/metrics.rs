@@ -0,0 +1,369 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Prometheus metrics of a node.
+//!
+//! All metrics are registered in the process-wide [`METRICS`], and updated by the
+//! components they measure, e.g. [`crate::repair`] or [`crate::network::Multiplexer`].
+//! Per-slot timings and the finalized slot are derived from [`ConsensusEvent`]s.
+//! [`MetricsServer`] exposes everything over HTTP in the Prometheus text format.
+//!
+//! Nodes running within the same process, e.g. in tests, share the same metrics.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
+
+use axum::Router;
+use axum::extract::State;
+use axum::routing::get;
+use log::debug;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder, exponential_buckets,
+};
+use tokio::net::TcpListener;
+use tokio::sync::{RwLock, broadcast};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::consensus::{AddCertError, Blockstore, ConsensusEvent, PoolError};
+use crate::network::TransactionNetwork;
+use crate::types::Slot;
+use crate::{All2All, Alpenglow, Disseminator};
+
+/// Process-wide metrics, see [module-level documentation](self).
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Collection of all metrics of a node.
+pub struct Metrics {
+    registry: Registry,
+
+    /// Highest finalized slot.
+    pub(crate) finalized_slot: IntGauge,
+    /// Time from first observing a slot until its block is notarized.
+    pub(crate) time_to_notarize: Histogram,
+    /// Time from first observing a slot until its block is finalized.
+    pub(crate) time_to_finalize: Histogram,
+
+    /// Votes received via all-to-all.
+    pub(crate) votes_received: IntCounter,
+    /// Votes rejected by the pool, labeled by [`PoolError`] variant.
+    pub(crate) votes_rejected: IntCounterVec,
+    /// Certificates received via all-to-all.
+    pub(crate) certs_received: IntCounter,
+    /// Certificates rejected by the pool, labeled by [`AddCertError`] variant.
+    pub(crate) certs_rejected: IntCounterVec,
+
+    /// Shreds received via block dissemination.
+    pub(crate) shreds_received: IntCounter,
+    /// Shreds forwarded to other nodes, as a relay.
+    pub(crate) shreds_forwarded: IntCounter,
+
+    /// Repair requests from other nodes that were answered.
+    pub(crate) repair_requests_served: IntCounter,
+    /// Own repair requests still waiting for a response.
+    pub(crate) repair_requests_outstanding: IntGauge,
+
+    /// Payload bytes sent, labeled by multiplexer channel.
+    pub(crate) network_bytes_sent: IntCounterVec,
+    /// Payload bytes received, labeled by multiplexer channel.
+    pub(crate) network_bytes_received: IntCounterVec,
+
+    /// Approximate RocksDB sizes, labeled by what is measured.
+    pub(crate) rocksdb_bytes: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("alpenglow".to_string()), None).unwrap();
+        let slot_buckets = exponential_buckets(0.025, 2.0, 12).unwrap();
+        let metrics = Self {
+            finalized_slot: IntGauge::new("finalized_slot", "Highest finalized slot").unwrap(),
+            time_to_notarize: Histogram::with_opts(
+                HistogramOpts::new(
+                    "time_to_notarize_seconds",
+                    "Time from first observing a slot until notarization",
+                )
+                .buckets(slot_buckets.clone()),
+            )
+            .unwrap(),
+            time_to_finalize: Histogram::with_opts(
+                HistogramOpts::new(
+                    "time_to_finalize_seconds",
+                    "Time from first observing a slot until finalization",
+                )
+                .buckets(slot_buckets),
+            )
+            .unwrap(),
+            votes_received: IntCounter::new("votes_received_total", "Votes received").unwrap(),
+            votes_rejected: IntCounterVec::new(
+                Opts::new("votes_rejected_total", "Votes rejected by the pool"),
+                &["reason"],
+            )
+            .unwrap(),
+            certs_received: IntCounter::new("certs_received_total", "Certificates received")
+                .unwrap(),
+            certs_rejected: IntCounterVec::new(
+                Opts::new("certs_rejected_total", "Certificates rejected by the pool"),
+                &["reason"],
+            )
+            .unwrap(),
+            shreds_received: IntCounter::new("shreds_received_total", "Shreds received").unwrap(),
+            shreds_forwarded: IntCounter::new("shreds_forwarded_total", "Shreds forwarded")
+                .unwrap(),
+            repair_requests_served: IntCounter::new(
+                "repair_requests_served_total",
+                "Repair requests answered",
+            )
+            .unwrap(),
+            repair_requests_outstanding: IntGauge::new(
+                "repair_requests_outstanding",
+                "Own repair requests awaiting a response",
+            )
+            .unwrap(),
+            network_bytes_sent: IntCounterVec::new(
+                Opts::new("network_sent_bytes_total", "Payload bytes sent"),
+                &["channel"],
+            )
+            .unwrap(),
+            network_bytes_received: IntCounterVec::new(
+                Opts::new("network_received_bytes_total", "Payload bytes received"),
+                &["channel"],
+            )
+            .unwrap(),
+            rocksdb_bytes: IntGaugeVec::new(
+                Opts::new("rocksdb_bytes", "Approximate RocksDB size"),
+                &["kind"],
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register_all();
+        metrics
+    }
+
+    fn register_all(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 14] = [
+            Box::new(self.finalized_slot.clone()),
+            Box::new(self.time_to_notarize.clone()),
+            Box::new(self.time_to_finalize.clone()),
+            Box::new(self.votes_received.clone()),
+            Box::new(self.votes_rejected.clone()),
+            Box::new(self.certs_received.clone()),
+            Box::new(self.certs_rejected.clone()),
+            Box::new(self.shreds_received.clone()),
+            Box::new(self.shreds_forwarded.clone()),
+            Box::new(self.repair_requests_served.clone()),
+            Box::new(self.repair_requests_outstanding.clone()),
+            Box::new(self.network_bytes_sent.clone()),
+            Box::new(self.network_bytes_received.clone()),
+            Box::new(self.rocksdb_bytes.clone()),
+        ];
+        for collector in collectors {
+            self.registry.register(collector).unwrap();
+        }
+    }
+
+    /// Counts a vote rejected by the pool with the given error.
+    pub(crate) fn vote_rejected(&self, err: &PoolError) {
+        let reason = match err {
+            PoolError::SlotOutOfBounds => "slot_out_of_bounds",
+            PoolError::InvalidSignature => "invalid_signature",
+            PoolError::Duplicate => "duplicate",
+            PoolError::Slashable(_) => "slashable",
+            PoolError::Storage => "storage",
+        };
+        self.votes_rejected.with_label_values(&[reason]).inc();
+    }
+
+    /// Counts a certificate rejected by the pool with the given error.
+    pub(crate) fn cert_rejected(&self, err: &AddCertError) {
+        let reason = match err {
+            AddCertError::SlotOutOfBounds => "slot_out_of_bounds",
+            AddCertError::ThresholdNotMet => "threshold_not_met",
+            AddCertError::InvalidSignature => "invalid_signature",
+            AddCertError::Duplicate => "duplicate",
+            AddCertError::Storage => "storage",
+        };
+        self.certs_rejected.with_label_values(&[reason]).inc();
+    }
+
+    /// Encodes all metrics in the Prometheus text format.
+    #[must_use]
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+/// HTTP server exposing [`METRICS`] of a single node on `GET /metrics`.
+pub struct MetricsServer {
+    blockstore: Arc<RwLock<Blockstore>>,
+}
+
+impl MetricsServer {
+    /// Creates a new server for the given `node`.
+    ///
+    /// Should be called before running the node, to observe all slots.
+    pub fn new<A, D, T>(node: &Alpenglow<A, D, T>) -> Self
+    where
+        A: All2All + Send + Sync + 'static,
+        D: Disseminator + Send + Sync + 'static,
+        T: TransactionNetwork + 'static,
+    {
+        tokio::spawn(track_events(node.subscribe()));
+        Self {
+            blockstore: node.blockstore(),
+        }
+    }
+
+    /// Serves metrics on `addr` until `cancel_token` is cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if binding to `addr` fails.
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        cancel_token: CancellationToken,
+    ) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve_on(listener, cancel_token).await
+    }
+
+    /// Same as [`Self::serve`], but on an already bound `listener`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections fails.
+    pub async fn serve_on(
+        self,
+        listener: TcpListener,
+        cancel_token: CancellationToken,
+    ) -> std::io::Result<()> {
+        debug!("serving metrics on {}", listener.local_addr()?);
+        let app = Router::new()
+            .route("/metrics", get(handle_scrape))
+            .with_state(Arc::new(self));
+        axum::serve(listener, app)
+            .with_graceful_shutdown(cancel_token.cancelled_owned())
+            .await
+    }
+}
+
+async fn handle_scrape(State(server): State<Arc<MetricsServer>>) -> String {
+    // RocksDB sizes are only sampled on demand
+    for (kind, bytes) in server.blockstore.read().await.db_sizes() {
+        let bytes = i64::try_from(bytes).unwrap_or(i64::MAX);
+        METRICS.rocksdb_bytes.with_label_values(&[kind]).set(bytes);
+    }
+    METRICS.encode()
+}
+
+/// Updates slot-based metrics from `events`, until the node shuts down.
+async fn track_events(mut events: broadcast::Receiver<ConsensusEvent>) {
+    let mut timer = SlotTimer::default();
+    loop {
+        match events.recv().await {
+            Ok(event) => timer.observe(&event, Instant::now()),
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Measures per-slot timings, from when a slot is first observed.
+#[derive(Default)]
+struct SlotTimer {
+    /// Time each pending slot was first observed, and whether it was notarized.
+    pending: BTreeMap<Slot, (Instant, bool)>,
+}
+
+impl SlotTimer {
+    fn observe(&mut self, event: &ConsensusEvent, now: Instant) {
+        let Some(slot) = event.slot() else {
+            return;
+        };
+        match event {
+            // carries the finalized slot, not the one we are waiting on
+            ConsensusEvent::Standstill(_) => return,
+            ConsensusEvent::Skipped(_) | ConsensusEvent::ImplicitlySkipped(_) => {
+                self.pending.remove(&slot);
+                return;
+            }
+            _ => {}
+        }
+
+        let (first_seen, notarized) = self.pending.entry(slot).or_insert((now, false));
+        let elapsed = now.duration_since(*first_seen).as_secs_f64();
+        if matches!(event, ConsensusEvent::Notarized { .. }) && !*notarized {
+            *notarized = true;
+            METRICS.time_to_notarize.observe(elapsed);
+        }
+        if event.is_finalization() {
+            METRICS.time_to_finalize.observe(elapsed);
+            if slot.inner() as i64 > METRICS.finalized_slot.get() {
+                METRICS.finalized_slot.set(slot.inner() as i64);
+            }
+            // older slots are finalized or skipped by now
+            self.pending = self.pending.split_off(&slot.next());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::crypto::hash;
+    use crate::crypto::merkle::BlockHash;
+
+    #[test]
+    fn slot_timer() {
+        let start = Instant::now();
+        let slot = Slot::new(1_000_000);
+        let block_hash: BlockHash = hash(b"block").into();
+        let notarized_before = METRICS.time_to_notarize.get_sample_count();
+        let finalized_before = METRICS.time_to_finalize.get_sample_count();
+
+        let mut timer = SlotTimer::default();
+        timer.observe(&ConsensusEvent::Skipped(slot.prev()), start);
+        assert!(timer.pending.is_empty());
+
+        let parent = (slot.prev(), hash(b"parent").into());
+        let received = ConsensusEvent::BlockReceived {
+            slot,
+            block_hash: block_hash.clone(),
+            parent,
+        };
+        timer.observe(&received, start);
+        let later = start + Duration::from_millis(100);
+        let notarized = ConsensusEvent::Notarized {
+            slot,
+            block_hash: block_hash.clone(),
+        };
+        timer.observe(&notarized, later);
+        timer.observe(&notarized, later);
+        timer.observe(&ConsensusEvent::SlowFinalized { slot, block_hash }, later);
+
+        assert!(timer.pending.is_empty());
+        assert!(METRICS.time_to_notarize.get_sample_count() > notarized_before);
+        assert!(METRICS.time_to_finalize.get_sample_count() > finalized_before);
+        assert!(METRICS.finalized_slot.get() >= slot.inner() as i64);
+        assert!(METRICS.encode().contains("alpenglow_finalized_slot"));
+    }
+
+    #[test]
+    fn rejections() {
+        METRICS.vote_rejected(&PoolError::Duplicate);
+        METRICS.cert_rejected(&AddCertError::ThresholdNotMet);
+        let encoded = METRICS.encode();
+        assert!(encoded.contains(r#"alpenglow_votes_rejected_total{reason="duplicate"}"#));
+        assert!(encoded.contains(r#"alpenglow_certs_rejected_total{reason="threshold_not_met"}"#));
+    }
+}
@@ -11,8 +11,27 @@
 //! Specific implementations for different underlying network stacks are provided:
 //! - [`UdpNetwork`] abstracts a simple UDP socket
 //! - [`TcpNetwork`] handles TCP connections under the hood
+//! - [`QuicNetwork`] provides authenticated and encrypted QUIC connections
 //! - [`SimulatedNetwork`] provides a simulated network for local testing
 //!
+//! Some implementations also know who sent each message they receive.
+//! These implement [`AuthenticatedNetwork`]:
+//! - [`QuicNetwork`] identifies peers by the key they authenticated with
+//! - [`SignedNetwork`] signs every packet, and works over any other [`Network`]
+//! - [`SimulatedNetwork`] knows the sender by construction
+//!
+//! Messages larger than [`MTU_BYTES`] can be sent over any [`AuthenticatedNetwork`]
+//! by wrapping it into a [`FragmentedNetwork`].
+//!
+//! Outgoing traffic of all of a node's networks can be prioritized by message class
+//! by wrapping each of them into a [`ScheduledNetwork`] sharing one [`EgressScheduler`].
+//!
+//! A [`Multiplexer`] runs several typed [`MultiplexedNetwork`]s over a single underlying network,
+//! so a node only needs one port.
+//!
+//! Traffic of any network can be recorded by a [`RecordingNetwork`],
+//! and later be replayed into a single node by [`ReplayNetwork`]s.
+//!
 //! # Examples
 //!
 //! ```rust
@@ -26,6 +45,12 @@
 //! }
 //! ```
 
+mod fragmented;
+mod multiplexed;
+mod quic;
+mod recording;
+mod scheduled;
+mod signed;
 pub mod simulated;
 mod tcp;
 mod udp;
@@ -34,15 +59,29 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use async_trait::async_trait;
 
+pub use self::fragmented::{DEFAULT_FRAGMENT_SIZE, Fragment, FragmentedNetwork};
+pub use self::multiplexed::{Channel, MultiplexedFrame, MultiplexedNetwork, Multiplexer};
+pub use self::quic::QuicNetwork;
+pub use self::recording::{
+    Direction, RecordingNetwork, ReplayNetwork, TraceRecord, TraceRecorder, TraceReplayer,
+    read_trace,
+};
+pub use self::scheduled::{
+    ClassMetrics, EgressConfig, EgressScheduler, MessageClass, ScheduledNetwork,
+};
+pub use self::signed::{SignedNetwork, SignedPacket};
 pub use self::simulated::SimulatedNetwork;
 pub use self::tcp::TcpNetwork;
 pub use self::udp::UdpNetwork;
-use crate::Transaction;
 use crate::consensus::ConsensusMessage;
 use crate::repair::{RepairRequest, RepairResponse};
 use crate::shredder::Shred;
+use crate::{Transaction, ValidatorId};
 
 /// Maximum payload size of a UDP packet.
+///
+/// Networks that send each message as a single packet only support messages up to this size.
+/// Use a [`FragmentedNetwork`] to send larger messages over them.
 pub const MTU_BYTES: usize = 1500;
 
 /// Abstraction of a network interface for sending and receiving messages.
@@ -70,6 +109,27 @@ pub trait Network: Send + Sync {
     // TODO: implement brodcast at `Network` level?
 
     async fn receive(&self) -> std::io::Result<Self::Recv>;
+
+    /// Receives up to `max` messages at once.
+    ///
+    /// Waits until at least one message is available, but not until `max` are available.
+    /// Implementators may override this to receive more efficiently.
+    /// The default implementation receives a single message via [`Network::receive`].
+    async fn receive_batch(&self, max: usize) -> std::io::Result<Vec<Self::Recv>> {
+        let _ = max;
+        let msg = self.receive().await?;
+        Ok(vec![msg])
+    }
+}
+
+/// A [`Network`] that can attribute received messages to their sender.
+///
+/// The sender is authenticated, either by the transport itself or by per-packet signatures.
+/// Messages that cannot be attributed to a known validator are never returned.
+#[async_trait]
+pub trait AuthenticatedNetwork: Network {
+    /// Receives the next message, together with the ID of the validator that sent it.
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, Self::Recv)>;
 }
 
 /// A marker trait that constrains [`Network`] to send and receive [`Shred`]
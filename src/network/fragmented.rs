@@ -0,0 +1,549 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fragmentation layer for messages larger than one MTU.
+//!
+//! This module provides [`FragmentedNetwork`], which wraps any [`AuthenticatedNetwork`]
+//! carrying [`Fragment`]s. Outgoing messages are split into fragments that each fit
+//! into a single packet of the underlying network. Incoming fragments are buffered
+//! per sender until all fragments of a message have arrived.
+//!
+//! Buffers are bounded in two ways:
+//! - Incomplete messages are discarded after a timeout.
+//! - The total size of buffered fragments is limited per sender.
+//!   If a sender exceeds its limit, its oldest incomplete messages are discarded.
+//!   This includes the bookkeeping for fragments that have not arrived yet,
+//!   so a single fragment cannot make the receiver allocate a large buffer.
+//!
+//! Per-sender limits require knowing who sent each fragment, which is why the
+//! underlying network needs to be an [`AuthenticatedNetwork`]. For transports
+//! without built-in authentication, wrap them into a [`SignedNetwork`] first.
+//!
+//! [`SignedNetwork`]: super::SignedNetwork
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::{debug, warn};
+use tokio::sync::Mutex;
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{AuthenticatedNetwork, MTU_BYTES, Network};
+use crate::ValidatorId;
+
+/// Default maximum number of message bytes carried by a single [`Fragment`].
+///
+/// Leaves room for the fragment header and for headers of wrapping layers,
+/// e.g. the signature added by [`SignedNetwork`](super::SignedNetwork).
+pub const DEFAULT_FRAGMENT_SIZE: usize = MTU_BYTES - 256;
+
+/// Smallest fragment size a sender may use.
+///
+/// Bounds the number of fragments a message of a given size can be split into.
+pub const MIN_FRAGMENT_SIZE: usize = 512;
+
+/// Bytes accounted for each fragment slot of a partially reassembled message.
+const SLOT_BYTES: usize = size_of::<Option<Vec<u8>>>();
+
+/// Default time after which incomplete messages are discarded.
+const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default limit on buffered fragment bytes per sender.
+const DEFAULT_MAX_BYTES_PER_PEER: usize = 8 * 1024 * 1024;
+
+/// A single fragment of a message, as sent over the underlying network.
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
+pub struct Fragment {
+    /// Sender-local ID of the message this fragment belongs to.
+    msg_id: u64,
+    /// Index of this fragment within the message.
+    index: u16,
+    /// Total number of fragments the message consists of.
+    count: u16,
+    /// Part of the serialized message carried by this fragment.
+    data: Vec<u8>,
+}
+
+/// A message that is partially reassembled.
+struct PartialMessage {
+    /// Fragments received so far, indexed by [`Fragment::index`].
+    fragments: Vec<Option<Vec<u8>>>,
+    /// Number of fragments received so far.
+    received: usize,
+    /// Total number of bytes received so far.
+    bytes: usize,
+    /// Time the first fragment of the message was received.
+    first_seen: Instant,
+}
+
+impl PartialMessage {
+    fn new(count: u16) -> Self {
+        Self {
+            fragments: vec![None; count as usize],
+            received: 0,
+            bytes: 0,
+            first_seen: Instant::now(),
+        }
+    }
+
+    /// Returns the number of bytes accounted against the sender's limit.
+    ///
+    /// This covers received data as well as slots for missing fragments.
+    fn footprint(&self) -> usize {
+        self.bytes + self.fragments.len() * SLOT_BYTES
+    }
+
+    fn is_complete(&self) -> bool {
+        self.received == self.fragments.len()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.bytes);
+        for fragment in self.fragments.into_iter().flatten() {
+            bytes.extend_from_slice(&fragment);
+        }
+        bytes
+    }
+}
+
+/// Reassembly buffers for all incomplete messages of a single sender.
+#[derive(Default)]
+struct PeerBuffer {
+    messages: HashMap<u64, PartialMessage>,
+    /// Total [`PartialMessage::footprint`] across all incomplete messages.
+    bytes: usize,
+}
+
+impl PeerBuffer {
+    /// Discards all incomplete messages that were started more than `timeout` ago.
+    fn evict_expired(&mut self, timeout: Duration) {
+        let bytes = &mut self.bytes;
+        self.messages.retain(|_, msg| {
+            let keep = msg.first_seen.elapsed() < timeout;
+            if !keep {
+                *bytes -= msg.footprint();
+            }
+            keep
+        });
+    }
+
+    /// Discards the oldest incomplete message, except for `msg_id`.
+    ///
+    /// Returns `false` if there was no such message to discard.
+    fn evict_oldest(&mut self, msg_id: u64) -> bool {
+        let oldest = self
+            .messages
+            .iter()
+            .filter(|(id, _)| **id != msg_id)
+            .min_by_key(|(_, msg)| msg.first_seen)
+            .map(|(id, _)| *id);
+        let Some(id) = oldest else {
+            return false;
+        };
+        let msg = self.messages.remove(&id).unwrap();
+        self.bytes -= msg.footprint();
+        true
+    }
+}
+
+/// Network wrapper that transparently fragments and reassembles large messages.
+pub struct FragmentedNetwork<N, S, R> {
+    network: N,
+    /// Maximum number of message bytes per fragment.
+    fragment_size: usize,
+    /// Time after which incomplete messages are discarded.
+    timeout: Duration,
+    /// Maximum number of buffered bytes per sender.
+    max_bytes_per_peer: usize,
+    /// ID to use for the next outgoing message.
+    next_msg_id: AtomicU64,
+    /// Reassembly buffers, indexed by sender.
+    buffers: Mutex<HashMap<ValidatorId, PeerBuffer>>,
+    _msg_types: PhantomData<(S, R)>,
+}
+
+impl<N, S, R> FragmentedNetwork<N, S, R>
+where
+    N: AuthenticatedNetwork<Send = Fragment, Recv = Fragment>,
+{
+    /// Creates a new `FragmentedNetwork` on top of the given `network`.
+    ///
+    /// Uses [`DEFAULT_FRAGMENT_SIZE`] and default reassembly limits.
+    pub fn new(network: N) -> Self {
+        Self {
+            network,
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            max_bytes_per_peer: DEFAULT_MAX_BYTES_PER_PEER,
+            // random start, so IDs are not reused immediately after a restart
+            next_msg_id: AtomicU64::new(rand::random()),
+            buffers: Mutex::new(HashMap::new()),
+            _msg_types: PhantomData,
+        }
+    }
+
+    /// Sets the maximum number of message bytes carried by each fragment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fragment_size` is less than [`MIN_FRAGMENT_SIZE`].
+    #[must_use]
+    pub fn with_fragment_size(mut self, fragment_size: usize) -> Self {
+        assert!(
+            fragment_size >= MIN_FRAGMENT_SIZE,
+            "fragments need to carry at least {MIN_FRAGMENT_SIZE} bytes"
+        );
+        self.fragment_size = fragment_size;
+        self
+    }
+
+    /// Sets the time after which incomplete messages are discarded.
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of bytes buffered for incomplete messages per sender.
+    ///
+    /// This also limits the size of the largest message that can be received.
+    #[must_use]
+    pub const fn with_max_bytes_per_peer(mut self, max_bytes: usize) -> Self {
+        self.max_bytes_per_peer = max_bytes;
+        self
+    }
+
+    /// Returns a reference to the underlying network.
+    pub const fn inner(&self) -> &N {
+        &self.network
+    }
+
+    /// Splits the serialized message `bytes` into fragments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the message needs more than `u16::MAX` fragments.
+    fn fragment(&self, bytes: &[u8]) -> Vec<Fragment> {
+        let msg_id = self.next_msg_id.fetch_add(1, Ordering::Relaxed);
+        let count = bytes.len().div_ceil(self.fragment_size).max(1);
+        let count = u16::try_from(count).expect("message too large to fragment");
+        (0..count)
+            .map(|index| {
+                let start = index as usize * self.fragment_size;
+                let end = (start + self.fragment_size).min(bytes.len());
+                Fragment {
+                    msg_id,
+                    index,
+                    count,
+                    data: bytes[start..end].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Adds the `fragment` received from `sender` to the reassembly buffers.
+    ///
+    /// Returns the serialized message if this completed it.
+    async fn reassemble(&self, sender: ValidatorId, fragment: Fragment) -> Option<Vec<u8>> {
+        if fragment.count == 1 && fragment.index == 0 {
+            return Some(fragment.data);
+        }
+        if fragment.index >= fragment.count
+            || fragment.count as usize > self.max_fragments()
+            || fragment.data.len() > self.max_bytes_per_peer
+        {
+            debug!("dropping malformed fragment from {sender}");
+            return None;
+        }
+
+        let mut buffers = self.buffers.lock().await;
+        for buffer in buffers.values_mut() {
+            buffer.evict_expired(self.timeout);
+        }
+        buffers.retain(|_, buffer| !buffer.messages.is_empty());
+        let buffer = buffers.entry(sender).or_default();
+
+        // make room for the new fragment (and its message's slots), if necessary
+        let mut needed = fragment.data.len();
+        if !buffer.messages.contains_key(&fragment.msg_id) {
+            needed += fragment.count as usize * SLOT_BYTES;
+        }
+        while buffer.bytes + needed > self.max_bytes_per_peer {
+            if !buffer.evict_oldest(fragment.msg_id) {
+                warn!("dropping message from {sender}, exceeds reassembly limit");
+                if let Some(msg) = buffer.messages.remove(&fragment.msg_id) {
+                    buffer.bytes -= msg.footprint();
+                }
+                return None;
+            }
+        }
+
+        let msg = buffer.messages.entry(fragment.msg_id).or_insert_with(|| {
+            buffer.bytes += fragment.count as usize * SLOT_BYTES;
+            PartialMessage::new(fragment.count)
+        });
+        if msg.fragments.len() != fragment.count as usize {
+            debug!("dropping fragment from {sender} with inconsistent count");
+            return None;
+        }
+        let slot = &mut msg.fragments[fragment.index as usize];
+        if slot.is_some() {
+            return None;
+        }
+        msg.received += 1;
+        msg.bytes += fragment.data.len();
+        buffer.bytes += fragment.data.len();
+        *slot = Some(fragment.data);
+
+        if !msg.is_complete() {
+            return None;
+        }
+        let msg = buffer.messages.remove(&fragment.msg_id).unwrap();
+        buffer.bytes -= msg.footprint();
+        Some(msg.into_bytes())
+    }
+
+    /// Returns the largest fragment count accepted for incoming messages.
+    ///
+    /// Any message that fits into the per-sender limit needs at most this many
+    /// fragments, as long as the sender uses at least [`MIN_FRAGMENT_SIZE`].
+    const fn max_fragments(&self) -> usize {
+        self.max_bytes_per_peer.div_ceil(MIN_FRAGMENT_SIZE)
+    }
+}
+
+#[async_trait]
+impl<N, S, R> Network for FragmentedNetwork<N, S, R>
+where
+    N: AuthenticatedNetwork<Send = Fragment, Recv = Fragment>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    type Recv = R;
+    type Send = S;
+
+    async fn send_to_many(
+        &self,
+        msg: &S,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let bytes = wincode::serialize(msg).unwrap();
+        let addrs = addrs.collect::<Vec<_>>();
+        for fragment in self.fragment(&bytes) {
+            self.network
+                .send_to_many(&fragment, addrs.iter().copied())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, msg: &S, addr: SocketAddr) -> std::io::Result<()> {
+        let bytes = wincode::serialize(msg).unwrap();
+        for fragment in self.fragment(&bytes) {
+            self.network.send(&fragment, addr).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.receive_from().await?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<N, S, R> AuthenticatedNetwork for FragmentedNetwork<N, S, R>
+where
+    N: AuthenticatedNetwork<Send = Fragment, Recv = Fragment>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
+        loop {
+            let (sender, fragment) = self.network.receive_from().await?;
+            let Some(bytes) = self.reassemble(sender, fragment).await else {
+                continue;
+            };
+            match wincode::deserialize(&bytes) {
+                Ok(msg) => return Ok((sender, msg)),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::network::simulated::SimulatedNetworkCore;
+    use crate::network::{SimulatedNetwork, localhost_ip_sockaddr};
+    use crate::test_utils::Ping;
+
+    async fn create_networks<M>(
+        core: &Arc<SimulatedNetworkCore>,
+    ) -> (
+        FragmentedNetwork<SimulatedNetwork<Fragment, Fragment>, M, M>,
+        FragmentedNetwork<SimulatedNetwork<Fragment, Fragment>, M, M>,
+    ) {
+        let net0 = FragmentedNetwork::new(core.join_unlimited(0).await);
+        let net1 = FragmentedNetwork::new(core.join_unlimited(1).await);
+        (net0, net1)
+    }
+
+    fn lossless_core() -> Arc<SimulatedNetworkCore> {
+        Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        )
+    }
+
+    #[tokio::test]
+    async fn small_message() {
+        let core = lossless_core();
+        let (net0, net1) = create_networks::<Ping>(&core).await;
+
+        net0.send(&Ping::default(), localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let (sender, msg) = net1.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(msg.0, Ping::default().0);
+    }
+
+    #[tokio::test]
+    async fn large_message() {
+        let core = lossless_core();
+        let (net0, net1) = create_networks::<Vec<u8>>(&core).await;
+
+        let msg = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        net0.send(&msg, localhost_ip_sockaddr(1)).await.unwrap();
+        let (sender, received) = net1.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(received, msg);
+    }
+
+    #[tokio::test]
+    async fn incomplete_message_times_out() {
+        let core = lossless_core();
+        let net0: SimulatedNetwork<Fragment, Fragment> = core.join_unlimited(0).await;
+        let net1: FragmentedNetwork<_, Vec<u8>, Vec<u8>> =
+            FragmentedNetwork::new(core.join_unlimited(1).await)
+                .with_timeout(Duration::from_millis(10));
+
+        // send only the first of two fragments
+        let fragment = Fragment {
+            msg_id: 0,
+            index: 0,
+            count: 2,
+            data: vec![0; 100],
+        };
+        net0.send(&fragment, localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let res = timeout(Duration::from_millis(200), net1.receive()).await;
+        assert!(res.is_err());
+        assert_eq!(net1.buffers.lock().await[&0].bytes, 100 + 2 * SLOT_BYTES);
+
+        // a later fragment triggers eviction of the stale message
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let fragment = Fragment {
+            msg_id: 1,
+            index: 0,
+            count: 2,
+            data: vec![0; 50],
+        };
+        net0.send(&fragment, localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let res = timeout(Duration::from_millis(200), net1.receive()).await;
+        assert!(res.is_err());
+        let buffers = net1.buffers.lock().await;
+        assert_eq!(buffers[&0].messages.len(), 1);
+        assert_eq!(buffers[&0].bytes, 50 + 2 * SLOT_BYTES);
+    }
+
+    #[tokio::test]
+    async fn fragment_from_other_peer_evicts_stale_messages() {
+        let core = lossless_core();
+        let net0: SimulatedNetwork<Fragment, Fragment> = core.join_unlimited(0).await;
+        let net1: FragmentedNetwork<_, Vec<u8>, Vec<u8>> =
+            FragmentedNetwork::new(core.join_unlimited(1).await)
+                .with_timeout(Duration::from_millis(10));
+        let net2: SimulatedNetwork<Fragment, Fragment> = core.join_unlimited(2).await;
+
+        let fragment = Fragment {
+            msg_id: 0,
+            index: 0,
+            count: 2,
+            data: vec![0; 100],
+        };
+        net0.send(&fragment, localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let res = timeout(Duration::from_millis(200), net1.receive()).await;
+        assert!(res.is_err());
+        assert!(net1.buffers.lock().await.contains_key(&0));
+
+        // peer 0 stays silent, a fragment from peer 2 evicts its stale message
+        net2.send(&fragment, localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let res = timeout(Duration::from_millis(200), net1.receive()).await;
+        assert!(res.is_err());
+        let buffers = net1.buffers.lock().await;
+        assert!(!buffers.contains_key(&0));
+        assert_eq!(buffers[&2].messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn excessive_fragment_count() {
+        let core = lossless_core();
+        let net0: SimulatedNetwork<Fragment, Fragment> = core.join_unlimited(0).await;
+        let net1: FragmentedNetwork<_, Vec<u8>, Vec<u8>> =
+            FragmentedNetwork::new(core.join_unlimited(1).await).with_max_bytes_per_peer(10_000);
+
+        // would need far more slots than the limit allows, is dropped outright
+        let fragment = Fragment {
+            msg_id: 0,
+            index: 0,
+            count: u16::MAX,
+            data: vec![0; 100],
+        };
+        net0.send(&fragment, localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let res = timeout(Duration::from_millis(200), net1.receive()).await;
+        assert!(res.is_err());
+        assert!(net1.buffers.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let core = lossless_core();
+        let net0: FragmentedNetwork<_, Vec<u8>, Vec<u8>> =
+            FragmentedNetwork::new(core.join_unlimited(0).await);
+        let net1: FragmentedNetwork<_, Vec<u8>, Vec<u8>> =
+            FragmentedNetwork::new(core.join_unlimited(1).await).with_max_bytes_per_peer(10_000);
+
+        // too large to be buffered, is dropped
+        let large = vec![1; 20_000];
+        net0.send(&large, localhost_ip_sockaddr(1)).await.unwrap();
+        // fits, is received
+        let small = vec![2; 5_000];
+        net0.send(&small, localhost_ip_sockaddr(1)).await.unwrap();
+
+        let (_, received) = net1.receive_from().await.unwrap();
+        assert_eq!(received, small);
+        assert!(net1.buffers.lock().await[&0].bytes <= 10_000);
+    }
+}
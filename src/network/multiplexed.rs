@@ -0,0 +1,417 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Multiplexing of several typed networks over a single underlying network.
+//!
+//! This module provides [`Multiplexer`], which wraps a single [`Network`] carrying
+//! [`MultiplexedFrame`]s, e.g. a [`UdpNetwork`](super::UdpNetwork) bound to one port.
+//! It hands out [`MultiplexedNetwork`]s, each implementing [`Network`] for its own
+//! message types. Outgoing messages are tagged with a [`Channel`]. Incoming frames
+//! are demultiplexed by a background task to the sub-network receiving on their channel.
+//!
+//! This allows a node to run behind a single forwarded port or on one radio channel.
+//! Frames for channels nobody receives on, or whose receiver is full, are dropped.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{AuthenticatedNetwork, Network};
+use crate::ValidatorId;
+use crate::metrics::METRICS;
+
+/// Maximum number of received messages buffered for each channel.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// Logical channel of a [`MultiplexedFrame`].
+///
+/// There is one channel per kind of message a node receives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Channel {
+    Consensus,
+    Shreds,
+    RepairRequests,
+    RepairResponses,
+    Transactions,
+}
+
+impl Channel {
+    const ALL: [Self; 5] = [
+        Self::Consensus,
+        Self::Shreds,
+        Self::RepairRequests,
+        Self::RepairResponses,
+        Self::Transactions,
+    ];
+
+    /// Returns the channel with the given wire representation, if any.
+    fn from_repr(repr: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|channel| *channel as u8 == repr)
+    }
+
+    /// Returns a short, human-readable name of the channel, e.g. for metrics.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Consensus => "consensus",
+            Self::Shreds => "shreds",
+            Self::RepairRequests => "repair_requests",
+            Self::RepairResponses => "repair_responses",
+            Self::Transactions => "transactions",
+        }
+    }
+}
+
+/// Wire format used by [`Multiplexer`].
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
+pub struct MultiplexedFrame {
+    /// The [`Channel`] this frame belongs to.
+    channel: u8,
+    /// Serialized message.
+    payload: Vec<u8>,
+}
+
+impl MultiplexedFrame {
+    /// Creates a new frame for the serialized message `payload` on the given `channel`.
+    ///
+    /// This is useful for sending to a multiplexed node without using a [`Multiplexer`].
+    #[must_use]
+    pub const fn new(channel: Channel, payload: Vec<u8>) -> Self {
+        Self {
+            channel: channel as u8,
+            payload,
+        }
+    }
+}
+
+/// Sending half for received payloads, tagged with their sender if known.
+type Inbox = mpsc::Sender<(Option<ValidatorId>, Vec<u8>)>;
+
+/// Demultiplexes frames of a single underlying network onto [`MultiplexedNetwork`]s.
+///
+/// The background task keeps running as long as the multiplexer or any of its sub-networks exist.
+pub struct Multiplexer<N> {
+    network: Arc<N>,
+    inboxes: Arc<std::sync::Mutex<HashMap<u8, Inbox>>>,
+    demux: Arc<DemuxTask>,
+}
+
+/// Handle to the background task of a [`Multiplexer`], which aborts it on drop.
+struct DemuxTask(JoinHandle<()>);
+
+impl Drop for DemuxTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<N> Multiplexer<N>
+where
+    N: Network<Send = MultiplexedFrame, Recv = MultiplexedFrame> + 'static,
+{
+    /// Creates a new `Multiplexer` on top of the given `network`.
+    ///
+    /// Spawns a background task that receives and demultiplexes all frames.
+    /// Sub-networks created by this do not know the sender of received messages.
+    /// Use [`Multiplexer::new_authenticated`] for that instead.
+    #[must_use]
+    pub fn new(network: N) -> Self {
+        let network = Arc::new(network);
+        let inboxes = Arc::default();
+        let demux = tokio::spawn(Self::demux(Arc::clone(&network), Arc::clone(&inboxes)));
+        Self {
+            network,
+            inboxes,
+            demux: Arc::new(DemuxTask(demux)),
+        }
+    }
+
+    /// Creates a new sub-network for messages of types `S` and `R`.
+    ///
+    /// Outgoing messages are sent on channel `send`, while this receives on channel `recv`.
+    /// These are usually different for request-response protocols.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another sub-network already receives on channel `recv`.
+    #[must_use]
+    pub fn channel<S, R>(&self, send: Channel, recv: Channel) -> MultiplexedNetwork<N, S, R> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let mut inboxes = self.inboxes.lock().unwrap();
+        let previous = inboxes.insert(recv as u8, tx);
+        assert!(previous.is_none(), "already receiving on channel {recv:?}");
+        MultiplexedNetwork {
+            network: Arc::clone(&self.network),
+            channel: send,
+            receiver: Mutex::new(rx),
+            _demux: Arc::clone(&self.demux),
+            _msg_types: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the underlying network.
+    pub fn inner(&self) -> &N {
+        &self.network
+    }
+
+    async fn demux(network: Arc<N>, inboxes: Arc<std::sync::Mutex<HashMap<u8, Inbox>>>) {
+        loop {
+            match network.receive().await {
+                Ok(frame) => Self::deliver(&inboxes, None, frame),
+                Err(err) => {
+                    warn!("stopping multiplexer, receiving failed with {err:?}");
+                    inboxes.lock().unwrap().clear();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn deliver(
+        inboxes: &std::sync::Mutex<HashMap<u8, Inbox>>,
+        sender: Option<ValidatorId>,
+        frame: MultiplexedFrame,
+    ) {
+        let inboxes = inboxes.lock().unwrap();
+        let Some(inbox) = inboxes.get(&frame.channel) else {
+            warn!("dropping frame for unknown channel {}", frame.channel);
+            return;
+        };
+        if let Some(channel) = Channel::from_repr(frame.channel) {
+            let bytes = METRICS
+                .network_bytes_received
+                .with_label_values(&[channel.name()]);
+            bytes.inc_by(frame.payload.len() as u64);
+        }
+        if inbox.try_send((sender, frame.payload)).is_err() {
+            warn!("dropping frame, channel {} is full", frame.channel);
+        }
+    }
+}
+
+impl<N> Multiplexer<N>
+where
+    N: AuthenticatedNetwork<Send = MultiplexedFrame, Recv = MultiplexedFrame> + 'static,
+{
+    /// Creates a new `Multiplexer` on top of the given authenticated `network`.
+    ///
+    /// Same as [`Multiplexer::new`], but sub-networks created by this
+    /// also implement [`AuthenticatedNetwork::receive_from`].
+    #[must_use]
+    pub fn new_authenticated(network: N) -> Self {
+        let network = Arc::new(network);
+        let inboxes = Arc::default();
+        let demux = tokio::spawn(Self::demux_authenticated(
+            Arc::clone(&network),
+            Arc::clone(&inboxes),
+        ));
+        Self {
+            network,
+            inboxes,
+            demux: Arc::new(DemuxTask(demux)),
+        }
+    }
+
+    async fn demux_authenticated(
+        network: Arc<N>,
+        inboxes: Arc<std::sync::Mutex<HashMap<u8, Inbox>>>,
+    ) {
+        loop {
+            match network.receive_from().await {
+                Ok((sender, frame)) => Self::deliver(&inboxes, Some(sender), frame),
+                Err(err) => {
+                    warn!("stopping multiplexer, receiving failed with {err:?}");
+                    inboxes.lock().unwrap().clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Typed sub-network of a [`Multiplexer`].
+pub struct MultiplexedNetwork<N, S, R> {
+    network: Arc<N>,
+    /// Channel outgoing messages are tagged with.
+    channel: Channel,
+    /// Receiver for incoming messages on this sub-network's channel.
+    receiver: Mutex<mpsc::Receiver<(Option<ValidatorId>, Vec<u8>)>>,
+    /// Keeps the background task running.
+    _demux: Arc<DemuxTask>,
+    _msg_types: PhantomData<(S, R)>,
+}
+
+impl<N, S, R> MultiplexedNetwork<N, S, R>
+where
+    S: SchemaWrite<Src = S>,
+    R: for<'de> SchemaRead<'de, Dst = R>,
+{
+    fn frame(&self, msg: &S) -> MultiplexedFrame {
+        MultiplexedFrame::new(self.channel, wincode::serialize(msg).unwrap())
+    }
+
+    /// Counts sending `frame` to `copies` destinations towards the metrics.
+    fn count_sent(&self, frame: &MultiplexedFrame, copies: usize) {
+        let bytes = METRICS
+            .network_bytes_sent
+            .with_label_values(&[self.channel.name()]);
+        bytes.inc_by((frame.payload.len() * copies) as u64);
+    }
+
+    /// Receives the next message that deserializes correctly, with its sender if known.
+    async fn receive_with_sender(&self) -> std::io::Result<(Option<ValidatorId>, R)> {
+        let mut receiver = self.receiver.lock().await;
+        loop {
+            let Some((sender, payload)) = receiver.recv().await else {
+                return Err(std::io::Error::other("multiplexer stopped"));
+            };
+            match wincode::deserialize(&payload) {
+                Ok(msg) => return Ok((sender, msg)),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<N, S, R> Network for MultiplexedNetwork<N, S, R>
+where
+    N: Network<Send = MultiplexedFrame, Recv = MultiplexedFrame>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    type Recv = R;
+    type Send = S;
+
+    async fn send_to_many(
+        &self,
+        msg: &S,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let frame = self.frame(msg);
+        let addrs = addrs.collect::<Vec<_>>();
+        self.count_sent(&frame, addrs.len());
+        self.network.send_to_many(&frame, addrs.into_iter()).await
+    }
+
+    async fn send(&self, msg: &S, addr: SocketAddr) -> std::io::Result<()> {
+        let frame = self.frame(msg);
+        self.count_sent(&frame, 1);
+        self.network.send(&frame, addr).await
+    }
+
+    async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.receive_with_sender().await?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<N, S, R> AuthenticatedNetwork for MultiplexedNetwork<N, S, R>
+where
+    N: AuthenticatedNetwork<Send = MultiplexedFrame, Recv = MultiplexedFrame>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    /// Receives the next message, together with the ID of the validator that sent it.
+    ///
+    /// Returns an error if the [`Multiplexer`] was not created with [`Multiplexer::new_authenticated`].
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
+        match self.receive_with_sender().await? {
+            (Some(sender), msg) => Ok((sender, msg)),
+            (None, _) => Err(std::io::Error::other(
+                "multiplexer does not know the sender, use `Multiplexer::new_authenticated`",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::simulated::SimulatedNetworkCore;
+    use crate::network::{UdpNetwork, localhost_ip_sockaddr};
+    use crate::test_utils::{Ping, Pong};
+
+    #[tokio::test]
+    async fn demultiplex() {
+        let mux0 = Multiplexer::new(UdpNetwork::new_with_any_port());
+        let mux1 = Multiplexer::new(UdpNetwork::new_with_any_port());
+        let addr1 = localhost_ip_sockaddr(mux1.inner().port());
+
+        let pings0: MultiplexedNetwork<_, Ping, Ping> =
+            mux0.channel(Channel::Consensus, Channel::Consensus);
+        let pongs0: MultiplexedNetwork<_, Pong, Pong> =
+            mux0.channel(Channel::Shreds, Channel::Shreds);
+        let pings1: MultiplexedNetwork<_, Ping, Ping> =
+            mux1.channel(Channel::Consensus, Channel::Consensus);
+        let pongs1: MultiplexedNetwork<_, Pong, Pong> =
+            mux1.channel(Channel::Shreds, Channel::Shreds);
+
+        // both arrive on the same port, but are received by different sub-networks
+        pongs0.send(&Pong([2; 32]), addr1).await.unwrap();
+        pings0.send(&Ping([1; 32]), addr1).await.unwrap();
+        assert_eq!(pings1.receive().await.unwrap().0, [1; 32]);
+        assert_eq!(pongs1.receive().await.unwrap().0, [2; 32]);
+    }
+
+    #[tokio::test]
+    async fn request_response() {
+        let mux0 = Multiplexer::new(UdpNetwork::new_with_any_port());
+        let mux1 = Multiplexer::new(UdpNetwork::new_with_any_port());
+        let addr0 = localhost_ip_sockaddr(mux0.inner().port());
+        let addr1 = localhost_ip_sockaddr(mux1.inner().port());
+
+        let requester: MultiplexedNetwork<_, Ping, Pong> =
+            mux0.channel(Channel::RepairRequests, Channel::RepairResponses);
+        let responder: MultiplexedNetwork<_, Pong, Ping> =
+            mux1.channel(Channel::RepairResponses, Channel::RepairRequests);
+        // sub-networks keep working without their multiplexers
+        drop((mux0, mux1));
+
+        requester.send(&Ping([1; 32]), addr1).await.unwrap();
+        let request = responder.receive().await.unwrap();
+        responder.send(&Pong(request.0), addr0).await.unwrap();
+        assert_eq!(requester.receive().await.unwrap().0, [1; 32]);
+    }
+
+    #[tokio::test]
+    async fn authenticated() {
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        );
+        let mux0 = Multiplexer::new_authenticated(core.join_unlimited(0).await);
+        let mux1 = Multiplexer::new_authenticated(core.join_unlimited(1).await);
+        let net0: MultiplexedNetwork<_, Ping, Ping> =
+            mux0.channel(Channel::Consensus, Channel::Consensus);
+        let net1: MultiplexedNetwork<_, Ping, Ping> =
+            mux1.channel(Channel::Consensus, Channel::Consensus);
+
+        net0.send(&Ping::default(), localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let (sender, _) = net1.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_channel() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let mux = Multiplexer::new(UdpNetwork::new_with_any_port());
+        let _: MultiplexedNetwork<_, Ping, Ping> =
+            mux.channel(Channel::Consensus, Channel::Consensus);
+        let _: MultiplexedNetwork<_, Ping, Ping> = mux.channel(Channel::Shreds, Channel::Consensus);
+    }
+}
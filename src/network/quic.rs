@@ -0,0 +1,560 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! QUIC network interface.
+//!
+//! This module provides an implementation of the [`Network`] trait for QUIC.
+//! It uses [`quinn`] under the hood, with [`rustls`] providing the TLS 1.3 handshake.
+//!
+//! Instead of X.509 certificates, peers authenticate each other with raw public keys
+//! (see [RFC 7250]), namely the validators' Ed25519 identity keys.
+//! Connections are mutually authenticated, and only known validators are accepted,
+//! in both directions. Accordingly, [`QuicNetwork`] implements [`AuthenticatedNetwork`].
+//!
+//! Messages that fit into a single QUIC datagram (e.g. shreds and votes) are sent
+//! unreliably as datagrams. Larger messages (e.g. repair responses) are sent reliably
+//! on a fresh unidirectional stream each.
+//!
+//! [RFC 7250]: https://tools.ietf.org/html/rfc7250
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{debug, warn};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig, TransportConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, SubjectPublicKeyInfoDer,
+    UnixTime,
+};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use tokio::sync::{Mutex, mpsc};
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{AuthenticatedNetwork, Network};
+use crate::crypto::signature::{PublicKey, SecretKey};
+use crate::{ValidatorId, ValidatorInfo};
+
+/// ALPN protocol identifier negotiated on every connection.
+const ALPN_PROTOCOL: &[u8] = b"alpenglow";
+
+/// Server name used when dialing a peer.
+///
+/// Peers are identified by their public key, so this is never checked.
+const SERVER_NAME: &str = "alpenglow";
+
+/// Maximum number of bytes accepted for a single message received on a stream.
+const MAX_STREAM_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Number of received messages buffered before connection handlers apply backpressure.
+const RECEIVE_CHANNEL_SIZE: usize = 1024;
+
+/// Interval of keep-alive packets, which prevent idle connections from timing out.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// DER prefix of a PKCS#8 v1 `PrivateKeyInfo` holding an Ed25519 seed (RFC 8410).
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// DER prefix of a `SubjectPublicKeyInfo` holding an Ed25519 public key (RFC 8410).
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Map from DER encoded `SubjectPublicKeyInfo` to the validator owning that key.
+type Identities = HashMap<Vec<u8>, ValidatorId>;
+
+/// Implementation of network abstraction over QUIC connections.
+pub struct QuicNetwork<S, R> {
+    endpoint: Endpoint,
+    /// Identities of all validators we accept connections from.
+    identities: Arc<Identities>,
+    /// Established connections, indexed by the peer's address.
+    ///
+    /// Contains both connections we dialed and connections we accepted.
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    /// Receiver for messages read by the connection handlers, tagged with their sender.
+    receiver: Mutex<mpsc::Receiver<(ValidatorId, Vec<u8>)>>,
+    /// Sender handed to the handlers of connections we dial.
+    sender: mpsc::Sender<(ValidatorId, Vec<u8>)>,
+    _msg_types: PhantomData<(S, R)>,
+}
+
+impl<S, R> QuicNetwork<S, R> {
+    /// Creates a new `QuicNetwork` instance bound to the given `port`.
+    ///
+    /// The node authenticates itself with `secret_key`.
+    /// Only peers authenticating with the [`ValidatorInfo::pubkey`] of one of the
+    /// `validators` are accepted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the UDP `port` is already in use.
+    /// Panics if called outside of a Tokio runtime.
+    #[must_use]
+    pub fn new(port: u16, secret_key: &SecretKey, validators: &[ValidatorInfo]) -> Self {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let identity = Arc::new(certified_key(secret_key, &provider));
+        let identities: Arc<Identities> = Arc::new(
+            validators
+                .iter()
+                .map(|v| (subject_public_key_info(&v.pubkey).to_vec(), v.id))
+                .collect(),
+        );
+        let verifier = Arc::new(ValidatorKeyVerifier {
+            identities: identities.clone(),
+            algorithms: provider.signature_verification_algorithms,
+        });
+
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        let transport = Arc::new(transport);
+
+        let mut server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_client_cert_verifier(verifier.clone())
+            .with_cert_resolver(Arc::new(
+                rustls::server::AlwaysResolvesServerRawPublicKeys::new(identity.clone()),
+            ));
+        server_crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let server_crypto = QuicServerConfig::try_from(server_crypto).unwrap();
+        let mut server_config = ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport_config(transport.clone());
+
+        let mut client_crypto = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_client_cert_resolver(Arc::new(
+                rustls::client::AlwaysResolvesClientRawPublicKeys::new(identity),
+            ));
+        client_crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let client_crypto = QuicClientConfig::try_from(client_crypto).unwrap();
+        let mut client_config = ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport_config(transport);
+
+        let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+        let mut endpoint = Endpoint::server(server_config, addr.into()).unwrap();
+        endpoint.set_default_client_config(client_config);
+
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, receiver) = mpsc::channel(RECEIVE_CHANNEL_SIZE);
+        tokio::spawn(accept_loop(
+            endpoint.clone(),
+            identities.clone(),
+            connections.clone(),
+            sender.clone(),
+        ));
+
+        Self {
+            endpoint,
+            identities,
+            connections,
+            receiver: Mutex::new(receiver),
+            sender,
+            _msg_types: PhantomData,
+        }
+    }
+
+    /// Creates a new `QuicNetwork` instance bound to an arbitrary port.
+    ///
+    /// The port is assigned by the OS.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    #[must_use]
+    pub fn new_with_any_port(secret_key: &SecretKey, validators: &[ValidatorInfo]) -> Self {
+        Self::new(0, secret_key, validators)
+    }
+
+    /// Returns the UDP port number the network is bound to.
+    pub fn port(&self) -> u16 {
+        self.endpoint.local_addr().unwrap().port()
+    }
+
+    /// Returns an open connection to `addr`, establishing a new one if needed.
+    async fn connection(&self, addr: SocketAddr) -> std::io::Result<Connection> {
+        if let Some(conn) = self.connections.lock().await.get(&addr)
+            && conn.close_reason().is_none()
+        {
+            return Ok(conn.clone());
+        }
+        let conn = self
+            .endpoint
+            .connect(addr, SERVER_NAME)
+            .map_err(std::io::Error::other)?
+            .await?;
+        self.connections.lock().await.insert(addr, conn.clone());
+        tokio::spawn(handle_connection(
+            conn.clone(),
+            self.identities.clone(),
+            self.sender.clone(),
+        ));
+        Ok(conn)
+    }
+
+    async fn send_serialized(&self, bytes: &[u8], addr: SocketAddr) -> std::io::Result<()> {
+        let conn = self.connection(addr).await?;
+        if conn
+            .max_datagram_size()
+            .is_some_and(|max| bytes.len() <= max)
+        {
+            conn.send_datagram(bytes.to_vec().into())
+                .map_err(std::io::Error::other)?;
+        } else {
+            let mut stream = conn.open_uni().await?;
+            stream.write_all(bytes).await?;
+            stream.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<S, R> Drop for QuicNetwork<S, R> {
+    fn drop(&mut self) {
+        // stops the accept loop and all connection handlers
+        self.endpoint.close(0u32.into(), b"");
+    }
+}
+
+#[async_trait]
+impl<S, R> Network for QuicNetwork<S, R>
+where
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    type Recv = R;
+    type Send = S;
+
+    async fn send_to_many(
+        &self,
+        msg: &S,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let bytes = &wincode::serialize(msg).unwrap();
+        let tasks = addrs.map(async move |addr| self.send_serialized(bytes, addr).await);
+        for res in join_all(tasks).await {
+            let () = res?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, msg: &Self::Send, addr: SocketAddr) -> std::io::Result<()> {
+        let bytes = &wincode::serialize(msg).unwrap();
+        self.send_serialized(bytes, addr).await
+    }
+
+    async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.receive_from().await?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<S, R> AuthenticatedNetwork for QuicNetwork<S, R>
+where
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
+        let mut receiver = self.receiver.lock().await;
+        loop {
+            let Some((sender, bytes)) = receiver.recv().await else {
+                return Err(std::io::ErrorKind::ConnectionAborted.into());
+            };
+            match wincode::deserialize(&bytes) {
+                Ok(msg) => return Ok((sender, msg)),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+    }
+}
+
+/// Accepts incoming connections until the `endpoint` is closed.
+async fn accept_loop(
+    endpoint: Endpoint,
+    identities: Arc<Identities>,
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    sender: mpsc::Sender<(ValidatorId, Vec<u8>)>,
+) {
+    while let Some(incoming) = endpoint.accept().await {
+        let identities = identities.clone();
+        let connections = connections.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let conn = match incoming.await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    debug!("incoming QUIC connection failed: {err}");
+                    return;
+                }
+            };
+            // also use this connection for sending to the peer
+            let addr = conn.remote_address();
+            connections.lock().await.insert(addr, conn.clone());
+            handle_connection(conn, identities, sender).await;
+        });
+    }
+}
+
+/// Forwards all datagrams and streams received on `conn` to `sender`.
+///
+/// Each message is tagged with the ID of the validator that authenticated the connection.
+/// Returns when the connection is closed or the receiving side is dropped.
+async fn handle_connection(
+    conn: Connection,
+    identities: Arc<Identities>,
+    sender: mpsc::Sender<(ValidatorId, Vec<u8>)>,
+) {
+    // the handshake only succeeds for known validators, so this always resolves
+    let Some(peer) = peer_id(&conn, &identities) else {
+        warn!("QUIC connection from unknown peer");
+        conn.close(0u32.into(), b"");
+        return;
+    };
+    loop {
+        let bytes = tokio::select! {
+            res = conn.read_datagram() => match res {
+                Ok(datagram) => datagram.to_vec(),
+                Err(err) => {
+                    debug!("QUIC connection closed: {err}");
+                    return;
+                }
+            },
+            res = conn.accept_uni() => match res {
+                Ok(mut stream) => {
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        match stream.read_to_end(MAX_STREAM_MESSAGE_BYTES).await {
+                            Ok(bytes) => {
+                                let _ = sender.send((peer, bytes)).await;
+                            }
+                            Err(err) => warn!("reading QUIC stream failed: {err}"),
+                        }
+                    });
+                    continue;
+                }
+                Err(err) => {
+                    debug!("QUIC connection closed: {err}");
+                    return;
+                }
+            },
+        };
+        if sender.send((peer, bytes)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Returns the ID of the validator that authenticated `conn`.
+fn peer_id(conn: &Connection, identities: &Identities) -> Option<ValidatorId> {
+    let certs = conn
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    identities.get(certs.first()?.as_ref()).copied()
+}
+
+/// Wraps the Ed25519 `secret_key` into a raw public key identity for TLS.
+fn certified_key(secret_key: &SecretKey, provider: &CryptoProvider) -> CertifiedKey {
+    let mut pkcs8 = ED25519_PKCS8_PREFIX.to_vec();
+    pkcs8.extend_from_slice(secret_key.as_bytes());
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8));
+    let signing_key = provider.key_provider.load_private_key(key_der).unwrap();
+    let spki = subject_public_key_info(&secret_key.to_pk());
+    CertifiedKey::new(vec![CertificateDer::from(spki.to_vec())], signing_key)
+}
+
+/// Encodes the Ed25519 public key `pk` as DER `SubjectPublicKeyInfo`.
+fn subject_public_key_info(pk: &PublicKey) -> SubjectPublicKeyInfoDer<'static> {
+    let mut spki = ED25519_SPKI_PREFIX.to_vec();
+    spki.extend_from_slice(pk.as_bytes());
+    SubjectPublicKeyInfoDer::from(spki)
+}
+
+/// Verifies that a peer presents one of the known validator public keys.
+///
+/// Acts as verifier for both, the server (when dialing) and the client (when accepting).
+#[derive(Debug)]
+struct ValidatorKeyVerifier {
+    /// Identities of all acceptable peers.
+    identities: Arc<Identities>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ValidatorKeyVerifier {
+    fn verify_peer(&self, end_entity: &CertificateDer<'_>) -> Result<(), rustls::Error> {
+        if self.identities.contains_key(end_entity.as_ref()) {
+            Ok(())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let spki = SubjectPublicKeyInfoDer::from(cert.as_ref());
+        rustls::crypto::verify_tls13_signature_with_raw_key(message, &spki, dss, &self.algorithms)
+    }
+}
+
+impl ServerCertVerifier for ValidatorKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verify_peer(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls13RequiredForQuic,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+impl ClientCertVerifier for ValidatorKeyVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.verify_peer(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls13RequiredForQuic,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::localhost_ip_sockaddr;
+    use crate::test_utils::{Ping, Pong, generate_validators_with_keys};
+
+    #[tokio::test]
+    async fn ping_pong() {
+        let (sks, _, validators) = generate_validators_with_keys(2);
+        let network1 = QuicNetwork::new_with_any_port(&sks[0], &validators);
+        let network2 = QuicNetwork::new_with_any_port(&sks[1], &validators);
+        let addr1 = localhost_ip_sockaddr(network1.port());
+        let addr2 = localhost_ip_sockaddr(network2.port());
+
+        network1.send(&Ping::default(), addr2).await.unwrap();
+        let (sender, msg): (_, Ping) = network2.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(msg.0, Ping::default().0);
+        network2.send(&Pong(msg.0), addr1).await.unwrap();
+        let (sender, msg): (_, Pong) = network1.receive_from().await.unwrap();
+        assert_eq!(sender, 1);
+        assert_eq!(msg.0, Ping::default().0);
+    }
+
+    #[tokio::test]
+    async fn large_message() {
+        let (sks, _, validators) = generate_validators_with_keys(2);
+        let network1: QuicNetwork<Vec<u8>, Vec<u8>> =
+            QuicNetwork::new_with_any_port(&sks[0], &validators);
+        let network2: QuicNetwork<Vec<u8>, Vec<u8>> =
+            QuicNetwork::new_with_any_port(&sks[1], &validators);
+        let addr2 = localhost_ip_sockaddr(network2.port());
+
+        // too large for a datagram, has to go over a stream
+        let msg = vec![42; 100_000];
+        network1.send(&msg, addr2).await.unwrap();
+        let (sender, received) = network2.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(received, msg);
+    }
+
+    #[tokio::test]
+    async fn unknown_peer() {
+        let (sks, _, validators) = generate_validators_with_keys(2);
+        let network1: QuicNetwork<Ping, Ping> =
+            QuicNetwork::new_with_any_port(&sks[0], &validators);
+        let (outsider_sks, _, outsiders) = generate_validators_with_keys(1);
+        let outsider: QuicNetwork<Ping, Ping> =
+            QuicNetwork::new_with_any_port(&outsider_sks[0], &outsiders);
+        let addr1 = localhost_ip_sockaddr(network1.port());
+
+        // handshake fails, since neither side accepts the other's key
+        assert!(outsider.send(&Ping::default(), addr1).await.is_err());
+    }
+}
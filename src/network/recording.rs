@@ -0,0 +1,507 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording and deterministic replay of network traffic.
+//!
+//! This module provides [`RecordingNetwork`], which wraps any [`Network`] and logs
+//! every message it sends or receives to a trace file via a [`TraceRecorder`].
+//! The recorder is shared by all networks of a node. Each network is labeled
+//! with the [`Channel`] it carries, same as for multiplexing.
+//!
+//! [`TraceReplayer`] reads such a trace and hands out a [`ReplayNetwork`] per channel.
+//! These return exactly the recorded incoming messages, at their recorded times
+//! relative to the start of the replay, and discard all outgoing messages.
+//! Plugging them into a single node reproduces that node's input offline.
+//!
+//! A trace file is a sequence of [`TraceRecord`]s, each serialized with `wincode`
+//! and prefixed by its length as a little-endian `u32`.
+//! The file is written by a background thread, so recording never blocks the node.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use async_trait::async_trait;
+use log::warn;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{AuthenticatedNetwork, Channel, Network};
+use crate::ValidatorId;
+
+/// Whether a [`TraceRecord`] holds a sent or a received message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SchemaRead, SchemaWrite)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A single message in a trace.
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
+pub struct TraceRecord {
+    /// Time since the start of the recording, in microseconds.
+    pub time_micros: u64,
+    /// The [`Channel`] of the network the message was sent or received on.
+    pub channel: u8,
+    pub direction: Direction,
+    /// Destination addresses of a sent message.
+    pub to: Vec<String>,
+    /// Sender of a received message, if known.
+    pub from: Option<ValidatorId>,
+    /// Serialized message.
+    pub payload: Vec<u8>,
+}
+
+/// Writes the traffic of [`RecordingNetwork`]s to a trace file.
+///
+/// Dropping the recorder waits until all records are written.
+pub struct TraceRecorder {
+    start: Instant,
+    writer: Option<TraceWriter>,
+}
+
+/// Handle to the background thread writing to the trace file.
+struct TraceWriter {
+    /// Length-prefixed serialized records to append to the file.
+    records: Sender<Vec<u8>>,
+    thread: JoinHandle<()>,
+}
+
+impl TraceRecorder {
+    /// Creates a new recorder, writing to a new file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let (records, receiver) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("trace-writer".to_string())
+            .spawn(move || Self::write_records(file, &receiver))?;
+        Ok(Self {
+            start: Instant::now(),
+            writer: Some(TraceWriter { records, thread }),
+        })
+    }
+
+    /// Creates a recorder that does not record anything.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            start: Instant::now(),
+            writer: None,
+        }
+    }
+
+    /// Returns `true` iff this recorder writes to a file.
+    #[must_use]
+    pub const fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Appends a record to the trace.
+    ///
+    /// The record is only queued here, it is written by the background thread.
+    fn record(
+        &self,
+        channel: Channel,
+        direction: Direction,
+        to: Vec<String>,
+        from: Option<ValidatorId>,
+        payload: Vec<u8>,
+    ) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let record = TraceRecord {
+            time_micros: self.start.elapsed().as_micros() as u64,
+            channel: channel as u8,
+            direction,
+            to,
+            from,
+            payload,
+        };
+        let len = wincode::serialized_size(&record).unwrap() as u32;
+        let mut bytes = len.to_le_bytes().to_vec();
+        bytes.extend(wincode::serialize(&record).unwrap());
+        if writer.records.send(bytes).is_err() {
+            warn!("trace writer stopped, dropping record");
+        }
+    }
+
+    /// Appends all `records` to `file`, until the recorder is dropped.
+    ///
+    /// The file is flushed whenever no more records are queued,
+    /// so the trace survives a crash of the node up to the last few records.
+    fn write_records(mut file: BufWriter<File>, records: &Receiver<Vec<u8>>) {
+        while let Ok(bytes) = records.recv() {
+            let res = std::iter::once(bytes)
+                .chain(records.try_iter())
+                .try_for_each(|bytes| file.write_all(&bytes))
+                .and_then(|()| file.flush());
+            if let Err(err) = res {
+                warn!("writing trace failed with {err:?}");
+            }
+        }
+    }
+}
+
+impl Drop for TraceRecorder {
+    fn drop(&mut self) {
+        if let Some(TraceWriter { records, thread }) = self.writer.take() {
+            // closing the channel lets the thread finish writing and exit
+            drop(records);
+            if thread.join().is_err() {
+                warn!("trace writer panicked");
+            }
+        }
+    }
+}
+
+/// Reads all records from the trace file at `path`.
+///
+/// A truncated last record, e.g. from a node that crashed while writing it, is ignored.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains an invalid record.
+pub fn read_trace(path: impl AsRef<Path>) -> std::io::Result<Vec<TraceRecord>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    loop {
+        let mut len = [0; 4];
+        let mut bytes = Vec::new();
+        let res = reader.read_exact(&mut len).and_then(|()| {
+            bytes.resize(u32::from_le_bytes(len) as usize, 0);
+            reader.read_exact(&mut bytes)
+        });
+        match res {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(records),
+            Err(err) => return Err(err),
+        }
+        let record = wincode::deserialize(&bytes)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, format!("{err:?}")))?;
+        records.push(record);
+    }
+}
+
+/// Network wrapper that records all sent and received messages.
+pub struct RecordingNetwork<N> {
+    network: N,
+    recorder: Arc<TraceRecorder>,
+    channel: Channel,
+}
+
+impl<N> RecordingNetwork<N> {
+    /// Creates a new `RecordingNetwork` on top of the given `network`.
+    ///
+    /// Messages are recorded with `recorder`, labeled with `channel`.
+    pub const fn new(network: N, recorder: Arc<TraceRecorder>, channel: Channel) -> Self {
+        Self {
+            network,
+            recorder,
+            channel,
+        }
+    }
+
+    /// Returns a reference to the underlying network.
+    pub const fn inner(&self) -> &N {
+        &self.network
+    }
+}
+
+impl<N: Network> RecordingNetwork<N> {
+    fn record_sent(&self, msg: &N::Send, to: &[SocketAddr])
+    where
+        N::Send: SchemaWrite<Src = N::Send>,
+    {
+        if self.recorder.is_enabled() {
+            let to = to.iter().map(ToString::to_string).collect();
+            let payload = wincode::serialize(msg).unwrap();
+            self.recorder
+                .record(self.channel, Direction::Sent, to, None, payload);
+        }
+    }
+
+    fn record_received(&self, msg: &N::Recv, from: Option<ValidatorId>)
+    where
+        N::Recv: SchemaWrite<Src = N::Recv>,
+    {
+        if self.recorder.is_enabled() {
+            let payload = wincode::serialize(msg).unwrap();
+            self.recorder
+                .record(self.channel, Direction::Received, Vec::new(), from, payload);
+        }
+    }
+}
+
+#[async_trait]
+impl<N> Network for RecordingNetwork<N>
+where
+    N: Network,
+    N::Send: SchemaWrite<Src = N::Send> + Sync,
+    N::Recv: SchemaWrite<Src = N::Recv> + Send,
+{
+    type Recv = N::Recv;
+    type Send = N::Send;
+
+    async fn send_to_many(
+        &self,
+        msg: &N::Send,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let addrs = addrs.collect::<Vec<_>>();
+        self.record_sent(msg, &addrs);
+        self.network.send_to_many(msg, addrs.into_iter()).await
+    }
+
+    async fn send(&self, msg: &N::Send, addr: SocketAddr) -> std::io::Result<()> {
+        self.record_sent(msg, &[addr]);
+        self.network.send(msg, addr).await
+    }
+
+    async fn receive(&self) -> std::io::Result<N::Recv> {
+        let msg = self.network.receive().await?;
+        self.record_received(&msg, None);
+        Ok(msg)
+    }
+
+    async fn receive_batch(&self, max: usize) -> std::io::Result<Vec<N::Recv>> {
+        let msgs = self.network.receive_batch(max).await?;
+        for msg in &msgs {
+            self.record_received(msg, None);
+        }
+        Ok(msgs)
+    }
+}
+
+#[async_trait]
+impl<N> AuthenticatedNetwork for RecordingNetwork<N>
+where
+    N: AuthenticatedNetwork,
+    N::Send: SchemaWrite<Src = N::Send> + Sync,
+    N::Recv: SchemaWrite<Src = N::Recv> + Send,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, N::Recv)> {
+        let (sender, msg) = self.network.receive_from().await?;
+        self.record_received(&msg, Some(sender));
+        Ok((sender, msg))
+    }
+}
+
+/// Replays the received messages of a trace.
+pub struct TraceReplayer {
+    start: Instant,
+    records: Vec<TraceRecord>,
+}
+
+impl TraceReplayer {
+    /// Creates a new replayer for the trace file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the trace cannot be read, see [`read_trace`].
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(read_trace(path)?))
+    }
+
+    /// Creates a new replayer for the given `records`.
+    ///
+    /// Recorded times are replayed relative to the creation of the replayer.
+    #[must_use]
+    pub fn new(records: Vec<TraceRecord>) -> Self {
+        Self {
+            start: Instant::now(),
+            records,
+        }
+    }
+
+    /// Returns all records of the trace.
+    #[must_use]
+    pub fn records(&self) -> &[TraceRecord] {
+        &self.records
+    }
+
+    /// Creates a network replaying the messages received on the given `channel`.
+    #[must_use]
+    pub fn channel<S, R>(&self, channel: Channel) -> ReplayNetwork<S, R> {
+        let records = self
+            .records
+            .iter()
+            .filter(|r| r.direction == Direction::Received && r.channel == channel as u8)
+            .cloned()
+            .collect();
+        ReplayNetwork {
+            start: self.start,
+            records: Mutex::new(records),
+            _msg_types: PhantomData,
+        }
+    }
+}
+
+/// Network that replays recorded incoming messages of a single channel.
+///
+/// Outgoing messages are discarded.
+/// After all recorded messages are returned, receiving never completes.
+pub struct ReplayNetwork<S, R> {
+    start: Instant,
+    /// Received messages that have not been replayed yet.
+    records: Mutex<VecDeque<TraceRecord>>,
+    _msg_types: PhantomData<(S, R)>,
+}
+
+impl<S, R> ReplayNetwork<S, R>
+where
+    R: for<'de> SchemaRead<'de, Dst = R>,
+{
+    /// Waits for the next recorded message to be due and returns it, with its sender if known.
+    async fn replay_next(&self) -> (Option<ValidatorId>, R) {
+        let mut records = self.records.lock().await;
+        loop {
+            let Some(record) = records.pop_front() else {
+                drop(records);
+                return std::future::pending().await;
+            };
+            tokio::time::sleep_until(self.start + Duration::from_micros(record.time_micros)).await;
+            match wincode::deserialize(&record.payload) {
+                Ok(msg) => return (record.from, msg),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S, R> Network for ReplayNetwork<S, R>
+where
+    S: Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    type Recv = R;
+    type Send = S;
+
+    async fn send_to_many(
+        &self,
+        _msg: &S,
+        _addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn send(&self, _msg: &S, _addr: SocketAddr) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.replay_next().await;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<S, R> AuthenticatedNetwork for ReplayNetwork<S, R>
+where
+    S: Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    /// Replays the next message, together with its recorded sender.
+    ///
+    /// Returns an error if the sender was not recorded.
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
+        match self.replay_next().await {
+            (Some(sender), msg) => Ok((sender, msg)),
+            (None, _) => Err(std::io::Error::other("sender was not recorded")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::simulated::SimulatedNetworkCore;
+    use crate::network::{SimulatedNetwork, localhost_ip_sockaddr};
+    use crate::test_utils::Ping;
+
+    fn trace_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("alpenglow-{name}-{}.trace", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let path = trace_path("record-and-replay");
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        );
+        let net0: SimulatedNetwork<Ping, Ping> = core.join_unlimited(0).await;
+        let recorder = Arc::new(TraceRecorder::create(&path).unwrap());
+        let net1: RecordingNetwork<SimulatedNetwork<Ping, Ping>> = RecordingNetwork::new(
+            core.join_unlimited(1).await,
+            Arc::clone(&recorder),
+            Channel::Consensus,
+        );
+
+        for i in 0..3 {
+            net0.send(&Ping([i; 32]), localhost_ip_sockaddr(1))
+                .await
+                .unwrap();
+            let (_, msg) = net1.receive_from().await.unwrap();
+            net1.send(&msg, localhost_ip_sockaddr(0)).await.unwrap();
+        }
+        // waits for all records to be written
+        drop(net1);
+        drop(recorder);
+
+        let replayer = TraceReplayer::open(&path).unwrap();
+        assert_eq!(replayer.records().len(), 6);
+        let mut sent = replayer
+            .records()
+            .iter()
+            .filter(|r| r.direction == Direction::Sent);
+        assert!(sent.all(|r| r.to == [localhost_ip_sockaddr(0).to_string()]));
+
+        // only received messages of the matching channel are replayed
+        let other: ReplayNetwork<Ping, Ping> = replayer.channel(Channel::Shreds);
+        assert!(other.records.lock().await.is_empty());
+        let replay: ReplayNetwork<Ping, Ping> = replayer.channel(Channel::Consensus);
+        for i in 0..3 {
+            let (sender, msg) = replay.receive_from().await.unwrap();
+            assert_eq!(sender, 0);
+            assert_eq!(msg.0, [i; 32]);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_trace() {
+        let path = trace_path("truncated");
+        let recorder = TraceRecorder::create(&path).unwrap();
+        let payload = wincode::serialize(&Ping::default()).unwrap();
+        recorder.record(Channel::Shreds, Direction::Received, vec![], None, payload);
+        drop(recorder);
+
+        // simulate crash during writing of the second record
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&100_u32.to_le_bytes()).unwrap();
+        file.write_all(&[0; 10]).unwrap();
+        drop(file);
+
+        let records = read_trace(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].channel, Channel::Shreds as u8);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,520 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Prioritized scheduling of outgoing traffic.
+//!
+//! This module provides [`EgressScheduler`], which is shared by all networks of a node,
+//! and [`ScheduledNetwork`], which wraps any [`Network`] to pass its outgoing messages
+//! through the scheduler. Each outgoing message is assigned a [`MessageClass`].
+//! Before it is sent, the scheduler has to grant it its share of the link.
+//!
+//! Classes are served by deficit round robin, visiting them in order of priority:
+//! certificates > votes > leader shreds > relayed shreds > repair > transactions.
+//! Whenever multiple classes are backlogged, each receives link capacity in
+//! proportion to its weight. Optionally, each class has a budget of bytes that
+//! may be queued at once. Messages exceeding their class's budget are dropped.
+//!
+//! Prioritization only has an effect if the scheduler's link rate is the bottleneck.
+//! It should thus be set at or slightly below the actual link capacity, e.g.
+//! the upload bandwidth of a [`SimulatedNetwork`](super::SimulatedNetwork).
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::sync::{Notify, oneshot};
+use tokio::task::JoinHandle;
+use wincode::SchemaWrite;
+
+use super::simulated::token_bucket::TokenBucket;
+use super::{AuthenticatedNetwork, MTU_BYTES, Network};
+use crate::ValidatorId;
+use crate::consensus::ConsensusMessage;
+
+/// Maximum number of destinations of a `send_to_many` covered by a single grant.
+///
+/// This bounds how long a large broadcast can block higher-priority messages.
+const MAX_DESTINATIONS_PER_GRANT: usize = 16;
+
+/// Number of different [`MessageClass`]es.
+const NUM_CLASSES: usize = MessageClass::ALL.len();
+
+/// Class of an outgoing message, which determines its priority.
+///
+/// Classes are ordered from highest to lowest priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MessageClass {
+    Certificate,
+    Vote,
+    /// Shreds sent by the leader of the slot.
+    LeaderShred,
+    /// Shreds forwarded by relays.
+    RelayedShred,
+    Repair,
+    Transaction,
+}
+
+impl MessageClass {
+    /// All classes, ordered from highest to lowest priority.
+    pub const ALL: [Self; 6] = [
+        Self::Certificate,
+        Self::Vote,
+        Self::LeaderShred,
+        Self::RelayedShred,
+        Self::Repair,
+        Self::Transaction,
+    ];
+
+    /// Returns the class of the given consensus message.
+    #[must_use]
+    pub const fn of_consensus_message(msg: &ConsensusMessage) -> Self {
+        match msg {
+            ConsensusMessage::Vote(_) => Self::Vote,
+            ConsensusMessage::Cert(_) => Self::Certificate,
+        }
+    }
+
+    /// Returns the weight of this class in the default [`EgressConfig`].
+    #[must_use]
+    pub const fn default_weight(self) -> usize {
+        match self {
+            Self::Certificate => 32,
+            Self::Vote => 16,
+            Self::LeaderShred => 8,
+            Self::RelayedShred => 4,
+            Self::Repair => 2,
+            Self::Transaction => 1,
+        }
+    }
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Configuration of an [`EgressScheduler`].
+#[derive(Clone, Debug)]
+pub struct EgressConfig {
+    /// Maximum outgoing rate in bytes per second, unlimited if `None`.
+    link_rate: Option<usize>,
+    /// Relative share of the link for each class.
+    weights: [usize; NUM_CLASSES],
+    /// Maximum number of bytes queued for each class, unlimited if `None`.
+    budgets: [Option<usize>; NUM_CLASSES],
+}
+
+impl Default for EgressConfig {
+    fn default() -> Self {
+        Self {
+            link_rate: None,
+            weights: MessageClass::ALL.map(MessageClass::default_weight),
+            budgets: [None; NUM_CLASSES],
+        }
+    }
+}
+
+impl EgressConfig {
+    /// Limits the outgoing rate to `bytes_per_sec`.
+    #[must_use]
+    pub const fn with_link_rate(mut self, bytes_per_sec: usize) -> Self {
+        self.link_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Sets the weight of the given `class`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is zero.
+    #[must_use]
+    pub fn with_weight(mut self, class: MessageClass, weight: usize) -> Self {
+        assert!(weight > 0, "weight must be positive");
+        self.weights[class.index()] = weight;
+        self
+    }
+
+    /// Limits the number of bytes that may be queued for the given `class`.
+    #[must_use]
+    pub const fn with_budget(mut self, class: MessageClass, bytes: usize) -> Self {
+        self.budgets[class.index()] = Some(bytes);
+        self
+    }
+}
+
+/// Metrics for a single [`MessageClass`] of an [`EgressScheduler`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassMetrics {
+    /// Number of messages that were granted to be sent.
+    pub sent_messages: u64,
+    /// Number of bytes that were granted to be sent.
+    pub sent_bytes: u64,
+    /// Number of messages dropped because the class exceeded its budget.
+    pub dropped_messages: u64,
+    /// Number of bytes dropped because the class exceeded its budget.
+    pub dropped_bytes: u64,
+    /// Number of bytes currently waiting to be sent.
+    pub queued_bytes: usize,
+}
+
+/// Egress scheduler shared across all networks of a node.
+pub struct EgressScheduler {
+    shared: Arc<Shared>,
+    dispatcher: JoinHandle<()>,
+}
+
+struct Shared {
+    config: EgressConfig,
+    state: Mutex<State>,
+    /// Wakes up the dispatcher whenever a new request is queued.
+    notify: Notify,
+}
+
+/// Request to send `bytes` bytes, waiting for `grant`.
+struct Request {
+    bytes: usize,
+    grant: oneshot::Sender<()>,
+}
+
+/// Deficit round robin state.
+struct State {
+    queues: [VecDeque<Request>; NUM_CLASSES],
+    /// Bytes each class may still send in the current round.
+    deficits: [usize; NUM_CLASSES],
+    /// Bytes each class may send per round.
+    quanta: [usize; NUM_CLASSES],
+    /// Index of the class currently being served.
+    current: usize,
+    /// Whether the current class already received its quantum this round.
+    topped_up: bool,
+    metrics: [ClassMetrics; NUM_CLASSES],
+}
+
+impl EgressScheduler {
+    /// Creates a new scheduler with the given `config`.
+    ///
+    /// Spawns a background task that grants the queued requests.
+    #[must_use]
+    pub fn new(config: EgressConfig) -> Self {
+        let state = State::new(config.weights.map(|weight| weight * MTU_BYTES));
+        let shared = Arc::new(Shared {
+            config,
+            state: Mutex::new(state),
+            notify: Notify::new(),
+        });
+        let dispatcher = tokio::spawn(Self::dispatch(Arc::clone(&shared)));
+        Self { shared, dispatcher }
+    }
+
+    /// Waits until sending `bytes` bytes of the given `class` is granted.
+    ///
+    /// Returns `false` if the class exceeded its budget and the message should be dropped.
+    pub async fn acquire(&self, class: MessageClass, bytes: usize) -> bool {
+        let budget = self.shared.config.budgets[class.index()];
+        let grant = self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .enqueue(class, bytes, budget);
+        let Some(grant) = grant else {
+            return false;
+        };
+        self.shared.notify.notify_one();
+        grant.await.is_ok()
+    }
+
+    /// Returns the current metrics for the given `class`.
+    #[must_use]
+    pub fn metrics(&self, class: MessageClass) -> ClassMetrics {
+        self.shared.state.lock().unwrap().metrics[class.index()]
+    }
+
+    async fn dispatch(shared: Arc<Shared>) {
+        let mut limiter = shared.config.link_rate.map(TokenBucket::new);
+        loop {
+            let next = shared.state.lock().unwrap().next();
+            let Some((class, request)) = next else {
+                shared.notify.notified().await;
+                continue;
+            };
+            if let Some(limiter) = &mut limiter {
+                limiter.wait_for(request.bytes).await;
+            }
+            let mut state = shared.state.lock().unwrap();
+            let metrics = &mut state.metrics[class];
+            metrics.queued_bytes -= request.bytes;
+            metrics.sent_messages += 1;
+            metrics.sent_bytes += request.bytes as u64;
+            drop(state);
+            // requester may have given up waiting, that is fine
+            let _ = request.grant.send(());
+        }
+    }
+}
+
+impl Drop for EgressScheduler {
+    fn drop(&mut self) {
+        self.dispatcher.abort();
+    }
+}
+
+impl State {
+    fn new(quanta: [usize; NUM_CLASSES]) -> Self {
+        Self {
+            queues: Default::default(),
+            deficits: [0; NUM_CLASSES],
+            quanta,
+            current: 0,
+            topped_up: false,
+            metrics: [ClassMetrics::default(); NUM_CLASSES],
+        }
+    }
+
+    /// Queues a request, unless it would exceed the class's `budget`.
+    fn enqueue(
+        &mut self,
+        class: MessageClass,
+        bytes: usize,
+        budget: Option<usize>,
+    ) -> Option<oneshot::Receiver<()>> {
+        let metrics = &mut self.metrics[class.index()];
+        if budget.is_some_and(|budget| metrics.queued_bytes + bytes > budget) {
+            metrics.dropped_messages += 1;
+            metrics.dropped_bytes += bytes as u64;
+            return None;
+        }
+        metrics.queued_bytes += bytes;
+        let (grant, receiver) = oneshot::channel();
+        self.queues[class.index()].push_back(Request { bytes, grant });
+        Some(receiver)
+    }
+
+    /// Dequeues the next request to grant, together with its class index.
+    fn next(&mut self) -> Option<(usize, Request)> {
+        if self.queues.iter().all(VecDeque::is_empty) {
+            return None;
+        }
+        loop {
+            let class = self.current;
+            let Some(head) = self.queues[class].front() else {
+                self.deficits[class] = 0;
+                self.advance();
+                continue;
+            };
+            if self.deficits[class] >= head.bytes {
+                self.deficits[class] -= head.bytes;
+                return self.queues[class].pop_front().map(|r| (class, r));
+            }
+            if self.topped_up {
+                self.advance();
+            } else {
+                self.deficits[class] += self.quanta[class];
+                self.topped_up = true;
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.current = (self.current + 1) % NUM_CLASSES;
+        self.topped_up = false;
+    }
+}
+
+/// Network wrapper that schedules all outgoing messages with an [`EgressScheduler`].
+///
+/// The class of each message is determined by the `classify` function.
+/// Messages dropped by the scheduler are not reported as errors.
+pub struct ScheduledNetwork<N, F> {
+    network: N,
+    scheduler: Arc<EgressScheduler>,
+    classify: F,
+}
+
+impl<N, F> ScheduledNetwork<N, F>
+where
+    N: Network,
+    F: Fn(&N::Send) -> MessageClass + Send + Sync,
+{
+    /// Creates a new `ScheduledNetwork` on top of the given `network`.
+    ///
+    /// Outgoing messages are classified by `classify` and scheduled with `scheduler`.
+    pub const fn new(network: N, scheduler: Arc<EgressScheduler>, classify: F) -> Self {
+        Self {
+            network,
+            scheduler,
+            classify,
+        }
+    }
+
+    /// Returns a reference to the underlying network.
+    pub const fn inner(&self) -> &N {
+        &self.network
+    }
+}
+
+#[async_trait]
+impl<N, F> Network for ScheduledNetwork<N, F>
+where
+    N: Network,
+    N::Send: SchemaWrite<Src = N::Send> + Sync,
+    F: Fn(&N::Send) -> MessageClass + Send + Sync,
+{
+    type Recv = N::Recv;
+    type Send = N::Send;
+
+    async fn send_to_many(
+        &self,
+        msg: &N::Send,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let class = (self.classify)(msg);
+        let bytes = wincode::serialized_size(msg).unwrap() as usize;
+        let addrs = addrs.collect::<Vec<_>>();
+        for chunk in addrs.chunks(MAX_DESTINATIONS_PER_GRANT) {
+            if self.scheduler.acquire(class, bytes * chunk.len()).await {
+                self.network
+                    .send_to_many(msg, chunk.iter().copied())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&self, msg: &N::Send, addr: SocketAddr) -> std::io::Result<()> {
+        let class = (self.classify)(msg);
+        let bytes = wincode::serialized_size(msg).unwrap() as usize;
+        if self.scheduler.acquire(class, bytes).await {
+            self.network.send(msg, addr).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> std::io::Result<N::Recv> {
+        self.network.receive().await
+    }
+
+    async fn receive_batch(&self, max: usize) -> std::io::Result<Vec<N::Recv>> {
+        self.network.receive_batch(max).await
+    }
+}
+
+#[async_trait]
+impl<N, F> AuthenticatedNetwork for ScheduledNetwork<N, F>
+where
+    N: AuthenticatedNetwork,
+    N::Send: SchemaWrite<Src = N::Send> + Sync,
+    F: Fn(&N::Send) -> MessageClass + Send + Sync,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, N::Recv)> {
+        self.network.receive_from().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::simulated::SimulatedNetworkCore;
+    use crate::network::{SimulatedNetwork, localhost_ip_sockaddr};
+    use crate::test_utils::Ping;
+
+    #[test]
+    fn weighted_round_robin() {
+        let config = EgressConfig::default()
+            .with_weight(MessageClass::Certificate, 2)
+            .with_weight(MessageClass::Transaction, 1);
+        let mut state = State::new(config.weights.map(|weight| weight * MTU_BYTES));
+        for _ in 0..4 {
+            state.enqueue(MessageClass::Transaction, MTU_BYTES, None);
+        }
+        for _ in 0..4 {
+            state.enqueue(MessageClass::Certificate, MTU_BYTES, None);
+        }
+
+        let mut order = Vec::new();
+        while let Some((class, _)) = state.next() {
+            order.push(MessageClass::ALL[class]);
+        }
+        let (c, t) = (MessageClass::Certificate, MessageClass::Transaction);
+        assert_eq!(order, [c, c, t, c, c, t, t, t]);
+    }
+
+    #[test]
+    fn budget() {
+        let mut state = State::new([MTU_BYTES; NUM_CLASSES]);
+        let budget = Some(2 * MTU_BYTES);
+        let class = MessageClass::Repair;
+        assert!(state.enqueue(class, MTU_BYTES, budget).is_some());
+        assert!(state.enqueue(class, MTU_BYTES, budget).is_some());
+        assert!(state.enqueue(class, MTU_BYTES, budget).is_none());
+        // other classes are not affected
+        assert!(
+            state
+                .enqueue(MessageClass::Vote, MTU_BYTES, budget)
+                .is_some()
+        );
+
+        let metrics = state.metrics[class.index()];
+        assert_eq!(metrics.queued_bytes, 2 * MTU_BYTES);
+        assert_eq!(metrics.dropped_messages, 1);
+        assert_eq!(metrics.dropped_bytes, MTU_BYTES as u64);
+    }
+
+    #[tokio::test]
+    async fn priority_under_contention() {
+        // link fits roughly one message every 10 ms
+        let config = EgressConfig::default().with_link_rate(100 * MTU_BYTES);
+        let scheduler = Arc::new(EgressScheduler::new(config));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for class in [MessageClass::Transaction; 10]
+            .into_iter()
+            .chain([MessageClass::Certificate])
+        {
+            let scheduler = Arc::clone(&scheduler);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                assert!(scheduler.acquire(class, MTU_BYTES).await);
+                tx.send(class).unwrap();
+            });
+            tokio::task::yield_now().await;
+        }
+        drop(tx);
+
+        let mut order = Vec::new();
+        while let Some(class) = rx.recv().await {
+            order.push(class);
+        }
+        assert_eq!(order.len(), 11);
+        let cert_pos = order.iter().position(|c| *c == MessageClass::Certificate);
+        assert!(cert_pos.unwrap() <= 2);
+
+        let metrics = scheduler.metrics(MessageClass::Transaction);
+        assert_eq!(metrics.sent_messages, 10);
+        assert_eq!(metrics.sent_bytes, 10 * MTU_BYTES as u64);
+        assert_eq!(metrics.queued_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn scheduled_network() {
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        );
+        let scheduler = Arc::new(EgressScheduler::new(EgressConfig::default()));
+        let net0: SimulatedNetwork<Ping, Ping> = core.join_unlimited(0).await;
+        let net1: SimulatedNetwork<Ping, Ping> = core.join_unlimited(1).await;
+        let net0 = ScheduledNetwork::new(net0, Arc::clone(&scheduler), |_| MessageClass::Vote);
+
+        net0.send(&Ping::default(), localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let (sender, _) = net1.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+
+        let metrics = scheduler.metrics(MessageClass::Vote);
+        assert_eq!(metrics.sent_messages, 1);
+        assert_eq!(metrics.queued_bytes, 0);
+    }
+}
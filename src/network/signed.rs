@@ -0,0 +1,228 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-packet sender authentication over any network.
+//!
+//! This module provides [`SignedNetwork`], which wraps any [`Network`] carrying
+//! [`SignedPacket`]s. Every outgoing message is signed with the validator's
+//! identity key. Every incoming message is verified against the
+//! [`ValidatorInfo::pubkey`] of its claimed sender, and dropped if invalid.
+//!
+//! This authenticates the sender, but does not protect against replays.
+//! Messages are also not encrypted.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use log::warn;
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{AuthenticatedNetwork, Network};
+use crate::crypto::Signature;
+use crate::crypto::signature::{PublicKey, SecretKey};
+use crate::{ValidatorId, ValidatorInfo};
+
+/// Wire format used by [`SignedNetwork`].
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
+pub struct SignedPacket {
+    /// The validator that claims to have sent this packet.
+    sender: ValidatorId,
+    /// Serialized message.
+    payload: Vec<u8>,
+    /// Signature over `sender` and `payload` by the sender's identity key.
+    signature: Signature,
+}
+
+impl SignedPacket {
+    fn new(sender: ValidatorId, payload: Vec<u8>, sk: &SecretKey) -> Self {
+        let signature = sk.sign(&Self::bytes_to_sign(sender, &payload));
+        Self {
+            sender,
+            payload,
+            signature,
+        }
+    }
+
+    fn verify(&self, pk: &PublicKey) -> bool {
+        let bytes = Self::bytes_to_sign(self.sender, &self.payload);
+        self.signature.verify(&bytes, pk)
+    }
+
+    fn bytes_to_sign(sender: ValidatorId, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + payload.len());
+        bytes.extend_from_slice(&sender.to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+}
+
+/// Network wrapper that authenticates the sender of every message.
+pub struct SignedNetwork<N, S, R> {
+    network: N,
+    own_id: ValidatorId,
+    secret_key: SecretKey,
+    /// Identity public keys of all validators we accept messages from.
+    pubkeys: HashMap<ValidatorId, PublicKey>,
+    _msg_types: PhantomData<(S, R)>,
+}
+
+impl<N, S, R> SignedNetwork<N, S, R>
+where
+    N: Network<Send = SignedPacket, Recv = SignedPacket>,
+{
+    /// Creates a new `SignedNetwork` on top of the given `network`.
+    ///
+    /// Outgoing messages are signed with `secret_key` on behalf of validator `own_id`.
+    /// Incoming messages are only accepted if sent by one of the `validators`.
+    pub fn new(
+        network: N,
+        own_id: ValidatorId,
+        secret_key: SecretKey,
+        validators: &[ValidatorInfo],
+    ) -> Self {
+        let pubkeys = validators.iter().map(|v| (v.id, v.pubkey)).collect();
+        Self {
+            network,
+            own_id,
+            secret_key,
+            pubkeys,
+            _msg_types: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the underlying network.
+    pub const fn inner(&self) -> &N {
+        &self.network
+    }
+
+    fn sign(&self, msg: &S) -> SignedPacket
+    where
+        S: SchemaWrite<Src = S>,
+    {
+        let payload = wincode::serialize(msg).unwrap();
+        SignedPacket::new(self.own_id, payload, &self.secret_key)
+    }
+}
+
+#[async_trait]
+impl<N, S, R> Network for SignedNetwork<N, S, R>
+where
+    N: Network<Send = SignedPacket, Recv = SignedPacket>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    type Recv = R;
+    type Send = S;
+
+    async fn send_to_many(
+        &self,
+        msg: &S,
+        addrs: impl Iterator<Item = SocketAddr> + Send,
+    ) -> std::io::Result<()> {
+        let packet = self.sign(msg);
+        self.network.send_to_many(&packet, addrs).await
+    }
+
+    async fn send(&self, msg: &S, addr: SocketAddr) -> std::io::Result<()> {
+        let packet = self.sign(msg);
+        self.network.send(&packet, addr).await
+    }
+
+    async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.receive_from().await?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<N, S, R> AuthenticatedNetwork for SignedNetwork<N, S, R>
+where
+    N: Network<Send = SignedPacket, Recv = SignedPacket>,
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
+        loop {
+            let packet = self.network.receive().await?;
+            let Some(pk) = self.pubkeys.get(&packet.sender) else {
+                warn!("dropping packet from unknown validator {}", packet.sender);
+                continue;
+            };
+            if !packet.verify(pk) {
+                warn!(
+                    "dropping packet with invalid signature from {}",
+                    packet.sender
+                );
+                continue;
+            }
+            match wincode::deserialize(&packet.payload) {
+                Ok(msg) => return Ok((packet.sender, msg)),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{UdpNetwork, localhost_ip_sockaddr};
+    use crate::test_utils::{Ping, generate_validators_with_keys};
+
+    #[tokio::test]
+    async fn authenticated_sender() {
+        let (sks, _, validators) = generate_validators_with_keys(2);
+        let network0: SignedNetwork<_, Ping, Ping> = SignedNetwork::new(
+            UdpNetwork::new_with_any_port(),
+            0,
+            sks[0].clone(),
+            &validators,
+        );
+        let network1: SignedNetwork<_, Ping, Ping> = SignedNetwork::new(
+            UdpNetwork::new_with_any_port(),
+            1,
+            sks[1].clone(),
+            &validators,
+        );
+        let addr1 = localhost_ip_sockaddr(network1.inner().port());
+
+        network0.send(&Ping::default(), addr1).await.unwrap();
+        let (sender, msg) = network1.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(msg.0, Ping::default().0);
+    }
+
+    #[tokio::test]
+    async fn forged_sender() {
+        let (sks, _, validators) = generate_validators_with_keys(3);
+        // validator 2 claims to be validator 0
+        let forger: SignedNetwork<_, Ping, Ping> = SignedNetwork::new(
+            UdpNetwork::new_with_any_port(),
+            0,
+            sks[2].clone(),
+            &validators,
+        );
+        let honest: SignedNetwork<_, Ping, Ping> = SignedNetwork::new(
+            UdpNetwork::new_with_any_port(),
+            0,
+            sks[0].clone(),
+            &validators,
+        );
+        let receiver: SignedNetwork<_, Ping, Ping> = SignedNetwork::new(
+            UdpNetwork::new_with_any_port(),
+            1,
+            sks[1].clone(),
+            &validators,
+        );
+        let addr = localhost_ip_sockaddr(receiver.inner().port());
+
+        // forged packet is dropped, only the honest one is received
+        forger.send(&Ping([1; 32]), addr).await.unwrap();
+        honest.send(&Ping([2; 32]), addr).await.unwrap();
+        let (sender, msg) = receiver.receive_from().await.unwrap();
+        assert_eq!(sender, 0);
+        assert_eq!(msg.0, [2; 32]);
+    }
+}
@@ -11,15 +11,18 @@
 //! These channels are artificially limited in bandwidth through token buckets.
 //! The core also delays delivery of packets, simulating network latency, and
 //! supports jitter as well as packet loss.
+//! Transient faults, like partitions that heal or nodes going dark, can be
+//! scheduled via a [`FaultSchedule`].
 //!
 //! Further, this module exposes real-world data via its sub-modules:
 //! - [`ping_data`] for latencies between Solana mainnet validators.
 //! - [`stake_distribution`] for working with the Solana mainnet stake distribution.
 
 mod core;
+mod faults;
 pub mod ping_data;
 pub mod stake_distribution;
-mod token_bucket;
+pub(super) mod token_bucket;
 
 use std::marker::PhantomData;
 use std::net::SocketAddr;
@@ -32,8 +35,9 @@ use tokio::sync::{Mutex, RwLock, mpsc};
 use wincode::{SchemaRead, SchemaWrite};
 
 pub use self::core::SimulatedNetworkCore;
+pub use self::faults::{Fault, FaultSchedule};
 use self::token_bucket::TokenBucket;
-use super::Network;
+use super::{AuthenticatedNetwork, Network};
 use crate::ValidatorId;
 use crate::network::MTU_BYTES;
 
@@ -43,8 +47,8 @@ pub struct SimulatedNetwork<S, R> {
     id: ValidatorId,
     /// Reference to the simulated network core this interface is attached to.
     network_core: Arc<SimulatedNetworkCore>,
-    /// Receiver for incoming messages.
-    receiver: Mutex<mpsc::Receiver<Vec<u8>>>,
+    /// Receiver for incoming messages, tagged with their sender.
+    receiver: Mutex<mpsc::Receiver<(ValidatorId, Vec<u8>)>>,
     /// Optional rate limiter.
     limiter: Option<RwLock<TokenBucket>>,
     _msg_types: PhantomData<(S, R)>,
@@ -98,8 +102,21 @@ where
     }
 
     async fn receive(&self) -> std::io::Result<R> {
+        let (_, msg) = self.receive_from().await?;
+        Ok(msg)
+    }
+}
+
+// the sender is the ID the sending interface joined the network core with
+#[async_trait]
+impl<S, R> AuthenticatedNetwork for SimulatedNetwork<S, R>
+where
+    S: SchemaWrite<Src = S> + Send + Sync,
+    R: for<'de> SchemaRead<'de, Dst = R> + Send + Sync,
+{
+    async fn receive_from(&self) -> std::io::Result<(ValidatorId, R)> {
         loop {
-            let Some(buf) = self.receiver.lock().await.recv().await else {
+            let Some((from, buf)) = self.receiver.lock().await.recv().await else {
                 return Err(std::io::Error::other("channel closed"));
             };
             let msg = match wincode::deserialize(&buf) {
@@ -109,7 +126,7 @@ where
                     continue;
                 }
             };
-            return Ok(msg);
+            return Ok((from, msg));
         }
     }
 }
@@ -146,7 +163,9 @@ mod tests {
 
         // other direction
         net2.send(&msg, localhost_ip_sockaddr(0)).await.unwrap();
-        let received: Ping = net1.receive().await.expect("didn't receive message");
+        let (sender, received): (_, Ping) =
+            net1.receive_from().await.expect("didn't receive message");
+        assert_eq!(sender, 1);
         if received.0 != msg.0 {
             panic!("received wrong message");
         }
@@ -4,18 +4,21 @@
 use std::collections::{BinaryHeap, HashMap};
 use std::marker::PhantomData;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use log::warn;
-use rand::Rng;
-use tokio::sync::{Mutex, RwLock, mpsc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::{Mutex, Notify, RwLock, mpsc};
+use tokio::time::{Instant, sleep_until};
 
 use super::SimulatedNetwork;
+use super::faults::{FaultSchedule, Faults};
 use super::token_bucket::TokenBucket;
 use crate::ValidatorId;
 
 struct SimulatedPacket {
-    _from: ValidatorId,
+    from: ValidatorId,
     to: ValidatorId,
     payload: Vec<u8>,
     deliver_at: Instant,
@@ -43,6 +46,10 @@ impl Eq for SimulatedPacket {}
 ///
 /// It stores virtual latencies for all links between any pair of nodes.
 /// Messages sent by nodes into the network core are then delayed accordingly.
+/// Additionally, faults such as partitions can be scheduled, see [`FaultSchedule`].
+///
+/// All timing is based on [`tokio::time`], so the core also works under a paused clock.
+/// Together with [`Self::with_seed`], this makes simulations reproducible.
 pub struct SimulatedNetworkCore {
     /// Map from node ID to channel for delivering packets.
     nodes: Arc<RwLock<HashMap<ValidatorId, mpsc::Sender<SimulatedPacket>>>>,
@@ -56,6 +63,12 @@ pub struct SimulatedNetworkCore {
     per_packet_loss_probability: f64,
     /// Priority queue of packets that are waiting to be delivered.
     pending: Arc<Mutex<BinaryHeap<SimulatedPacket>>>,
+    /// Wakes up the delivery task whenever a new packet is queued.
+    packet_queued: Arc<Notify>,
+    /// Current and scheduled faults, applied when delivering packets.
+    faults: Arc<std::sync::Mutex<Faults>>,
+    /// Randomness for packet loss and jitter.
+    rng: Arc<std::sync::Mutex<StdRng>>,
 }
 
 impl SimulatedNetworkCore {
@@ -67,23 +80,53 @@ impl SimulatedNetworkCore {
             mpsc::Sender<SimulatedPacket>,
         >::new()));
 
+        let packet_queued = Arc::new(Notify::new());
+        let faults = Arc::new(std::sync::Mutex::new(Faults::default()));
+        let rng = Arc::new(std::sync::Mutex::new(StdRng::from_rng(&mut rand::rng())));
+
         let p = pending.clone();
         let n = nodes.clone();
+        let q = packet_queued.clone();
+        let f = faults.clone();
+        let r = rng.clone();
         tokio::spawn(async move {
             loop {
-                let mut guard = p.lock().await;
-                if let Some(msg) = guard.peek()
-                    && msg.deliver_at <= Instant::now()
-                {
-                    let msg = guard.pop().unwrap();
-                    let n_guard = n.read().await;
-                    let channel = n_guard.get(&msg.to).unwrap();
-                    if let Err(_e) = channel.send(msg).await {
-                        #[cfg(test)]
-                        println!("sending failed. Ignoring");
-                        warn!("sending failed. Ignoring");
+                // sleep until the next packet is due, or a new packet is queued
+                // NOTE: timers have millisecond resolution, so packets may be up to 1 ms late
+                let next = p.lock().await.peek().map(|msg| msg.deliver_at);
+                match next {
+                    Some(deliver_at) if deliver_at <= Instant::now() => {}
+                    Some(deliver_at) => {
+                        tokio::select! {
+                            () = sleep_until(deliver_at) => {}
+                            () = q.notified() => {}
+                        }
+                        continue;
+                    }
+                    None => {
+                        q.notified().await;
+                        continue;
                     }
                 }
+
+                let msg = p.lock().await.pop().unwrap();
+                let dropped = {
+                    let mut faults = f.lock().unwrap();
+                    faults.advance(Instant::now());
+                    let loss = faults.loss(msg.from, msg.to);
+                    faults.should_drop(msg.from, msg.to)
+                        || (loss > 0.0 && r.lock().unwrap().random_range(0.0..1.0) < loss)
+                };
+                if dropped {
+                    continue;
+                }
+                let n_guard = n.read().await;
+                let channel = n_guard.get(&msg.to).unwrap();
+                if let Err(_e) = channel.send(msg).await {
+                    #[cfg(test)]
+                    println!("sending failed. Ignoring");
+                    warn!("sending failed. Ignoring");
+                }
             }
         });
 
@@ -94,6 +137,9 @@ impl SimulatedNetworkCore {
             per_packet_jitter_ms: jitter_ms,
             per_packet_loss_probability: packet_loss,
             pending,
+            packet_queued,
+            faults,
+            rng,
         }
     }
 
@@ -118,6 +164,16 @@ impl SimulatedNetworkCore {
         self
     }
 
+    /// Turns this instance into a new instance with seeded randomness.
+    ///
+    /// Packet loss and jitter are then the same in every run with the same `seed`,
+    /// as long as packets are sent in the same order.
+    #[must_use]
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
     /// Adds a node *without* bandwidth limits to the simulated network.
     ///
     /// The node is registered in the network core with channels.
@@ -139,7 +195,7 @@ impl SimulatedNetworkCore {
         // background task: receive and push to buffer
         tokio::spawn(async move {
             while let Some(msg) = pb_rx.recv().await {
-                br_tx.send(msg.payload).await.unwrap();
+                br_tx.send((msg.from, msg.payload)).await.unwrap();
             }
         });
 
@@ -181,7 +237,7 @@ impl SimulatedNetworkCore {
             let mut limiter = TokenBucket::new(dl_bw);
             while let Some(msg) = pb_rx.recv().await {
                 limiter.wait_for(msg.payload.len()).await;
-                br_tx.send(msg.payload).await.unwrap();
+                br_tx.send((msg.from, msg.payload)).await.unwrap();
             }
         });
 
@@ -218,33 +274,56 @@ impl SimulatedNetworkCore {
         self.latencies.write().await.insert((from, to), latency);
     }
 
+    /// Schedules the faults in `schedule`, with times relative to now.
+    ///
+    /// This can be called multiple times, all schedules are merged.
+    pub fn schedule_faults(&self, schedule: FaultSchedule) {
+        self.faults
+            .lock()
+            .unwrap()
+            .schedule(Instant::now(), schedule);
+    }
+
     /// Sends a simulated message from one node to another.
     ///
     /// This schedules delivery for the message after the correct propagation delay.
     pub async fn send(&self, payload: Vec<u8>, from: ValidatorId, to: ValidatorId) {
-        if rand::rng().random_range(0.0..1.0) < self.per_packet_loss_probability {
+        let (lost, jitter) = {
+            let mut rng = self.rng.lock().unwrap();
+            let lost = rng.random_range(0.0..1.0) < self.per_packet_loss_probability;
+            let jitter = if self.per_packet_jitter_ms > 0.0 {
+                rng.random_range(0.0..self.per_packet_jitter_ms)
+            } else {
+                0.0
+            };
+            (lost, jitter)
+        };
+        if lost {
             return;
         }
 
         let now = Instant::now();
+        let fault_latency = {
+            let mut faults = self.faults.lock().unwrap();
+            faults.advance(now);
+            faults.latency(from, to)
+        };
         let guard = self.latencies.read().await;
-        let mut latency = *guard.get(&(from, to)).unwrap_or(&self.default_latency);
-        if self.per_packet_jitter_ms > 0.0 {
-            let jitter = rand::rng().random_range(0.0..self.per_packet_jitter_ms);
-            latency += Duration::from_secs_f64(jitter / 1000.0);
-        }
+        let mut latency = fault_latency
+            .unwrap_or_else(|| *guard.get(&(from, to)).unwrap_or(&self.default_latency));
+        latency += Duration::from_secs_f64(jitter / 1000.0);
         if from == to {
             latency = Duration::from_millis(0);
         }
 
         let packet = SimulatedPacket {
             deliver_at: now + latency,
-            _from: from,
+            from,
             to,
             payload,
         };
-        let mut guard = self.pending.lock().await;
-        guard.push(packet);
+        self.pending.lock().await.push(packet);
+        self.packet_queued.notify_one();
     }
 }
 
@@ -385,6 +464,52 @@ mod tests {
         assert_eq!(received, PingOrPong::Pong([0; 32]));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn paused_clock() {
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        );
+        let net1: SimulatedNetwork<Ping, Ping> = core.join_unlimited(0).await;
+        let net2: SimulatedNetwork<Ping, Ping> = core.join_unlimited(1).await;
+        core.set_latency(0, 1, Duration::from_secs(60)).await;
+
+        // latency is exact in virtual time
+        let start = Instant::now();
+        net1.send(&Ping::default(), localhost_ip_sockaddr(1))
+            .await
+            .unwrap();
+        let _: Ping = net2.receive().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn transient_isolation() {
+        let core = Arc::new(
+            SimulatedNetworkCore::default()
+                .with_jitter(0.0)
+                .with_packet_loss(0.0),
+        );
+        let net1: SimulatedNetwork<Ping, Ping> = core.join_unlimited(0).await;
+        let net2: SimulatedNetwork<Ping, Ping> = core.join_unlimited(1).await;
+        core.set_latency(0, 1, Duration::from_millis(10)).await;
+        let schedule =
+            FaultSchedule::default().with_isolation(1, Duration::ZERO, Duration::from_millis(100));
+        core.schedule_faults(schedule);
+
+        // dropped while node is isolated
+        let msg = Ping::default();
+        net1.send(&msg, localhost_ip_sockaddr(1)).await.unwrap();
+        let max_time = Duration::from_millis(50);
+        assert!(timeout(max_time, net2.receive()).await.is_err());
+
+        // delivered again after isolation ends
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        net1.send(&msg, localhost_ip_sockaddr(1)).await.unwrap();
+        let _: Ping = timeout(max_time, net2.receive()).await.unwrap().unwrap();
+    }
+
     #[tokio::test]
     async fn packet_loss() {
         // set up network with two nodes and 50% packet loss
@@ -0,0 +1,304 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scheduled faults for the simulated network.
+//!
+//! A [`FaultSchedule`] describes [`Fault`]s that start and end at given times,
+//! such as network partitions, isolated nodes, and lossy or slow links.
+//! It is applied via [`SimulatedNetworkCore::schedule_faults`](super::SimulatedNetworkCore::schedule_faults).
+//! Nodes are identified by the ID they joined the network core with.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::ValidatorId;
+
+/// A change to the simulated network, applied at a scheduled time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Splits the network into the given groups of nodes.
+    ///
+    /// Packets between nodes in different groups are dropped.
+    /// Nodes that are not in any group can still reach all other nodes.
+    /// Replaces any previous partition.
+    Partition(Vec<Vec<ValidatorId>>),
+    /// Removes the current partition.
+    HealPartition,
+    /// Drops all packets sent by or to the given node.
+    Isolate(ValidatorId),
+    /// Ends the isolation of the given node.
+    Reconnect(ValidatorId),
+    /// Sets the probability of losing packets on the link `from` -> `to`.
+    ///
+    /// This applies on top of the network core's uniform packet loss.
+    /// Setting the probability to `0.0` removes the extra loss.
+    LinkLoss {
+        from: ValidatorId,
+        to: ValidatorId,
+        probability: f64,
+    },
+    /// Overrides the latency of the link `from` -> `to`.
+    ///
+    /// Setting the latency to `None` reverts to the network core's configured latency.
+    LinkLatency {
+        from: ValidatorId,
+        to: ValidatorId,
+        latency: Option<Duration>,
+    },
+}
+
+/// Faults to apply at given times, relative to when the schedule is applied.
+#[derive(Clone, Debug, Default)]
+pub struct FaultSchedule {
+    events: Vec<(Duration, Fault)>,
+}
+
+impl FaultSchedule {
+    /// Adds a single `fault` at time `at`.
+    #[must_use]
+    pub fn with_fault(mut self, at: Duration, fault: Fault) -> Self {
+        self.events.push((at, fault));
+        self
+    }
+
+    /// Partitions the network into `groups` from `start` for `duration`.
+    #[must_use]
+    pub fn with_partition(
+        self,
+        groups: Vec<Vec<ValidatorId>>,
+        start: Duration,
+        duration: Duration,
+    ) -> Self {
+        self.with_fault(start, Fault::Partition(groups))
+            .with_fault(start + duration, Fault::HealPartition)
+    }
+
+    /// Isolates `node` from all other nodes from `start` for `duration`.
+    #[must_use]
+    pub fn with_isolation(self, node: ValidatorId, start: Duration, duration: Duration) -> Self {
+        self.with_fault(start, Fault::Isolate(node))
+            .with_fault(start + duration, Fault::Reconnect(node))
+    }
+
+    /// Loses packets on the link `from` -> `to` with `probability` from `start` for `duration`.
+    #[must_use]
+    pub fn with_link_loss(
+        self,
+        from: ValidatorId,
+        to: ValidatorId,
+        probability: f64,
+        start: Duration,
+        duration: Duration,
+    ) -> Self {
+        let fault = Fault::LinkLoss {
+            from,
+            to,
+            probability,
+        };
+        let heal = Fault::LinkLoss {
+            from,
+            to,
+            probability: 0.0,
+        };
+        self.with_fault(start, fault)
+            .with_fault(start + duration, heal)
+    }
+
+    /// Changes the latency of the link `from` -> `to` from `start` for `duration`.
+    #[must_use]
+    pub fn with_link_latency(
+        self,
+        from: ValidatorId,
+        to: ValidatorId,
+        latency: Duration,
+        start: Duration,
+        duration: Duration,
+    ) -> Self {
+        let fault = Fault::LinkLatency {
+            from,
+            to,
+            latency: Some(latency),
+        };
+        let heal = Fault::LinkLatency {
+            from,
+            to,
+            latency: None,
+        };
+        self.with_fault(start, fault)
+            .with_fault(start + duration, heal)
+    }
+}
+
+/// Faults currently in effect, as well as scheduled future faults.
+#[derive(Default)]
+pub(super) struct Faults {
+    /// Scheduled faults, ordered by time.
+    scheduled: Vec<(Instant, Fault)>,
+    /// Partition group of each node, if partitioned.
+    groups: HashMap<ValidatorId, usize>,
+    isolated: HashSet<ValidatorId>,
+    link_loss: HashMap<(ValidatorId, ValidatorId), f64>,
+    link_latency: HashMap<(ValidatorId, ValidatorId), Duration>,
+}
+
+impl Faults {
+    /// Adds all faults in `schedule`, with times relative to `start`.
+    pub(super) fn schedule(&mut self, start: Instant, schedule: FaultSchedule) {
+        let events = schedule.events.into_iter();
+        self.scheduled
+            .extend(events.map(|(at, fault)| (start + at, fault)));
+        self.scheduled.sort_by_key(|(at, _)| *at);
+    }
+
+    /// Applies all scheduled faults that are due at time `now`.
+    pub(super) fn advance(&mut self, now: Instant) {
+        let due = self.scheduled.partition_point(|(at, _)| *at <= now);
+        let faults = self.scheduled.drain(..due).collect::<Vec<_>>();
+        for (_, fault) in faults {
+            self.apply(fault);
+        }
+    }
+
+    /// Returns `true` if a packet on the link `from` -> `to` has to be dropped.
+    ///
+    /// This covers partitions and isolation, for random loss see [`Self::loss`].
+    pub(super) fn should_drop(&self, from: ValidatorId, to: ValidatorId) -> bool {
+        if from == to {
+            return false;
+        }
+        if self.isolated.contains(&from) || self.isolated.contains(&to) {
+            return true;
+        }
+        if let (Some(g1), Some(g2)) = (self.groups.get(&from), self.groups.get(&to))
+            && g1 != g2
+        {
+            return true;
+        }
+        false
+    }
+
+    /// Returns the extra probability of losing a packet on the link `from` -> `to`.
+    pub(super) fn loss(&self, from: ValidatorId, to: ValidatorId) -> f64 {
+        self.link_loss.get(&(from, to)).copied().unwrap_or(0.0)
+    }
+
+    /// Returns the overridden latency of the link `from` -> `to`, if any.
+    pub(super) fn latency(&self, from: ValidatorId, to: ValidatorId) -> Option<Duration> {
+        self.link_latency.get(&(from, to)).copied()
+    }
+
+    fn apply(&mut self, fault: Fault) {
+        match fault {
+            Fault::Partition(groups) => {
+                self.groups = groups
+                    .into_iter()
+                    .enumerate()
+                    .flat_map(|(i, group)| group.into_iter().map(move |node| (node, i)))
+                    .collect();
+            }
+            Fault::HealPartition => self.groups.clear(),
+            Fault::Isolate(node) => {
+                self.isolated.insert(node);
+            }
+            Fault::Reconnect(node) => {
+                self.isolated.remove(&node);
+            }
+            Fault::LinkLoss {
+                from,
+                to,
+                probability,
+            } => {
+                if probability > 0.0 {
+                    self.link_loss.insert((from, to), probability);
+                } else {
+                    self.link_loss.remove(&(from, to));
+                }
+            }
+            Fault::LinkLatency { from, to, latency } => match latency {
+                Some(latency) => {
+                    self.link_latency.insert((from, to), latency);
+                }
+                None => {
+                    self.link_latency.remove(&(from, to));
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition() {
+        let start = Instant::now();
+        let mut faults = Faults::default();
+        let schedule = FaultSchedule::default().with_partition(
+            vec![vec![0, 1], vec![2]],
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        faults.schedule(start, schedule);
+
+        // not yet started
+        faults.advance(start);
+        assert!(!faults.should_drop(0, 2));
+
+        faults.advance(start + Duration::from_secs(1));
+        assert!(!faults.should_drop(0, 1));
+        assert!(faults.should_drop(0, 2));
+        assert!(faults.should_drop(2, 1));
+        // nodes outside of any group are not affected
+        assert!(!faults.should_drop(3, 2));
+        assert!(!faults.should_drop(0, 3));
+
+        // healed
+        faults.advance(start + Duration::from_secs(2));
+        assert!(!faults.should_drop(0, 2));
+    }
+
+    #[test]
+    fn isolation() {
+        let start = Instant::now();
+        let mut faults = Faults::default();
+        let schedule =
+            FaultSchedule::default().with_isolation(1, Duration::ZERO, Duration::from_secs(1));
+        faults.schedule(start, schedule);
+
+        faults.advance(start);
+        assert!(faults.should_drop(0, 1));
+        assert!(faults.should_drop(1, 2));
+        assert!(!faults.should_drop(0, 2));
+        assert!(!faults.should_drop(1, 1));
+
+        faults.advance(start + Duration::from_secs(1));
+        assert!(!faults.should_drop(0, 1));
+        assert!(!faults.should_drop(1, 2));
+    }
+
+    #[test]
+    fn asymmetric_link() {
+        let start = Instant::now();
+        let mut faults = Faults::default();
+        let latency = Duration::from_millis(500);
+        let duration = Duration::from_secs(1);
+        let schedule = FaultSchedule::default()
+            .with_link_loss(0, 1, 1.0, Duration::ZERO, duration)
+            .with_link_latency(1, 0, latency, Duration::ZERO, duration);
+        faults.schedule(start, schedule);
+
+        faults.advance(start);
+        assert_eq!(faults.loss(0, 1), 1.0);
+        assert_eq!(faults.loss(1, 0), 0.0);
+        assert!(!faults.should_drop(0, 1));
+        assert_eq!(faults.latency(1, 0), Some(latency));
+        assert_eq!(faults.latency(0, 1), None);
+
+        faults.advance(start + duration);
+        assert_eq!(faults.loss(0, 1), 0.0);
+        assert_eq!(faults.latency(1, 0), None);
+    }
+}
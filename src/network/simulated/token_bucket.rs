@@ -37,8 +37,22 @@ impl TokenBucket {
         self.last_refill = now;
     }
 
-    /// Waits until the bucket has at least `bytes` tokens.
-    pub async fn wait_for(&mut self, tokens: usize) {
+    /// Waits until the bucket has provided `tokens` tokens.
+    ///
+    /// Requests larger than the bucket's capacity are served in multiple parts.
+    pub async fn wait_for(&mut self, mut tokens: usize) {
+        while tokens > self.capacity {
+            self.take(self.capacity).await;
+            tokens -= self.capacity;
+        }
+        self.take(tokens).await;
+    }
+
+    /// Waits until the bucket has at least `tokens` tokens and removes them.
+    ///
+    /// `tokens` must not exceed the capacity, otherwise this never returns.
+    async fn take(&mut self, tokens: usize) {
+        debug_assert!(tokens <= self.capacity);
         loop {
             self.refill();
 
@@ -76,6 +90,21 @@ mod tests {
         assert!(elapsed < expected * (1.0 + ACCURACY));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn larger_than_capacity() {
+        let rate = 1024 * 1024;
+        let mut bucket = TokenBucket::new(rate);
+        let now = Instant::now();
+
+        let tokens = 3 * bucket.capacity + 1;
+        bucket.wait_for(tokens).await;
+
+        let elapsed = now.elapsed().as_secs_f64();
+        let expected = tokens as f64 / rate as f64;
+        assert!(elapsed > expected * (1.0 - ACCURACY));
+        assert!(elapsed < expected * (1.0 + ACCURACY));
+    }
+
     #[tokio::test]
     async fn low_rate() {
         // 256 KiB/s : 1000 packets a 1500 bytes
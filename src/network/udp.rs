@@ -5,16 +5,23 @@
 //!
 //! This module provides an implementation of the [`Network`] trait for UDP sockets.
 //! It is essentially a wrapper around [`tokio::net::UdpSocket`].
+//!
+//! Packets are sent and received in batches where possible, see [`mmsg`].
+//! Receive buffers are allocated once and reused for all packets.
+
+mod mmsg;
 
+use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 use async_trait::async_trait;
-use futures::future::join_all;
 use log::warn;
 use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
 use wincode::{SchemaRead, SchemaWrite};
 
+use self::mmsg::BATCH_SIZE;
 use super::MTU_BYTES;
 use crate::network::Network;
 
@@ -24,10 +31,20 @@ use crate::network::Network;
 /// since messages we send in our protocols should fit in one MTU size packet.
 const RECEIVE_BUFFER_SIZE: usize = MTU_BYTES;
 
+/// Reusable buffers for receiving a batch of packets.
+struct ReceiveBuffers {
+    bufs: Box<[[u8; RECEIVE_BUFFER_SIZE]; BATCH_SIZE]>,
+    lens: [usize; BATCH_SIZE],
+    truncated: [bool; BATCH_SIZE],
+}
+
 /// Implementation of network abstraction over a simple UDP socket.
 pub struct UdpNetwork<S, R> {
     socket: UdpSocket,
-    _msg_types: PhantomData<(S, R)>,
+    buffers: Mutex<ReceiveBuffers>,
+    /// Messages that were received in a batch, but not yet returned.
+    pending: Mutex<VecDeque<R>>,
+    _msg_types: PhantomData<S>,
 }
 
 impl<S, R> UdpNetwork<S, R> {
@@ -40,8 +57,15 @@ impl<S, R> UdpNetwork<S, R> {
     pub fn new(port: u16) -> Self {
         let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
         let socket = futures::executor::block_on(UdpSocket::bind(addr)).unwrap();
+        let buffers = ReceiveBuffers {
+            bufs: Box::new([[0; RECEIVE_BUFFER_SIZE]; BATCH_SIZE]),
+            lens: [0; BATCH_SIZE],
+            truncated: [false; BATCH_SIZE],
+        };
         Self {
             socket,
+            buffers: Mutex::new(buffers),
+            pending: Mutex::new(VecDeque::new()),
             _msg_types: PhantomData,
         }
     }
@@ -64,6 +88,42 @@ impl<S, R> UdpNetwork<S, R> {
         assert_eq!(bytes.len(), bytes_sent);
         Ok(())
     }
+
+    /// Receives a batch of up to `max` packets and deserializes them into `msgs`.
+    ///
+    /// Waits until at least one packet is available.
+    /// Packets that were truncated or fail to deserialize are dropped.
+    async fn receive_into(&self, msgs: &mut VecDeque<R>, max: usize) -> std::io::Result<()>
+    where
+        R: for<'de> SchemaRead<'de, Dst = R>,
+    {
+        let mut buffers = self.buffers.lock().await;
+        let ReceiveBuffers {
+            bufs,
+            lens,
+            truncated,
+        } = &mut *buffers;
+        let max = max.clamp(1, BATCH_SIZE);
+        let received = mmsg::recv_batch(
+            &self.socket,
+            &mut bufs[..max],
+            &mut lens[..max],
+            &mut truncated[..max],
+        )
+        .await?;
+        let dropped = truncated[..received].iter().filter(|t| **t).count();
+        if dropped > 0 {
+            warn!("dropping {dropped} packets larger than {RECEIVE_BUFFER_SIZE} bytes");
+        }
+        let packets = bufs.iter().zip(lens.iter()).zip(truncated.iter());
+        for ((buf, len), _) in packets.take(received).filter(|(_, truncated)| !**truncated) {
+            match wincode::deserialize(&buf[..*len]) {
+                Ok(msg) => msgs.push_back(msg),
+                Err(err) => warn!("deserializing failed with {err:?}"),
+            }
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -80,12 +140,35 @@ where
         msg: &S,
         addrs: impl Iterator<Item = SocketAddr> + Send,
     ) -> std::io::Result<()> {
-        let bytes = &wincode::serialize(msg).unwrap();
-        let tasks = addrs.map(async move |addr| self.send_serialized(bytes, addr).await);
-        for res in join_all(tasks).await {
-            let () = res?;
+        let bytes = wincode::serialize(msg).unwrap();
+        assert!(bytes.len() <= MTU_BYTES, "each message should fit in MTU");
+        let addrs = addrs.collect::<Vec<_>>();
+        let mut sent = 0;
+        let mut failed = 0;
+        let mut first_err = None;
+        while sent < addrs.len() {
+            let batch = &addrs[sent..addrs.len().min(sent + BATCH_SIZE)];
+            match mmsg::send_batch(&self.socket, &bytes, batch).await {
+                Ok(count) => sent += count,
+                Err(err) => {
+                    // the first destination of the batch failed, continue with the rest
+                    warn!("failed to send to {}: {err}", batch[0]);
+                    failed += 1;
+                    sent += 1;
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        match first_err {
+            None => Ok(()),
+            Some(err) => Err(std::io::Error::new(
+                err.kind(),
+                format!(
+                    "failed to send to {failed} of {} destinations, first error: {err}",
+                    addrs.len()
+                ),
+            )),
         }
-        Ok(())
     }
 
     async fn send(&self, msg: &Self::Send, addr: SocketAddr) -> std::io::Result<()> {
@@ -94,18 +177,22 @@ where
     }
 
     async fn receive(&self) -> std::io::Result<R> {
-        let mut buf = [0; RECEIVE_BUFFER_SIZE];
+        let mut pending = self.pending.lock().await;
         loop {
-            let _bytes_recved = self.socket.recv(&mut buf).await?;
-            let msg = match wincode::deserialize(&buf) {
-                Ok(r) => r,
-                Err(err) => {
-                    warn!("deserializing failed with {err:?}");
-                    continue;
-                }
-            };
-            return Ok(msg);
+            if let Some(msg) = pending.pop_front() {
+                return Ok(msg);
+            }
+            self.receive_into(&mut pending, BATCH_SIZE).await?;
+        }
+    }
+
+    async fn receive_batch(&self, max: usize) -> std::io::Result<Vec<R>> {
+        let mut pending = self.pending.lock().await;
+        while pending.is_empty() {
+            self.receive_into(&mut pending, max).await?;
         }
+        let count = max.clamp(1, pending.len());
+        Ok(pending.drain(..count).collect())
     }
 }
 
@@ -141,4 +228,68 @@ mod tests {
         let msg: Pong = socket1.receive().await.unwrap();
         assert_eq!(msg.0, Ping::default().0);
     }
+
+    #[tokio::test]
+    async fn batch() {
+        let sender: UdpNetwork<Ping, Ping> = UdpNetwork::new_with_any_port();
+        let receivers: Vec<UdpNetwork<Ping, Ping>> =
+            (0..100).map(|_| UdpNetwork::new_with_any_port()).collect();
+        let addrs = receivers.iter().map(|r| localhost_ip_sockaddr(r.port()));
+
+        // send to more destinations than fit into one batch
+        sender.send_to_many(&Ping([1; 32]), addrs).await.unwrap();
+        for receiver in &receivers {
+            let msg = receiver.receive().await.unwrap();
+            assert_eq!(msg.0, [1; 32]);
+        }
+
+        // receive multiple messages at once
+        let addr = localhost_ip_sockaddr(receivers[0].port());
+        for i in 0..10 {
+            sender.send(&Ping([i; 32]), addr).await.unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 10 {
+            let msgs = receivers[0]
+                .receive_batch(10 - received.len())
+                .await
+                .unwrap();
+            assert!(!msgs.is_empty());
+            received.extend(msgs.into_iter().map(|msg| msg.0[0]));
+        }
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn oversized_packet_is_dropped() {
+        let receiver: UdpNetwork<Ping, Ping> = UdpNetwork::new_with_any_port();
+        let addr = localhost_ip_sockaddr(receiver.port());
+        let raw = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // would be truncated to a valid-looking prefix
+        let mut oversized = wincode::serialize(&Ping([7; 32])).unwrap();
+        oversized.resize(2 * RECEIVE_BUFFER_SIZE, 0);
+        raw.send_to(&oversized, addr).await.unwrap();
+        raw.send_to(&wincode::serialize(&Ping([1; 32])).unwrap(), addr)
+            .await
+            .unwrap();
+
+        let msg = receiver.receive().await.unwrap();
+        assert_eq!(msg.0, [1; 32]);
+    }
+
+    #[tokio::test]
+    async fn send_to_many_continues_after_error() {
+        let sender: UdpNetwork<Ping, Ping> = UdpNetwork::new_with_any_port();
+        let receiver: UdpNetwork<Ping, Ping> = UdpNetwork::new_with_any_port();
+        // IPv6 destinations cannot be reached from an IPv4 socket
+        let unreachable = SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 1));
+        let addrs = [unreachable, localhost_ip_sockaddr(receiver.port())];
+
+        let res = sender.send_to_many(&Ping([1; 32]), addrs.into_iter()).await;
+        assert!(res.is_err());
+        let msg = receiver.receive().await.unwrap();
+        assert_eq!(msg.0, [1; 32]);
+    }
 }
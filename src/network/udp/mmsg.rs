@@ -0,0 +1,222 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Batched socket I/O.
+//!
+//! On Linux, this uses the `recvmmsg` and `sendmmsg` system calls to handle
+//! many packets with a single system call.
+//! On other platforms, it falls back to handling one packet at a time.
+
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+use super::RECEIVE_BUFFER_SIZE;
+
+/// Maximum number of packets handled by a single system call.
+pub const BATCH_SIZE: usize = 64;
+
+/// Receives up to `bufs.len()` packets into `bufs`.
+///
+/// Waits until at least one packet is available.
+/// Writes the length of each received packet into `lens`.
+/// Packets that did not fit into their buffer are flagged in `truncated`.
+/// Returns the number of packets received.
+#[cfg(target_os = "linux")]
+pub async fn recv_batch(
+    socket: &UdpSocket,
+    bufs: &mut [[u8; RECEIVE_BUFFER_SIZE]],
+    lens: &mut [usize],
+    truncated: &mut [bool],
+) -> std::io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    use tokio::io::Interest;
+
+    let fd = socket.as_raw_fd();
+    socket
+        .async_io(Interest::READABLE, || {
+            linux::recvmmsg(fd, bufs, lens, truncated)
+        })
+        .await
+}
+
+/// Sends `bytes` to up to `addrs.len()` destinations.
+///
+/// Returns the number of destinations the packet was sent to.
+/// These are always a prefix of `addrs`.
+#[cfg(target_os = "linux")]
+pub async fn send_batch(
+    socket: &UdpSocket,
+    bytes: &[u8],
+    addrs: &[SocketAddr],
+) -> std::io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    use tokio::io::Interest;
+
+    let fd = socket.as_raw_fd();
+    socket
+        .async_io(Interest::WRITABLE, || linux::sendmmsg(fd, bytes, addrs))
+        .await
+}
+
+/// Receives a single packet into `bufs[0]`.
+///
+/// Truncation cannot be detected portably, so `truncated[0]` is always `false`.
+#[cfg(not(target_os = "linux"))]
+pub async fn recv_batch(
+    socket: &UdpSocket,
+    bufs: &mut [[u8; RECEIVE_BUFFER_SIZE]],
+    lens: &mut [usize],
+    truncated: &mut [bool],
+) -> std::io::Result<usize> {
+    lens[0] = socket.recv(&mut bufs[0]).await?;
+    truncated[0] = false;
+    Ok(1)
+}
+
+/// Sends `bytes` to `addrs[0]` only.
+#[cfg(not(target_os = "linux"))]
+pub async fn send_batch(
+    socket: &UdpSocket,
+    bytes: &[u8],
+    addrs: &[SocketAddr],
+) -> std::io::Result<usize> {
+    socket.send_to(bytes, addrs[0]).await?;
+    Ok(1)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::net::SocketAddr;
+    use std::os::fd::RawFd;
+
+    use smallvec::SmallVec;
+
+    use super::{BATCH_SIZE, RECEIVE_BUFFER_SIZE};
+
+    /// Non-blocking `recvmmsg` into `bufs`.
+    pub fn recvmmsg(
+        fd: RawFd,
+        bufs: &mut [[u8; RECEIVE_BUFFER_SIZE]],
+        lens: &mut [usize],
+        truncated: &mut [bool],
+    ) -> std::io::Result<usize> {
+        let mut iovecs = bufs
+            .iter_mut()
+            .take(BATCH_SIZE)
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect::<SmallVec<[_; BATCH_SIZE]>>();
+        let mut headers = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                // SAFETY: all-zero is a valid `mmsghdr` (null pointers and zero lengths)
+                let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect::<SmallVec<[_; BATCH_SIZE]>>();
+
+        // SAFETY: each header points to one iovec, which points to a buffer that outlives the call
+        let received = unsafe {
+            libc::recvmmsg(
+                fd,
+                headers.as_mut_ptr(),
+                headers.len() as u32,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if received < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let received = received as usize;
+        let outputs = lens.iter_mut().zip(truncated.iter_mut());
+        for ((len, truncated), header) in outputs.zip(&headers[..received]) {
+            *len = header.msg_len as usize;
+            *truncated = header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0;
+        }
+        Ok(received)
+    }
+
+    /// Non-blocking `sendmmsg` of `bytes` to all of `addrs`.
+    pub fn sendmmsg(fd: RawFd, bytes: &[u8], addrs: &[SocketAddr]) -> std::io::Result<usize> {
+        let mut sockaddrs = addrs
+            .iter()
+            .take(BATCH_SIZE)
+            .map(to_sockaddr)
+            .collect::<SmallVec<[_; BATCH_SIZE]>>();
+        let mut iovec = libc::iovec {
+            // never written to, `sendmmsg` only reads from the buffer
+            iov_base: bytes.as_ptr().cast_mut().cast(),
+            iov_len: bytes.len(),
+        };
+        let mut headers = sockaddrs
+            .iter_mut()
+            .map(|(sockaddr, len)| {
+                // SAFETY: all-zero is a valid `mmsghdr` (null pointers and zero lengths)
+                let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                header.msg_hdr.msg_name = std::ptr::from_mut(sockaddr).cast();
+                header.msg_hdr.msg_namelen = *len;
+                header.msg_hdr.msg_iov = &raw mut iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect::<SmallVec<[_; BATCH_SIZE]>>();
+
+        // SAFETY: each header points to a valid address and to the iovec, which both outlive the call
+        let sent = unsafe {
+            libc::sendmmsg(
+                fd,
+                headers.as_mut_ptr(),
+                headers.len() as u32,
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if sent < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+
+    /// Converts `addr` into its C representation.
+    fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: all-zero is a valid `sockaddr_storage`
+        let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                let sockaddr = libc::sockaddr_in {
+                    sin_family: libc::AF_INET as libc::sa_family_t,
+                    sin_port: addr.port().to_be(),
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                    },
+                    sin_zero: [0; 8],
+                };
+                // SAFETY: `sockaddr_storage` is large enough and suitably aligned for any address
+                unsafe { std::ptr::write((&raw mut storage).cast(), sockaddr) };
+                std::mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                let sockaddr = libc::sockaddr_in6 {
+                    sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                    sin6_port: addr.port().to_be(),
+                    sin6_flowinfo: addr.flowinfo(),
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: addr.ip().octets(),
+                    },
+                    sin6_scope_id: addr.scope_id(),
+                };
+                // SAFETY: `sockaddr_storage` is large enough and suitably aligned for any address
+                unsafe { std::ptr::write((&raw mut storage).cast(), sockaddr) };
+                std::mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
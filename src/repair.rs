@@ -8,32 +8,50 @@
 //! the leaves of this tree are the Merkle roots of each of the block's slices.
 //! Each repair response is accompanied by a Merkle proof and can thus be
 //! individually verified.
+//!
+//! Requests are sent to peers picked by [`peers::PeerScores`], which prefers
+//! peers that answered quickly in the past and avoids unresponsive or faulty ones.
+//! Requests that time out are retried, each time sent to more peers.
+//!
+//! To save round trips, requests for multiple slice roots or shreds are batched,
+//! see [`RepairRequestType::SliceRoots`] and [`RepairRequestType::Shreds`].
+//! Batch requests are answered with one response per requested item,
+//! so each response still fits into a single packet and can be verified on its own.
 
-use std::collections::{BTreeMap, BinaryHeap, HashSet};
-use std::net::SocketAddr;
+mod peers;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use log::{debug, trace, warn};
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::consensus::{Blockstore, DELTA, EpochInfo, Pool};
+use self::peers::{PeerScores, fanout};
+use crate::consensus::{Blockstore, DELTA, EpochInfo, Pool, Timeline};
 use crate::crypto::merkle::{DoubleMerkleProof, DoubleMerkleTree, MerkleRoot, SliceRoot};
 use crate::crypto::{Hash, hash};
-use crate::disseminator::rotor::{SamplingStrategy, StakeWeightedSampler};
-use crate::network::{Network, RepairNetwork, RepairRequestNetwork};
-use crate::shredder::{Shred, ShredIndex};
+use crate::metrics::METRICS;
+use crate::network::{AuthenticatedNetwork, Network, RepairNetwork, RepairRequestNetwork};
+use crate::shredder::{Shred, ShredIndex, TOTAL_SHREDS};
 use crate::types::SliceIndex;
 use crate::{BlockId, ValidatorId};
 
 /// Maximum time to wait for a response to a repair request.
 ///
-/// After a request times out we retry it from another node.
+/// After a request times out we retry it from more nodes, see [`peers::fanout`].
 const REPAIR_TIMEOUT: Duration = DELTA.checked_mul(2).unwrap();
 
+/// Maximum number of slice roots requested by a single [`RepairRequestType::SliceRoots`].
+///
+/// Larger ranges are ignored beyond this, to limit the traffic a single request can cause.
+const MAX_SLICE_ROOTS_PER_REQUEST: usize = 64;
+
 /// Different types of [`RepairRequest`] messages.
-#[derive(Clone, Debug, PartialEq, Eq, SchemaRead, SchemaWrite)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, SchemaRead, SchemaWrite)]
 pub enum RepairRequestType {
     /// Request for the total number of slices in block with a given hash.
     LastSliceRoot(BlockId),
@@ -41,6 +59,15 @@ pub enum RepairRequestType {
     SliceRoot(BlockId, SliceIndex),
     /// Request for shred, identified by block hash, slice index and shred index.
     Shred(BlockId, SliceIndex, ShredIndex),
+    /// Request for the root hashes of a range of slices, identified by block hash and
+    /// first and last slice index (inclusive).
+    ///
+    /// Answered with one [`RepairResponse::SliceRoot`] per slice.
+    SliceRoots(BlockId, SliceIndex, SliceIndex),
+    /// Request for multiple shreds of a slice, identified by block hash, slice index and shred indices.
+    ///
+    /// Answered with one [`RepairResponse::Shred`] per shred.
+    Shreds(BlockId, SliceIndex, Vec<ShredIndex>),
 }
 
 impl RepairRequestType {
@@ -53,12 +80,52 @@ impl RepairRequestType {
         let msg_bytes = wincode::serialize(&repair).unwrap();
         hash(&msg_bytes)
     }
+
+    /// Combines requests for single slice roots and shreds into as few batch requests as possible.
+    ///
+    /// Consecutive slice roots of the same block are requested as one [`Self::SliceRoots`],
+    /// shreds of the same slice as one [`Self::Shreds`].
+    /// Any other request types are kept as they are.
+    fn batch(mut requests: Vec<Self>) -> Vec<Self> {
+        requests.sort();
+        let mut batches = Vec::<Self>::new();
+        for request in requests {
+            match (batches.last_mut(), request) {
+                (
+                    Some(Self::SliceRoots(batch_block, first, last)),
+                    Self::SliceRoot(block_id, slice),
+                ) if *batch_block == block_id
+                    && slice.inner() == last.inner() + 1
+                    && slice.inner() - first.inner() < MAX_SLICE_ROOTS_PER_REQUEST =>
+                {
+                    *last = slice;
+                }
+                (
+                    Some(Self::Shreds(batch_block, batch_slice, indices)),
+                    Self::Shred(block_id, slice, index),
+                ) if *batch_block == block_id && *batch_slice == slice => {
+                    indices.push(index);
+                }
+                (_, Self::SliceRoot(block_id, slice)) => {
+                    batches.push(Self::SliceRoots(block_id, slice, slice));
+                }
+                (_, Self::Shred(block_id, slice, index)) => {
+                    batches.push(Self::Shreds(block_id, slice, vec![index]));
+                }
+                (_, request) => batches.push(request),
+            }
+        }
+        batches
+    }
 }
 
 /// Request messages for the repair sub-protocol.
 #[derive(Clone, Debug, SchemaRead, SchemaWrite)]
 pub struct RepairRequest {
     /// The validator that sent the message.
+    ///
+    /// This is self-declared by the sender.
+    /// It is only checked by [`RepairRequestHandler::run_authenticated`].
     sender: ValidatorId,
     /// The type of repair message sent.
     req_type: RepairRequestType,
@@ -125,65 +192,165 @@ where
     ///
     /// Listens for repair requests on `self.network`.
     /// Looks up the corresponding data in `self.blockstore` and sends replies.
+    ///
+    /// Responses go to the self-declared sender of each request, and a batch request
+    /// is answered with many responses. So on networks where the sender can be spoofed,
+    /// use [`Self::run_authenticated`] instead, as the node does.
     pub async fn run(&self) {
         loop {
-            let request = self.network.receive().await.unwrap();
-            self.answer_request(request).await.unwrap();
+            let request = match self.network.receive().await {
+                Ok(request) => request,
+                Err(err) => {
+                    warn!("failed to receive repair request: {err}");
+                    continue;
+                }
+            };
+            if let Err(err) = self.answer_request(request).await {
+                warn!("failed to answer repair request: {err}");
+            }
         }
     }
 
     /// Tries to answer the given repair request.
     ///
-    /// If we do not have the necessary information in blockstore, the request is ignored.
-    /// Otherwise, the correct response is sent back to the sender of the request.
+    /// Batch requests are answered with one response per requested item.
+    /// Any items we do not have the necessary information for in blockstore are ignored.
+    /// All responses are sent back to the sender of the request.
     async fn answer_request(&self, request: RepairRequest) -> std::io::Result<()> {
         trace!("answering repair request: {request:?}");
-        let response = match &request.req_type {
-            RepairRequestType::LastSliceRoot(block_id) => {
-                let blockstore = self.blockstore.read().await;
-                let Some(last_slice) = blockstore.get_last_slice_index(block_id) else {
-                    return Ok(());
-                };
-                let Some(root) = blockstore.get_slice_root(block_id, last_slice) else {
-                    return Ok(());
-                };
-                let Some(proof) = blockstore.create_double_merkle_proof(block_id, last_slice)
-                else {
-                    return Ok(());
-                };
-                RepairResponse::LastSliceRoot(request.req_type, last_slice, root.clone(), proof)
-            }
-            RepairRequestType::SliceRoot(block_id, slice) => {
-                let blockstore = self.blockstore.read().await;
-                let Some(root) = blockstore.get_slice_root(block_id, *slice) else {
-                    return Ok(());
-                };
-                let Some(proof) = blockstore.create_double_merkle_proof(block_id, *slice) else {
-                    return Ok(());
-                };
-                RepairResponse::SliceRoot(request.req_type, root.clone(), proof)
-            }
-            RepairRequestType::Shred(block_id, slice, shred) => {
-                let blockstore = self.blockstore.read().await;
-                let Some(shred) = blockstore.get_shred(block_id, *slice, *shred).cloned() else {
-                    return Ok(());
-                };
-                RepairResponse::Shred(request.req_type, shred.into_shred())
+        let responses = {
+            let blockstore = self.blockstore.read().await;
+            let blockstore = &**blockstore;
+            match request.req_type {
+                RepairRequestType::LastSliceRoot(ref block_id) => {
+                    let Some(last_slice) = blockstore.get_last_slice_index(block_id) else {
+                        return Ok(());
+                    };
+                    let Some(root) = blockstore.get_slice_root(block_id, last_slice) else {
+                        return Ok(());
+                    };
+                    let Some(proof) = blockstore.create_double_merkle_proof(block_id, last_slice)
+                    else {
+                        return Ok(());
+                    };
+                    let root = root.clone();
+                    vec![RepairResponse::LastSliceRoot(
+                        request.req_type,
+                        last_slice,
+                        root,
+                        proof,
+                    )]
+                }
+                RepairRequestType::SliceRoot(block_id, slice) => {
+                    slice_root_response(blockstore, block_id, slice)
+                        .into_iter()
+                        .collect()
+                }
+                RepairRequestType::Shred(block_id, slice, shred) => {
+                    shred_response(blockstore, block_id, slice, shred)
+                        .into_iter()
+                        .collect()
+                }
+                RepairRequestType::SliceRoots(block_id, first, last) => (first.inner()
+                    ..=last.inner())
+                    .take(MAX_SLICE_ROOTS_PER_REQUEST)
+                    .filter_map(SliceIndex::new)
+                    .filter_map(|slice| slice_root_response(blockstore, block_id.clone(), slice))
+                    .collect(),
+                RepairRequestType::Shreds(block_id, slice, shreds) => shreds
+                    .into_iter()
+                    .take(TOTAL_SHREDS)
+                    .filter_map(|shred| shred_response(blockstore, block_id.clone(), slice, shred))
+                    .collect(),
             }
         };
-        self.send_response(response, request.sender).await
+        if responses.is_empty() {
+            return Ok(());
+        }
+
+        let to = self
+            .epoch_info
+            .validator(request.sender)
+            .repair_response_address;
+        for response in &responses {
+            self.network.send(response, to).await?;
+        }
+        METRICS.repair_requests_served.inc();
+        Ok(())
     }
+}
 
-    async fn send_response(
-        &self,
-        response: RepairResponse,
-        validator: ValidatorId,
-    ) -> std::io::Result<()> {
-        let to = self.epoch_info.validator(validator).repair_response_address;
-        self.network.send(&response, to).await
+/// Creates the response to a [`RepairRequestType::SliceRoot`] request, if possible.
+fn slice_root_response(
+    blockstore: &(dyn Blockstore + Send + Sync),
+    block_id: BlockId,
+    slice: SliceIndex,
+) -> Option<RepairResponse> {
+    let root = blockstore.get_slice_root(&block_id, slice)?.clone();
+    let proof = blockstore.create_double_merkle_proof(&block_id, slice)?;
+    let req_type = RepairRequestType::SliceRoot(block_id, slice);
+    Some(RepairResponse::SliceRoot(req_type, root, proof))
+}
+
+/// Creates the response to a [`RepairRequestType::Shred`] request, if possible.
+fn shred_response(
+    blockstore: &(dyn Blockstore + Send + Sync),
+    block_id: BlockId,
+    slice: SliceIndex,
+    index: ShredIndex,
+) -> Option<RepairResponse> {
+    let shred = blockstore.get_shred(&block_id, slice, index)?;
+    let req_type = RepairRequestType::Shred(block_id, slice, index);
+    Some(RepairResponse::Shred(req_type, shred.into_shred()))
+}
+
+impl<N> RepairRequestHandler<N>
+where
+    N: RepairRequestNetwork + AuthenticatedNetwork,
+{
+    /// Main loop of the repair request handler, for authenticated networks.
+    ///
+    /// Same as [`Self::run`], but ignores any request whose self-declared sender
+    /// differs from the sender authenticated by `self.network`.
+    /// This prevents nodes from directing repair responses at other validators.
+    pub async fn run_authenticated(&self) {
+        loop {
+            let (sender, request) = match self.network.receive_from().await {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!("failed to receive repair request: {err}");
+                    continue;
+                }
+            };
+            if request.sender != sender {
+                warn!(
+                    "ignoring repair request from {sender} claiming to be from {}",
+                    request.sender
+                );
+                continue;
+            }
+            if let Err(err) = self.answer_request(request).await {
+                warn!("failed to answer repair request: {err}");
+            }
+        }
     }
 }
 
+/// A repair request that is waiting for a response.
+struct OutstandingRequest {
+    req_type: RepairRequestType,
+    /// Number of times this request was retried before.
+    attempt: u32,
+    /// When the latest attempt was sent.
+    sent_at: Instant,
+    /// Peers the latest attempt was sent to, that have not responded yet.
+    peers: Vec<ValidatorId>,
+    /// Whether a valid response was received already.
+    ///
+    /// Answered requests are kept until they time out, to notice peers that never respond.
+    answered: bool,
+}
+
 /// Instance of double-Merkle based block repair protocol.
 ///
 /// This is used by the node to repair blocks that it is missing.
@@ -192,20 +359,27 @@ pub struct Repair<N: Network> {
     blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>>,
     pool: Arc<RwLock<Box<dyn Pool + Send + Sync>>>,
     slice_roots: BTreeMap<(BlockId, SliceIndex), SliceRoot>,
-    outstanding_requests: BTreeMap<Hash, RepairRequestType>,
-    request_timeouts: BinaryHeap<(Instant, Hash)>,
+    outstanding_requests: BTreeMap<Hash, OutstandingRequest>,
+    /// Expiry times of outstanding requests, earliest first.
+    ///
+    /// May contain stale entries for requests that were answered or sent again since.
+    request_timeouts: BinaryHeap<Reverse<(Instant, Hash)>>,
     network: N,
-    sampler: StakeWeightedSampler,
+    /// Statistics about peers, used to pick whom to send requests to.
+    peers: PeerScores,
     epoch_info: Arc<EpochInfo>,
+    /// Records which slots needed repair, see [`Timeline`].
+    timeline: Timeline,
 }
 
 impl<N> Repair<N>
 where
-    N: RepairNetwork,
+    N: RepairNetwork + AuthenticatedNetwork,
 {
     /// Creates a new repair instance.
     ///
     /// Given `network` will be used for sending repair requests and receiving repair responses.
+    /// It needs to authenticate senders, so that invalid responses can be blamed on their sender.
     /// Any repaired shreds will be written into the provided `blockstore`.
     pub fn new(
         blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>>,
@@ -213,8 +387,7 @@ where
         network: N,
         epoch_info: Arc<EpochInfo>,
     ) -> Self {
-        let validators = epoch_info.validators.clone();
-        let sampler = StakeWeightedSampler::new(validators);
+        let peers = PeerScores::new(epoch_info.own_id, epoch_info.validators.clone());
         Self {
             blockstore,
             pool,
@@ -222,39 +395,93 @@ where
             outstanding_requests: BTreeMap::new(),
             request_timeouts: BinaryHeap::new(),
             network,
-            sampler,
+            peers,
+            timeline: Timeline::new(epoch_info.own_id),
             epoch_info,
         }
     }
 
+    /// Seeds the randomness used for picking peers, which otherwise comes from the OS.
+    ///
+    /// This should only be used for reproducible tests and simulations.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.peers = self.peers.with_seed(seed);
+        self
+    }
+
+    /// Sets the timeline to record repairs in.
+    pub fn set_timeline(&mut self, timeline: Timeline) {
+        self.timeline = timeline;
+    }
+
     /// Main loop of the repair protocol.
     ///
     /// Listens to incoming requests for blocks to repair on `self.repair_channel`.
     /// Inititates the corresponding repair process and handles ongoing repairs.
     pub async fn repair_loop(&mut self, mut repair_receiver: tokio::sync::mpsc::Receiver<BlockId>) {
         loop {
-            let next_timeout = self.request_timeouts.peek().map(|(t, _)| t);
+            let next_timeout = self.request_timeouts.peek().map(|Reverse((t, _))| t);
             let sleep_duration = match next_timeout {
                 None => std::time::Duration::MAX,
                 Some(t) => t.duration_since(Instant::now()),
             };
             tokio::select! {
                 // handle repair response from network
-                res = self.network.receive() => self.handle_response(res.unwrap()).await,
+                res = self.network.receive_from() => {
+                    let (sender, response) = res.unwrap();
+                    self.handle_response(sender, response).await;
+                }
                 // handle request for repairing new block
                 Some(block_id) = repair_receiver.recv() => {
                     self.repair_block(block_id).await;
                 }
-                // handle next request timeout
-                () = tokio::time::sleep(sleep_duration) => {
-                    let Some((_, hash)) = self.request_timeouts.pop() else {
-                        continue;
-                    };
-                    if let Some(request) = self.outstanding_requests.remove(&hash) {
-                        debug!("retrying timed-out repair request {request:?}");
-                        self.send_request(request).await.unwrap();
-                    }
-                }
+                // handle request timeouts
+                () = tokio::time::sleep(sleep_duration) => self.retry_timed_out().await,
+            }
+        }
+    }
+
+    /// Retries all outstanding requests that timed out.
+    ///
+    /// Requests are batched again, so a lost batch request is retried as a batch.
+    /// Peers that did not respond in time are blamed, even if another peer answered the request.
+    async fn retry_timed_out(&mut self) {
+        let now = Instant::now();
+        let mut retries = BTreeMap::<u32, Vec<RepairRequestType>>::new();
+        let mut timed_out_peers = BTreeSet::new();
+        while let Some(Reverse((expiry, _))) = self.request_timeouts.peek() {
+            if *expiry > now {
+                break;
+            }
+            let Reverse((expiry, hash)) = self.request_timeouts.pop().unwrap();
+            // skip timeouts of requests that were answered or sent again since
+            let Some(request) = self.outstanding_requests.get(&hash) else {
+                continue;
+            };
+            if request.sent_at + REPAIR_TIMEOUT != expiry {
+                continue;
+            }
+            let request = self.outstanding_requests.remove(&hash).unwrap();
+            timed_out_peers.extend(request.peers);
+            if request.answered {
+                continue;
+            }
+            METRICS.repair_requests_outstanding.dec();
+            retries
+                .entry(request.attempt + 1)
+                .or_default()
+                .push(request.req_type);
+        }
+
+        // count each peer only once, even if it missed a whole batch
+        for peer in timed_out_peers {
+            self.peers.record_timeout(peer, now);
+        }
+        for (attempt, requests) in retries {
+            debug!("retrying {} timed-out repair requests", requests.len());
+            if let Err(err) = self.send_requests(requests, attempt).await {
+                warn!("failed to retry repair requests: {err}");
             }
         }
     }
@@ -269,31 +496,45 @@ where
         }
 
         debug!("repairing block {h} in slot {slot}");
+        self.timeline.record_repair(*slot);
         let req = RepairRequestType::LastSliceRoot(block_id);
-        self.send_request(req).await.unwrap();
+        if let Err(err) = self.send_requests(vec![req], 0).await {
+            warn!("failed to send repair request: {err}");
+        }
     }
 
-    /// Handles a repair response, storing the received data.
+    /// Handles a repair response from `sender`, storing the received data.
     ///
     /// If the response contains a shred, it will be stored in the [`Blockstore`].
     /// Otherwise, metadata is stored in the [`Repair`] struct itself.
-    /// Does nothing if the provided `response` is not well-formed.
-    async fn handle_response(&mut self, response: RepairResponse) {
-        trace!("handling repair response: {response:?}");
+    /// Does nothing if the provided `response` is not well-formed, except for
+    /// blaming the peer that sent it, see [`Self::reject_response`].
+    /// Responses from peers we did not ask are ignored without blaming anyone.
+    async fn handle_response(&mut self, sender: ValidatorId, response: RepairResponse) {
+        trace!("handling repair response from {sender}: {response:?}");
         let request_hash = response.request_type().hash();
 
         // check whether we are (still) waiting on response to this request
-        let Some(_) = self.outstanding_requests.remove(&request_hash) else {
+        let Some(request) = self.outstanding_requests.get_mut(&request_hash) else {
             warn!("received repair response for unknown request {response:?}");
             return;
         };
+        let Some(pos) = request.peers.iter().position(|peer| *peer == sender) else {
+            warn!("received repair response from {sender}, which was not asked");
+            return;
+        };
+        if request.answered {
+            // another peer was faster, but this one did not time out
+            request.peers.swap_remove(pos);
+            return;
+        }
 
         match response {
             RepairResponse::LastSliceRoot(req_type, last_slice, root, proof) => {
                 // check validity of response
                 let RepairRequestType::LastSliceRoot(block_id) = &req_type else {
                     warn!("repair response (LastSliceRoot) to mismatching request {req_type:?}");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 };
                 let (_, block_hash) = block_id;
                 if !DoubleMerkleTree::check_proof_last(
@@ -303,48 +544,49 @@ where
                     &proof,
                 ) {
                     warn!("repair response (LastSliceRoot) with invalid proof");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 }
+                self.complete_request(&request_hash, sender);
 
                 // store slice Merkle root
                 self.slice_roots
                     .insert((block_id.clone(), last_slice), root);
 
-                // issue next requests
-                // TODO: do not request last slice root again
-                // TODO: already requests shreds for last slice here
-                for slice in last_slice.until() {
-                    let req_type = RepairRequestType::SliceRoot(block_id.clone(), slice);
-                    self.send_request(req_type).await.unwrap();
+                // issue next requests, the last slice root is already known
+                let slice_roots = last_slice
+                    .until()
+                    .filter(|slice| *slice != last_slice)
+                    .map(|slice| RepairRequestType::SliceRoot(block_id.clone(), slice))
+                    .collect();
+                if let Err(err) = self.send_requests(slice_roots, 0).await {
+                    warn!("failed to request slice roots: {err}");
                 }
+                self.request_missing_shreds(block_id, last_slice).await;
             }
             RepairResponse::SliceRoot(req_type, root, proof) => {
                 // check validity of response
                 let RepairRequestType::SliceRoot(ref block_id, slice) = req_type else {
                     warn!("repair response (SliceRoot) to mismatching request {req_type:?}");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 };
                 let (_, block_hash) = block_id;
                 if !DoubleMerkleTree::check_proof(&root, slice.inner(), block_hash, &proof) {
                     warn!("repair response (SliceRoot) with invalid proof");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 }
+                self.complete_request(&request_hash, sender);
 
                 // store slice Merkle root
                 self.slice_roots.insert((block_id.clone(), slice), root);
 
                 // issue next requests
-                // HACK: workaround for when other nodes don't have the first `DATA_SHREDS` shreds
-                for shred_index in ShredIndex::all() {
-                    let req = RepairRequestType::Shred(block_id.clone(), slice, shred_index);
-                    self.send_request(req).await.unwrap();
-                }
+                self.request_missing_shreds(block_id, slice).await;
             }
             RepairResponse::Shred(req_type, shred) => {
                 // check validity of response
                 let RepairRequestType::Shred(ref block_id, slice, index) = req_type else {
                     warn!("repair response (Shred) to mismatching request {req_type:?}");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 };
                 let (slot, block_hash) = block_id;
                 if shred.payload().header.slot != *slot
@@ -352,15 +594,16 @@ where
                     || shred.payload().shred_index != index
                 {
                     warn!("repair response (Shred) for mismatching shred index");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 }
                 let Some(root) = self.slice_roots.get(&(block_id.clone(), slice)) else {
                     unreachable!("issued repair request (Shred) before knowing slice root");
                 };
                 if !shred.verify_path_only(root) {
                     warn!("repair response (Shred) with invalid Merkle proof");
-                    return;
+                    return self.reject_response(&request_hash, sender).await;
                 }
+                self.complete_request(&request_hash, sender);
 
                 // store shred
                 let res = self
@@ -386,40 +629,129 @@ where
         }
     }
 
-    async fn send_request(&mut self, req_type: RepairRequestType) -> std::io::Result<()> {
-        let hash = req_type.hash();
+    /// Marks the outstanding request with the given `hash` as answered by `sender`.
+    ///
+    /// The request is kept until it times out, to also blame any other peers that never respond.
+    fn complete_request(&mut self, hash: &Hash, sender: ValidatorId) {
+        let Some(request) = self.outstanding_requests.get_mut(hash) else {
+            return;
+        };
+        METRICS.repair_requests_outstanding.dec();
+        request.answered = true;
+        request.peers.retain(|peer| *peer != sender);
+        self.peers
+            .record_response(sender, request.sent_at.elapsed());
+        if request.peers.is_empty() {
+            self.outstanding_requests.remove(hash);
+        }
+    }
 
-        let expiry = Instant::now() + REPAIR_TIMEOUT;
-        self.outstanding_requests
-            .insert(hash.clone(), req_type.clone());
-        self.request_timeouts.retain(|(_, h)| h != &hash);
-        self.request_timeouts.push((expiry, hash));
+    /// Handles an invalid response from `sender` to the outstanding request with the given `hash`.
+    ///
+    /// The `sender` is blamed. Since it is authenticated, others cannot get it blamed by spoofing responses.
+    /// If no other peer can still answer the request, it is retried immediately.
+    async fn reject_response(&mut self, hash: &Hash, sender: ValidatorId) {
+        self.peers.record_invalid(sender, Instant::now());
+        let Some(request) = self.outstanding_requests.get_mut(hash) else {
+            return;
+        };
+        request.peers.retain(|peer| *peer != sender);
+        if !request.peers.is_empty() {
+            return;
+        }
+        let request = self.outstanding_requests.remove(hash).unwrap();
+        METRICS.repair_requests_outstanding.dec();
+        if let Err(err) = self
+            .send_requests(vec![request.req_type], request.attempt + 1)
+            .await
+        {
+            warn!("failed to retry repair request: {err}");
+        }
+    }
 
-        let request = RepairRequest {
-            sender: self.epoch_info.own_id,
-            req_type,
+    /// Requests all shreds of the given `slice` that are not in blockstore yet.
+    ///
+    /// This requests more than the `DATA_SHREDS` needed to decode the slice,
+    /// as other nodes may not hold all shreds.
+    async fn request_missing_shreds(&mut self, block_id: &BlockId, slice: SliceIndex) {
+        let missing = {
+            let blockstore = self.blockstore.read().await;
+            ShredIndex::all()
+                .filter(|index| blockstore.get_shred(block_id, slice, *index).is_none())
+                .map(|index| RepairRequestType::Shred(block_id.clone(), slice, index))
+                .collect()
         };
-        // HACK: magic number to fix high-failure scenarios
-        let mut to_all = HashSet::new();
-        for _ in 0..10 {
-            to_all.insert(self.pick_random_peer());
-            if to_all.len() == 3 {
-                break;
-            }
+        if let Err(err) = self.send_requests(missing, 0).await {
+            warn!("failed to request missing shreds: {err}");
         }
-        self.network
-            .send_to_many(&request, to_all.into_iter())
-            .await?;
-        Ok(())
     }
 
-    fn pick_random_peer(&self) -> SocketAddr {
-        let mut rng = rand::rng();
-        let mut peer_info = self.sampler.sample_info(&mut rng);
-        while peer_info.id == self.epoch_info.own_id {
-            peer_info = self.sampler.sample_info(&mut rng);
+    /// Sends the given `requests`, which were retried `attempt` times before.
+    ///
+    /// Requests are tracked individually, but sent in as few batch requests as possible, see [`RepairRequestType::batch`].
+    /// The number of peers they are sent to grows with `attempt`, see [`fanout`].
+    ///
+    /// Peers that sending fails for are blamed right away and not waited on.
+    /// The requests stay outstanding regardless, so they are retried once they time out.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last send error, after trying all peers.
+    async fn send_requests(
+        &mut self,
+        requests: Vec<RepairRequestType>,
+        attempt: u32,
+    ) -> std::io::Result<()> {
+        if requests.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let peers = self.peers.pick(fanout(attempt), now);
+
+        let mut hashes = Vec::with_capacity(requests.len());
+        for req_type in &requests {
+            let hash = req_type.hash();
+            hashes.push(hash.clone());
+            let outstanding = OutstandingRequest {
+                req_type: req_type.clone(),
+                attempt,
+                sent_at: now,
+                peers: peers.clone(),
+                answered: false,
+            };
+            let previous = self.outstanding_requests.insert(hash.clone(), outstanding);
+            if previous.is_none_or(|previous| previous.answered) {
+                METRICS.repair_requests_outstanding.inc();
+            }
+            self.request_timeouts
+                .push(Reverse((now + REPAIR_TIMEOUT, hash)));
+        }
+
+        let mut result = Ok(());
+        let mut failed_peers = BTreeSet::new();
+        for req_type in RepairRequestType::batch(requests) {
+            let request = RepairRequest {
+                sender: self.epoch_info.own_id,
+                req_type,
+            };
+            for peer in &peers {
+                let addr = self.epoch_info.validator(*peer).repair_request_address;
+                if let Err(err) = self.network.send(&request, addr).await {
+                    failed_peers.insert(*peer);
+                    result = Err(err);
+                }
+            }
+        }
+
+        for peer in failed_peers {
+            self.peers.record_timeout(peer, now);
+            for hash in &hashes {
+                if let Some(request) = self.outstanding_requests.get_mut(hash) {
+                    request.peers.retain(|p| *p != peer);
+                }
+            }
         }
-        peer_info.repair_request_address
+        result
     }
 }
 
@@ -433,8 +765,9 @@ mod tests {
     use crate::consensus::{BlockstoreImpl, PoolImpl};
     use crate::crypto::signature::SecretKey;
     use crate::network::simulated::SimulatedNetworkCore;
-    use crate::network::{SimulatedNetwork, localhost_ip_sockaddr};
+    use crate::network::{Channel, Multiplexer, SimulatedNetwork, localhost_ip_sockaddr};
     use crate::shredder::TOTAL_SHREDS;
+    use crate::storage::MemoryStorage;
     use crate::test_utils::{create_random_shredded_block, generate_validators};
     use crate::types::Slot;
     use crate::types::slice_index::MAX_SLICES_PER_BLOCK;
@@ -489,15 +822,19 @@ mod tests {
         let epoch_info = Arc::new(epoch_info);
 
         // set up blockstore
+        let storage = Arc::new(MemoryStorage::default());
         let (votor_tx, votor_rx) = tokio::sync::mpsc::channel(100);
-        let blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>> = Arc::new(RwLock::new(
-            Box::new(BlockstoreImpl::new(epoch_info.clone(), votor_tx.clone())),
-        ));
+        let blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>> =
+            Arc::new(RwLock::new(Box::new(BlockstoreImpl::new(
+                epoch_info.clone(),
+                Arc::clone(&storage),
+                votor_tx.clone(),
+            ))));
 
         // set up pool
         let (repair_tx, repair_rx) = tokio::sync::mpsc::channel(100);
         let pool: Arc<RwLock<Box<dyn Pool + Send + Sync>>> = Arc::new(RwLock::new(Box::new(
-            PoolImpl::new(epoch_info.clone(), votor_tx, repair_tx.clone()),
+            PoolImpl::new(epoch_info.clone(), storage, votor_tx, repair_tx.clone()),
         )));
 
         // create and start Repair instance
@@ -571,55 +908,55 @@ mod tests {
         let port1 = localhost_ip_sockaddr(3);
         other_network_request.send(&response, port1).await.unwrap();
 
-        // expect SliceRoot requests next
+        // expect batched requests for all other slice roots, and
+        // for the shreds of each slice as soon as its root is known
+        let mut num_requests = 0;
         let mut slice_roots_requested = BTreeSet::new();
-        for _ in 0..num_slices {
+        let mut shreds_requested = BTreeSet::new();
+        while shreds_requested.len() < num_slices {
             let msg = other_network_request.receive().await.unwrap();
-
-            for slice in SliceIndex::all().take(num_slices) {
-                let req_type = RepairRequestType::SliceRoot(block_to_repair.clone(), slice);
-                if msg.req_type == req_type {
-                    slice_roots_requested.insert(slice);
-                    break;
+            num_requests += 1;
+            let responses: Vec<_> = match msg.req_type {
+                RepairRequestType::SliceRoots(block_id, first, last) => {
+                    assert_eq!(block_id, block_to_repair);
+                    last.until()
+                        .skip(first.inner())
+                        .map(|slice| {
+                            assert!(slice_roots_requested.insert(slice));
+                            let req_type = RepairRequestType::SliceRoot(block_id.clone(), slice);
+                            let root = shreds[slice.inner()][0].merkle_root.clone();
+                            let proof = merkle_tree.create_proof(slice.inner());
+                            RepairResponse::SliceRoot(req_type, root, proof)
+                        })
+                        .collect()
                 }
-            }
-        }
-
-        // assert all other slice roots requested + answer the requests
-        for slice in SliceIndex::all().take(num_slices) {
-            assert!(slice_roots_requested.contains(&slice));
-            let req_type = RepairRequestType::SliceRoot(block_to_repair.clone(), slice);
-            let root = shreds[slice.inner()][0].merkle_root.clone();
-            let proof = merkle_tree.create_proof(slice.inner());
-            let response = RepairResponse::SliceRoot(req_type, root, proof);
-            other_network_request.send(&response, port1).await.unwrap();
-
-            // expect Shred requests for this slice next
-            let mut shreds_requested = BTreeSet::new();
-            for _ in ShredIndex::all() {
-                let msg = other_network_request.receive().await.unwrap();
-                for shred_index in ShredIndex::all() {
-                    let req_type =
-                        RepairRequestType::Shred(block_to_repair.clone(), slice, shred_index);
-                    if msg.req_type == req_type {
-                        shreds_requested.insert(shred_index);
-                        break;
-                    }
+                RepairRequestType::Shreds(block_id, slice, indices) => {
+                    assert_eq!(block_id, block_to_repair);
+                    assert_eq!(indices.len(), TOTAL_SHREDS);
+                    assert!(shreds_requested.insert(slice));
+                    indices
+                        .into_iter()
+                        .map(|index| {
+                            let req_type = RepairRequestType::Shred(block_id.clone(), slice, index);
+                            let shred = shreds[slice.inner()][*index].clone().into_shred();
+                            RepairResponse::Shred(req_type, shred)
+                        })
+                        .collect()
                 }
-            }
-
-            // assert all shreds requested + answer the requests
-            let slice_shreds = shreds[slice.inner()].clone();
-            for (shred_index, shred) in slice_shreds.into_iter().take(TOTAL_SHREDS).enumerate() {
-                let shred_index = ShredIndex::new(shred_index).unwrap();
-                assert!(shreds_requested.contains(&shred_index));
-                let req_type =
-                    RepairRequestType::Shred(block_to_repair.clone(), slice, shred_index);
-                let response = RepairResponse::Shred(req_type, shred.into_shred());
+                req_type => panic!("unexpected repair request {req_type:?}"),
+            };
+            for response in responses {
                 other_network_request.send(&response, port1).await.unwrap();
             }
         }
 
+        // last slice root is not requested again, and slice roots are batched
+        let last_slice = SliceIndex::new_unchecked(num_slices - 1);
+        let other_slices: BTreeSet<_> = last_slice.until().filter(|s| *s != last_slice).collect();
+        assert_eq!(slice_roots_requested, other_slices);
+        let slice_roots_requests = (num_slices - 1).div_ceil(MAX_SLICE_ROOTS_PER_REQUEST);
+        assert_eq!(num_requests, slice_roots_requests + num_slices);
+
         // after some time block should be repaired
         tokio::time::sleep(Duration::from_millis(100)).await;
         assert!(
@@ -631,6 +968,102 @@ mod tests {
         );
     }
 
+    /// Repairs a block from several peers over a lossy network.
+    ///
+    /// One of the peers does not have the block, so it never answers.
+    /// Repair still succeeds by retrying with other peers, and the unresponsive peer is deprioritized.
+    #[tokio::test(start_paused = true)]
+    async fn repair_over_lossy_network() {
+        const PEERS: u64 = 4;
+        let (_, epoch_info) = generate_validators(PEERS + 1);
+        let mut validators = epoch_info.validators.clone();
+        let leader_key = SecretKey::new(&mut rand::rng());
+        validators[0].pubkey = leader_key.to_pk();
+        for v in &mut validators {
+            v.repair_request_address = localhost_ip_sockaddr(v.id as u16);
+            v.repair_response_address = localhost_ip_sockaddr(v.id as u16);
+        }
+        let core = Arc::new(SimulatedNetworkCore::new(10, 1.0, 0.1).with_seed(42));
+
+        let slot = Slot::genesis().next();
+        let (block_hash, _, shreds) = create_random_shredded_block(slot, 2, &leader_key);
+        let block_to_repair = (slot, block_hash);
+
+        // all peers serve repair requests, but validator 1 does not have the block
+        let mut votor_receivers = Vec::new();
+        for id in 0..PEERS {
+            let epoch_info = Arc::new(EpochInfo::new(id, validators.clone()));
+            let (votor_tx, votor_rx) = tokio::sync::mpsc::channel(100);
+            votor_receivers.push(votor_rx);
+            let blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>> =
+                Arc::new(RwLock::new(Box::new(BlockstoreImpl::new(
+                    epoch_info.clone(),
+                    Arc::new(MemoryStorage::default()),
+                    votor_tx,
+                ))));
+            if id != 1 {
+                let mut b = blockstore.write().await;
+                for shred in shreds.iter().flatten() {
+                    let _ = b
+                        .add_shred_from_disseminator(shred.clone().into_shred())
+                        .await;
+                }
+            }
+            let mux = Multiplexer::new_authenticated(core.join_unlimited(id).await);
+            let network = mux.channel(Channel::RepairResponses, Channel::RepairRequests);
+            let handler = RepairRequestHandler::new(epoch_info, blockstore, network);
+            tokio::spawn(async move {
+                handler.run_authenticated().await;
+            });
+        }
+
+        // last validator repairs the block
+        let epoch_info = Arc::new(EpochInfo::new(PEERS, validators));
+        let storage = Arc::new(MemoryStorage::default());
+        let (votor_tx, votor_rx) = tokio::sync::mpsc::channel(100);
+        votor_receivers.push(votor_rx);
+        let blockstore: Arc<RwLock<Box<dyn Blockstore + Send + Sync>>> =
+            Arc::new(RwLock::new(Box::new(BlockstoreImpl::new(
+                epoch_info.clone(),
+                Arc::clone(&storage),
+                votor_tx.clone(),
+            ))));
+        let (repair_tx, repair_rx) = tokio::sync::mpsc::channel(100);
+        let pool: Arc<RwLock<Box<dyn Pool + Send + Sync>>> = Arc::new(RwLock::new(Box::new(
+            PoolImpl::new(epoch_info.clone(), storage, votor_tx, repair_tx.clone()),
+        )));
+        let mux = Multiplexer::new_authenticated(core.join_unlimited(PEERS).await);
+        let network = mux.channel(Channel::RepairRequests, Channel::RepairResponses);
+        let mut repair =
+            Repair::new(Arc::clone(&blockstore), pool, network, epoch_info).with_seed(42);
+        repair_tx.send(block_to_repair.clone()).await.unwrap();
+
+        // block should be repaired after a few retries,
+        // afterwards let the remaining requests time out
+        let repaired = async {
+            while blockstore
+                .read()
+                .await
+                .get_block(&block_to_repair)
+                .is_none()
+            {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            tokio::time::sleep(REPAIR_TIMEOUT).await;
+        };
+        tokio::select! {
+            () = repair.repair_loop(repair_rx) => unreachable!(),
+            res = tokio::time::timeout(REPAIR_TIMEOUT * 10, repaired) => assert!(res.is_ok()),
+        }
+
+        // the peer that never answers is now the least preferred one
+        let unresponsive = repair.peers.score(1);
+        for peer in [0, 2, 3] {
+            assert!(unresponsive < repair.peers.score(peer));
+        }
+        drop(votor_receivers);
+    }
+
     #[tokio::test]
     async fn answer_requests() {
         const SLICES: usize = 2;
@@ -727,4 +1160,100 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn answer_batch_requests() {
+        const SLICES: usize = 3;
+        let (_sender, blockstore, _other_network_request, other_network, sk) =
+            create_repair_instance().await;
+
+        // create a block and ingest it into blockstore
+        let slot = Slot::genesis().next();
+        let (block_hash, _, shreds) = create_random_shredded_block(slot, SLICES, &sk);
+        let block_id = (slot, block_hash);
+        for slice_shreds in shreds.clone() {
+            let mut b = blockstore.write().await;
+            for shred in slice_shreds {
+                let _ = b.add_shred_from_disseminator(shred.into_shred()).await;
+            }
+        }
+        let port1 = localhost_ip_sockaddr(2);
+
+        // range of slice roots is answered with one response per slice
+        let first = SliceIndex::new_unchecked(1);
+        let last = SliceIndex::new_unchecked(SLICES - 1);
+        let request = RepairRequest {
+            req_type: RepairRequestType::SliceRoots(block_id.clone(), first, last),
+            sender: 0,
+        };
+        other_network.send(&request, port1).await.unwrap();
+        for slice in last.until().skip(first.inner()) {
+            let msg = other_network.receive().await.unwrap();
+            let RepairResponse::SliceRoot(req_type, root, _) = msg else {
+                panic!("not SliceRoot response");
+            };
+            assert_eq!(
+                req_type,
+                RepairRequestType::SliceRoot(block_id.clone(), slice)
+            );
+            assert_eq!(root, shreds[slice.inner()][0].merkle_root);
+        }
+
+        // multiple shreds are answered with one response per shred
+        let slice = SliceIndex::new_unchecked(0);
+        let indices: Vec<_> = ShredIndex::all().step_by(2).collect();
+        let request = RepairRequest {
+            req_type: RepairRequestType::Shreds(block_id.clone(), slice, indices.clone()),
+            sender: 0,
+        };
+        other_network.send(&request, port1).await.unwrap();
+        for index in indices {
+            let msg = other_network.receive().await.unwrap();
+            let RepairResponse::Shred(req_type, shred) = msg else {
+                panic!("not Shred response");
+            };
+            assert_eq!(
+                req_type,
+                RepairRequestType::Shred(block_id.clone(), slice, index)
+            );
+            assert_eq!(shred.payload().data, shreds[0][*index].payload().data);
+        }
+    }
+
+    #[test]
+    fn batch_requests() {
+        let block_id: BlockId = (Slot::new(1), hash(b"block").into());
+        let slices = SliceIndex::all().take(MAX_SLICE_ROOTS_PER_REQUEST + 2);
+        let mut requests: Vec<_> = slices
+            .map(|slice| RepairRequestType::SliceRoot(block_id.clone(), slice))
+            .collect();
+        let slice = SliceIndex::new_unchecked(3);
+        let mut indices: Vec<_> = ShredIndex::all().collect();
+        indices.reverse();
+        for index in indices {
+            requests.push(RepairRequestType::Shred(block_id.clone(), slice, index));
+        }
+        requests.push(RepairRequestType::LastSliceRoot(block_id.clone()));
+
+        // slice roots are split into ranges of limited size, other requests are kept
+        let batches = RepairRequestType::batch(requests);
+        let index = |i| SliceIndex::new_unchecked(i);
+        assert_eq!(
+            batches,
+            vec![
+                RepairRequestType::LastSliceRoot(block_id.clone()),
+                RepairRequestType::SliceRoots(
+                    block_id.clone(),
+                    index(0),
+                    index(MAX_SLICE_ROOTS_PER_REQUEST - 1)
+                ),
+                RepairRequestType::SliceRoots(
+                    block_id.clone(),
+                    index(MAX_SLICE_ROOTS_PER_REQUEST),
+                    index(MAX_SLICE_ROOTS_PER_REQUEST + 1)
+                ),
+                RepairRequestType::Shreds(block_id, slice, ShredIndex::all().collect()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,272 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scoring of peers to send repair requests to.
+//!
+//! For each peer, [`PeerScores`] tracks how often it answered our requests,
+//! how quickly it did so, and whether it sent responses with invalid proofs.
+//! Peers are then picked stake-weighted, scaled by their score.
+//! Peers that repeatedly time out or send invalid data are avoided for a while.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use rand::seq::IndexedRandom;
+use tokio::time::Instant;
+
+use super::REPAIR_TIMEOUT;
+use crate::{ValidatorId, ValidatorInfo};
+
+/// Number of peers a repair request is sent to on the first attempt.
+///
+/// Sending to more than one peer right away keeps repair fast under packet loss,
+/// as one lost request or response does not delay it by a whole [`REPAIR_TIMEOUT`].
+const BASE_FANOUT: usize = 3;
+
+/// Maximum number of peers a single repair request is sent to.
+pub(super) const MAX_FANOUT: usize = 8;
+
+/// Number of timeouts in a row after which a peer is avoided.
+const MAX_CONSECUTIVE_TIMEOUTS: u32 = 3;
+
+/// Time for which a peer is avoided after its first offense.
+///
+/// Doubles with every further offense, up to [`MAX_BACKOFF`].
+const BASE_BACKOFF: Duration = REPAIR_TIMEOUT;
+
+/// Maximum time for which a peer is avoided.
+const MAX_BACKOFF: Duration = REPAIR_TIMEOUT.checked_mul(32).unwrap();
+
+/// Returns the number of peers to send a request to on the given `attempt`.
+///
+/// The first attempt (0) goes to [`BASE_FANOUT`] peers, every retry doubles this.
+pub(super) fn fanout(attempt: u32) -> usize {
+    BASE_FANOUT
+        .checked_shl(attempt)
+        .map_or(MAX_FANOUT, |n| n.min(MAX_FANOUT))
+}
+
+/// Statistics about the repair responses of a single peer.
+#[derive(Clone, Debug, Default)]
+struct PeerStats {
+    /// Requests this peer answered with a valid response.
+    responses: u64,
+    /// Requests this peer was asked and did not answer in time.
+    timeouts: u64,
+    /// Responses from this peer that failed verification.
+    invalid: u64,
+    /// Exponentially weighted moving average of response latency.
+    latency: Option<Duration>,
+    /// Timeouts since the last valid response.
+    consecutive_timeouts: u32,
+    /// Until when this peer is avoided, if at all.
+    backoff_until: Option<Instant>,
+}
+
+impl PeerStats {
+    /// Returns the score of this peer, a value in `(0, 1]`.
+    ///
+    /// Peers without any history start out at `0.5`.
+    fn score(&self) -> f64 {
+        let answered = (self.responses + 1) as f64;
+        let total = (self.responses + self.timeouts + self.invalid + 2) as f64;
+        let response_rate = answered / total;
+        let latency = self.latency.map_or(0.0, |l| l.as_secs_f64());
+        response_rate / (1.0 + latency / REPAIR_TIMEOUT.as_secs_f64())
+    }
+
+    fn is_backed_off(&self, now: Instant) -> bool {
+        self.backoff_until.is_some_and(|until| until > now)
+    }
+
+    /// Avoids this peer for a time that doubles with each of its `offenses`.
+    fn back_off(&mut self, offenses: u32, now: Instant) {
+        let factor = 1_u32
+            .checked_shl(offenses.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let backoff = BASE_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF);
+        self.backoff_until = Some(now + backoff);
+    }
+}
+
+/// Keeps track of the quality of repair peers and picks peers accordingly.
+pub(super) struct PeerScores {
+    own_id: ValidatorId,
+    validators: Vec<ValidatorInfo>,
+    stats: BTreeMap<ValidatorId, PeerStats>,
+    /// Randomness for picking peers.
+    ///
+    /// Seeded from OS entropy, so other nodes cannot predict whom we will ask.
+    rng: SmallRng,
+}
+
+impl PeerScores {
+    /// Creates a new instance, without any statistics about peers yet.
+    pub(super) fn new(own_id: ValidatorId, validators: Vec<ValidatorInfo>) -> Self {
+        Self {
+            own_id,
+            validators,
+            stats: BTreeMap::new(),
+            rng: SmallRng::from_os_rng(),
+        }
+    }
+
+    /// Makes picking peers deterministic, for reproducible tests and simulations.
+    #[must_use]
+    pub(super) fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Returns the score of `peer`, a value in `(0, 1]`.
+    pub(super) fn score(&self, peer: ValidatorId) -> f64 {
+        self.stats.get(&peer).map_or(0.5, PeerStats::score)
+    }
+
+    /// Picks up to `count` distinct peers to send a repair request to.
+    ///
+    /// Peers are picked randomly, weighted by stake times score.
+    /// Peers that are currently backed off are only picked if there are no others.
+    pub(super) fn pick(&mut self, count: usize, now: Instant) -> Vec<ValidatorId> {
+        let peers: Vec<_> = self
+            .validators
+            .iter()
+            .filter(|v| v.id != self.own_id)
+            .collect();
+        let available: Vec<_> = peers
+            .iter()
+            .copied()
+            .filter(|v| !self.stats.get(&v.id).is_some_and(|s| s.is_backed_off(now)))
+            .collect();
+        let candidates = if available.is_empty() {
+            peers
+        } else {
+            available
+        };
+        let weighted: Vec<_> = candidates
+            .into_iter()
+            .map(|v| (v.id, v.stake as f64 * self.score(v.id)))
+            .collect();
+        weighted
+            .choose_multiple_weighted(&mut self.rng, count, |(_, weight)| *weight)
+            .map(|chosen| chosen.map(|(id, _)| *id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Records a valid response from `peer`, received `latency` after sending the request.
+    pub(super) fn record_response(&mut self, peer: ValidatorId, latency: Duration) {
+        let stats = self.stats.entry(peer).or_default();
+        stats.responses += 1;
+        stats.consecutive_timeouts = 0;
+        stats.backoff_until = None;
+        stats.latency = Some(match stats.latency {
+            None => latency,
+            Some(avg) => (avg * 7 + latency) / 8,
+        });
+    }
+
+    /// Records that `peer` did not answer a request in time.
+    ///
+    /// After [`MAX_CONSECUTIVE_TIMEOUTS`] timeouts in a row, the peer is backed off.
+    pub(super) fn record_timeout(&mut self, peer: ValidatorId, now: Instant) {
+        let stats = self.stats.entry(peer).or_default();
+        stats.timeouts += 1;
+        stats.consecutive_timeouts += 1;
+        if stats.consecutive_timeouts >= MAX_CONSECUTIVE_TIMEOUTS {
+            let offenses = stats.consecutive_timeouts - MAX_CONSECUTIVE_TIMEOUTS + 1;
+            stats.back_off(offenses, now);
+        }
+    }
+
+    /// Records that `peer` sent a response that failed verification.
+    ///
+    /// The peer is backed off immediately.
+    pub(super) fn record_invalid(&mut self, peer: ValidatorId, now: Instant) {
+        let stats = self.stats.entry(peer).or_default();
+        stats.invalid += 1;
+        let offenses = u32::try_from(stats.invalid).unwrap_or(u32::MAX);
+        stats.back_off(offenses, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::generate_validators;
+
+    fn create_peer_scores(num_validators: u64) -> PeerScores {
+        let (_, epoch_info) = generate_validators(num_validators);
+        PeerScores::new(0, epoch_info.validators.clone()).with_seed(0)
+    }
+
+    /// Counts how often each peer is picked as the single peer over many rounds.
+    fn count_picks(peers: &mut PeerScores, now: Instant) -> BTreeMap<ValidatorId, usize> {
+        let mut counts = BTreeMap::new();
+        for _ in 0..1000 {
+            for peer in peers.pick(1, now) {
+                *counts.entry(peer).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn never_picks_self() {
+        let mut peers = create_peer_scores(4);
+        let now = Instant::now();
+        let picked = peers.pick(10, now);
+        assert_eq!(picked.len(), 3);
+        assert!(!picked.contains(&0));
+    }
+
+    #[test]
+    fn prefers_responsive_peers() {
+        let mut peers = create_peer_scores(3);
+        let now = Instant::now();
+        for _ in 0..10 {
+            peers.record_response(1, Duration::from_millis(10));
+            peers.record_timeout(2, now);
+            peers.record_response(2, REPAIR_TIMEOUT);
+        }
+        let counts = count_picks(&mut peers, now);
+        assert!(counts[&1] > 2 * counts[&2]);
+    }
+
+    #[test]
+    fn backs_off_from_bad_peers() {
+        let mut peers = create_peer_scores(3);
+        let now = Instant::now();
+
+        // a single invalid response leads to backoff
+        peers.record_invalid(1, now);
+        let counts = count_picks(&mut peers, now);
+        assert_eq!(counts.get(&1), None);
+
+        // a few timeouts in a row are tolerated, but not more
+        for _ in 0..MAX_CONSECUTIVE_TIMEOUTS - 1 {
+            peers.record_timeout(2, now);
+        }
+        assert!(count_picks(&mut peers, now).contains_key(&2));
+        peers.record_timeout(2, now);
+
+        // with all peers backed off, they are still picked
+        let counts = count_picks(&mut peers, now);
+        assert!(counts.contains_key(&1) && counts.contains_key(&2));
+
+        // backoff ends after some time, or after a valid response
+        let later = now + BASE_BACKOFF;
+        assert!(count_picks(&mut peers, later).contains_key(&1));
+        peers.record_response(2, Duration::from_millis(10));
+        assert!(!peers.stats[&2].is_backed_off(now));
+    }
+
+    #[test]
+    fn escalating_fanout() {
+        assert_eq!(fanout(0), BASE_FANOUT);
+        assert_eq!(fanout(1), 2 * BASE_FANOUT);
+        assert_eq!(fanout(2), MAX_FANOUT);
+        assert_eq!(fanout(100), MAX_FANOUT);
+    }
+}
@@ -0,0 +1,503 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSON-RPC API for querying a running node and submitting transactions.
+//!
+//! [`RpcServer`] serves [JSON-RPC 2.0](https://www.jsonrpc.org/specification) over HTTP.
+//! Requests are `POST`ed to `/`, with the following methods:
+//! - `getHealth`: `"ok"` if the node finalized a block recently, an error otherwise.
+//! - `getFinalizedSlot`: highest finalized slot.
+//! - `getBlock(slot | hash)`: finalized block in the given slot, or block with the given hex hash.
+//! - `getCerts(slot)`: all certificates the node holds for the given slot.
+//! - `getLeaderSchedule([firstSlot, [numSlots]])`: leaders for the given range of slots,
+//!   at most one epoch.
+//! - `getValidators`: all validators of the current epoch.
+//! - `sendTransaction(hex)`: forwards the hex-encoded transaction to the upcoming leaders.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::routing::post;
+use log::{debug, warn};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tokio::sync::{RwLock, broadcast};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::consensus::{
+    BlockMetadata, Blockstore, Cert, ConsensusEvent, EpochInfo, Pool, SLOTS_PER_EPOCH,
+    SLOTS_PER_WINDOW,
+};
+use crate::crypto::Hash;
+use crate::crypto::merkle::{BlockHash, MerkleRoot};
+use crate::network::Network;
+use crate::types::Slot;
+use crate::{All2All, Alpenglow, Block, Disseminator, MAX_TRANSACTION_SIZE, Transaction};
+
+/// Node is considered unhealthy if it did not finalize any block for this long.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(300);
+/// Number of slots returned by `getLeaderSchedule` by default.
+const DEFAULT_LEADER_SCHEDULE_SLOTS: u64 = 10 * SLOTS_PER_WINDOW;
+/// Maximum number of slots returned by a single `getLeaderSchedule` call.
+const MAX_LEADER_SCHEDULE_SLOTS: u64 = SLOTS_PER_EPOCH;
+/// Number of leader windows, starting with the current one, `sendTransaction` forwards to.
+const FORWARD_WINDOWS: u64 = 2;
+
+/// Error codes defined by the JSON-RPC 2.0 specification.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Application-defined error code, returned by `getHealth` if the node is unhealthy.
+const NODE_UNHEALTHY: i64 = -32000;
+
+/// Error returned by a JSON-RPC method.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+/// JSON-RPC server for a single node.
+///
+/// `N` is the network used to forward submitted transactions to leaders.
+pub struct RpcServer<N> {
+    state: Arc<RpcState<N>>,
+}
+
+struct RpcState<N> {
+    epoch_info: Arc<EpochInfo>,
+    pool: Arc<RwLock<Box<dyn Pool + Send + Sync>>>,
+    blockstore: Arc<RwLock<Blockstore>>,
+    txs_network: N,
+    /// Time of the last finalization, or when the server was created.
+    last_progress: std::sync::Mutex<Instant>,
+}
+
+impl<N> RpcServer<N>
+where
+    N: Network<Send = Transaction> + 'static,
+{
+    /// Creates a new server for the given `node`.
+    ///
+    /// Should be called before running the node, to observe all finalizations.
+    /// Submitted transactions are forwarded over `txs_network`.
+    pub fn new<A, D, T>(node: &Alpenglow<A, D, T>, txs_network: N) -> Self
+    where
+        A: All2All + Send + Sync + 'static,
+        D: Disseminator + Send + Sync + 'static,
+        T: crate::network::TransactionNetwork + 'static,
+    {
+        let state = Arc::new(RpcState {
+            epoch_info: node.get_epoch_info(),
+            pool: node.get_pool(),
+            blockstore: node.blockstore(),
+            txs_network,
+            last_progress: std::sync::Mutex::new(Instant::now()),
+        });
+        tokio::spawn(track_progress(Arc::downgrade(&state), node.subscribe()));
+        Self { state }
+    }
+
+    /// Serves requests on `addr` until `cancel_token` is cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if binding to `addr` fails.
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        cancel_token: CancellationToken,
+    ) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve_on(listener, cancel_token).await
+    }
+
+    /// Same as [`Self::serve`], but on an already bound `listener`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections fails.
+    pub async fn serve_on(
+        self,
+        listener: TcpListener,
+        cancel_token: CancellationToken,
+    ) -> std::io::Result<()> {
+        debug!("serving JSON-RPC on {}", listener.local_addr()?);
+        let app = Router::new()
+            .route("/", post(handle_http::<N>))
+            .with_state(self.state);
+        axum::serve(listener, app)
+            .with_graceful_shutdown(cancel_token.cancelled_owned())
+            .await
+    }
+
+    /// Handles a single JSON-RPC request, given as raw bytes.
+    ///
+    /// Returns the JSON-RPC response, also for malformed requests.
+    pub async fn handle(&self, request: &[u8]) -> Value {
+        self.state.handle(request).await
+    }
+}
+
+async fn handle_http<N>(State(state): State<Arc<RpcState<N>>>, body: Bytes) -> axum::Json<Value>
+where
+    N: Network<Send = Transaction> + 'static,
+{
+    axum::Json(state.handle(&body).await)
+}
+
+/// Keeps [`RpcState::last_progress`] up to date, until the server is dropped.
+async fn track_progress<N>(
+    state: std::sync::Weak<RpcState<N>>,
+    mut events: broadcast::Receiver<ConsensusEvent>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) if event.is_finalization() => {}
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        *state.last_progress.lock().unwrap() = Instant::now();
+    }
+}
+
+impl<N> RpcState<N>
+where
+    N: Network<Send = Transaction>,
+{
+    async fn handle(&self, request: &[u8]) -> Value {
+        let request: Value = match serde_json::from_slice(request) {
+            Ok(request) => request,
+            Err(err) => {
+                return response(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, err.to_string())),
+                );
+            }
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return response(id, Err(RpcError::new(INVALID_REQUEST, "missing method")));
+        };
+        let params = match request.get("params") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(params)) => params.clone(),
+            Some(_) => {
+                let err = RpcError::new(INVALID_REQUEST, "params must be an array");
+                return response(id, Err(err));
+            }
+        };
+        let result = match method {
+            "getHealth" => self.get_health(),
+            "getFinalizedSlot" => self.get_finalized_slot().await,
+            "getBlock" => self.get_block(&params).await,
+            "getCerts" => self.get_certs(&params).await,
+            "getLeaderSchedule" => self.get_leader_schedule(&params).await,
+            "getValidators" => self.get_validators(),
+            "sendTransaction" => self.send_transaction(&params).await,
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {method}"),
+            )),
+        };
+        response(id, result)
+    }
+
+    fn get_health(&self) -> RpcResult {
+        let elapsed = self.last_progress.lock().unwrap().elapsed();
+        if elapsed > HEALTH_TIMEOUT {
+            let msg = format!("no finalization for {} s", elapsed.as_secs());
+            return Err(RpcError::new(NODE_UNHEALTHY, msg));
+        }
+        Ok(json!("ok"))
+    }
+
+    async fn get_finalized_slot(&self) -> RpcResult {
+        Ok(json!(self.pool.read().await.finalized_slot().inner()))
+    }
+
+    async fn get_block(&self, params: &[Value]) -> RpcResult {
+        let found = match params.first() {
+            Some(Value::Number(_)) => {
+                let slot = slot_param(params, 0)?;
+                let hash = self.pool.read().await.finalized_block(slot);
+                let blockstore = self.blockstore.read().await;
+                hash.and_then(|hash| blockstore.load_block_from_db(slot, hash))
+                    .map(|block| (slot, block))
+            }
+            Some(Value::String(hash)) => {
+                let hash = parse_hash(hash)?;
+                self.blockstore.read().await.load_block_by_hash(hash)
+            }
+            _ => return Err(RpcError::invalid_params("expected slot or block hash")),
+        };
+        let Some((slot, block)) = found else {
+            return Ok(Value::Null);
+        };
+        let metadata = self
+            .blockstore
+            .read()
+            .await
+            .load_block_metadata(slot, block.block_hash());
+        Ok(block_to_json(slot, &block, metadata.as_ref()))
+    }
+
+    async fn get_certs(&self, params: &[Value]) -> RpcResult {
+        let slot = slot_param(params, 0)?;
+        let certs = self.pool.read().await.certs(slot);
+        Ok(Value::Array(certs.iter().map(cert_to_json).collect()))
+    }
+
+    async fn get_leader_schedule(&self, params: &[Value]) -> RpcResult {
+        let first_slot = match params.first() {
+            Some(_) => slot_param(params, 0)?,
+            None => self.pool.read().await.finalized_slot().next(),
+        };
+        let num_slots = match params.get(1) {
+            Some(n) => n
+                .as_u64()
+                .ok_or_else(|| RpcError::invalid_params("numSlots must be an integer"))?,
+            None => DEFAULT_LEADER_SCHEDULE_SLOTS,
+        };
+        if num_slots > MAX_LEADER_SCHEDULE_SLOTS {
+            let msg = format!("numSlots must be at most {MAX_LEADER_SCHEDULE_SLOTS}");
+            return Err(RpcError::invalid_params(msg));
+        }
+        let schedule = (first_slot.inner()..first_slot.inner().saturating_add(num_slots))
+            .map(|slot| {
+                let leader = self.epoch_info.leader(Slot::new(slot)).id;
+                json!({ "slot": slot, "leader": leader })
+            })
+            .collect();
+        Ok(Value::Array(schedule))
+    }
+
+    fn get_validators(&self) -> RpcResult {
+        serde_json::to_value(&self.epoch_info.validators)
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string()))
+    }
+
+    async fn send_transaction(&self, params: &[Value]) -> RpcResult {
+        let Some(Value::String(tx)) = params.first() else {
+            return Err(RpcError::invalid_params("expected hex-encoded transaction"));
+        };
+        let tx = hex::decode(tx).map_err(|err| RpcError::invalid_params(err.to_string()))?;
+        if tx.len() > MAX_TRANSACTION_SIZE {
+            let msg = format!("transaction exceeds {MAX_TRANSACTION_SIZE} bytes");
+            return Err(RpcError::invalid_params(msg));
+        }
+
+        // forward to leaders of the current and next windows
+        let next_slot = self.pool.read().await.finalized_slot().next();
+        let mut leaders = (0..FORWARD_WINDOWS)
+            .map(|w| {
+                self.epoch_info
+                    .leader(Slot::new(next_slot.inner() + w * SLOTS_PER_WINDOW))
+            })
+            .collect::<Vec<_>>();
+        leaders.dedup_by_key(|leader| leader.id);
+        let addrs = leaders.iter().map(|leader| leader.all2all_address);
+        if let Err(err) = self.txs_network.send_to_many(&Transaction(tx), addrs).await {
+            warn!("failed to forward transaction: {err}");
+            return Err(RpcError::new(INTERNAL_ERROR, err.to_string()));
+        }
+        let ids = leaders.iter().map(|leader| leader.id).collect::<Vec<_>>();
+        Ok(json!({ "forwardedTo": ids }))
+    }
+}
+
+fn response(id: Value, result: RpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+fn slot_param(params: &[Value], index: usize) -> Result<Slot, RpcError> {
+    params
+        .get(index)
+        .and_then(Value::as_u64)
+        .map(Slot::new)
+        .ok_or_else(|| RpcError::invalid_params("expected slot"))
+}
+
+fn parse_hash(hash: &str) -> Result<BlockHash, RpcError> {
+    let bytes = hex::decode(hash).map_err(|err| RpcError::invalid_params(err.to_string()))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| RpcError::invalid_params("block hash must be 32 bytes"))?;
+    Ok(Hash::from(bytes).into())
+}
+
+fn block_to_json(slot: Slot, block: &Block, metadata: Option<&BlockMetadata>) -> Value {
+    json!({
+        "slot": slot.inner(),
+        "blockHash": hex::encode(block.block_hash()),
+        "parentSlot": block.parent().inner(),
+        "parentHash": hex::encode(block.parent_hash()),
+        "producer": metadata.map(|m| m.producer),
+        "proposedTimestamp": metadata.map(|m| m.proposed_timestamp),
+        "finalizedTimestamp": metadata.and_then(|m| m.finalized_timestamp),
+    })
+}
+
+fn cert_to_json(cert: &Cert) -> Value {
+    let kind = match cert {
+        Cert::Notar(_) => "notar",
+        Cert::NotarFallback(_) => "notarFallback",
+        Cert::Skip(_) => "skip",
+        Cert::FastFinal(_) => "fastFinal",
+        Cert::Final(_) => "final",
+    };
+    json!({
+        "type": kind,
+        "slot": cert.slot().inner(),
+        "blockHash": cert.block_hash().map(|hash| hex::encode(hash.as_hash())),
+        "stake": cert.stake(),
+        "signers": cert.signers().collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::consensus::{BlockstoreImpl, PoolImpl, Vote};
+    use crate::network::SimulatedNetwork;
+    use crate::network::simulated::SimulatedNetworkCore;
+    use crate::storage::{MemoryStorage, Storage};
+    use crate::test_utils::{create_random_shredded_block, generate_validators_with_keys};
+
+    type TestState = RpcState<SimulatedNetwork<Transaction, Transaction>>;
+
+    /// Creates the state of a server whose node finalized a block in slot 1.
+    ///
+    /// Returns the state together with the hash of the finalized block.
+    async fn finalized_state() -> (TestState, BlockHash) {
+        let (signing_sks, sks, validators) = generate_validators_with_keys(11);
+        let epoch_info = Arc::new(EpochInfo::new(0, validators));
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let (votor_tx, _votor_rx) = mpsc::channel(1024);
+        let (repair_tx, _repair_rx) = mpsc::channel(1024);
+        let mut blockstore =
+            BlockstoreImpl::new(epoch_info.clone(), storage.clone(), votor_tx.clone());
+        let mut pool = PoolImpl::new(epoch_info.clone(), storage, votor_tx, repair_tx);
+
+        let slot = Slot::genesis().next();
+        let leader = epoch_info.leader(slot).id as usize;
+        let (hash, _, shreds) = create_random_shredded_block(slot, 1, &signing_sks[leader]);
+        for shred in shreds.into_iter().flatten() {
+            let _ = blockstore
+                .add_shred_from_disseminator(shred.into_shred())
+                .await;
+        }
+        for v in 0..7 {
+            let vote = Vote::new_notar(slot, hash.clone(), &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+            let vote = Vote::new_final(slot, &sks[v as usize], v);
+            assert_eq!(pool.add_vote(vote).await, Ok(()));
+        }
+        assert_eq!(pool.finalized_slot(), slot);
+
+        let pool: Box<dyn Pool + Send + Sync> = Box::new(pool);
+        let core = Arc::new(SimulatedNetworkCore::default());
+        let state = RpcState {
+            epoch_info,
+            pool: Arc::new(RwLock::new(pool)),
+            blockstore: Arc::new(RwLock::new(blockstore)),
+            txs_network: core.join_unlimited(0).await,
+            last_progress: std::sync::Mutex::new(Instant::now()),
+        };
+        (state, hash)
+    }
+
+    async fn call(state: &TestState, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        state.handle(request.to_string().as_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn finalized_block() {
+        let (state, hash) = finalized_state().await;
+
+        let slot = call(&state, "getFinalizedSlot", json!([])).await;
+        assert_eq!(slot["result"], 1);
+
+        let certs = call(&state, "getCerts", json!([1])).await;
+        let certs = certs["result"].as_array().unwrap();
+        assert!(certs.iter().any(|c| c["slot"] == 1));
+
+        let block = call(&state, "getBlock", json!([1])).await;
+        let block = &block["result"];
+        assert_eq!(block["slot"], 1);
+        assert_eq!(block["blockHash"], hex::encode(&hash));
+        let by_hash = call(&state, "getBlock", json!([hex::encode(&hash)])).await;
+        assert_eq!(&by_hash["result"], block);
+    }
+
+    #[tokio::test]
+    async fn leader_schedule_limit() {
+        let (state, _) = finalized_state().await;
+
+        let max = MAX_LEADER_SCHEDULE_SLOTS;
+        let schedule = call(&state, "getLeaderSchedule", json!([0, max])).await;
+        assert_eq!(schedule["result"].as_array().unwrap().len(), max as usize);
+
+        let resp = call(&state, "getLeaderSchedule", json!([0, max + 1])).await;
+        assert_eq!(resp["error"]["code"], INVALID_PARAMS);
+        let resp = call(&state, "getLeaderSchedule", json!([0, u64::MAX])).await;
+        assert_eq!(resp["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn error_response() {
+        let err = RpcError::new(METHOD_NOT_FOUND, "unknown method foo");
+        let resp = response(json!(7), Err(err));
+        assert_eq!(resp["id"], 7);
+        assert_eq!(resp["error"]["code"], METHOD_NOT_FOUND);
+        assert!(resp.get("result").is_none());
+    }
+
+    #[test]
+    fn params() {
+        let params = vec![json!(5), json!("abc")];
+        assert_eq!(slot_param(&params, 0), Ok(Slot::new(5)));
+        assert!(slot_param(&params, 1).is_err());
+        assert!(slot_param(&params, 2).is_err());
+
+        let hash = hex::encode([7; 32]);
+        assert_eq!(parse_hash(&hash), Ok(BlockHash::from(Hash::from([7; 32]))));
+        assert!(parse_hash("abc").is_err());
+        assert!(parse_hash(&hex::encode([7; 31])).is_err());
+    }
+}
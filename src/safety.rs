@@ -0,0 +1,441 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks consensus safety across multiple nodes.
+//!
+//! A [`SafetyChecker`] collects the certificates of all honest nodes in a test cluster.
+//! From these it derives which blocks each node finalized, and checks that:
+//! - at most one block is finalized in each slot,
+//! - the finalized blocks form a single chain, according to their parent links, AND
+//! - no slot with a finalized block also has a skip certificate.
+//!
+//! The first violation is kept, along with the certificates proving it.
+//! Certificates are fed in by wrapping each node's [`All2All`] in an [`ObservingAll2All`],
+//! as honest nodes broadcast every certificate they add to their pool.
+//! Parent links are fed in separately, see [`SafetyChecker::observe_parent`].
+//!
+//! With the `simulation` feature, `SimulatedCluster::check_safety`
+//! sets this up for all honest nodes of a simulated cluster.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::consensus::{Cert, ConsensusMessage};
+use crate::crypto::merkle::BlockHash;
+use crate::types::Slot;
+use crate::{All2All, BlockId, ValidatorId};
+
+/// A block a specific node considers finalized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finalization {
+    /// Node that finalized the block.
+    pub node: ValidatorId,
+    /// Hash of the finalized block.
+    pub block_hash: BlockHash,
+    /// Certificates proving finalization.
+    ///
+    /// Either a fast-finalization certificate, or finalization and notarization certificates.
+    pub evidence: Vec<Cert>,
+}
+
+/// A skip certificate a specific node observed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkipEvidence {
+    /// Node that observed the skip certificate.
+    pub node: ValidatorId,
+    /// The skip certificate.
+    pub cert: Cert,
+}
+
+/// Violation of one of the safety invariants checked by [`SafetyChecker`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum SafetyViolation {
+    #[error("conflicting blocks finalized in slot {slot}: {first:?} vs. {second:?}")]
+    ConflictingFinalization {
+        slot: Slot,
+        first: Finalization,
+        second: Finalization,
+    },
+    #[error("block finalized in skipped slot {slot}: {finalization:?} vs. {skip:?}")]
+    FinalizedAndSkipped {
+        slot: Slot,
+        finalization: Finalization,
+        skip: SkipEvidence,
+    },
+    #[error(
+        "block {block:?} with parent {parent:?} (reported by node {node}) is finalized, \
+         but conflicts with finalized block in slot {slot}: {child:?} vs. {conflicting:?}"
+    )]
+    InconsistentChain {
+        node: ValidatorId,
+        block: BlockId,
+        parent: BlockId,
+        slot: Slot,
+        child: Finalization,
+        conflicting: Finalization,
+    },
+}
+
+/// Certificates relevant for finalization that a single node observed.
+#[derive(Default)]
+struct NodeCerts {
+    notar: BTreeMap<Slot, Cert>,
+    finalize: BTreeMap<Slot, Cert>,
+}
+
+/// Checks safety invariants across the certificates observed by multiple nodes.
+#[derive(Default)]
+pub struct SafetyChecker {
+    /// Relevant certificates, per node.
+    certs: BTreeMap<ValidatorId, NodeCerts>,
+    /// First finalization observed for each slot.
+    finalized: BTreeMap<Slot, Finalization>,
+    /// First skip certificate observed for each slot.
+    skipped: BTreeMap<Slot, SkipEvidence>,
+    /// Known parent links, with the node that reported them.
+    parents: BTreeMap<BlockId, (ValidatorId, BlockId)>,
+    /// First violation found, if any.
+    violation: Option<SafetyViolation>,
+}
+
+impl SafetyChecker {
+    /// Creates a new checker that has not observed anything yet.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the given `node` added `cert` to its pool.
+    pub fn observe_cert(&mut self, node: ValidatorId, cert: &Cert) {
+        let slot = cert.slot();
+        match cert {
+            Cert::Notar(_) => {
+                let certs = self.certs.entry(node).or_default();
+                certs.notar.insert(slot, cert.clone());
+                if let Some(final_cert) = certs.finalize.get(&slot) {
+                    let evidence = vec![final_cert.clone(), cert.clone()];
+                    self.finalize(node, cert.block_hash().unwrap().clone(), evidence);
+                }
+            }
+            Cert::Final(_) => {
+                let certs = self.certs.entry(node).or_default();
+                certs.finalize.insert(slot, cert.clone());
+                if let Some(notar_cert) = certs.notar.get(&slot) {
+                    let block_hash = notar_cert.block_hash().unwrap().clone();
+                    let evidence = vec![cert.clone(), notar_cert.clone()];
+                    self.finalize(node, block_hash, evidence);
+                }
+            }
+            Cert::FastFinal(_) => {
+                let block_hash = cert.block_hash().unwrap().clone();
+                self.finalize(node, block_hash, vec![cert.clone()]);
+            }
+            Cert::Skip(_) => {
+                let skip = SkipEvidence {
+                    node,
+                    cert: cert.clone(),
+                };
+                if let Some(finalization) = self.finalized.get(&slot).cloned() {
+                    self.report(SafetyViolation::FinalizedAndSkipped {
+                        slot,
+                        finalization,
+                        skip: skip.clone(),
+                    });
+                }
+                self.skipped.entry(slot).or_insert(skip);
+            }
+            Cert::NotarFallback(_) => {}
+        }
+    }
+
+    /// Records that the given `node` knows `parent` to be the parent of `block`.
+    pub fn observe_parent(&mut self, node: ValidatorId, block: BlockId, parent: BlockId) {
+        if self.parents.contains_key(&block) {
+            return;
+        }
+        self.parents.insert(block.clone(), (node, parent));
+        self.check_link(&block);
+    }
+
+    /// Returns the blocks finalized so far, by slot.
+    ///
+    /// For each slot, this is the first finalization observed from any node.
+    #[must_use]
+    pub const fn finalized(&self) -> &BTreeMap<Slot, Finalization> {
+        &self.finalized
+    }
+
+    /// Returns the first safety violation found so far, if any.
+    #[must_use]
+    pub const fn violation(&self) -> Option<&SafetyViolation> {
+        self.violation.as_ref()
+    }
+
+    /// Returns the first safety violation found so far as an error, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`SafetyViolation`] if any invariant was violated.
+    pub fn check(&self) -> Result<(), SafetyViolation> {
+        self.violation.clone().map_or(Ok(()), Err)
+    }
+
+    fn finalize(&mut self, node: ValidatorId, block_hash: BlockHash, evidence: Vec<Cert>) {
+        let slot = evidence[0].slot();
+        let finalization = Finalization {
+            node,
+            block_hash,
+            evidence,
+        };
+        if let Some(first) = self.finalized.get(&slot).cloned() {
+            if first.block_hash != finalization.block_hash {
+                self.report(SafetyViolation::ConflictingFinalization {
+                    slot,
+                    first,
+                    second: finalization,
+                });
+            }
+            return;
+        }
+        if let Some(skip) = self.skipped.get(&slot).cloned() {
+            self.report(SafetyViolation::FinalizedAndSkipped {
+                slot,
+                finalization: finalization.clone(),
+                skip,
+            });
+        }
+        let block = (slot, finalization.block_hash.clone());
+        self.finalized.insert(slot, finalization);
+
+        // check all links this finalization may be relevant for
+        let children = self.parents.iter().filter(|(child, (_, parent))| {
+            parent.0 <= slot && slot < child.0 && self.is_finalized(child)
+        });
+        let mut links = children.map(|(child, _)| child.clone()).collect::<Vec<_>>();
+        links.push(block);
+        for child in links {
+            self.check_link(&child);
+        }
+    }
+
+    /// Checks the finalized chain is consistent with the parent link of `block`.
+    ///
+    /// If `block` is finalized, its parent must be finalized as well,
+    /// and no other block may be finalized in any slot between the two.
+    fn check_link(&mut self, block: &BlockId) {
+        if !self.is_finalized(block) {
+            return;
+        }
+        let Some((node, parent)) = self.parents.get(block) else {
+            return;
+        };
+        let conflicting = self
+            .finalized
+            .range(parent.0..block.0)
+            .find(|(slot, f)| **slot != parent.0 || f.block_hash != parent.1);
+        if let Some((&slot, conflicting)) = conflicting {
+            let violation = SafetyViolation::InconsistentChain {
+                node: *node,
+                block: block.clone(),
+                parent: parent.clone(),
+                slot,
+                child: self.finalized[&block.0].clone(),
+                conflicting: conflicting.clone(),
+            };
+            self.report(violation);
+        }
+    }
+
+    fn is_finalized(&self, (slot, hash): &BlockId) -> bool {
+        self.finalized
+            .get(slot)
+            .is_some_and(|f| &f.block_hash == hash)
+    }
+
+    fn report(&mut self, violation: SafetyViolation) {
+        if self.violation.is_none() {
+            self.violation = Some(violation);
+        }
+    }
+}
+
+/// Wrapper around an [`All2All`] that reports all broadcast certificates to a [`SafetyChecker`].
+pub struct ObservingAll2All<A: All2All> {
+    all2all: A,
+    node: ValidatorId,
+    checker: Arc<Mutex<SafetyChecker>>,
+}
+
+impl<A: All2All> ObservingAll2All<A> {
+    /// Creates a new wrapper, reporting certificates as observed by `node`.
+    pub const fn new(all2all: A, node: ValidatorId, checker: Arc<Mutex<SafetyChecker>>) -> Self {
+        Self {
+            all2all,
+            node,
+            checker,
+        }
+    }
+}
+
+#[async_trait]
+impl<A: All2All + Send + Sync> All2All for ObservingAll2All<A> {
+    async fn broadcast(&self, msg: &ConsensusMessage) -> std::io::Result<()> {
+        if let ConsensusMessage::Cert(cert) = msg {
+            let mut checker = self.checker.lock().unwrap();
+            checker.observe_cert(self.node, cert);
+        }
+        self.all2all.broadcast(msg).await
+    }
+
+    async fn receive(&self) -> std::io::Result<ConsensusMessage> {
+        self.all2all.receive().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{FastFinalCert, FinalCert, NotarCert, SkipCert, Vote};
+    use crate::crypto::Hash;
+    use crate::test_utils::generate_validators;
+
+    struct Certs {
+        sks: Vec<crate::crypto::aggsig::SecretKey>,
+        validators: Vec<crate::ValidatorInfo>,
+    }
+
+    impl Certs {
+        fn new() -> Self {
+            let (sks, epoch_info) = generate_validators(4);
+            let validators = epoch_info.validators.clone();
+            Self { sks, validators }
+        }
+
+        fn notar(&self, slot: Slot, hash: &BlockHash) -> Cert {
+            let votes = self.votes(|sk, v| Vote::new_notar(slot, hash.clone(), sk, v));
+            Cert::Notar(NotarCert::new_unchecked(&votes, &self.validators))
+        }
+
+        fn fast_final(&self, slot: Slot, hash: &BlockHash) -> Cert {
+            let votes = self.votes(|sk, v| Vote::new_notar(slot, hash.clone(), sk, v));
+            Cert::FastFinal(FastFinalCert::new_unchecked(&votes, &self.validators))
+        }
+
+        fn finalize(&self, slot: Slot) -> Cert {
+            let votes = self.votes(|sk, v| Vote::new_final(slot, sk, v));
+            Cert::Final(FinalCert::new_unchecked(&votes, &self.validators))
+        }
+
+        fn skip(&self, slot: Slot) -> Cert {
+            let votes = self.votes(|sk, v| Vote::new_skip(slot, sk, v));
+            Cert::Skip(SkipCert::new_unchecked(&votes, &self.validators))
+        }
+
+        fn votes(
+            &self,
+            vote: impl Fn(&crate::crypto::aggsig::SecretKey, ValidatorId) -> Vote,
+        ) -> Vec<Vote> {
+            let sks = self.sks.iter().enumerate();
+            sks.map(|(v, sk)| vote(sk, v as ValidatorId)).collect()
+        }
+    }
+
+    #[test]
+    fn consistent() {
+        let certs = Certs::new();
+        let mut checker = SafetyChecker::new();
+        let slot1 = Slot::genesis().next();
+        let slot3 = slot1.next().next();
+        let hash1: BlockHash = Hash::random_for_test().into();
+        let hash3: BlockHash = Hash::random_for_test().into();
+
+        // slow finalization on node 0, fast finalization on node 1
+        checker.observe_cert(0, &certs.finalize(slot1));
+        checker.observe_cert(0, &certs.notar(slot1, &hash1));
+        checker.observe_cert(1, &certs.fast_final(slot1, &hash1));
+        checker.observe_cert(1, &certs.skip(slot1.next()));
+        checker.observe_cert(1, &certs.fast_final(slot3, &hash3));
+        checker.observe_parent(0, (slot3, hash3.clone()), (slot1, hash1.clone()));
+
+        assert_eq!(checker.check(), Ok(()));
+        assert_eq!(checker.finalized().len(), 2);
+        assert_eq!(checker.finalized()[&slot1].node, 0);
+        assert_eq!(checker.finalized()[&slot1].evidence.len(), 2);
+        assert_eq!(checker.finalized()[&slot3].block_hash, hash3);
+    }
+
+    #[test]
+    fn conflicting_finalization() {
+        let certs = Certs::new();
+        let mut checker = SafetyChecker::new();
+        let slot = Slot::genesis().next();
+        let hash1: BlockHash = Hash::random_for_test().into();
+        let hash2: BlockHash = Hash::random_for_test().into();
+
+        checker.observe_cert(0, &certs.fast_final(slot, &hash1));
+        checker.observe_cert(1, &certs.notar(slot, &hash2));
+        assert_eq!(checker.check(), Ok(()));
+        checker.observe_cert(1, &certs.finalize(slot));
+
+        let Err(SafetyViolation::ConflictingFinalization { first, second, .. }) = checker.check()
+        else {
+            panic!("expected conflicting finalization");
+        };
+        assert_eq!((first.node, second.node), (0, 1));
+        assert_eq!(
+            second.evidence,
+            vec![certs.finalize(slot), certs.notar(slot, &hash2)]
+        );
+    }
+
+    #[test]
+    fn finalized_and_skipped() {
+        let certs = Certs::new();
+        let mut checker = SafetyChecker::new();
+        let slot = Slot::genesis().next();
+        let hash: BlockHash = Hash::random_for_test().into();
+
+        checker.observe_cert(0, &certs.skip(slot));
+        checker.observe_cert(1, &certs.fast_final(slot, &hash));
+        let Err(SafetyViolation::FinalizedAndSkipped { skip, .. }) = checker.check() else {
+            panic!("expected finalized and skipped slot");
+        };
+        assert_eq!(skip.node, 0);
+    }
+
+    #[test]
+    fn inconsistent_chain() {
+        let certs = Certs::new();
+        let slot1 = Slot::genesis().next();
+        let slot2 = slot1.next();
+        let slot3 = slot2.next();
+        let hash1: BlockHash = Hash::random_for_test().into();
+        let hash2: BlockHash = Hash::random_for_test().into();
+        let hash3: BlockHash = Hash::random_for_test().into();
+
+        // finalized block skips over finalized slot 2, link observed last
+        let mut checker = SafetyChecker::new();
+        checker.observe_cert(0, &certs.fast_final(slot2, &hash2));
+        checker.observe_cert(0, &certs.fast_final(slot3, &hash3));
+        checker.observe_parent(1, (slot3, hash3.clone()), (slot1, hash1.clone()));
+        let Err(SafetyViolation::InconsistentChain { slot, node, .. }) = checker.check() else {
+            panic!("expected inconsistent chain");
+        };
+        assert_eq!((slot, node), (slot2, 1));
+
+        // finalized parent differs from link, link observed first
+        let mut checker = SafetyChecker::new();
+        checker.observe_parent(1, (slot2, hash2.clone()), (slot1, hash1));
+        checker.observe_cert(0, &certs.fast_final(slot2, &hash2));
+        assert_eq!(checker.check(), Ok(()));
+        let other_hash: BlockHash = Hash::random_for_test().into();
+        checker.observe_cert(0, &certs.fast_final(slot1, &other_hash));
+        let Err(SafetyViolation::InconsistentChain { slot, .. }) = checker.check() else {
+            panic!("expected inconsistent chain");
+        };
+        assert_eq!(slot, slot1);
+    }
+}
@@ -78,8 +78,9 @@ impl ValidatedShred {
 
     /// Creates a new [`ValidatedShred`] when the inner [`Shred`] does not need to be verified.
     ///
-    /// Used only by the parent module to create a validated shred when it is guaranteed that the inner shred comes from verified sources and does not need to be verified.
-    pub(super) fn new_validated(shred: Shred) -> Self {
+    /// Used to create a validated shred when it is guaranteed that the inner shred comes from verified sources and does not need to be verified,
+    /// e.g. the shredder itself or the node's own storage.
+    pub(crate) fn new_validated(shred: Shred) -> Self {
         Self(shred)
     }
 
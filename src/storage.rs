@@ -0,0 +1,710 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Persistent storage of a node.
+//!
+//! Data is split into typed columns, see [`schema`].
+//! Each [`Column`] defines the type of its keys and values,
+//! so callers never deal with raw bytes.
+//! Keys are binary and ordered by slot, which makes range deletions cheap.
+//!
+//! Backends implement the [`Storage`] trait on raw bytes:
+//! - [`RocksDbStorage`] keeps a RocksDB database on disk, for production.
+//! - [`MemoryStorage`] keeps everything in memory, for tests.
+//!
+//! Typed access is implemented once for all backends, on `dyn Storage`.
+//! Which backend a node uses, and where, is chosen with a [`StorageConfig`].
+//! How long old data is kept is chosen with a [`RetentionPolicy`],
+//! optionally exporting it to an [`archive`] before it is deleted.
+
+pub mod archive;
+pub mod inspect;
+mod memory;
+pub mod migrations;
+mod retention;
+mod rocks;
+pub mod schema;
+
+use std::ops::{Bound, RangeBounds};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+pub use self::memory::MemoryStorage;
+pub use self::migrations::SCHEMA_VERSION;
+pub use self::retention::{RetentionPolicy, enforce_retention, run_retention};
+pub use self::rocks::RocksDbStorage;
+use self::schema::slot_prefix;
+pub use self::schema::{
+    BlockHashes, Blocks, CertKey, Certs, Column, ColumnKey, Metadata, ShredKey, Shreds, SlotColumn,
+    VoteKey, Votes,
+};
+use crate::{Slot, ValidatorId};
+
+/// Default root directory, under which each node keeps its database in a subdirectory named by its ID.
+pub const DEFAULT_DATA_DIR: &str = "ledger";
+
+/// Name of the column holding records not belonging to any [`Column`].
+const DEFAULT_COLUMN: &str = "default";
+
+/// Key of the highest finalized slot in the default column.
+const FINALIZED_SLOT_KEY: &[u8] = b"finalized_slot";
+
+/// Key of the first slot whose full data is retained in the default column.
+const RETAINED_SLOT_KEY: &[u8] = b"retained_slot";
+
+/// Errors that can occur when accessing [`Storage`].
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("RocksDB error: {0}")]
+    RocksDb(#[from] rocksdb::Error),
+    #[error("can not encode value for column `{0}`")]
+    Encode(&'static str),
+    #[error("can not decode entry in column `{0}`")]
+    Decode(&'static str),
+    #[error("schema version {found} is newer than supported version {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("schema version {found} must be migrated to {supported}, which needs write access")]
+    MigrationRequired { found: u32, supported: u32 },
+}
+
+/// Backend used for the storage of a node.
+#[derive(Clone, Debug)]
+pub enum StorageBackend {
+    /// [`RocksDbStorage`] in a subdirectory of the given data root, named by the node's ID.
+    RocksDb(PathBuf),
+    /// [`MemoryStorage`], which loses all data when the node stops.
+    InMemory,
+}
+
+/// Storage configuration of a node.
+#[derive(Clone, Debug)]
+pub struct StorageConfig {
+    backend: StorageBackend,
+    sync_writes: bool,
+    retention: RetentionPolicy,
+}
+
+impl StorageConfig {
+    /// Creates a new config using the given `backend`.
+    ///
+    /// Does not sync writes to disk and keeps all data, unless changed.
+    #[must_use]
+    pub fn new(backend: StorageBackend) -> Self {
+        Self {
+            backend,
+            sync_writes: false,
+            retention: RetentionPolicy::default(),
+        }
+    }
+
+    /// Creates a new config using [`StorageBackend::InMemory`].
+    #[must_use]
+    pub fn in_memory() -> Self {
+        Self::new(StorageBackend::InMemory)
+    }
+
+    /// Sets whether writes are synced to disk, see [`RocksDbStorage::with_sync_writes`].
+    #[must_use]
+    pub fn with_sync_writes(mut self, sync_writes: bool) -> Self {
+        self.sync_writes = sync_writes;
+        self
+    }
+
+    /// Sets the [`RetentionPolicy`] deciding how long old data is kept.
+    #[must_use]
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns the [`RetentionPolicy`] of this config.
+    #[must_use]
+    pub const fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    /// Opens the storage of the node with the given `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can not be opened or migrated.
+    pub fn open(&self, id: ValidatorId) -> Result<Arc<dyn Storage>, StorageError> {
+        match &self.backend {
+            StorageBackend::RocksDb(data_dir) => {
+                let storage = RocksDbStorage::open(data_dir.join(id.to_string()))?
+                    .with_sync_writes(self.sync_writes);
+                Ok(Arc::new(storage))
+            }
+            StorageBackend::InMemory => Ok(Arc::new(MemoryStorage::default())),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::new(StorageBackend::RocksDb(PathBuf::from(DEFAULT_DATA_DIR)))
+    }
+}
+
+/// Where to start iterating over a column, see [`Storage::iter_raw`].
+#[derive(Clone, Copy, Debug)]
+pub enum IterMode<'a> {
+    /// Ascending from the smallest key.
+    Start,
+    /// Descending from the largest key.
+    End,
+    /// Ascending from the smallest key not less than the given one.
+    From(&'a [u8]),
+}
+
+/// A single write, applied atomically with others by [`Storage::write`].
+#[derive(Clone, Debug)]
+pub enum WriteOp {
+    Put {
+        column: &'static str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        column: &'static str,
+        key: Vec<u8>,
+    },
+    /// Deletes all keys in `[from, to)`.
+    DeleteRange {
+        column: &'static str,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+}
+
+/// Iterator over raw entries of a column.
+pub type RawIter<'a> = Box<dyn Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), StorageError>> + 'a>;
+
+/// Backend of the storage of a node, holding raw bytes in named columns.
+///
+/// Besides all [`Column`]s, each backend has a column named `"default"`.
+/// Use the typed methods on `dyn Storage` instead of calling these directly.
+pub trait Storage: Send + Sync {
+    /// Reads the value stored under `key` in `column`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails.
+    fn get_raw(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Iterates over the entries of `column` in key order, starting according to `mode`.
+    fn iter_raw(&self, column: &'static str, mode: IterMode<'_>) -> RawIter<'_>;
+
+    /// Atomically applies all given writes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, in which case none of the writes are applied.
+    fn write(&self, ops: Vec<WriteOp>) -> Result<(), StorageError>;
+
+    /// Returns the schema version of the stored data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the version record can not be read.
+    fn schema_version(&self) -> Result<u32, StorageError>;
+
+    /// Returns approximate sizes in bytes over all columns, labeled by what they measure.
+    fn sizes(&self) -> [(&'static str, u64); 3];
+
+    /// Compacts all columns, reclaiming the space of deleted entries.
+    ///
+    /// This may take long and should not be called from async code directly.
+    fn compact(&self);
+}
+
+impl dyn Storage + '_ {
+    /// Reads the value stored under `key` in column `C`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the stored value can not be decoded.
+    pub fn get<C: Column>(&self, key: &C::Key) -> Result<Option<C::Value>, StorageError> {
+        match self.get_raw(C::NAME, &key.encode())? {
+            Some(bytes) => Ok(Some(decode_value::<C>(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `value` under `key` in column `C`, replacing any previous value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing fails.
+    pub fn put<C: Column>(&self, key: &C::Key, value: &C::Value) -> Result<(), StorageError> {
+        let mut batch = self.batch();
+        batch.put::<C>(key, value)?;
+        batch.commit()
+    }
+
+    /// Deletes the value stored under `key` in column `C`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn delete<C: Column>(&self, key: &C::Key) -> Result<(), StorageError> {
+        let mut batch = self.batch();
+        batch.delete::<C>(key);
+        batch.commit()
+    }
+
+    /// Deletes all entries of column `C` within the given range of slots.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn delete_slots<C: SlotColumn>(
+        &self,
+        slots: impl RangeBounds<Slot>,
+    ) -> Result<(), StorageError> {
+        let mut batch = self.batch();
+        batch.delete_slots::<C>(slots);
+        batch.commit()
+    }
+
+    /// Starts a set of writes that is applied atomically by [`StorageBatch::commit`].
+    #[must_use]
+    pub fn batch(&self) -> StorageBatch<'_> {
+        StorageBatch {
+            storage: self,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Iterates over all entries of column `C` in ascending key order.
+    pub fn iter<C: Column>(
+        &self,
+    ) -> impl Iterator<Item = Result<(C::Key, C::Value), StorageError>> {
+        self.iter_raw(C::NAME, IterMode::Start)
+            .map(decode_entry::<C>)
+    }
+
+    /// Iterates over all entries of column `C` in descending key order.
+    pub fn iter_rev<C: Column>(
+        &self,
+    ) -> impl Iterator<Item = Result<(C::Key, C::Value), StorageError>> {
+        self.iter_raw(C::NAME, IterMode::End).map(decode_entry::<C>)
+    }
+
+    /// Iterates over entries of column `C` in ascending key order, starting at `slot`.
+    pub fn iter_from<C: SlotColumn>(
+        &self,
+        slot: Slot,
+    ) -> impl Iterator<Item = Result<(C::Key, C::Value), StorageError>> {
+        let prefix = slot_prefix(slot);
+        self.iter_raw(C::NAME, IterMode::From(&prefix))
+            .map(decode_entry::<C>)
+    }
+
+    /// Iterates over all entries of column `C` in the given `slot`.
+    pub fn iter_slot<C: SlotColumn>(
+        &self,
+        slot: Slot,
+    ) -> impl Iterator<Item = Result<(C::Key, C::Value), StorageError>> {
+        let prefix = slot_prefix(slot);
+        self.iter_raw(C::NAME, IterMode::From(&prefix))
+            .take_while(move |item| {
+                item.as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&prefix))
+            })
+            .map(decode_entry::<C>)
+    }
+
+    /// Returns the highest finalized slot recorded with [`Self::set_finalized_slot`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the stored slot can not be decoded.
+    pub fn finalized_slot(&self) -> Result<Option<Slot>, StorageError> {
+        match self.get_raw(DEFAULT_COLUMN, FINALIZED_SLOT_KEY)? {
+            None => Ok(None),
+            Some(bytes) => {
+                let bytes = bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StorageError::Decode(DEFAULT_COLUMN))?;
+                Ok(Some(Slot::new(u64::from_be_bytes(bytes))))
+            }
+        }
+    }
+
+    /// Records `slot` as the highest finalized slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn set_finalized_slot(&self, slot: Slot) -> Result<(), StorageError> {
+        let mut batch = self.batch();
+        batch.set_finalized_slot(slot);
+        batch.commit()
+    }
+
+    /// Returns the first slot whose full data (blocks, shreds and votes) is retained.
+    ///
+    /// Before this slot, only headers and certificates are kept, see [`RetentionPolicy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the stored slot can not be decoded.
+    pub fn retained_slot(&self) -> Result<Slot, StorageError> {
+        match self.get_raw(DEFAULT_COLUMN, RETAINED_SLOT_KEY)? {
+            None => Ok(Slot::genesis()),
+            Some(bytes) => {
+                let bytes = bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StorageError::Decode(DEFAULT_COLUMN))?;
+                Ok(Slot::new(u64::from_be_bytes(bytes)))
+            }
+        }
+    }
+
+    /// Deletes all data of slots after `slot`, to recover from a corrupted tail of the ledger.
+    ///
+    /// Lowers the finalized slot to the highest finalized slot not after `slot`,
+    /// and the retained slot to at most the slot after `slot`.
+    /// Returns the number of deleted blocks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or writing fails, in which case nothing is deleted.
+    pub fn truncate_after(&self, slot: Slot) -> Result<usize, StorageError> {
+        use crate::consensus::Cert;
+
+        let beyond = (Bound::Excluded(slot), Bound::Unbounded);
+        let mut batch = self.batch();
+        let mut deleted = 0;
+        for item in self.iter_from::<Metadata>(slot.next()) {
+            let ((_, hash), _) = item?;
+            batch.delete::<BlockHashes>(&hash);
+            deleted += 1;
+        }
+        batch.delete_slots::<Blocks>(beyond);
+        batch.delete_slots::<Metadata>(beyond);
+        batch.delete_slots::<Shreds>(beyond);
+        batch.delete_slots::<Certs>(beyond);
+        batch.delete_slots::<Votes>(beyond);
+        if self
+            .finalized_slot()?
+            .is_some_and(|finalized| finalized > slot)
+        {
+            let mut finalized = Slot::genesis();
+            for item in self.iter::<Certs>() {
+                let (key, cert) = item?;
+                if key.slot > slot {
+                    break;
+                }
+                if matches!(cert, Cert::FastFinal(_) | Cert::Final(_)) {
+                    finalized = key.slot;
+                }
+            }
+            batch.set_finalized_slot(finalized);
+        }
+        if self.retained_slot()? > slot.next() {
+            batch.set_retained_slot(slot.next());
+        }
+        batch.commit()?;
+        Ok(deleted)
+    }
+
+    /// Asserts the invariants that must hold at any point, in particular after a crash.
+    ///
+    /// - every block has its metadata and is indexed by its hash
+    /// - there is no metadata without its block, unless its full data was pruned
+    /// - the finalized slot is backed by a finalization certificate
+    #[cfg(test)]
+    pub(crate) fn assert_consistent(&self) {
+        use crate::consensus::Cert;
+
+        for item in self.iter::<Blocks>() {
+            let ((slot, hash), _) = item.unwrap();
+            let block_id = (slot, hash.clone());
+            assert!(self.get::<Metadata>(&block_id).unwrap().is_some());
+            assert_eq!(self.get::<BlockHashes>(&hash).unwrap(), Some(slot));
+        }
+        let retained = self.retained_slot().unwrap();
+        for item in self.iter_from::<Metadata>(retained) {
+            let (block_id, _) = item.unwrap();
+            assert!(self.get::<Blocks>(&block_id).unwrap().is_some());
+        }
+        if let Some(slot) = self.finalized_slot().unwrap()
+            && slot > Slot::genesis()
+        {
+            let finalized = self
+                .iter_slot::<Certs>(slot)
+                .any(|item| matches!(item.unwrap().1, Cert::FastFinal(_) | Cert::Final(_)));
+            assert!(finalized, "finalized slot {slot} has no finalization cert");
+        }
+    }
+}
+
+/// Writes to [`Storage`] that are applied atomically.
+///
+/// Nothing is written unless [`StorageBatch::commit`] is called.
+pub struct StorageBatch<'a> {
+    storage: &'a dyn Storage,
+    ops: Vec<WriteOp>,
+}
+
+impl StorageBatch<'_> {
+    /// Adds storing `value` under `key` in column `C` to the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding fails.
+    pub fn put<C: Column>(&mut self, key: &C::Key, value: &C::Value) -> Result<(), StorageError> {
+        let value = wincode::serialize(value).map_err(|_| StorageError::Encode(C::NAME))?;
+        self.ops.push(WriteOp::Put {
+            column: C::NAME,
+            key: key.encode(),
+            value,
+        });
+        Ok(())
+    }
+
+    /// Adds deleting the value stored under `key` in column `C` to the batch.
+    pub fn delete<C: Column>(&mut self, key: &C::Key) {
+        self.ops.push(WriteOp::Delete {
+            column: C::NAME,
+            key: key.encode(),
+        });
+    }
+
+    /// Adds deleting all entries of column `C` within the given range of slots to the batch.
+    pub fn delete_slots<C: SlotColumn>(&mut self, slots: impl RangeBounds<Slot>) {
+        if let Some((from, to)) = slot_range_keys(slots) {
+            self.ops.push(WriteOp::DeleteRange {
+                column: C::NAME,
+                from,
+                to,
+            });
+        }
+    }
+
+    /// Adds recording `slot` as the highest finalized slot to the batch.
+    pub fn set_finalized_slot(&mut self, slot: Slot) {
+        self.ops.push(WriteOp::Put {
+            column: DEFAULT_COLUMN,
+            key: FINALIZED_SLOT_KEY.to_vec(),
+            value: slot.inner().to_be_bytes().to_vec(),
+        });
+    }
+
+    /// Adds recording `slot` as the first slot whose full data is retained to the batch.
+    pub fn set_retained_slot(&mut self, slot: Slot) {
+        self.ops.push(WriteOp::Put {
+            column: DEFAULT_COLUMN,
+            key: RETAINED_SLOT_KEY.to_vec(),
+            value: slot.inner().to_be_bytes().to_vec(),
+        });
+    }
+
+    /// Atomically applies all writes of this batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, in which case none of the writes are applied.
+    pub fn commit(self) -> Result<(), StorageError> {
+        self.storage.write(self.ops)
+    }
+}
+
+/// Key greater than any key in any column.
+const KEY_UPPER_BOUND: [u8; 64] = [0xFF; 64];
+
+/// Returns the key range `[from, to)` covering the given range of slots in a [`SlotColumn`].
+///
+/// Returns `None` if the range is empty.
+fn slot_range_keys(slots: impl RangeBounds<Slot>) -> Option<(Vec<u8>, Vec<u8>)> {
+    let from = match slots.start_bound() {
+        Bound::Included(slot) => slot_prefix(*slot).to_vec(),
+        Bound::Excluded(slot) if *slot == Slot::new(u64::MAX) => return None,
+        Bound::Excluded(slot) => slot_prefix(slot.next()).to_vec(),
+        Bound::Unbounded => Vec::new(),
+    };
+    let to = match slots.end_bound() {
+        Bound::Included(slot) if *slot == Slot::new(u64::MAX) => KEY_UPPER_BOUND.to_vec(),
+        Bound::Included(slot) => slot_prefix(slot.next()).to_vec(),
+        Bound::Excluded(slot) => slot_prefix(*slot).to_vec(),
+        Bound::Unbounded => KEY_UPPER_BOUND.to_vec(),
+    };
+    Some((from, to))
+}
+
+fn decode_value<C: Column>(bytes: &[u8]) -> Result<C::Value, StorageError> {
+    wincode::deserialize(bytes).map_err(|_| StorageError::Decode(C::NAME))
+}
+
+fn decode_entry<C: Column>(
+    item: Result<(Box<[u8]>, Box<[u8]>), StorageError>,
+) -> Result<(C::Key, C::Value), StorageError> {
+    let (key, value) = item?;
+    let key = C::Key::decode(&key).ok_or(StorageError::Decode(C::NAME))?;
+    Ok((key, decode_value::<C>(&value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValidatorInfo;
+    use crate::consensus::{Cert, SkipCert, Vote};
+    use crate::crypto::aggsig::SecretKey;
+    use crate::crypto::merkle::GENESIS_BLOCK_HASH;
+    use crate::test_utils::{create_finalized_chain, generate_validators};
+
+    fn skip_cert(slot: Slot, sks: &[SecretKey], validators: &[ValidatorInfo]) -> Cert {
+        let votes: Vec<_> = sks
+            .iter()
+            .enumerate()
+            .map(|(v, sk)| Vote::new_skip(slot, sk, v as u64))
+            .collect();
+        Cert::Skip(SkipCert::new_unchecked(&votes, validators))
+    }
+
+    /// Returns a fresh instance of each backend.
+    fn backends() -> [Arc<dyn Storage>; 2] {
+        [
+            Arc::new(MemoryStorage::default()),
+            Arc::new(RocksDbStorage::open_temporary()),
+        ]
+    }
+
+    #[test]
+    fn fresh_database() {
+        for storage in backends() {
+            assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+            assert_eq!(storage.finalized_slot().unwrap(), None);
+            assert_eq!(storage.retained_slot().unwrap(), Slot::genesis());
+            assert_eq!(storage.iter::<Certs>().count(), 0);
+
+            storage.set_finalized_slot(Slot::new(12)).unwrap();
+            assert_eq!(storage.finalized_slot().unwrap(), Some(Slot::new(12)));
+        }
+    }
+
+    #[test]
+    fn typed_columns() {
+        let (sks, epoch_info) = generate_validators(4);
+        let skip_cert = |slot| skip_cert(Slot::new(slot), &sks, &epoch_info.validators);
+        for storage in backends() {
+            let vote = Vote::new_notar(Slot::new(3), GENESIS_BLOCK_HASH, &sks[0], 0);
+            storage.put::<Votes>(&VoteKey::new(&vote), &vote).unwrap();
+            assert_eq!(
+                storage.get::<Votes>(&VoteKey::new(&vote)).unwrap(),
+                Some(vote)
+            );
+
+            for slot in 0..8 {
+                let cert = skip_cert(slot);
+                storage.put::<Certs>(&CertKey::new(&cert), &cert).unwrap();
+            }
+            let slots = |storage: &dyn Storage| {
+                storage
+                    .iter::<Certs>()
+                    .map(|item| item.unwrap().0.slot.inner())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(slots(&*storage), (0..8).collect::<Vec<_>>());
+            assert_eq!(storage.iter_slot::<Certs>(Slot::new(5)).count(), 1);
+            assert_eq!(storage.iter_from::<Certs>(Slot::new(5)).count(), 3);
+            let last = storage.iter_rev::<Certs>().next().unwrap().unwrap();
+            assert_eq!(last.0.slot, Slot::new(7));
+
+            storage.delete_slots::<Certs>(Slot::new(6)..).unwrap();
+            storage.delete_slots::<Certs>(..=Slot::new(1)).unwrap();
+            assert_eq!(slots(&*storage), [2, 3, 4, 5]);
+            storage
+                .delete::<Certs>(&CertKey::new(&skip_cert(2)))
+                .unwrap();
+            assert_eq!(slots(&*storage), [3, 4, 5]);
+
+            let mut batch = storage.batch();
+            batch.delete_slots::<Certs>(..Slot::new(5));
+            let cert = skip_cert(9);
+            batch.put::<Certs>(&CertKey::new(&cert), &cert).unwrap();
+            assert_eq!(slots(&*storage), [3, 4, 5]);
+            batch.commit().unwrap();
+            assert_eq!(slots(&*storage), [5, 9]);
+        }
+    }
+
+    #[test]
+    fn crash_mid_batch() {
+        let (sks, epoch_info) = generate_validators(4);
+        let skip_cert = |slot| skip_cert(Slot::new(slot), &sks, &epoch_info.validators);
+        let rocksdb = RocksDbStorage::open_temporary().with_sync_writes(true);
+        let storage: &dyn Storage = &rocksdb;
+
+        let cert = skip_cert(1);
+        storage.put::<Certs>(&CertKey::new(&cert), &cert).unwrap();
+
+        // crash while a batch is being built, none of it is persisted
+        let mut batch = storage.batch();
+        let cert = skip_cert(2);
+        batch.put::<Certs>(&CertKey::new(&cert), &cert).unwrap();
+        batch.set_finalized_slot(Slot::new(2));
+        let crashed: Arc<dyn Storage> = Arc::new(rocksdb.crash_snapshot());
+        assert_eq!(crashed.iter::<Certs>().count(), 1);
+        assert_eq!(crashed.finalized_slot().unwrap(), None);
+        crashed.assert_consistent();
+
+        // crash after committing, all of it is persisted
+        batch.commit().unwrap();
+        let crashed: Arc<dyn Storage> = Arc::new(rocksdb.crash_snapshot());
+        assert_eq!(crashed.iter::<Certs>().count(), 2);
+        assert_eq!(crashed.finalized_slot().unwrap(), Some(Slot::new(2)));
+    }
+
+    #[test]
+    fn truncate() {
+        let (sks, epoch_info) = generate_validators(4);
+        for storage in backends() {
+            let chain = create_finalized_chain(&*storage, 6, &sks, &epoch_info);
+            assert_eq!(storage.truncate_after(Slot::new(3)).unwrap(), 3);
+            assert_eq!(storage.iter::<Blocks>().count(), 3);
+            assert_eq!(storage.iter_from::<Certs>(Slot::new(4)).count(), 0);
+            assert_eq!(storage.get::<BlockHashes>(&chain[3].1).unwrap(), None);
+            assert_eq!(storage.finalized_slot().unwrap(), Some(Slot::new(3)));
+            storage.assert_consistent();
+
+            // truncating beyond the tail is a no-op
+            assert_eq!(storage.truncate_after(Slot::new(5)).unwrap(), 0);
+            assert_eq!(storage.iter::<Blocks>().count(), 3);
+
+            // the retained slot does not stay past the truncated tail
+            let mut batch = storage.batch();
+            batch.delete_slots::<Blocks>(..Slot::new(3));
+            batch.set_retained_slot(Slot::new(3));
+            batch.commit().unwrap();
+            assert_eq!(storage.truncate_after(Slot::new(1)).unwrap(), 2);
+            assert_eq!(storage.retained_slot().unwrap(), Slot::new(2));
+            assert_eq!(storage.finalized_slot().unwrap(), Some(Slot::new(1)));
+            storage.assert_consistent();
+        }
+    }
+
+    #[test]
+    fn open_from_config() {
+        let data_dir =
+            std::env::temp_dir().join(format!("alpenglow-config-{}", std::process::id()));
+        let config = StorageConfig::new(StorageBackend::RocksDb(data_dir.clone()));
+        let storage = config.open(3).unwrap();
+        storage.set_finalized_slot(Slot::new(5)).unwrap();
+        drop(storage);
+        assert!(data_dir.join("3").is_dir());
+        let storage = config.open(3).unwrap();
+        assert_eq!(storage.finalized_slot().unwrap(), Some(Slot::new(5)));
+        drop(storage);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+
+        let storage = StorageConfig::in_memory().open(3).unwrap();
+        assert_eq!(storage.finalized_slot().unwrap(), None);
+    }
+}
@@ -0,0 +1,634 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Archive of finalized blocks in self-verifying segment files.
+//!
+//! A segment holds a contiguous part of the finalized chain, ordered by slot.
+//! Each block comes with its metadata and the certificates stored for it.
+//! A segment can be verified knowing only the validator set:
+//! - a SHA-256 checksum over the whole file detects corruption
+//! - each block is the parent of the next one
+//! - all certificates carry enough valid signatures and are for their block
+//! - the last block is finalized by its own certificates,
+//!   which implies that all its ancestors are finalized too
+//!
+//! Layout: `magic (8) | header | block … | checksum (32)`,
+//! where the [`SegmentInfo`] header and each [`ArchivedBlock`] are encoded with
+//! [`wincode`] and prefixed by their big-endian `u64` length.
+//!
+//! Segments are written and read one block at a time, so only a single block
+//! has to be held in memory at once.
+
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use wincode::{SchemaRead, SchemaWrite};
+
+use super::{BlockHashes, Blocks, CertKey, Certs, Metadata, Storage, StorageError};
+use crate::consensus::{BlockMetadata, Cert, EpochInfo};
+use crate::crypto::merkle::{BlockHash, MerkleRoot};
+use crate::shredder::MAX_DATA_PER_SLICE;
+use crate::types::slice_index::MAX_SLICES_PER_BLOCK;
+use crate::{Block, BlockId, Slot};
+
+/// Magic bytes at the start of every segment file.
+const SEGMENT_MAGIC: [u8; 8] = *b"AGLSEG\0\0";
+
+/// Version of the segment format written by this software.
+pub const SEGMENT_VERSION: u32 = 1;
+
+/// Length of the checksum at the end of every segment file.
+const CHECKSUM_LEN: usize = 32;
+
+/// Number of slots after which [`export_to_dir`] starts a new segment.
+///
+/// A segment ends with an explicitly finalized block, so it can cover more
+/// slots if there is no such block within this many slots.
+pub const SEGMENT_SLOTS: u64 = 1024;
+
+/// Maximum length of a single section when reading a segment.
+///
+/// Covers the largest possible block, with ample room for its metadata and certificates.
+const MAX_SECTION_LEN: u64 = (MAX_SLICES_PER_BLOCK * MAX_DATA_PER_SLICE) as u64 + (1 << 20);
+
+/// Errors that can occur when exporting or importing segments.
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("not a segment file")]
+    BadMagic,
+    #[error("segment version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("segment is malformed")]
+    Malformed,
+    #[error("segment checksum does not match its contents")]
+    ChecksumMismatch,
+    #[error("invalid certificate for block in slot {0}")]
+    InvalidCert(Slot),
+    #[error("block in slot {0} does not extend the previous block")]
+    BrokenChain(Slot),
+    #[error("last block in slot {0} is not finalized")]
+    NotFinalized(Slot),
+    #[error("block in slot {0} was deleted during export")]
+    Deleted(Slot),
+}
+
+/// Header of a segment, describing its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SchemaRead, SchemaWrite)]
+pub struct SegmentInfo {
+    /// Version of the segment format.
+    pub version: u32,
+    /// Slot of the first block in the segment.
+    pub first_slot: Slot,
+    /// Slot of the last block in the segment, which is explicitly finalized.
+    pub last_slot: Slot,
+    /// Number of blocks in the segment.
+    pub num_blocks: u64,
+}
+
+impl SegmentInfo {
+    /// Returns the header of a segment holding exactly the blocks of `chain`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chain` is empty.
+    fn for_chain(chain: &[BlockId]) -> Self {
+        Self {
+            version: SEGMENT_VERSION,
+            first_slot: chain[0].0,
+            last_slot: chain[chain.len() - 1].0,
+            num_blocks: chain.len() as u64,
+        }
+    }
+
+    /// Returns the file name under which this segment is written by [`export_to_dir`].
+    #[must_use]
+    pub fn file_name(&self) -> String {
+        format!(
+            "segment-{:020}-{:020}.seg",
+            self.first_slot.inner(),
+            self.last_slot.inner()
+        )
+    }
+}
+
+/// A finalized block as stored in a segment.
+#[derive(Clone, Debug, SchemaRead, SchemaWrite)]
+pub struct ArchivedBlock {
+    pub block: Block,
+    pub metadata: BlockMetadata,
+    /// Certificates for this block, i.e. all but skip certificates of its slot.
+    pub certs: Vec<Cert>,
+}
+
+impl ArchivedBlock {
+    /// Returns the slot and hash of this block.
+    #[must_use]
+    pub fn block_id(&self) -> BlockId {
+        (self.metadata.slot, self.block.hash.clone())
+    }
+}
+
+/// Returns the hash of the block finalized in `slot`, according to the stored certificates.
+///
+/// Returns `None` if no block in `slot` is explicitly finalized.
+/// Blocks may still be implicitly finalized, by a finalized descendant.
+///
+/// # Errors
+///
+/// Returns an error if reading the certificates fails.
+pub fn finalized_hash(
+    storage: &dyn Storage,
+    slot: Slot,
+) -> Result<Option<BlockHash>, StorageError> {
+    let certs = storage
+        .iter_slot::<Certs>(slot)
+        .map(|item| item.map(|(_, cert)| cert))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(finalized_by(&certs).cloned())
+}
+
+/// Returns the hash of the block finalized by the given certificates of a single slot, if any.
+fn finalized_by(certs: &[Cert]) -> Option<&BlockHash> {
+    let mut notarized = None;
+    let mut slow_finalized = false;
+    for cert in certs {
+        match cert {
+            Cert::FastFinal(_) => return cert.block_hash(),
+            Cert::Final(_) => slow_finalized = true,
+            Cert::Notar(_) => notarized = cert.block_hash(),
+            Cert::NotarFallback(_) | Cert::Skip(_) => {}
+        }
+    }
+    if slow_finalized { notarized } else { None }
+}
+
+/// Collects the IDs of the finalized chain of stored blocks within `slots`.
+///
+/// The chain ends with the last explicitly finalized block within `slots`
+/// and is followed back through parents, until a block is outside `slots` or not stored.
+fn finalized_chain(
+    storage: &dyn Storage,
+    slots: Range<Slot>,
+) -> Result<Vec<BlockId>, StorageError> {
+    let mut finalized_slots = Vec::new();
+    for item in storage.iter_from::<Certs>(slots.start) {
+        let (key, cert) = item?;
+        if key.slot >= slots.end {
+            break;
+        }
+        if matches!(cert, Cert::FastFinal(_) | Cert::Final(_)) {
+            finalized_slots.push(key.slot);
+        }
+    }
+    let mut next = None;
+    for slot in finalized_slots.into_iter().rev() {
+        if let Some(hash) = finalized_hash(storage, slot)?
+            && storage.get::<Blocks>(&(slot, hash.clone()))?.is_some()
+        {
+            next = Some((slot, hash));
+            break;
+        }
+    }
+
+    let mut chain = Vec::new();
+    while let Some(block_id) = next.take() {
+        if block_id.0 < slots.start {
+            break;
+        }
+        let Some(block) = storage.get::<Blocks>(&block_id)? else {
+            break;
+        };
+        if storage.get::<Metadata>(&block_id)?.is_none() {
+            break;
+        }
+        if block_id.0 > Slot::genesis() {
+            next = Some((block.parent, block.parent_hash));
+        }
+        chain.push(block_id);
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Loads the block with the given `block_id` from storage, as stored in a segment.
+fn load_archived_block(
+    storage: &dyn Storage,
+    block_id: &BlockId,
+) -> Result<ArchivedBlock, ArchiveError> {
+    let (Some(block), Some(metadata)) = (
+        storage.get::<Blocks>(block_id)?,
+        storage.get::<Metadata>(block_id)?,
+    ) else {
+        return Err(ArchiveError::Deleted(block_id.0));
+    };
+    let mut certs = Vec::new();
+    for item in storage.iter_slot::<Certs>(block_id.0) {
+        let (_, cert) = item?;
+        let for_block = match &cert {
+            Cert::Final(_) => true,
+            Cert::Skip(_) => false,
+            _ => cert.block_hash() == Some(&block_id.1),
+        };
+        if for_block {
+            certs.push(cert);
+        }
+    }
+    Ok(ArchivedBlock {
+        block,
+        metadata,
+        certs,
+    })
+}
+
+/// Writes the finalized chain within `slots` as a segment to `writer`.
+///
+/// Blocks after the last explicitly finalized block within `slots` are not included.
+/// Returns `None`, and writes nothing, if there is no such block.
+///
+/// # Errors
+///
+/// Returns an error if reading from storage or writing fails.
+pub fn export_segment(
+    storage: &dyn Storage,
+    slots: Range<Slot>,
+    writer: impl Write,
+) -> Result<Option<SegmentInfo>, ArchiveError> {
+    let chain = finalized_chain(storage, slots)?;
+    if chain.is_empty() {
+        return Ok(None);
+    }
+    let info = SegmentInfo::for_chain(&chain);
+    let blocks = chain.iter().map(|id| load_archived_block(storage, id));
+    write_segment(writer, &info, blocks)?;
+    Ok(Some(info))
+}
+
+/// Writes the finalized chain within `slots` as segment files into `dir`.
+///
+/// A new segment is started roughly every [`SEGMENT_SLOTS`] slots.
+/// Each file is named by [`SegmentInfo::file_name`] and only appears once it is complete.
+/// Returns the written segments in order, which is empty if there is no
+/// finalized block within `slots`.
+///
+/// # Errors
+///
+/// Returns an error if reading from storage or writing a file fails.
+/// Segments completed before the error remain in `dir`.
+pub fn export_to_dir(
+    storage: &dyn Storage,
+    slots: Range<Slot>,
+    dir: &Path,
+) -> Result<Vec<SegmentInfo>, ArchiveError> {
+    let mut segments = Vec::new();
+    let mut start = slots.start;
+    let mut end = start;
+    while end < slots.end {
+        end = Slot::new(end.inner().saturating_add(SEGMENT_SLOTS)).min(slots.end);
+        let chain = finalized_chain(storage, start..end)?;
+        if chain.is_empty() {
+            // extend the segment until it contains an explicitly finalized block
+            continue;
+        }
+        let info = SegmentInfo::for_chain(&chain);
+        std::fs::create_dir_all(dir)?;
+        // unique name, so concurrent exports into the same directory do not collide
+        let temp_path = dir.join(format!(
+            "{}.{:016x}.tmp",
+            info.file_name(),
+            rand::random::<u64>()
+        ));
+        let result = write_segment_file(storage, &temp_path, &info, &chain);
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        std::fs::rename(&temp_path, dir.join(info.file_name()))?;
+        segments.push(info);
+        start = info.last_slot.next();
+        end = start;
+    }
+    Ok(segments)
+}
+
+/// Writes the blocks of `chain` as a segment with header `info` to a new file at `path`.
+fn write_segment_file(
+    storage: &dyn Storage,
+    path: &Path,
+    info: &SegmentInfo,
+    chain: &[BlockId],
+) -> Result<(), ArchiveError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let blocks = chain.iter().map(|id| load_archived_block(storage, id));
+    write_segment(&mut writer, info, blocks)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes a segment with header `info` and the given `blocks` to `writer`.
+///
+/// Blocks are encoded and written one at a time, as they are produced by `blocks`.
+fn write_segment(
+    mut writer: impl Write,
+    info: &SegmentInfo,
+    blocks: impl IntoIterator<Item = Result<ArchivedBlock, ArchiveError>>,
+) -> Result<(), ArchiveError> {
+    let mut hasher = Sha256::new();
+    let mut write = |bytes: &[u8]| -> std::io::Result<()> {
+        hasher.update(bytes);
+        writer.write_all(bytes)
+    };
+    write(&SEGMENT_MAGIC)?;
+    let mut write_section = |bytes: Vec<u8>| -> std::io::Result<()> {
+        write(&(bytes.len() as u64).to_be_bytes())?;
+        write(&bytes)
+    };
+    let encode_error = |_| StorageError::Encode("segment");
+    write_section(wincode::serialize(info).map_err(encode_error)?)?;
+    for block in blocks {
+        write_section(wincode::serialize(&block?).map_err(encode_error)?)?;
+    }
+    let checksum: [u8; CHECKSUM_LEN] = hasher.finalize().into();
+    writer.write_all(&checksum)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reader that feeds all bytes read through it into a SHA-256 hasher.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Reads and fully verifies a segment from `reader`.
+///
+/// The segment is read one section at a time, never more than [`MAX_SECTION_LEN`] bytes.
+/// Certificates are verified against the validators in `epoch_info`.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, or if the segment is corrupted or does not verify.
+pub fn read_segment(
+    reader: impl Read,
+    epoch_info: &EpochInfo,
+) -> Result<(SegmentInfo, Vec<ArchivedBlock>), ArchiveError> {
+    let mut reader = HashingReader {
+        inner: reader,
+        hasher: Sha256::new(),
+    };
+    let mut magic = [0; SEGMENT_MAGIC.len()];
+    read_exact(&mut reader, &mut magic)?;
+    if magic != SEGMENT_MAGIC {
+        return Err(ArchiveError::BadMagic);
+    }
+    let info: SegmentInfo =
+        wincode::deserialize(&next_section(&mut reader)?).map_err(|_| ArchiveError::Malformed)?;
+    if info.version != SEGMENT_VERSION {
+        return Err(ArchiveError::UnsupportedVersion(info.version));
+    }
+    let mut chain = Vec::new();
+    for _ in 0..info.num_blocks {
+        let entry = wincode::deserialize(&next_section(&mut reader)?)
+            .map_err(|_| ArchiveError::Malformed)?;
+        chain.push(entry);
+    }
+
+    let HashingReader { mut inner, hasher } = reader;
+    let mut checksum = [0; CHECKSUM_LEN];
+    read_exact(&mut inner, &mut checksum)?;
+    if inner.read(&mut [0])? != 0 {
+        return Err(ArchiveError::Malformed);
+    }
+    if hasher.finalize()[..] != checksum[..] {
+        return Err(ArchiveError::ChecksumMismatch);
+    }
+    verify_chain(&info, &chain, epoch_info)?;
+    Ok((info, chain))
+}
+
+/// Reads the next length-prefixed section from `reader`.
+fn next_section(reader: &mut impl Read) -> Result<Vec<u8>, ArchiveError> {
+    let mut len = [0; 8];
+    read_exact(reader, &mut len)?;
+    let len = u64::from_be_bytes(len);
+    if len > MAX_SECTION_LEN {
+        return Err(ArchiveError::Malformed);
+    }
+    let mut section = vec![0; len as usize];
+    read_exact(reader, &mut section)?;
+    Ok(section)
+}
+
+/// Fills `buf` from `reader`, treating a premature end of the segment as malformed.
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), ArchiveError> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => ArchiveError::Malformed,
+        _ => ArchiveError::Io(err),
+    })
+}
+
+/// Checks that `chain` matches `info` and is a certified part of the finalized chain.
+fn verify_chain(
+    info: &SegmentInfo,
+    chain: &[ArchivedBlock],
+    epoch_info: &EpochInfo,
+) -> Result<(), ArchiveError> {
+    let (Some(first), Some(last)) = (chain.first(), chain.last()) else {
+        return Err(ArchiveError::Malformed);
+    };
+    if first.metadata.slot != info.first_slot || last.metadata.slot != info.last_slot {
+        return Err(ArchiveError::Malformed);
+    }
+    let mut parent: Option<BlockId> = None;
+    for entry in chain {
+        let (slot, hash) = entry.block_id();
+        if &entry.metadata.hash != hash.as_hash() {
+            return Err(ArchiveError::Malformed);
+        }
+        for cert in &entry.certs {
+            let valid = cert.slot() == slot
+                && cert.block_hash().is_none_or(|h| h == &hash)
+                && cert.check_threshold(epoch_info)
+                && cert.check_sig(&epoch_info.validators);
+            if !valid {
+                return Err(ArchiveError::InvalidCert(slot));
+            }
+        }
+        if let Some((parent_slot, parent_hash)) = parent
+            && (entry.block.parent != parent_slot || entry.block.parent_hash != parent_hash)
+        {
+            return Err(ArchiveError::BrokenChain(slot));
+        }
+        parent = Some((slot, hash));
+    }
+    if finalized_by(&last.certs) != Some(&last.block.hash) {
+        return Err(ArchiveError::NotFinalized(last.metadata.slot));
+    }
+    Ok(())
+}
+
+/// Reads and verifies a segment from `reader`, then stores its contents.
+///
+/// Blocks, their metadata and certificates are written in one batch.
+/// Lowers the retained slot if necessary,
+/// so the imported blocks are kept until the next retention run,
+/// and raises the finalized slot if the segment extends past it.
+///
+/// # Errors
+///
+/// Returns an error if the segment does not verify or storing fails.
+pub fn import_segment(
+    storage: &dyn Storage,
+    reader: impl Read,
+    epoch_info: &EpochInfo,
+) -> Result<SegmentInfo, ArchiveError> {
+    let (info, chain) = read_segment(reader, epoch_info)?;
+    let mut batch = storage.batch();
+    for entry in &chain {
+        let block_id = entry.block_id();
+        batch.put::<Blocks>(&block_id, &entry.block)?;
+        batch.put::<Metadata>(&block_id, &entry.metadata)?;
+        batch.put::<BlockHashes>(&block_id.1, &block_id.0)?;
+        for cert in &entry.certs {
+            batch.put::<Certs>(&CertKey::new(cert), cert)?;
+        }
+    }
+    if storage.retained_slot()? > info.first_slot {
+        batch.set_retained_slot(info.first_slot);
+    }
+    if storage
+        .finalized_slot()?
+        .is_none_or(|slot| slot < info.last_slot)
+    {
+        batch.set_finalized_slot(info.last_slot);
+    }
+    batch.commit()?;
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::{create_finalized_chain, generate_validators};
+
+    #[test]
+    fn export_and_import() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 5, &sks, &epoch_info);
+
+        let mut segment = Vec::new();
+        let info = export_segment(&*storage, Slot::new(2)..Slot::new(5), &mut segment)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.first_slot, Slot::new(2));
+        assert_eq!(info.last_slot, Slot::new(4));
+        assert_eq!(info.num_blocks, 3);
+
+        let restored: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        assert_eq!(
+            import_segment(&*restored, segment.as_slice(), &epoch_info).unwrap(),
+            info
+        );
+        for block_id in &chain[1..4] {
+            assert!(restored.get::<Blocks>(block_id).unwrap().is_some());
+            assert_eq!(
+                finalized_hash(&*restored, block_id.0).unwrap(),
+                Some(block_id.1.clone())
+            );
+        }
+        assert_eq!(restored.iter::<Blocks>().count(), 3);
+        assert_eq!(restored.finalized_slot().unwrap(), Some(Slot::new(4)));
+        restored.assert_consistent();
+
+        // nothing to export without finalized blocks
+        let empty = export_segment(&*storage, Slot::new(6)..Slot::new(9), Vec::new()).unwrap();
+        assert!(empty.is_none());
+    }
+
+    #[test]
+    fn reject_corrupted() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        create_finalized_chain(&*storage, 3, &sks, &epoch_info);
+        let mut segment = Vec::new();
+        export_segment(&*storage, Slot::genesis()..Slot::new(4), &mut segment).unwrap();
+
+        let mut flipped = segment.clone();
+        flipped[20] ^= 1;
+        let result = read_segment(flipped.as_slice(), &epoch_info);
+        assert!(matches!(result, Err(ArchiveError::ChecksumMismatch)));
+        let result = read_segment(&segment[8..], &epoch_info);
+        assert!(matches!(result, Err(ArchiveError::BadMagic)));
+        let result = read_segment(&segment[..16], &epoch_info);
+        assert!(matches!(result, Err(ArchiveError::Malformed)));
+    }
+
+    #[test]
+    fn reject_unverified() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        create_finalized_chain(&*storage, 3, &sks, &epoch_info);
+        let chain = finalized_chain(&*storage, Slot::genesis()..Slot::new(4))
+            .unwrap()
+            .iter()
+            .map(|id| load_archived_block(&*storage, id).unwrap())
+            .collect::<Vec<_>>();
+        let encode = |chain: &[ArchivedBlock]| {
+            let ids = chain
+                .iter()
+                .map(ArchivedBlock::block_id)
+                .collect::<Vec<_>>();
+            let mut segment = Vec::new();
+            let blocks = chain.iter().cloned().map(Ok);
+            write_segment(&mut segment, &SegmentInfo::for_chain(&ids), blocks).unwrap();
+            segment
+        };
+        let reencode =
+            |chain: &[ArchivedBlock]| read_segment(encode(chain).as_slice(), &epoch_info);
+        assert!(reencode(&chain).is_ok());
+
+        // last block without its finalization certificate
+        let mut unfinalized = chain.clone();
+        unfinalized[2]
+            .certs
+            .retain(|cert| !matches!(cert, Cert::Final(_)));
+        let result = reencode(&unfinalized);
+        assert!(matches!(result, Err(ArchiveError::NotFinalized(_))));
+
+        // gap in the chain
+        let gap = [chain[0].clone(), chain[2].clone()];
+        let result = reencode(&gap);
+        assert!(matches!(result, Err(ArchiveError::BrokenChain(_))));
+
+        // certificate for another block
+        let mut wrong_cert = chain.clone();
+        wrong_cert[1].certs = chain[0].certs.clone();
+        let result = reencode(&wrong_cert);
+        assert!(matches!(result, Err(ArchiveError::InvalidCert(_))));
+
+        // certificate from unknown validators
+        let (_, other_epoch) = generate_validators(4);
+        let result = read_segment(encode(&chain).as_slice(), &other_epoch);
+        assert!(matches!(result, Err(ArchiveError::InvalidCert(_))));
+    }
+}
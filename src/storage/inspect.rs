@@ -0,0 +1,220 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consistency checks and walks over the stored ledger, as used by `ledger_tool`.
+
+use std::collections::BTreeSet;
+
+use super::archive::finalized_hash;
+use super::{Blocks, Certs, Metadata, Storage, StorageError};
+use crate::Slot;
+use crate::consensus::Cert;
+use crate::crypto::merkle::BlockHash;
+
+/// Holes in the stored ledger, found by [`find_gaps`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Gaps {
+    /// Inclusive ranges of slots with neither a block nor a skip certificate.
+    pub missing: Vec<(Slot, Slot)>,
+    /// Blocks whose parent is not stored, as slot, hash and parent slot.
+    pub orphans: Vec<(Slot, BlockHash, Slot)>,
+}
+
+impl Gaps {
+    /// Returns `true` if no gaps were found.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.orphans.is_empty()
+    }
+}
+
+/// Finds gaps in slots `first` to `finalized`, inclusive.
+///
+/// Parents before `first` are not checked, so `first` should be after genesis
+/// and not before the retained slot.
+///
+/// # Errors
+///
+/// Returns an error if reading from storage fails.
+pub fn find_gaps(
+    storage: &dyn Storage,
+    first: Slot,
+    finalized: Slot,
+) -> Result<Gaps, StorageError> {
+    let mut gaps = Gaps::default();
+    let mut covered = BTreeSet::new();
+    for item in storage.iter_from::<Blocks>(first) {
+        let ((slot, hash), block) = item?;
+        covered.insert(slot);
+        let parent = (block.parent(), BlockHash::from(block.parent_hash()));
+        if parent.0 >= first && storage.get::<Blocks>(&parent)?.is_none() {
+            gaps.orphans.push((slot, hash, parent.0));
+        }
+    }
+    for item in storage.iter_from::<Certs>(first) {
+        let (key, cert) = item?;
+        if matches!(cert, Cert::Skip(_)) {
+            covered.insert(key.slot);
+        }
+    }
+
+    for slot in (first.inner()..=finalized.inner()).map(Slot::new) {
+        if covered.contains(&slot) {
+            continue;
+        }
+        match gaps.missing.last_mut() {
+            Some((_, end)) if end.next() == slot => *end = slot,
+            _ => gaps.missing.push((slot, slot)),
+        }
+    }
+    Ok(gaps)
+}
+
+/// A block on the finalized chain, as returned by [`walk_finalized_chain`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainLink {
+    pub slot: Slot,
+    pub hash: BlockHash,
+    /// Whether the block is finalized by its own certificates, rather than by a descendant.
+    pub explicit: bool,
+    pub finalized_timestamp: Option<u64>,
+}
+
+/// Walks the finalized chain back from the highest explicitly finalized block, newest first.
+///
+/// Stops after `limit` blocks, at genesis, or at the first block whose full data is not stored.
+///
+/// # Errors
+///
+/// Returns an error if reading from storage fails.
+pub fn walk_finalized_chain(
+    storage: &dyn Storage,
+    finalized: Slot,
+    limit: usize,
+) -> Result<Vec<ChainLink>, StorageError> {
+    let mut next = None;
+    for item in storage.iter_rev::<Certs>() {
+        let (key, _) = item?;
+        if key.slot > finalized {
+            continue;
+        }
+        if let Some(hash) = finalized_hash(storage, key.slot)? {
+            next = Some((key.slot, hash));
+            break;
+        }
+    }
+
+    let mut chain = Vec::new();
+    while let Some((slot, hash)) = next.take() {
+        if chain.len() == limit {
+            break;
+        }
+        let explicit = finalized_hash(storage, slot)?.as_ref() == Some(&hash);
+        let metadata = storage.get::<Metadata>(&(slot, hash.clone()))?;
+        chain.push(ChainLink {
+            slot,
+            hash: hash.clone(),
+            explicit,
+            finalized_timestamp: metadata.and_then(|metadata| metadata.finalized_timestamp),
+        });
+        if slot == Slot::genesis() {
+            break;
+        }
+        next = storage
+            .get::<Blocks>(&(slot, hash))?
+            .map(|block| (block.parent(), BlockHash::from(block.parent_hash())));
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::consensus::{SkipCert, Vote};
+    use crate::storage::{CertKey, MemoryStorage};
+    use crate::test_utils::{create_finalized_chain, generate_validators};
+
+    #[test]
+    fn gaps() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 6, &sks, &epoch_info);
+        let first = Slot::genesis().next();
+        let finalized = Slot::new(6);
+        assert!(find_gaps(&*storage, first, finalized).unwrap().is_empty());
+
+        // a missing block leaves a gap and orphans its child
+        storage.delete::<Blocks>(&chain[2]).unwrap();
+        let gaps = find_gaps(&*storage, first, finalized).unwrap();
+        assert_eq!(gaps.missing, [(Slot::new(3), Slot::new(3))]);
+        assert_eq!(
+            gaps.orphans,
+            [(Slot::new(4), chain[3].1.clone(), Slot::new(3))]
+        );
+
+        // a skip certificate covers the slot, adjacent gaps are merged
+        let votes: Vec<_> = (0..)
+            .zip(&sks)
+            .map(|(v, sk)| Vote::new_skip(Slot::new(3), sk, v))
+            .collect();
+        let cert = Cert::Skip(SkipCert::new_unchecked(&votes, &epoch_info.validators));
+        storage.put::<Certs>(&CertKey::new(&cert), &cert).unwrap();
+        storage.delete::<Blocks>(&chain[4]).unwrap();
+        storage.delete::<Blocks>(&chain[5]).unwrap();
+        let gaps = find_gaps(&*storage, first, finalized).unwrap();
+        assert_eq!(gaps.missing, [(Slot::new(5), Slot::new(6))]);
+        assert_eq!(gaps.orphans.len(), 1);
+
+        // parents before the first slot are not checked
+        let gaps = find_gaps(&*storage, Slot::new(4), Slot::new(4)).unwrap();
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn finalized_chain() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 5, &sks, &epoch_info);
+        let slots = |links: &[ChainLink]| {
+            links
+                .iter()
+                .map(|link| link.slot.inner())
+                .collect::<Vec<_>>()
+        };
+
+        let links = walk_finalized_chain(&*storage, Slot::new(5), 10).unwrap();
+        assert_eq!(slots(&links), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(links[0].hash, chain[4].1);
+        assert!(links[..5].iter().all(|link| link.explicit));
+        assert_eq!(
+            walk_finalized_chain(&*storage, Slot::new(5), 2)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // blocks without a finalization certificate are finalized by their children
+        let certs: Vec<_> = storage
+            .iter_slot::<Certs>(Slot::new(4))
+            .map(Result::unwrap)
+            .collect();
+        for (key, cert) in certs {
+            if matches!(cert, Cert::Final(_)) {
+                storage.delete::<Certs>(&key).unwrap();
+            }
+        }
+        let links = walk_finalized_chain(&*storage, Slot::new(5), 10).unwrap();
+        assert!(!links[1].explicit);
+
+        // the tip is the highest explicitly finalized block not after the finalized slot
+        let links = walk_finalized_chain(&*storage, Slot::new(4), 10).unwrap();
+        assert_eq!(slots(&links), [3, 2, 1, 0]);
+
+        // the walk ends where full data is pruned
+        storage.delete_slots::<Blocks>(..Slot::new(3)).unwrap();
+        let links = walk_finalized_chain(&*storage, Slot::new(5), 10).unwrap();
+        assert_eq!(slots(&links), [5, 4, 3, 2]);
+    }
+}
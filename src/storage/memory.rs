@@ -0,0 +1,96 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! [`Storage`] backend keeping all data in memory.
+//!
+//! This is meant for tests, which should neither touch the disk nor share state.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{PoisonError, RwLock};
+
+use super::{IterMode, RawIter, SCHEMA_VERSION, Storage, StorageError, WriteOp};
+
+/// Entries of a single column, ordered by key.
+type ColumnData = BTreeMap<Box<[u8]>, Box<[u8]>>;
+
+/// Storage that keeps all columns in memory, and loses them on drop.
+///
+/// It always uses the current [`SCHEMA_VERSION`].
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    columns: RwLock<HashMap<&'static str, ColumnData>>,
+}
+
+impl Storage for MemoryStorage {
+    fn get_raw(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        let columns = self.columns.read().unwrap_or_else(PoisonError::into_inner);
+        let value = columns.get(column).and_then(|data| data.get(key));
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn iter_raw(&self, column: &'static str, mode: IterMode<'_>) -> RawIter<'_> {
+        let columns = self.columns.read().unwrap_or_else(PoisonError::into_inner);
+        let Some(data) = columns.get(column) else {
+            return Box::new(std::iter::empty());
+        };
+        // copy entries, so no lock is held while iterating
+        let entries: Vec<_> = match mode {
+            IterMode::Start => data.iter().collect(),
+            IterMode::End => data.iter().rev().collect(),
+            IterMode::From(key) => data
+                .range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+                .collect(),
+        };
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn write(&self, ops: Vec<WriteOp>) -> Result<(), StorageError> {
+        let mut columns = self.columns.write().unwrap_or_else(PoisonError::into_inner);
+        for op in ops {
+            match op {
+                WriteOp::Put { column, key, value } => {
+                    let data = columns.entry(column).or_default();
+                    data.insert(key.into(), value.into());
+                }
+                WriteOp::Delete { column, key } => {
+                    if let Some(data) = columns.get_mut(column) {
+                        data.remove(key.as_slice());
+                    }
+                }
+                WriteOp::DeleteRange { column, from, to } => {
+                    if let Some(data) = columns.get_mut(column) {
+                        data.retain(|key, _| key[..] < from[..] || key[..] >= to[..]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn schema_version(&self) -> Result<u32, StorageError> {
+        Ok(SCHEMA_VERSION)
+    }
+
+    fn sizes(&self) -> [(&'static str, u64); 3] {
+        let columns = self.columns.read().unwrap_or_else(PoisonError::into_inner);
+        let size: usize = columns
+            .values()
+            .flat_map(|data| data.iter())
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        [
+            ("sst_files", 0),
+            ("live_data", size as u64),
+            ("memtables", size as u64),
+        ]
+    }
+
+    fn compact(&self) {
+        // deleted entries are dropped immediately
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Versioned migrations of the node database schema.
+//!
+//! The schema version is stored in the default column family.
+//! A database without a version record is at version 0.
+//! On opening, all migrations newer than the stored version are applied in order.
+//!
+//! To change the schema, append a new [`Migration`] to [`MIGRATIONS`].
+//! Existing migrations must never be changed or reordered.
+
+use log::info;
+use rocksdb::{DB, IteratorMode, WriteBatch};
+
+use super::StorageError;
+use super::schema::{BlockHashes, Blocks, Column, ColumnKey};
+use crate::BlockId;
+
+/// Key of the schema version record in the default column family.
+pub(super) const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// Schema version after applying all [`MIGRATIONS`].
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// A single step upgrading the schema from `version - 1` to `version`.
+pub(super) struct Migration {
+    /// Version of the schema after applying this migration.
+    pub(super) version: u32,
+    /// Human-readable summary, for logging.
+    pub(super) description: &'static str,
+    /// Applies the migration, must be idempotent.
+    pub(super) run: fn(&DB) -> Result<(), StorageError>,
+}
+
+/// All migrations, ordered by version.
+pub(super) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "typed column families, dropping legacy string keys",
+        run: drop_legacy_keys,
+    },
+    Migration {
+        version: 2,
+        description: "index blocks by hash",
+        run: index_block_hashes,
+    },
+];
+
+/// Brings the schema of `db` up to [`SCHEMA_VERSION`].
+///
+/// # Errors
+///
+/// Returns [`StorageError::UnsupportedVersion`] if the database was written
+/// by a newer version of this software, or any error of a migration step.
+pub(super) fn migrate(db: &DB) -> Result<(), StorageError> {
+    let version = schema_version(db)?;
+    if version > SCHEMA_VERSION {
+        return Err(StorageError::UnsupportedVersion {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        info!(
+            "migrating storage to schema version {}: {}",
+            migration.version, migration.description
+        );
+        (migration.run)(db)?;
+        db.put(SCHEMA_VERSION_KEY, migration.version.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the schema version of `db`, which is 0 if it has none.
+pub(super) fn schema_version(db: &DB) -> Result<u32, StorageError> {
+    match db.get(SCHEMA_VERSION_KEY)? {
+        None => Ok(0),
+        Some(bytes) => {
+            let bytes = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| StorageError::Decode("default"))?;
+            Ok(u32::from_be_bytes(bytes))
+        }
+    }
+}
+
+/// Removes all entries of the old layout from the default column family.
+///
+/// Before version 1, blocks and certificates were stored in the default column family
+/// under string keys like `{slot:016X}{hash}`, `meta|…` and `cert|{slot}|{kind}`.
+/// Their values used an encoding that is no longer supported, so they are dropped.
+/// Nodes recover the dropped data via repair.
+fn drop_legacy_keys(db: &DB) -> Result<(), StorageError> {
+    let mut batch = WriteBatch::default();
+    let mut dropped = 0;
+    for item in db.iterator(IteratorMode::Start) {
+        let (key, _) = item?;
+        if &*key != SCHEMA_VERSION_KEY {
+            batch.delete(key);
+            dropped += 1;
+        }
+    }
+    db.write(batch)?;
+    if dropped > 0 {
+        info!("dropped {dropped} entries of the legacy storage layout");
+    }
+    Ok(())
+}
+
+/// Fills the [`BlockHashes`] index for all blocks stored before it existed.
+fn index_block_hashes(db: &DB) -> Result<(), StorageError> {
+    // all column families are created when opening the database
+    let blocks = db.cf_handle(Blocks::NAME).unwrap();
+    let index = db.cf_handle(BlockHashes::NAME).unwrap();
+    let mut batch = WriteBatch::default();
+    for item in db.iterator_cf(blocks, IteratorMode::Start) {
+        let (key, _) = item?;
+        let (slot, hash) = BlockId::decode(&key).ok_or(StorageError::Decode(Blocks::NAME))?;
+        let slot =
+            wincode::serialize(&slot).map_err(|_| StorageError::Encode(BlockHashes::NAME))?;
+        batch.put_cf(index, hash.encode(), slot);
+    }
+    db.write(batch)?;
+    Ok(())
+}
@@ -0,0 +1,477 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retention of old data in the node database.
+//!
+//! Full data of finalized slots, i.e. [`Blocks`], [`Shreds`] and [`Votes`],
+//! is deleted once it falls outside the [`RetentionPolicy`].
+//! Headers, i.e. [`Metadata`] and the [`BlockHashes`] index, and [`Certs`] are kept forever.
+//! Data that is not finalized yet is never deleted.
+//!
+//! [`Shreds`] are only needed to serve repair of recent slots. Unlike the rest of
+//! the full data, they are deleted after a fixed number of slots by default.
+//!
+//! If an archive directory is configured, full data is exported to a segment
+//! file (see [`archive`](super::archive)) before it is deleted.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio_util::sync::CancellationToken;
+
+use super::archive::{self, ArchiveError};
+use super::schema::{Column, slot_prefix};
+#[cfg(doc)]
+use super::{BlockHashes, Certs, Metadata};
+use super::{Blocks, IterMode, Shreds, Storage, StorageError, Votes};
+use crate::Slot;
+
+/// Columns holding the full data of slots, which is subject to retention.
+const FULL_DATA_COLUMNS: [&str; 3] = [Blocks::NAME, Shreds::NAME, Votes::NAME];
+
+/// Default number of most recent finalized slots for which shreds are kept.
+const DEFAULT_MAX_SHRED_SLOTS: u64 = 1024;
+
+/// Policy deciding how long full data of finalized slots is kept.
+///
+/// If both limits are set, data is deleted as soon as either of them is exceeded.
+/// By default, everything except for old shreds is kept forever.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    max_slots: Option<u64>,
+    max_bytes: Option<u64>,
+    max_shred_slots: u64,
+    archive_dir: Option<PathBuf>,
+    interval: Duration,
+}
+
+impl RetentionPolicy {
+    /// Keeps full data of only the `max_slots` most recent finalized slots.
+    #[must_use]
+    pub fn with_max_slots(mut self, max_slots: u64) -> Self {
+        self.max_slots = Some(max_slots);
+        self
+    }
+
+    /// Keeps full data of only as many recent finalized slots as fit into `max_bytes`.
+    ///
+    /// Sizes are measured as the encoded size of entries, before compression.
+    #[must_use]
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Keeps shreds of only the `max_shred_slots` most recent finalized slots.
+    ///
+    /// Shreds are kept to serve repair after a restart.
+    /// Defaults to 1024 slots, and is further limited by [`Self::with_max_slots`].
+    #[must_use]
+    pub const fn with_max_shred_slots(mut self, max_shred_slots: u64) -> Self {
+        self.max_shred_slots = max_shred_slots;
+        self
+    }
+
+    /// Exports finalized blocks to segment files in `archive_dir` before deleting them.
+    #[must_use]
+    pub fn with_archive_dir(mut self, archive_dir: impl Into<PathBuf>) -> Self {
+        self.archive_dir = Some(archive_dir.into());
+        self
+    }
+
+    /// Sets how often [`run_retention`] enforces the policy.
+    ///
+    /// Defaults to one minute.
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns `true` if this policy never deletes any blocks.
+    ///
+    /// Old shreds may be deleted regardless, see [`Self::with_max_shred_slots`].
+    #[must_use]
+    pub const fn keeps_all(&self) -> bool {
+        self.max_slots.is_none() && self.max_bytes.is_none()
+    }
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_slots: None,
+            max_bytes: None,
+            max_shred_slots: DEFAULT_MAX_SHRED_SLOTS,
+            archive_dir: None,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Enforces the retention `policy` on `storage` once.
+///
+/// Returns the new retained slot (see `retained_slot` on `dyn Storage`), or `None` if no blocks
+/// were deleted. Shreds of old slots are deleted independently, see [`prune_shreds`].
+///
+/// With an archive directory, data is only deleted up to the last block that could be
+/// archived, so nothing is lost that is not covered by a segment file.
+///
+/// # Errors
+///
+/// Returns an error if accessing storage or archiving fails.
+/// In that case nothing is deleted.
+pub fn enforce_retention(
+    storage: &dyn Storage,
+    policy: &RetentionPolicy,
+) -> Result<Option<Slot>, ArchiveError> {
+    enforce_retention_cached(storage, policy, &mut SlotSizes::default())
+}
+
+/// Same as [`enforce_retention`], reusing slot sizes measured in previous passes.
+fn enforce_retention_cached(
+    storage: &dyn Storage,
+    policy: &RetentionPolicy,
+    slot_sizes: &mut SlotSizes,
+) -> Result<Option<Slot>, ArchiveError> {
+    let Some(finalized) = storage.finalized_slot()? else {
+        return Ok(None);
+    };
+    let shred_cutoff = prune_shreds(storage, policy, finalized)?;
+    if policy.keeps_all() {
+        return Ok(None);
+    }
+    let retained = storage.retained_slot()?;
+
+    let by_slots = policy.max_slots.map_or(Slot::genesis(), |max_slots| {
+        Slot::new((finalized.inner() + 1).saturating_sub(max_slots))
+    });
+    let by_bytes = match policy.max_bytes {
+        Some(max_bytes) => {
+            slot_sizes.update(storage, retained, shred_cutoff, finalized)?;
+            slot_sizes.cutoff(max_bytes)
+        }
+        None => Slot::genesis(),
+    };
+    // never delete the highest finalized slot, nor anything after it
+    let mut cutoff = by_slots.max(by_bytes).min(finalized);
+    if cutoff <= retained {
+        return Ok(None);
+    }
+
+    if let Some(archive_dir) = &policy.archive_dir {
+        let segments = archive::export_to_dir(storage, retained..cutoff, archive_dir)?;
+        for segment in &segments {
+            info!(
+                "archived {} blocks of slots {} to {}",
+                segment.num_blocks, segment.first_slot, segment.last_slot
+            );
+        }
+        match segments.last() {
+            Some(segment) => cutoff = segment.last_slot.next(),
+            None => {
+                let has_blocks = storage
+                    .iter_from::<Blocks>(retained)
+                    .next()
+                    .transpose()?
+                    .is_some_and(|((slot, _), _)| slot < cutoff);
+                if has_blocks {
+                    // wait until there is a finalized block to end the segment with
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    let mut batch = storage.batch();
+    batch.delete_slots::<Blocks>(..cutoff);
+    batch.delete_slots::<Shreds>(..cutoff);
+    batch.delete_slots::<Votes>(..cutoff);
+    batch.set_retained_slot(cutoff);
+    batch.commit()?;
+    info!(
+        "pruned full data of slots {retained} to {}",
+        cutoff.inner() - 1
+    );
+    Ok(Some(cutoff))
+}
+
+/// Deletes shreds of slots before the `max_shred_slots` most recent finalized slots.
+///
+/// Returns the first slot whose shreds are kept.
+fn prune_shreds(
+    storage: &dyn Storage,
+    policy: &RetentionPolicy,
+    finalized: Slot,
+) -> Result<Slot, StorageError> {
+    let cutoff = Slot::new((finalized.inner() + 1).saturating_sub(policy.max_shred_slots));
+    // avoid adding a range tombstone on every pass if there is nothing to delete
+    let oldest = storage.iter::<Shreds>().next().transpose()?;
+    if oldest.is_some_and(|(key, _)| key.block_id.0 < cutoff) {
+        let mut batch = storage.batch();
+        batch.delete_slots::<Shreds>(..cutoff);
+        batch.commit()?;
+    }
+    Ok(cutoff)
+}
+
+/// Sizes of the full data of finalized slots, kept across retention passes.
+///
+/// Each pass only scans the slots finalized since the previous one.
+/// Entries added to a slot after it was scanned, e.g. late votes, are not counted.
+#[derive(Debug)]
+struct SlotSizes {
+    /// Encoded size per slot, for each of the [`FULL_DATA_COLUMNS`].
+    sizes: BTreeMap<Slot, [u64; 3]>,
+    /// First slot that has not been scanned yet.
+    next: Slot,
+}
+
+impl Default for SlotSizes {
+    fn default() -> Self {
+        Self {
+            sizes: BTreeMap::new(),
+            next: Slot::genesis(),
+        }
+    }
+}
+
+impl SlotSizes {
+    /// Forgets deleted data and measures slots finalized since the last update.
+    fn update(
+        &mut self,
+        storage: &dyn Storage,
+        retained: Slot,
+        shred_cutoff: Slot,
+        finalized: Slot,
+    ) -> Result<(), StorageError> {
+        self.sizes = self.sizes.split_off(&retained);
+        // shreds of these slots were deleted by `prune_shreds`
+        for (_, sizes) in self.sizes.range_mut(..shred_cutoff) {
+            sizes[1] = 0;
+        }
+        let from = self.next.max(retained);
+        if from > finalized {
+            return Ok(());
+        }
+        let prefix = slot_prefix(from);
+        for (i, column) in FULL_DATA_COLUMNS.into_iter().enumerate() {
+            for item in storage.iter_raw(column, IterMode::From(&prefix)) {
+                let (key, value) = item?;
+                // keys of all full data columns start with the slot
+                let slot = Slot::new(u64::from_be_bytes(key[..8].try_into().unwrap()));
+                if slot > finalized {
+                    break;
+                }
+                self.sizes.entry(slot).or_default()[i] += (key.len() + value.len()) as u64;
+            }
+        }
+        self.next = finalized.next();
+        Ok(())
+    }
+
+    /// Returns the first slot such that full data from there on fits into `max_bytes`.
+    fn cutoff(&self, max_bytes: u64) -> Slot {
+        let mut total = 0;
+        for (slot, sizes) in self.sizes.iter().rev() {
+            total += sizes.iter().sum::<u64>();
+            if total > max_bytes {
+                return slot.next();
+            }
+        }
+        Slot::genesis()
+    }
+}
+
+/// Periodically enforces the retention `policy` on `storage`, until cancelled.
+///
+/// After deleting data, compacts the storage to reclaim disk space.
+/// Both run on a blocking thread, so they do not stall other tasks.
+pub async fn run_retention(
+    storage: Arc<dyn Storage>,
+    policy: RetentionPolicy,
+    cancel_token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(policy.interval);
+    let mut slot_sizes = SlotSizes::default();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = cancel_token.cancelled() => return,
+        }
+        let storage = Arc::clone(&storage);
+        let policy = policy.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let pruned = enforce_retention_cached(&*storage, &policy, &mut slot_sizes);
+            if matches!(pruned, Ok(Some(_))) {
+                storage.compact();
+            }
+            (pruned, slot_sizes)
+        })
+        .await;
+        match result {
+            Ok((pruned, sizes)) => {
+                slot_sizes = sizes;
+                if let Err(err) = pruned {
+                    warn!("failed to enforce retention policy: {err}");
+                }
+            }
+            Err(err) => {
+                warn!("retention task failed: {err}");
+                slot_sizes = SlotSizes::default();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signature::SecretKey;
+    use crate::storage::archive::{SegmentInfo, import_segment};
+    use crate::storage::{BlockHashes, Certs, MemoryStorage, Metadata, ShredKey};
+    use crate::test_utils::{
+        create_finalized_chain, create_random_shredded_block, generate_validators,
+    };
+    use crate::types::SliceIndex;
+
+    #[test]
+    fn prune_by_slots() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 10, &sks, &epoch_info);
+
+        // nothing is deleted without limits
+        let policy = RetentionPolicy::default();
+        assert_eq!(enforce_retention(&*storage, &policy).unwrap(), None);
+
+        let policy = RetentionPolicy::default().with_max_slots(4);
+        let cutoff = enforce_retention(&*storage, &policy).unwrap();
+        assert_eq!(cutoff, Some(Slot::new(7)));
+        assert_eq!(storage.retained_slot().unwrap(), Slot::new(7));
+        for (slot, hash) in &chain {
+            let block_id = (*slot, hash.clone());
+            let retained = *slot >= Slot::new(7);
+            assert_eq!(
+                storage.get::<Blocks>(&block_id).unwrap().is_some(),
+                retained
+            );
+            // headers and certificates are kept forever
+            assert!(storage.get::<Metadata>(&block_id).unwrap().is_some());
+            assert_eq!(storage.get::<BlockHashes>(hash).unwrap(), Some(*slot));
+            assert!(storage.iter_slot::<Certs>(*slot).next().is_some());
+        }
+        storage.assert_consistent();
+
+        // enforcing again is a no-op
+        assert_eq!(enforce_retention(&*storage, &policy).unwrap(), None);
+    }
+
+    #[test]
+    fn prune_by_bytes() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        create_finalized_chain(&*storage, 10, &sks, &epoch_info);
+
+        let slot_size = slot_size(&*storage, Slot::new(10));
+        let policy = RetentionPolicy::default().with_max_bytes(3 * slot_size);
+        let mut slot_sizes = SlotSizes::default();
+        let cutoff = enforce_retention_cached(&*storage, &policy, &mut slot_sizes).unwrap();
+        assert_eq!(cutoff, Some(Slot::new(8)));
+
+        // sizes of deleted slots are forgotten on the next pass
+        let policy = RetentionPolicy::default().with_max_bytes(0);
+        let cutoff = enforce_retention_cached(&*storage, &policy, &mut slot_sizes).unwrap();
+        assert_eq!(
+            slot_sizes.sizes.keys().collect::<Vec<_>>(),
+            [&Slot::new(8), &Slot::new(9), &Slot::new(10)]
+        );
+
+        // the highest finalized slot is never deleted
+        assert_eq!(cutoff, Some(Slot::new(10)));
+        assert_eq!(storage.iter::<Blocks>().count(), 1);
+    }
+
+    #[test]
+    fn prune_old_shreds() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 10, &sks, &epoch_info);
+        let leader_sk = SecretKey::new(&mut rand::rng());
+        let mut batch = storage.batch();
+        for block_id in &chain {
+            let (_, _, shreds) = create_random_shredded_block(block_id.0, 1, &leader_sk);
+            let shred = shreds[0][0].clone().into_shred();
+            let key = ShredKey {
+                block_id: block_id.clone(),
+                slice: SliceIndex::first(),
+                shred: shred.payload().shred_index,
+            };
+            batch.put::<Shreds>(&key, &shred).unwrap();
+        }
+        batch.commit().unwrap();
+
+        // blocks are kept forever, shreds only for the most recent slots
+        let policy = RetentionPolicy::default().with_max_shred_slots(3);
+        assert_eq!(enforce_retention(&*storage, &policy).unwrap(), None);
+        assert_eq!(storage.iter::<Blocks>().count(), 10);
+        let slots = storage
+            .iter::<Shreds>()
+            .map(|item| item.unwrap().0.block_id.0)
+            .collect::<Vec<_>>();
+        assert_eq!(slots, [8, 9, 10].map(Slot::new));
+        storage.assert_consistent();
+    }
+
+    /// Returns the size of the full data of `slot`.
+    fn slot_size(storage: &dyn Storage, slot: Slot) -> u64 {
+        FULL_DATA_COLUMNS
+            .into_iter()
+            .flat_map(|column| storage.iter_raw(column, IterMode::Start))
+            .map(Result::unwrap)
+            .filter(|(key, _)| key[..8] == slot.inner().to_be_bytes())
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum()
+    }
+
+    #[test]
+    fn archive_before_pruning() {
+        let (sks, epoch_info) = generate_validators(4);
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let chain = create_finalized_chain(&*storage, 10, &sks, &epoch_info);
+        let archive_dir =
+            std::env::temp_dir().join(format!("alpenglow-archive-{}", std::process::id()));
+
+        let policy = RetentionPolicy::default()
+            .with_max_slots(4)
+            .with_archive_dir(&archive_dir);
+        assert_eq!(
+            enforce_retention(&*storage, &policy).unwrap(),
+            Some(Slot::new(7))
+        );
+        let segment = SegmentInfo {
+            version: archive::SEGMENT_VERSION,
+            first_slot: Slot::new(1),
+            last_slot: Slot::new(6),
+            num_blocks: 6,
+        };
+        let path = archive_dir.join(segment.file_name());
+
+        // archived blocks can be re-imported into a fresh node
+        let restored: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(
+            import_segment(&*restored, file, &epoch_info).unwrap(),
+            segment
+        );
+        for block_id in &chain[..6] {
+            assert!(restored.get::<Blocks>(block_id).unwrap().is_some());
+        }
+        assert_eq!(restored.finalized_slot().unwrap(), Some(Slot::new(6)));
+        restored.assert_consistent();
+        std::fs::remove_dir_all(&archive_dir).unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! [`Storage`] backend keeping a RocksDB database on disk.
+//!
+//! Each [`Column`](super::Column) is a RocksDB column family.
+
+use std::path::{Path, PathBuf};
+
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, DB, Direction, IteratorMode, Options, WriteBatch,
+    WriteOptions,
+};
+
+use super::schema::COLUMN_FAMILIES;
+use super::{IterMode, RawIter, SCHEMA_VERSION, Storage, StorageError, WriteOp, migrations};
+
+/// RocksDB database of a single node.
+///
+/// This is shared between the blockstore and the pool of a node.
+pub struct RocksDbStorage {
+    db: DB,
+    /// Whether writes are synced to disk before they are acknowledged.
+    sync_writes: bool,
+    /// Deletes the database directory after the database is closed, if set.
+    // NOTE: this must be declared after `db`, so it is dropped after it.
+    _temp_dir: Option<TempDir>,
+}
+
+impl RocksDbStorage {
+    /// Opens the database at `path`, creating it if missing.
+    ///
+    /// Migrates the schema to [`SCHEMA_VERSION`] if necessary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can not be opened or migrated.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let cfs = COLUMN_FAMILIES
+            .into_iter()
+            .map(|name| ColumnFamilyDescriptor::new(name, Options::default()));
+        let db = DB::open_cf_descriptors(&opts, path, cfs)?;
+        migrations::migrate(&db)?;
+        Ok(Self {
+            db,
+            sync_writes: false,
+            _temp_dir: None,
+        })
+    }
+
+    /// Opens the existing database at `path` for reading only.
+    ///
+    /// This can be done while a node has the database open, to inspect it.
+    /// Any writes fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can not be opened,
+    /// or if its schema is not at [`SCHEMA_VERSION`].
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let db = DB::open_cf_for_read_only(&Options::default(), path, COLUMN_FAMILIES, false)?;
+        let version = migrations::schema_version(&db)?;
+        if version > SCHEMA_VERSION {
+            return Err(StorageError::UnsupportedVersion {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        if version < SCHEMA_VERSION {
+            return Err(StorageError::MigrationRequired {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        Ok(Self {
+            db,
+            sync_writes: false,
+            _temp_dir: None,
+        })
+    }
+
+    /// Sets whether writes are synced to disk before they are acknowledged.
+    ///
+    /// Without syncing, all writes survive a crash of the process,
+    /// but the most recent writes may be lost if the machine crashes.
+    /// Either way, each write or [`StorageBatch`](super::StorageBatch) is applied atomically.
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn with_sync_writes(mut self, sync_writes: bool) -> Self {
+        self.sync_writes = sync_writes;
+        self
+    }
+
+    /// Opens a fresh database in a temporary directory, deleted on drop.
+    #[cfg(test)]
+    pub(crate) fn open_temporary() -> Self {
+        let path = TempDir::new_path();
+        let mut storage = Self::open(&path).expect("open temporary storage");
+        storage._temp_dir = Some(TempDir(path));
+        storage
+    }
+
+    /// Simulates a crash of the node, followed by a restart.
+    ///
+    /// Returns a temporary copy of the database as it would be found on disk
+    /// if the process was killed right now, opened like on a restart.
+    #[cfg(test)]
+    pub(crate) fn crash_snapshot(&self) -> Self {
+        let path = TempDir::new_path();
+        rocksdb::checkpoint::Checkpoint::new(&self.db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(&path))
+            .expect("create checkpoint");
+        let mut storage = Self::open(&path).expect("reopen after crash");
+        storage._temp_dir = Some(TempDir(path));
+        storage
+    }
+
+    fn cf(&self, column: &'static str) -> &ColumnFamily {
+        // all column families are created when opening the database
+        self.db.cf_handle(column).unwrap()
+    }
+}
+
+impl Storage for RocksDbStorage {
+    fn get_raw(&self, column: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.db.get_cf(self.cf(column), key)?)
+    }
+
+    fn iter_raw(&self, column: &'static str, mode: IterMode<'_>) -> RawIter<'_> {
+        let mode = match mode {
+            IterMode::Start => IteratorMode::Start,
+            IterMode::End => IteratorMode::End,
+            IterMode::From(key) => IteratorMode::From(key, Direction::Forward),
+        };
+        let iter = self.db.iterator_cf(self.cf(column), mode);
+        Box::new(iter.map(|item| item.map_err(StorageError::from)))
+    }
+
+    fn write(&self, ops: Vec<WriteOp>) -> Result<(), StorageError> {
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            match op {
+                WriteOp::Put { column, key, value } => batch.put_cf(self.cf(column), key, value),
+                WriteOp::Delete { column, key } => batch.delete_cf(self.cf(column), key),
+                WriteOp::DeleteRange { column, from, to } => {
+                    batch.delete_range_cf(self.cf(column), from, to);
+                }
+            }
+        }
+        let mut opts = WriteOptions::default();
+        opts.set_sync(self.sync_writes);
+        self.db.write_opt(batch, &opts)?;
+        Ok(())
+    }
+
+    fn schema_version(&self) -> Result<u32, StorageError> {
+        migrations::schema_version(&self.db)
+    }
+
+    fn sizes(&self) -> [(&'static str, u64); 3] {
+        let property = |name: &str| {
+            let default = self.db.property_int_value(name).ok().flatten().unwrap_or(0);
+            COLUMN_FAMILIES
+                .into_iter()
+                .filter_map(|cf| self.db.cf_handle(cf))
+                .filter_map(|cf| self.db.property_int_value_cf(cf, name).ok().flatten())
+                .sum::<u64>()
+                + default
+        };
+        [
+            ("sst_files", property("rocksdb.total-sst-files-size")),
+            ("live_data", property("rocksdb.estimate-live-data-size")),
+            ("memtables", property("rocksdb.cur-size-all-mem-tables")),
+        ]
+    }
+
+    fn compact(&self) {
+        for column in COLUMN_FAMILIES {
+            self.db
+                .compact_range_cf(self.cf(column), None::<&[u8]>, None::<&[u8]>);
+        }
+    }
+}
+
+/// Directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Returns a new, not yet existing path in the system's temporary directory.
+    fn new_path() -> PathBuf {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("alpenglow-storage-{}-{id}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Slot;
+
+    #[test]
+    fn migrate_legacy_layout() {
+        let storage = RocksDbStorage::open_temporary();
+        let path = storage._temp_dir.as_ref().unwrap().0.clone();
+        // simulate a database written before schema versions existed
+        storage.db.delete(migrations::SCHEMA_VERSION_KEY).unwrap();
+        storage
+            .db
+            .put(b"cert|0000000000000001|2", b"legacy")
+            .unwrap();
+        storage
+            .db
+            .put(b"meta|final_slot", 1u64.to_be_bytes())
+            .unwrap();
+        let temp_dir = {
+            let mut storage = storage;
+            storage._temp_dir.take()
+        };
+
+        let storage = RocksDbStorage::open(&path).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(storage.db.get(b"meta|final_slot").unwrap(), None);
+        assert_eq!(storage.db.get(b"cert|0000000000000001|2").unwrap(), None);
+
+        // refuse to open databases written by newer versions
+        storage
+            .db
+            .put(
+                migrations::SCHEMA_VERSION_KEY,
+                (SCHEMA_VERSION + 1).to_be_bytes(),
+            )
+            .unwrap();
+        drop(storage);
+        assert!(matches!(
+            RocksDbStorage::open(&path),
+            Err(StorageError::UnsupportedVersion { .. })
+        ));
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn read_only() {
+        let storage = RocksDbStorage::open_temporary();
+        let path = storage._temp_dir.as_ref().unwrap().0.clone();
+        let storage: &dyn Storage = &storage;
+        storage.set_finalized_slot(Slot::new(3)).unwrap();
+
+        // can be opened while the database is in use
+        let read_only = RocksDbStorage::open_read_only(&path).unwrap();
+        let read_only: &dyn Storage = &read_only;
+        assert_eq!(read_only.finalized_slot().unwrap(), Some(Slot::new(3)));
+        assert!(read_only.set_finalized_slot(Slot::new(4)).is_err());
+        assert_eq!(storage.finalized_slot().unwrap(), Some(Slot::new(3)));
+
+        let missing = path.with_extension("missing");
+        assert!(RocksDbStorage::open_read_only(&missing).is_err());
+        assert!(!missing.exists());
+    }
+}
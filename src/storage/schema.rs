@@ -0,0 +1,337 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Column families of the node database and their typed keys.
+//!
+//! All keys are binary. Keys of [`SlotColumn`]s start with the big-endian slot number,
+//! so that RocksDB's lexicographic order is the slot order.
+//! This allows iterating over and deleting ranges of slots efficiently.
+//! Secondary indices, like [`BlockHashes`], are keyed differently.
+
+use wincode::{SchemaRead, SchemaWrite};
+
+use crate::consensus::{BlockMetadata, Cert, Vote, VoteKind};
+use crate::crypto::Hash;
+use crate::crypto::merkle::{BlockHash, GENESIS_BLOCK_HASH, MerkleRoot};
+use crate::shredder::{Shred, ShredIndex};
+use crate::types::SliceIndex;
+use crate::{Block, BlockId, Slot, ValidatorId};
+
+/// Names of all column families, excluding RocksDB's default column family.
+pub(super) const COLUMN_FAMILIES: [&str; 6] = [
+    Blocks::NAME,
+    BlockHashes::NAME,
+    Metadata::NAME,
+    Certs::NAME,
+    Votes::NAME,
+    Shreds::NAME,
+];
+
+/// A column family holding values of a single type under keys of a single type.
+pub trait Column {
+    /// Name of the RocksDB column family.
+    const NAME: &'static str;
+    /// Type of the keys in this column.
+    type Key: ColumnKey;
+    /// Type of the values in this column, encoded with [`wincode`].
+    type Value: SchemaWrite<Src = Self::Value> + for<'de> SchemaRead<'de, Dst = Self::Value>;
+}
+
+/// A [`Column`] whose keys start with the big-endian slot number.
+pub trait SlotColumn: Column {}
+
+/// Binary key of a [`Column`].
+pub trait ColumnKey: Sized {
+    /// Encodes the key.
+    fn encode(&self) -> Vec<u8>;
+    /// Decodes a key previously encoded with [`ColumnKey::encode`].
+    ///
+    /// Returns `None` if `bytes` is not a valid key.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// Reconstructed blocks.
+pub struct Blocks;
+
+impl Column for Blocks {
+    const NAME: &'static str = "blocks";
+    type Key = BlockId;
+    type Value = Block;
+}
+
+impl SlotColumn for Blocks {}
+
+/// Index of the slot of each block in [`Blocks`], by block hash.
+pub struct BlockHashes;
+
+impl Column for BlockHashes {
+    const NAME: &'static str = "block_hashes";
+    type Key = BlockHash;
+    type Value = Slot;
+}
+
+/// Metadata of reconstructed blocks.
+pub struct Metadata;
+
+impl Column for Metadata {
+    const NAME: &'static str = "metadata";
+    type Key = BlockId;
+    type Value = BlockMetadata;
+}
+
+impl SlotColumn for Metadata {}
+
+/// Certificates, at most one per slot and kind.
+pub struct Certs;
+
+impl Column for Certs {
+    const NAME: &'static str = "certs";
+    type Key = CertKey;
+    type Value = Cert;
+}
+
+impl SlotColumn for Certs {}
+
+/// Votes, at most one per slot, validator and kind.
+pub struct Votes;
+
+impl Column for Votes {
+    const NAME: &'static str = "votes";
+    type Key = VoteKey;
+    type Value = Vote;
+}
+
+impl SlotColumn for Votes {}
+
+/// Shreds of blocks.
+pub struct Shreds;
+
+impl Column for Shreds {
+    const NAME: &'static str = "shreds";
+    type Key = ShredKey;
+    type Value = Shred;
+}
+
+impl SlotColumn for Shreds {}
+
+/// Returns the key prefix of all entries in the given `slot`.
+pub(super) fn slot_prefix(slot: Slot) -> [u8; 8] {
+    slot.inner().to_be_bytes()
+}
+
+fn decode_slot(bytes: &[u8]) -> Option<Slot> {
+    Some(Slot::new(u64::from_be_bytes(
+        bytes.get(..8)?.try_into().ok()?,
+    )))
+}
+
+fn decode_hash(bytes: &[u8]) -> Option<BlockHash> {
+    let bytes: [u8; 32] = bytes.try_into().ok()?;
+    Some(Hash::from(bytes).into())
+}
+
+/// Layout: `slot (8) | block hash (32)`.
+impl ColumnKey for BlockId {
+    fn encode(&self) -> Vec<u8> {
+        let (slot, hash) = self;
+        [&slot_prefix(*slot)[..], hash.as_hash().as_ref()].concat()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 40 {
+            return None;
+        }
+        Some((decode_slot(bytes)?, decode_hash(&bytes[8..])?))
+    }
+}
+
+/// Layout: `block hash (32)`.
+impl ColumnKey for BlockHash {
+    fn encode(&self) -> Vec<u8> {
+        self.as_hash().as_ref().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        decode_hash(bytes)
+    }
+}
+
+/// Key of a certificate in the [`Certs`] column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CertKey {
+    pub slot: Slot,
+    /// Type of the certificate, see [`CertKey::new`].
+    pub kind: u8,
+}
+
+impl CertKey {
+    /// Returns the key under which `cert` is stored.
+    #[must_use]
+    pub fn new(cert: &Cert) -> Self {
+        let kind = match cert {
+            Cert::Notar(_) => 0,
+            Cert::NotarFallback(_) => 1,
+            Cert::Skip(_) => 2,
+            Cert::FastFinal(_) => 3,
+            Cert::Final(_) => 4,
+        };
+        Self {
+            slot: cert.slot(),
+            kind,
+        }
+    }
+}
+
+/// Layout: `slot (8) | kind (1)`.
+impl ColumnKey for CertKey {
+    fn encode(&self) -> Vec<u8> {
+        [&slot_prefix(self.slot)[..], &[self.kind]].concat()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 9 {
+            return None;
+        }
+        Some(Self {
+            slot: decode_slot(bytes)?,
+            kind: bytes[8],
+        })
+    }
+}
+
+/// Key of a vote in the [`Votes`] column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoteKey {
+    pub slot: Slot,
+    pub signer: ValidatorId,
+    /// Type of the vote, see [`VoteKey::new`].
+    pub kind: u8,
+    /// Block hash voted for, or [`GENESIS_BLOCK_HASH`] for votes without one.
+    pub hash: BlockHash,
+}
+
+impl VoteKey {
+    /// Returns the key under which `vote` is stored.
+    #[must_use]
+    pub fn new(vote: &Vote) -> Self {
+        let kind = match vote.kind() {
+            VoteKind::Notar(..) => 0,
+            VoteKind::NotarFallback(..) => 1,
+            VoteKind::Skip(_) => 2,
+            VoteKind::SkipFallback(_) => 3,
+            VoteKind::Final(_) => 4,
+        };
+        Self {
+            slot: vote.slot(),
+            signer: vote.signer(),
+            kind,
+            hash: vote.block_hash().cloned().unwrap_or(GENESIS_BLOCK_HASH),
+        }
+    }
+}
+
+/// Layout: `slot (8) | signer (8) | kind (1) | block hash (32)`.
+impl ColumnKey for VoteKey {
+    fn encode(&self) -> Vec<u8> {
+        [
+            &slot_prefix(self.slot)[..],
+            &self.signer.to_be_bytes(),
+            &[self.kind],
+            self.hash.as_hash().as_ref(),
+        ]
+        .concat()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 49 {
+            return None;
+        }
+        Some(Self {
+            slot: decode_slot(bytes)?,
+            signer: u64::from_be_bytes(bytes[8..16].try_into().ok()?),
+            kind: bytes[16],
+            hash: decode_hash(&bytes[17..])?,
+        })
+    }
+}
+
+/// Key of a shred in the [`Shreds`] column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShredKey {
+    pub block_id: BlockId,
+    pub slice: SliceIndex,
+    pub shred: ShredIndex,
+}
+
+/// Layout: `slot (8) | block hash (32) | slice index (8) | shred index (8)`.
+impl ColumnKey for ShredKey {
+    fn encode(&self) -> Vec<u8> {
+        [
+            &self.block_id.encode()[..],
+            &(self.slice.inner() as u64).to_be_bytes(),
+            &(*self.shred as u64).to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 56 {
+            return None;
+        }
+        let index = |range: std::ops::Range<usize>| {
+            Some(u64::from_be_bytes(bytes.get(range)?.try_into().ok()?) as usize)
+        };
+        Some(Self {
+            block_id: BlockId::decode(&bytes[..40])?,
+            slice: SliceIndex::new(index(40..48)?)?,
+            shred: ShredIndex::new(index(48..56)?)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_roundtrip() {
+        let hash: BlockHash = Hash::random_for_test().into();
+        let block_id = (Slot::new(7), hash.clone());
+        assert_eq!(BlockId::decode(&block_id.encode()), Some(block_id.clone()));
+        assert_eq!(BlockHash::decode(&hash.encode()), Some(hash.clone()));
+
+        let cert_key = CertKey {
+            slot: Slot::new(7),
+            kind: 3,
+        };
+        assert_eq!(CertKey::decode(&cert_key.encode()), Some(cert_key));
+
+        let vote_key = VoteKey {
+            slot: Slot::new(7),
+            signer: 42,
+            kind: 1,
+            hash,
+        };
+        assert_eq!(VoteKey::decode(&vote_key.encode()), Some(vote_key));
+
+        let shred_key = ShredKey {
+            block_id,
+            slice: SliceIndex::new_unchecked(2),
+            shred: ShredIndex::new(5).unwrap(),
+        };
+        assert_eq!(ShredKey::decode(&shred_key.encode()), Some(shred_key));
+
+        assert_eq!(CertKey::decode(&[0; 8]), None);
+        assert_eq!(BlockId::decode(&[0; 41]), None);
+    }
+
+    #[test]
+    fn keys_sorted_by_slot() {
+        let key = |slot| CertKey {
+            slot: Slot::new(slot),
+            kind: 0,
+        };
+        assert!(key(255).encode() < key(256).encode());
+        assert!(key(1).encode() < key(u64::MAX).encode());
+    }
+}
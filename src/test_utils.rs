@@ -9,16 +9,19 @@ use rand::RngCore;
 use wincode::{SchemaRead, SchemaWrite};
 
 use crate::all2all::TrivialAll2All;
-use crate::consensus::{ConsensusMessage, EpochInfo};
+use crate::consensus::{
+    BlockMetadata, Cert, ConsensusMessage, EpochInfo, FinalCert, NotarCert, Vote,
+};
 use crate::crypto::aggsig::SecretKey;
-use crate::crypto::merkle::{BlockHash, DoubleMerkleTree};
+use crate::crypto::merkle::{BlockHash, DoubleMerkleTree, GENESIS_BLOCK_HASH, MerkleRoot};
 use crate::crypto::{Hash, signature};
 use crate::network::simulated::SimulatedNetworkCore;
 use crate::network::{SimulatedNetwork, localhost_ip_sockaddr};
 use crate::shredder::{MAX_DATA_PER_SLICE, RegularShredder, Shredder, ValidatedShred};
+use crate::storage::{BlockHashes, Blocks, CertKey, Certs, Metadata, Storage};
 use crate::types::{Slice, SliceHeader, SliceIndex, SlicePayload};
 use crate::{
-    BlockId, MAX_TRANSACTION_SIZE, Slot, Transaction, ValidatorId, ValidatorInfo, VotorEvent,
+    Block, BlockId, MAX_TRANSACTION_SIZE, Slot, Transaction, ValidatorId, ValidatorInfo, VotorEvent,
 };
 
 /// A simple ping network message.
@@ -40,6 +43,17 @@ pub enum PingOrPong {
 ///
 /// Returns the voting secret keys of all validators and the [`EpochInfo`] of validator 0.
 pub fn generate_validators(num_validators: u64) -> (Vec<SecretKey>, Arc<EpochInfo>) {
+    let (_, voting_sks, validators) = generate_validators_with_keys(num_validators);
+    let epoch_info = Arc::new(EpochInfo::new(0, validators));
+    (voting_sks, epoch_info)
+}
+
+/// Generates [`ValidatorInfo`] for the given number of validators.
+///
+/// Returns the identity secret keys, the voting secret keys and the info of all validators.
+pub fn generate_validators_with_keys(
+    num_validators: u64,
+) -> (Vec<signature::SecretKey>, Vec<SecretKey>, Vec<ValidatorInfo>) {
     let mut rng = rand::rng();
     let mut sks = Vec::new();
     let mut voting_sks = Vec::new();
@@ -58,8 +72,7 @@ pub fn generate_validators(num_validators: u64) -> (Vec<SecretKey>, Arc<EpochInf
             repair_response_address: localhost_ip_sockaddr(0),
         });
     }
-    let epoch_info = Arc::new(EpochInfo::new(0, validators));
-    (voting_sks, epoch_info)
+    (sks, voting_sks, validators)
 }
 
 /// Creates [`TrivialAll2All`] instances for the given validators.
@@ -133,6 +146,67 @@ pub fn create_random_block(slot: Slot, num_slices: usize) -> Vec<Slice> {
     slices
 }
 
+/// Stores a chain of `num_slots` finalized blocks in slots `1..=num_slots` in `storage`.
+///
+/// Each block comes with its metadata, hash index entry, and notarization and
+/// finalization certificates signed by all validators with keys `sks`.
+/// Also records the last slot as the highest finalized slot.
+///
+/// Returns the IDs of all blocks, ordered by slot.
+pub fn create_finalized_chain(
+    storage: &dyn Storage,
+    num_slots: u64,
+    sks: &[SecretKey],
+    epoch_info: &EpochInfo,
+) -> Vec<BlockId> {
+    let validators = &epoch_info.validators;
+    let mut parent = (Slot::genesis(), GENESIS_BLOCK_HASH);
+    let mut chain = Vec::new();
+    let mut batch = storage.batch();
+    for slot in (1..=num_slots).map(Slot::new) {
+        let hash: BlockHash = Hash::random_for_test().into();
+        let block = Block {
+            _slot: slot,
+            hash: hash.clone(),
+            parent: parent.0,
+            parent_hash: parent.1.clone(),
+            _transactions: vec![Transaction(vec![0; 64])],
+        };
+        let metadata = BlockMetadata {
+            slot,
+            hash: hash.as_hash().clone(),
+            producer: 0,
+            proposed_timestamp: 0,
+            finalized_timestamp: None,
+        };
+        let signers = sks.iter().zip(0..);
+        let notar_votes: Vec<_> = signers
+            .clone()
+            .map(|(sk, v)| Vote::new_notar(slot, hash.clone(), sk, v))
+            .collect();
+        let final_votes: Vec<_> = signers
+            .map(|(sk, v)| Vote::new_final(slot, sk, v))
+            .collect();
+        let certs = [
+            Cert::Notar(NotarCert::new_unchecked(&notar_votes, validators)),
+            Cert::Final(FinalCert::new_unchecked(&final_votes, validators)),
+        ];
+
+        let block_id = (slot, hash.clone());
+        batch.put::<Blocks>(&block_id, &block).unwrap();
+        batch.put::<Metadata>(&block_id, &metadata).unwrap();
+        batch.put::<BlockHashes>(&hash, &slot).unwrap();
+        for cert in &certs {
+            batch.put::<Certs>(&CertKey::new(cert), cert).unwrap();
+        }
+        chain.push(block_id.clone());
+        parent = block_id;
+    }
+    batch.set_finalized_slot(Slot::new(num_slots));
+    batch.commit().unwrap();
+    chain
+}
+
 /// Asserts that two [`VotorEvent`]s are equal.
 ///
 /// Panics if they are not equal.
@@ -0,0 +1,316 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configurable export of `fastrace` spans.
+//!
+//! Binaries select a [`TracingBackend`] and sampling rate via [`TracingConfig`],
+//! then call [`enable_tracing`] once at startup and [`fastrace::flush`] before exiting.
+//! By default, spans are not exported at all, so nodes do not depend on a collector.
+//!
+//! Spans covering a single slot should be created with [`slot_span`].
+//! These carry a correlation ID derived only from the slot, so that the same slot
+//! can be found across the traces of all nodes. Sampling is decided per slot
+//! the same way, so either all nodes record a given slot or none does.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use fastrace::Span;
+use fastrace::collector::{Config, Reporter, SpanRecord};
+use fastrace_opentelemetry::OpenTelemetryReporter;
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::crypto::hash;
+use crate::types::Slot;
+
+/// Endpoint used for [`TracingBackend::Otlp`] if none is given explicitly.
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://127.0.0.1:4317";
+
+/// Fraction of slots for which [`slot_span`] records spans, stored as `f64` bits.
+static SAMPLING_RATE: AtomicU64 = AtomicU64::new(1.0f64.to_bits());
+
+/// Where spans are exported to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TracingBackend {
+    /// Spans are not collected at all.
+    #[default]
+    None,
+    /// Spans are exported via OTLP over gRPC to the given endpoint.
+    Otlp(String),
+    /// Spans are appended to the given file, one JSON object per line.
+    JsonFile(PathBuf),
+    /// Spans are printed to stdout, one JSON object per line.
+    Stdout,
+}
+
+impl FromStr for TracingBackend {
+    type Err = String;
+
+    /// Parses `none`, `stdout`, `otlp`, `otlp=<endpoint>` or `file=<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "none" => Ok(Self::None),
+            None if s == "stdout" => Ok(Self::Stdout),
+            None if s == "otlp" => Ok(Self::Otlp(DEFAULT_OTLP_ENDPOINT.to_string())),
+            Some(("otlp", endpoint)) if !endpoint.is_empty() => {
+                Ok(Self::Otlp(endpoint.to_string()))
+            }
+            Some(("file", path)) if !path.is_empty() => Ok(Self::JsonFile(path.into())),
+            _ => Err(format!(
+                "invalid tracing backend `{s}`, expected one of: none, stdout, otlp[=<endpoint>], file=<path>"
+            )),
+        }
+    }
+}
+
+/// Errors that can occur when enabling tracing.
+#[derive(Debug, Error)]
+pub enum TracingError {
+    #[error("sampling rate {0} is not within [0, 1]")]
+    InvalidSamplingRate(f64),
+    #[error("can not build OTLP exporter: {0}")]
+    Otlp(#[from] ExporterBuildError),
+    #[error("can not open span file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Tracing configuration of a binary.
+#[derive(Clone, Debug)]
+pub struct TracingConfig {
+    service_name: String,
+    backend: TracingBackend,
+    sampling_rate: f64,
+}
+
+impl TracingConfig {
+    /// Creates a new config, identifying spans as coming from `service_name`.
+    ///
+    /// Uses [`TracingBackend::None`] and records all slots, unless changed.
+    #[must_use]
+    pub fn new(service_name: impl Into<String>) -> Self {
+        Self {
+            service_name: service_name.into(),
+            backend: TracingBackend::None,
+            sampling_rate: 1.0,
+        }
+    }
+
+    /// Sets the backend spans are exported to.
+    #[must_use]
+    pub fn with_backend(mut self, backend: TracingBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets the fraction of slots for which spans are recorded, within `[0, 1]`.
+    #[must_use]
+    pub fn with_sampling_rate(mut self, sampling_rate: f64) -> Self {
+        self.sampling_rate = sampling_rate;
+        self
+    }
+}
+
+/// Enables tracing according to the given `config`.
+///
+/// # Errors
+///
+/// Returns an error if the sampling rate is invalid or the backend can not be set up.
+pub fn enable_tracing(config: &TracingConfig) -> Result<(), TracingError> {
+    if !(0.0..=1.0).contains(&config.sampling_rate) {
+        return Err(TracingError::InvalidSamplingRate(config.sampling_rate));
+    }
+    SAMPLING_RATE.store(config.sampling_rate.to_bits(), Ordering::Relaxed);
+
+    match &config.backend {
+        TracingBackend::None => {}
+        TracingBackend::Otlp(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint.clone())
+                .with_protocol(opentelemetry_otlp::Protocol::Grpc)
+                .with_timeout(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT)
+                .build()?;
+            let reporter = OpenTelemetryReporter::new(
+                exporter,
+                Cow::Owned(
+                    Resource::builder()
+                        .with_attributes([KeyValue::new(
+                            "service.name",
+                            config.service_name.clone(),
+                        )])
+                        .build(),
+                ),
+                InstrumentationScope::builder("alpenglow")
+                    .with_version(env!("CARGO_PKG_VERSION"))
+                    .build(),
+            );
+            fastrace::set_reporter(reporter, Config::default());
+        }
+        TracingBackend::JsonFile(path) => {
+            let file = File::options().create(true).append(true).open(path)?;
+            let reporter = JsonReporter::new(BufWriter::new(file), &config.service_name);
+            fastrace::set_reporter(reporter, Config::default());
+        }
+        TracingBackend::Stdout => {
+            let reporter = JsonReporter::new(std::io::stdout(), &config.service_name);
+            fastrace::set_reporter(reporter, Config::default());
+        }
+    }
+    Ok(())
+}
+
+/// Creates a span for work on the given `slot`, as a child of the local parent.
+///
+/// The span carries the slot's [`slot_correlation_id`].
+/// Returns a no-op span if the slot is not sampled.
+#[must_use]
+pub fn slot_span(slot: Slot) -> Span {
+    if !is_slot_sampled(slot) {
+        return Span::noop();
+    }
+    Span::enter_with_local_parent(format!("slot {slot}"))
+        .with_property(|| ("slot", slot.to_string()))
+        .with_property(|| ("slot.correlation_id", slot_correlation_id(slot)))
+}
+
+/// Returns the correlation ID of `slot`, which is the same on all nodes.
+#[must_use]
+pub fn slot_correlation_id(slot: Slot) -> String {
+    hex::encode(&slot_digest(slot).as_ref()[..16])
+}
+
+/// Returns `true` iff spans of `slot` are recorded under the current sampling rate.
+fn is_slot_sampled(slot: Slot) -> bool {
+    let rate = f64::from_bits(SAMPLING_RATE.load(Ordering::Relaxed));
+    if rate >= 1.0 {
+        return true;
+    }
+    let digest = slot_digest(slot);
+    let bytes = digest.as_ref()[..8].try_into().unwrap();
+    (u64::from_be_bytes(bytes) as f64 / u64::MAX as f64) < rate
+}
+
+fn slot_digest(slot: Slot) -> crate::crypto::Hash {
+    hash(&[b"slot-correlation".as_slice(), &slot.inner().to_be_bytes()].concat())
+}
+
+/// [`Reporter`] writing each span as a single line of JSON.
+struct JsonReporter<W> {
+    writer: W,
+    service_name: String,
+}
+
+impl<W: Write> JsonReporter<W> {
+    fn new(writer: W, service_name: &str) -> Self {
+        Self {
+            writer,
+            service_name: service_name.to_string(),
+        }
+    }
+
+    fn to_json(&self, span: &SpanRecord) -> serde_json::Value {
+        let properties = |props: &[(Cow<'static, str>, Cow<'static, str>)]| {
+            props
+                .iter()
+                .map(|(k, v)| (k.to_string(), json!(v)))
+                .collect::<serde_json::Map<_, _>>()
+        };
+        let events = span
+            .events
+            .iter()
+            .map(|event| {
+                json!({
+                    "name": event.name,
+                    "timestamp_unix_ns": event.timestamp_unix_ns,
+                    "properties": properties(&event.properties),
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "service": self.service_name,
+            "trace_id": format!("{:032x}", span.trace_id.0),
+            "span_id": format!("{:016x}", span.span_id.0),
+            "parent_id": format!("{:016x}", span.parent_id.0),
+            "name": span.name,
+            "begin_time_unix_ns": span.begin_time_unix_ns,
+            "duration_ns": span.duration_ns,
+            "properties": properties(&span.properties),
+            "events": events,
+        })
+    }
+}
+
+impl<W: Write + Send + 'static> Reporter for JsonReporter<W> {
+    fn report(&mut self, spans: Vec<SpanRecord>) {
+        for span in &spans {
+            let line = self.to_json(span);
+            if let Err(err) = writeln!(self.writer, "{line}") {
+                log::warn!("failed to write span: {err}");
+                return;
+            }
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_backend() {
+        assert_eq!("none".parse(), Ok(TracingBackend::None));
+        assert_eq!("stdout".parse(), Ok(TracingBackend::Stdout));
+        assert_eq!(
+            "otlp".parse(),
+            Ok(TracingBackend::Otlp(DEFAULT_OTLP_ENDPOINT.to_string()))
+        );
+        assert_eq!(
+            "otlp=http://10.0.0.1:4317".parse(),
+            Ok(TracingBackend::Otlp("http://10.0.0.1:4317".to_string()))
+        );
+        assert_eq!(
+            "file=spans.jsonl".parse(),
+            Ok(TracingBackend::JsonFile("spans.jsonl".into()))
+        );
+        assert!("file=".parse::<TracingBackend>().is_err());
+        assert!("jaeger".parse::<TracingBackend>().is_err());
+    }
+
+    #[test]
+    fn correlation_ids() {
+        let slot = Slot::new(42);
+        assert_eq!(
+            slot_correlation_id(slot),
+            slot_correlation_id(Slot::new(42))
+        );
+        assert_ne!(slot_correlation_id(slot), slot_correlation_id(slot.next()));
+        assert_eq!(slot_correlation_id(slot).len(), 32);
+    }
+
+    #[test]
+    fn json_reporter() {
+        let span = SpanRecord {
+            name: "slot 42".into(),
+            properties: vec![("slot".into(), "42".into())],
+            ..SpanRecord::default()
+        };
+        let mut reporter = JsonReporter::new(Vec::new(), "test");
+        reporter.report(vec![span.clone(), span]);
+        let output = String::from_utf8(reporter.writer).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let json: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(json["service"], "test");
+        assert_eq!(json["name"], "slot 42");
+        assert_eq!(json["properties"]["slot"], "42");
+    }
+}
@@ -30,7 +30,9 @@ impl SliceIndex {
     }
 
     /// Creates a new slice index.
-    fn new(index: usize) -> Option<Self> {
+    ///
+    /// Returns `None` if `index` is not in the valid range.
+    pub(crate) fn new(index: usize) -> Option<Self> {
         if index >= MAX_SLICES_PER_BLOCK {
             None
         } else {
@@ -5,6 +5,7 @@
 
 use std::fmt::Display;
 
+use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
 /// Number of slots in each leader window.
@@ -18,7 +19,20 @@ pub const SLOTS_PER_EPOCH: u64 = 18_000;
 
 /// Slot number type.
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, SchemaRead, SchemaWrite)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    SchemaRead,
+    SchemaWrite,
+)]
 pub struct Slot(u64);
 
 impl Slot {
@@ -5,27 +5,27 @@ slow_tests () {
 	echo "Starting in 3 seconds..."
 	sleep 3
 	# run performance tests sequentially in release mode
-	cargo nextest run --release --jobs=1 --run-ignored=only
+	cargo nextest run --release --jobs=1 --run-ignored=only --all-features
 	# run other tests in parallel in debug mode
-	RUST_BACKTRACE=1 cargo nextest run
+	RUST_BACKTRACE=1 cargo nextest run --all-features
 }
 
 fast_tests () {
 	echo "🚀 Running fast tests!"
 	sleep 1
-	RUST_BACKTRACE=1 cargo nextest run --all-targets
+	RUST_BACKTRACE=1 cargo nextest run --all-targets --all-features
 }
 
 doc_tests () {
 	echo "📜 Running documentation tests!"
 	sleep 1
-	RUST_BACKTRACE=1 cargo test --doc
+	RUST_BACKTRACE=1 cargo test --doc --all-features
 }
 
 sequential_tests () {
 	echo "🦥 Running sequential tests!"
 	sleep 1
-		RUST_BACKTRACE=1 cargo nextest run --release --jobs=1 --run-ignored=only \
+		RUST_BACKTRACE=1 cargo nextest run --release --jobs=1 --run-ignored=only --all-features \
 		network::simulated::core::tests::asymmetric \
 		network::simulated::core::tests::symmetric \
 		network::simulated::token_bucket::tests::extreme_rate \
@@ -0,0 +1,91 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cluster tests with Byzantine nodes, see [`alpenglow::byzantine`].
+//!
+//! All scenarios run 11 nodes with equal stake, 2 of which are Byzantine (<20% of stake).
+
+use std::time::Duration;
+
+use alpenglow::byzantine::Behavior;
+use alpenglow::cluster::{ClusterConfig, SimulatedCluster};
+
+const NUM_NODES: u64 = 11;
+const BYZANTINE_NODES: [u64; 2] = [0, 1];
+
+#[test]
+fn equivocating_leaders() {
+    run_scenario(1, &[Behavior::EquivocatingLeader; 2]);
+}
+
+#[test]
+fn withholding_relays() {
+    run_scenario(2, &[Behavior::WithholdingRelay; 2]);
+}
+
+#[test]
+fn double_voting() {
+    run_scenario(3, &[Behavior::DoubleVoting; 2]);
+}
+
+#[test]
+fn withholding_votes() {
+    run_scenario(4, &[Behavior::WithholdingVotes; 2]);
+}
+
+#[test]
+fn cert_spamming() {
+    run_scenario(5, &[Behavior::CertSpamming; 2]);
+}
+
+#[test]
+fn mixed() {
+    run_scenario(6, &[Behavior::EquivocatingLeader, Behavior::DoubleVoting]);
+}
+
+/// Runs a cluster where [`BYZANTINE_NODES`] exhibit the given `behaviors`.
+///
+/// Checks liveness and safety for the honest nodes.
+fn run_scenario(seed: u64, behaviors: &[Behavior; 2]) {
+    let mut config = ClusterConfig::new(NUM_NODES).with_seed(seed);
+    for (node, behavior) in BYZANTINE_NODES.into_iter().zip(behaviors) {
+        config = config.with_behavior(node, *behavior);
+    }
+    config.run(|cluster| async move {
+        assert_progress(&cluster, seed, 6).await;
+        assert_safety(&cluster, seed).await;
+    });
+}
+
+/// Checks that all honest nodes finalize new slots every 10 minutes.
+async fn assert_progress(cluster: &SimulatedCluster, seed: u64, intervals: usize) {
+    let mut finalized = cluster.finalized_slots().await;
+    for _ in 0..intervals {
+        cluster.run_for(mins(10)).await;
+        let new_finalized = cluster.finalized_slots().await;
+        for node in honest_nodes(cluster) {
+            let i = node as usize;
+            assert!(
+                new_finalized[i] > finalized[i],
+                "no progress on node {node} after {:?} (seed {seed})",
+                cluster.elapsed()
+            );
+        }
+        finalized = new_finalized;
+    }
+}
+
+/// Checks that honest nodes never finalized conflicting blocks.
+async fn assert_safety(cluster: &SimulatedCluster, seed: u64) {
+    if let Err(violation) = cluster.check_safety().await {
+        panic!("safety violation: {violation} (seed {seed})");
+    }
+}
+
+fn honest_nodes(cluster: &SimulatedCluster) -> impl Iterator<Item = u64> + '_ {
+    (0..cluster.num_nodes() as u64).filter(|&node| cluster.is_honest(node))
+}
+
+const fn mins(mins: u64) -> Duration {
+    Duration::from_secs(60 * mins)
+}
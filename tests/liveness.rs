@@ -1,10 +1,12 @@
 // Copyright (c) Anza Technology, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::Arc;
 use std::time::Duration;
 
-use alpenglow::create_test_nodes;
+use alpenglow::network::simulated::{FaultSchedule, SimulatedNetworkCore};
 use alpenglow::types::Slot;
+use alpenglow::{create_simulated_test_nodes, create_test_nodes};
 use log::debug;
 use rand::prelude::*;
 
@@ -50,12 +52,37 @@ async fn three_nodes_crash() {
     liveness_test(3, 1).await;
 }
 
-// TODO: implement transient failure test
-//
-// #[tokio::test]
-// async fn transient_failure() {
-//     liveness_test(11, 1).await;
-// }
+#[tokio::test]
+#[ignore]
+async fn transient_failure() {
+    // one node goes dark for 20 s, then rejoins
+    let faults = FaultSchedule::default().with_isolation(0, secs(10), secs(20));
+    transient_failure_test(11, faults, &[0]).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn healing_partition() {
+    // maximum tolerable minority is cut off for 20 s, then the partition heals
+    let groups = vec![(0..4).collect(), (4..11).collect()];
+    let faults = FaultSchedule::default().with_partition(groups, secs(10), secs(20));
+    transient_failure_test(11, faults, &[0, 1, 2, 3]).await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn lossy_links() {
+    // one node loses most packets it sends for 20 s
+    let mut faults = FaultSchedule::default();
+    for to in 1..11 {
+        faults = faults.with_link_loss(0, to, 0.9, secs(10), secs(20));
+    }
+    transient_failure_test(11, faults, &[0]).await;
+}
+
+const fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
 
 async fn liveness_test(num_nodes: usize, num_crashes: usize) {
     liveness_test_internal(num_nodes, num_crashes, true).await
@@ -119,3 +146,54 @@ async fn liveness_test_internal(num_nodes: usize, num_crashes: usize, should_suc
         token.cancel();
     }
 }
+
+/// Runs `num_nodes` nodes on a simulated network with the given `faults`.
+///
+/// Nodes not listed in `affected` have to make progress throughout.
+/// All nodes have to make progress again after all faults have healed (within 30 s).
+async fn transient_failure_test(num_nodes: usize, faults: FaultSchedule, affected: &[usize]) {
+    let core = Arc::new(SimulatedNetworkCore::default().with_packet_loss(0.0));
+    let nodes = create_simulated_test_nodes(&core, num_nodes as u64, &mut rand::rng()).await;
+    let mut node_cancel_tokens = Vec::new();
+    let mut pools = Vec::new();
+    for node in nodes {
+        pools.push(node.get_pool());
+        node_cancel_tokens.push(node.get_cancel_token());
+        tokio::spawn(node.run());
+    }
+    core.schedule_faults(faults);
+
+    // unaffected nodes have to make progress while faults are in effect
+    let mut finalized = vec![Slot::new(0); num_nodes];
+    for t in 1..=3 {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        for (i, pool) in pools.iter().enumerate() {
+            let new_finalized = pool.read().await.finalized_slot();
+            if !affected.contains(&i) {
+                assert!(
+                    new_finalized > finalized[i],
+                    "no progress on node {} after {} s",
+                    i,
+                    10 * t
+                );
+            }
+            finalized[i] = new_finalized;
+        }
+    }
+
+    // all nodes have to catch up after faults healed
+    let healed_at = finalized.iter().max().copied().unwrap();
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    for (i, pool) in pools.iter().enumerate() {
+        let new_finalized = pool.read().await.finalized_slot();
+        debug!("node {i} finalized slot {new_finalized:?} after healing");
+        assert!(
+            new_finalized > healed_at,
+            "node {i} did not recover after faults healed"
+        );
+    }
+
+    for token in node_cancel_tokens {
+        token.cancel();
+    }
+}
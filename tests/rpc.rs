@@ -0,0 +1,121 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the JSON-RPC API, see [`alpenglow::rpc`].
+
+use std::net::SocketAddr;
+
+use alpenglow::Transaction;
+use alpenglow::create_test_nodes;
+use alpenglow::network::{Channel, MultiplexedFrame, MultiplexedNetwork, Multiplexer, UdpNetwork};
+use alpenglow::rpc::RpcServer;
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+const NUM_NODES: u64 = 4;
+
+type TxNetwork =
+    MultiplexedNetwork<UdpNetwork<MultiplexedFrame, MultiplexedFrame>, Transaction, Transaction>;
+
+#[tokio::test]
+async fn queries() {
+    let cluster = LocalCluster::start().await;
+
+    let health = cluster.call("getHealth", json!([])).await;
+    assert_eq!(health["result"], "ok");
+
+    let validators = cluster.call("getValidators", json!([])).await;
+    let validators = validators["result"].as_array().unwrap();
+    assert_eq!(validators.len(), NUM_NODES as usize);
+    assert_eq!(validators[1]["id"], 1);
+
+    let schedule = cluster.call("getLeaderSchedule", json!([0, 8])).await;
+    let schedule = schedule["result"].as_array().unwrap();
+    let leaders = schedule.iter().map(|s| s["leader"].as_u64().unwrap());
+    assert_eq!(leaders.collect::<Vec<_>>(), [0, 0, 1, 1, 2, 2, 3, 3]);
+
+    let slot = cluster.call("getFinalizedSlot", json!([])).await;
+    assert!(slot["result"].is_u64());
+
+    let tx = hex::encode([1; 64]);
+    let sent = cluster.call("sendTransaction", json!([tx])).await;
+    assert!(!sent["result"]["forwardedTo"].as_array().unwrap().is_empty());
+
+    cluster.stop();
+}
+
+#[tokio::test]
+async fn errors() {
+    let cluster = LocalCluster::start().await;
+
+    let resp = cluster.call("getNothing", json!([])).await;
+    assert_eq!(resp["error"]["code"], -32601);
+
+    let resp = cluster.call("getCerts", json!(["slot"])).await;
+    assert_eq!(resp["error"]["code"], -32602);
+
+    let resp = cluster.call("getBlock", json!(["not hex"])).await;
+    assert_eq!(resp["error"]["code"], -32602);
+
+    let tx = hex::encode([1; 4096]);
+    let resp = cluster.call("sendTransaction", json!([tx])).await;
+    assert_eq!(resp["error"]["code"], -32602);
+
+    let resp = cluster.post("{ not json").await;
+    assert_eq!(resp["error"]["code"], -32700);
+    assert_eq!(resp["id"], Value::Null);
+
+    cluster.stop();
+}
+
+/// Local cluster of [`NUM_NODES`] nodes, where node 0 serves the JSON-RPC API.
+struct LocalCluster {
+    rpc_addr: SocketAddr,
+    cancel_tokens: Vec<CancellationToken>,
+    client: reqwest::Client,
+}
+
+impl LocalCluster {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_addr = listener.local_addr().unwrap();
+        let mut cancel_tokens = Vec::new();
+        for (i, node) in create_test_nodes(NUM_NODES).into_iter().enumerate() {
+            let cancel_token = node.get_cancel_token();
+            if i == 0 {
+                let txs: TxNetwork = Multiplexer::new(UdpNetwork::new_with_any_port())
+                    .channel(Channel::Transactions, Channel::Transactions);
+                let rpc = RpcServer::new(&node, txs);
+                tokio::spawn(rpc.serve_on(listener, cancel_token.clone()));
+            }
+            cancel_tokens.push(cancel_token);
+            tokio::spawn(node.run());
+        }
+        Self {
+            rpc_addr,
+            cancel_tokens,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let resp = self.post(request.to_string()).await;
+        assert_eq!(resp["jsonrpc"], "2.0");
+        assert_eq!(resp["id"], 1);
+        resp
+    }
+
+    async fn post(&self, body: impl Into<reqwest::Body>) -> Value {
+        let url = format!("http://{}/", self.rpc_addr);
+        let resp = self.client.post(url).body(body).send().await.unwrap();
+        resp.json().await.unwrap()
+    }
+
+    fn stop(self) {
+        for token in self.cancel_tokens {
+            token.cancel();
+        }
+    }
+}
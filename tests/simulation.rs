@@ -0,0 +1,105 @@
+// Copyright (c) Anza Technology, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cluster tests in virtual time, see [`alpenglow::cluster`].
+
+use std::time::Duration;
+
+use alpenglow::cluster::{ClusterConfig, SimulatedCluster};
+use alpenglow::network::simulated::FaultSchedule;
+use alpenglow::types::Slot;
+
+#[test]
+fn only_correct_nodes() {
+    let config = ClusterConfig::new(6).with_seed(1);
+    let seed = config.seed();
+    config.run(|cluster| async move {
+        assert_progress(&cluster, seed, 6).await;
+        assert_safety(&cluster, seed).await;
+    });
+}
+
+#[test]
+fn max_crashes() {
+    let config = ClusterConfig::new(11).with_seed(2);
+    let seed = config.seed();
+    config.run(|cluster| async move {
+        cluster.run_for(mins(5)).await;
+        for node in 0..4 {
+            cluster.crash(node);
+        }
+        assert_progress(&cluster, seed, 6).await;
+        assert_safety(&cluster, seed).await;
+    });
+}
+
+#[test]
+fn healing_partition() {
+    // maximum tolerable minority is cut off for 20 minutes, then the partition heals
+    let groups = vec![(0..4).collect(), (4..11).collect()];
+    let faults = FaultSchedule::default().with_partition(groups, mins(10), mins(20));
+    let config = ClusterConfig::new(11).with_seed(3).with_faults(faults);
+    let seed = config.seed();
+    config.run(|cluster| async move {
+        cluster.run_for(mins(30)).await;
+        let healed_at = max_finalized(&cluster).await;
+        cluster.run_for(mins(30)).await;
+        for (node, slot) in cluster.finalized_slots().await.into_iter().enumerate() {
+            assert!(
+                slot > healed_at,
+                "node {node} did not recover after partition healed (seed {seed})"
+            );
+        }
+        assert_safety(&cluster, seed).await;
+    });
+}
+
+// tokio's own randomness can only be seeded with `tokio_unstable`, see `alpenglow::cluster`
+#[cfg(tokio_unstable)]
+#[test]
+fn same_seed_same_outcome() {
+    let run = |seed| {
+        let config = ClusterConfig::new(4).with_seed(seed).with_packet_loss(0.05);
+        config.run(|cluster| async move {
+            cluster.run_for(mins(30)).await;
+            cluster.finalized_slots().await
+        })
+    };
+    assert_eq!(run(4), run(4));
+}
+
+/// Checks that all correct nodes finalize new slots every 10 minutes.
+async fn assert_progress(cluster: &SimulatedCluster, seed: u64, intervals: usize) {
+    let mut finalized = cluster.finalized_slots().await;
+    for _ in 0..intervals {
+        cluster.run_for(mins(10)).await;
+        let new_finalized = cluster.finalized_slots().await;
+        for node in 0..cluster.num_nodes() {
+            if cluster.is_crashed(node as u64) {
+                continue;
+            }
+            assert!(
+                new_finalized[node] > finalized[node],
+                "no progress on node {node} after {:?} (seed {seed})",
+                cluster.elapsed()
+            );
+        }
+        finalized = new_finalized;
+    }
+}
+
+/// Checks that no two nodes finalized conflicting blocks.
+async fn assert_safety(cluster: &SimulatedCluster, seed: u64) {
+    if let Err(violation) = cluster.check_safety().await {
+        panic!("safety violation: {violation} (seed {seed})");
+    }
+}
+
+async fn max_finalized(cluster: &SimulatedCluster) -> Slot {
+    let slots = cluster.finalized_slots().await;
+    slots.into_iter().max().unwrap()
+}
+
+const fn mins(mins: u64) -> Duration {
+    Duration::from_secs(60 * mins)
+}